@@ -0,0 +1,522 @@
+use std::collections::hash_map::Entry;
+use std::collections::{HashMap, HashSet};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc;
+use std::thread;
+
+use anyhow::{anyhow, bail, Result};
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use rayon::prelude::*;
+
+use crate::dedup::{ExternalDedup, SortedRecords};
+use crate::hasher::{self, Hasher};
+use crate::source::{BinaryRecords, Prehashed, Source};
+use crate::storage::{HashRecord, RecordKey, Storage};
+
+pub(crate) const BATCH_SIZE: usize = 100_000;
+const PIPELINE_DEPTH: usize = 4;
+const PARALLEL_SORT_THRESHOLD: usize = 100_000;
+// Binary records can be large; bound a batch by bytes as well as count
+const BINARY_BATCH_BYTES: usize = 64 * 1024 * 1024;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum SortMode {
+    // Parallel once there are enough records to amortize the thread pool
+    #[default]
+    Auto,
+    Serial,
+    Parallel,
+}
+
+impl SortMode {
+    fn parallel(self, len: usize) -> bool {
+        match self {
+            SortMode::Auto => len >= PARALLEL_SORT_THRESHOLD,
+            SortMode::Serial => false,
+            SortMode::Parallel => true,
+        }
+    }
+}
+
+pub struct BuildOptions {
+    pub algorithms: Vec<String>,
+    pub source_name: Option<String>,
+    pub progress: Option<Box<dyn FnMut(BuildProgress)>>,
+}
+
+impl Default for BuildOptions {
+    fn default() -> Self {
+        Self {
+            algorithms: vec!["sha256".to_string()],
+            source_name: None,
+            progress: None,
+        }
+    }
+}
+
+impl BuildOptions {
+    pub fn new(algorithms: impl IntoIterator<Item = impl Into<String>>) -> Self {
+        Self {
+            algorithms: algorithms.into_iter().map(Into::into).collect(),
+            ..Self::default()
+        }
+    }
+
+    pub fn with_source_name(mut self, name: impl Into<String>) -> Self {
+        self.source_name = Some(name.into());
+        self
+    }
+
+    pub fn with_progress(mut self, progress: impl FnMut(BuildProgress) + 'static) -> Self {
+        self.progress = Some(Box::new(progress));
+        self
+    }
+}
+
+// Reported once per ingested batch and once when ingestion finishes.
+// With disk dedup, words are counted first while unique_words and records
+// stay 0, then the distinct words are hashed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BuildProgress {
+    pub total_words: usize,
+    pub unique_words: usize,
+    pub records: usize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BuildSummary {
+    pub total_words: usize,
+    pub unique_words: usize,
+    pub records: usize,
+    // Distinct preimages that hashed to an already-seen hash; the first is kept
+    pub collisions: usize,
+}
+
+pub fn build(
+    source: &dyn Source,
+    options: &mut BuildOptions,
+    storage: &mut dyn Storage,
+) -> Result<BuildSummary> {
+    let hashers = options
+        .algorithms
+        .iter()
+        .map(|name| hasher::get_hasher(name).ok_or_else(|| anyhow!("Unknown algorithm: {}", name)))
+        .collect::<Result<Vec<_>>>()?;
+    let source_name = options.source_name.as_deref().unwrap_or(source.name());
+    let progress: &mut dyn FnMut(BuildProgress) = match options.progress.as_mut() {
+        Some(progress) => progress,
+        None => &mut |_| {},
+    };
+
+    let Ingested {
+        total_words,
+        unique_words,
+        records,
+        collisions,
+    } = ingest(source.words()?, &hashers, source_name, progress, None)?;
+
+    let mut records: Vec<HashRecord> = records.into_values().collect();
+    sort_records(&mut records, SortMode::Auto);
+    write_records(storage, &records)?;
+
+    Ok(BuildSummary {
+        total_words,
+        unique_words,
+        records: records.len(),
+        collisions,
+    })
+}
+
+// (hash, algorithm) is unique per record, so an unstable sort gives the same
+// order as a stable one without allocating a merge buffer.
+pub fn sort_records(records: &mut [HashRecord], mode: SortMode) {
+    let cmp = |a: &HashRecord, b: &HashRecord| {
+        a.hash.cmp(&b.hash).then_with(|| a.algorithm.cmp(&b.algorithm))
+    };
+    if mode.parallel(records.len()) {
+        records.par_sort_unstable_by(cmp);
+    } else {
+        records.sort_unstable_by(cmp);
+    }
+}
+
+pub fn sort_records_by_algorithm(records: &mut [HashRecord], mode: SortMode) {
+    let cmp = |a: &HashRecord, b: &HashRecord| {
+        a.algorithm.cmp(&b.algorithm).then_with(|| a.hash.cmp(&b.hash))
+    };
+    if mode.parallel(records.len()) {
+        records.par_sort_unstable_by(cmp);
+    } else {
+        records.sort_unstable_by(cmp);
+    }
+}
+
+pub fn write_records(storage: &mut dyn Storage, records: &[HashRecord]) -> Result<()> {
+    for chunk in records.chunks(BATCH_SIZE) {
+        storage.write_batch(chunk.to_vec())?;
+    }
+    storage.finish()
+}
+
+// Streams the records once, handing every batch to each sink, then
+// finishes them all
+pub fn write_sorted(sinks: &mut [&mut dyn Storage], records: SortedRecords) -> Result<()> {
+    let mut batch = Vec::with_capacity(BATCH_SIZE);
+    for record in records {
+        batch.push(record?);
+        if batch.len() == BATCH_SIZE {
+            write_batch_to_all(sinks, std::mem::take(&mut batch))?;
+        }
+    }
+    if !batch.is_empty() {
+        write_batch_to_all(sinks, batch)?;
+    }
+    for sink in sinks.iter_mut() {
+        sink.finish()?;
+    }
+    Ok(())
+}
+
+fn write_batch_to_all(sinks: &mut [&mut dyn Storage], batch: Vec<HashRecord>) -> Result<()> {
+    if let Some((last, rest)) = sinks.split_last_mut() {
+        for sink in rest {
+            sink.write_batch(batch.clone())?;
+        }
+        last.write_batch(batch)?;
+    }
+    Ok(())
+}
+
+pub(crate) struct Ingested {
+    pub total_words: usize,
+    pub unique_words: usize,
+    pub records: HashMap<RecordKey, HashRecord>,
+    pub collisions: usize,
+}
+
+// Reading/dedup (this thread), hashing and map merging run as three
+// pipeline stages connected by bounded channels, so IO, hashing and the
+// serial merge overlap. Batches stay in order, keeping output deterministic.
+pub(crate) fn ingest(
+    words: Box<dyn Iterator<Item = String>>,
+    hashers: &[Box<dyn Hasher>],
+    source_name: &str,
+    progress: &mut dyn FnMut(BuildProgress),
+    external: Option<ExternalDedup>,
+) -> Result<Ingested> {
+    let (batch_tx, batch_rx) = mpsc::sync_channel::<Vec<String>>(PIPELINE_DEPTH);
+    let (records_tx, records_rx) = mpsc::sync_channel::<Vec<HashRecord>>(PIPELINE_DEPTH);
+    let hash_count = AtomicUsize::new(0);
+
+    thread::scope(|scope| {
+        scope.spawn(move || {
+            for batch in batch_rx {
+                if records_tx.send(hash_words(&batch, hashers, source_name)).is_err() {
+                    break;
+                }
+            }
+        });
+
+        let merger = scope.spawn(|| {
+            let mut records_map: HashMap<RecordKey, HashRecord> = HashMap::new();
+            let mut collisions = 0;
+            for records in records_rx {
+                collisions += merge_new_records(records, &mut records_map);
+                hash_count.store(records_map.len(), Ordering::Relaxed);
+            }
+            (records_map, collisions)
+        });
+
+        let mut total_words = 0usize;
+        let mut queued_words = 0usize;
+        let mut seen: HashSet<String> = HashSet::new();
+        let mut batch: Vec<String> = Vec::with_capacity(BATCH_SIZE);
+
+        // With disk dedup every word goes through the sorted runs first, so
+        // only distinct words reach the hashing stage.
+        let (words, deduped): (Box<dyn Iterator<Item = Result<String>>>, bool) = match external {
+            Some(mut external) => {
+                for word in words {
+                    total_words += 1;
+                    external.insert(word)?;
+                    if total_words.is_multiple_of(BATCH_SIZE) {
+                        progress(BuildProgress {
+                            total_words,
+                            unique_words: 0,
+                            records: 0,
+                        });
+                    }
+                }
+                (external.into_unique()?, true)
+            }
+            None => (Box::new(words.map(Ok)), false),
+        };
+
+        for word in words {
+            let word = word?;
+            if !deduped {
+                total_words += 1;
+                if !seen.insert(word.clone()) {
+                    continue;
+                }
+            }
+            batch.push(word);
+
+            if batch.len() >= BATCH_SIZE {
+                queued_words += batch.len();
+                let full = std::mem::replace(&mut batch, Vec::with_capacity(BATCH_SIZE));
+                batch_tx.send(full).expect("hashing stage alive");
+
+                progress(BuildProgress {
+                    total_words,
+                    unique_words: queued_words,
+                    records: hash_count.load(Ordering::Relaxed),
+                });
+            }
+        }
+
+        if !batch.is_empty() {
+            queued_words += batch.len();
+            batch_tx.send(batch).expect("hashing stage alive");
+        }
+        drop(batch_tx);
+
+        let (records, collisions) = merger.join().expect("merge stage panicked");
+        let unique_words = queued_words;
+        progress(BuildProgress {
+            total_words,
+            unique_words,
+            records: records.len(),
+        });
+
+        Ok(Ingested {
+            total_words,
+            unique_words,
+            records,
+            collisions,
+        })
+    })
+}
+
+pub(crate) struct PrehashedIngested {
+    pub ingested: Ingested,
+    pub occurrences: u64,
+    pub invalid: usize,
+    pub first_invalid: Option<String>,
+}
+
+pub(crate) fn ingest_prehashed(
+    prehashed: Prehashed,
+    source_name: &str,
+    progress: &mut dyn FnMut(BuildProgress),
+    validate_hex: bool,
+) -> Result<PrehashedIngested> {
+    let mut records: HashMap<RecordKey, HashRecord> = HashMap::new();
+    let mut total_words = 0usize;
+    let mut occurrences = 0u64;
+    let mut invalid = 0usize;
+    let mut first_invalid = None;
+
+    let expected_len = if validate_hex {
+        let hasher = hasher::get_hasher(prehashed.algorithm)
+            .ok_or_else(|| anyhow!("Unknown algorithm: {}", prehashed.algorithm))?;
+        Some(hasher.output_len())
+    } else {
+        None
+    };
+
+    for entry in prehashed.entries {
+        let decoded = entry.and_then(|entry| {
+            let hash = hex::decode(&entry.hash)
+                .map_err(|_| anyhow!("Invalid hex hash: {}", entry.hash))?;
+            if let Some(expected) = expected_len.filter(|&len| len != hash.len()) {
+                bail!(
+                    "Hash {} is {} bytes, {} needs {}",
+                    entry.hash,
+                    hash.len(),
+                    prehashed.algorithm,
+                    expected
+                );
+            }
+            Ok((hash, entry.count))
+        });
+
+        let (hash, count) = match decoded {
+            Ok(decoded) => decoded,
+            Err(e) if validate_hex => {
+                invalid += 1;
+                first_invalid.get_or_insert_with(|| format!("{:#}", e));
+                continue;
+            }
+            Err(e) => return Err(e),
+        };
+
+        total_words += 1;
+        occurrences += count.unwrap_or(1);
+
+        let key = (hash.clone(), prehashed.algorithm.to_string());
+        records.entry(key).or_insert_with(|| HashRecord {
+            hash,
+            preimage: String::new(),
+            algorithm: prehashed.algorithm.to_string(),
+            sources: vec![source_name.to_string()],
+        });
+
+        if total_words.is_multiple_of(BATCH_SIZE) {
+            progress(BuildProgress {
+                total_words,
+                unique_words: records.len(),
+                records: records.len(),
+            });
+        }
+    }
+    progress(BuildProgress {
+        total_words,
+        unique_words: records.len(),
+        records: records.len(),
+    });
+
+    Ok(PrehashedIngested {
+        ingested: Ingested {
+            total_words,
+            unique_words: records.len(),
+            records,
+            collisions: 0,
+        },
+        occurrences,
+        invalid,
+        first_invalid,
+    })
+}
+
+// Hashes raw byte records (files, certificates, ...). The preimage column is
+// UTF-8, so each record is stored base64-encoded.
+pub(crate) fn ingest_binary(
+    blobs: BinaryRecords,
+    hashers: &[Box<dyn Hasher>],
+    source_name: &str,
+    progress: &mut dyn FnMut(BuildProgress),
+) -> Result<Ingested> {
+    let mut records: HashMap<RecordKey, HashRecord> = HashMap::new();
+    let mut seen: HashSet<[u8; 32]> = HashSet::new();
+    let mut total_words = 0usize;
+    let mut collisions = 0usize;
+    let mut batch: Vec<Vec<u8>> = Vec::new();
+    let mut batch_bytes = 0usize;
+
+    let flush = |batch: &mut Vec<Vec<u8>>, records: &mut HashMap<RecordKey, HashRecord>| {
+        let hashed = batch
+            .par_iter()
+            .flat_map(|blob| {
+                let preimage = BASE64.encode(blob);
+                hashers
+                    .iter()
+                    .map(|hasher| HashRecord {
+                        hash: hasher.hash(blob),
+                        preimage: preimage.clone(),
+                        algorithm: hasher.name().to_string(),
+                        sources: vec![source_name.to_string()],
+                    })
+                    .collect::<Vec<_>>()
+            })
+            .collect();
+        batch.clear();
+        merge_new_records(hashed, records)
+    };
+
+    for blob in blobs {
+        let blob = blob?;
+        total_words += 1;
+        if !seen.insert(*blake3::hash(&blob).as_bytes()) {
+            continue;
+        }
+        batch_bytes += blob.len();
+        batch.push(blob);
+
+        if batch.len() >= BATCH_SIZE || batch_bytes >= BINARY_BATCH_BYTES {
+            collisions += flush(&mut batch, &mut records);
+            batch_bytes = 0;
+            progress(BuildProgress {
+                total_words,
+                unique_words: seen.len(),
+                records: records.len(),
+            });
+        }
+    }
+    collisions += flush(&mut batch, &mut records);
+    progress(BuildProgress {
+        total_words,
+        unique_words: seen.len(),
+        records: records.len(),
+    });
+
+    Ok(Ingested {
+        total_words,
+        unique_words: seen.len(),
+        records,
+        collisions,
+    })
+}
+
+// A word yields at most one record per algorithm, so a preimage with more
+// records than that points at a bug upstream, e.g. a transform emitting the
+// same word under different hashes. Hash-only records have no preimage and
+// are skipped. Returns the offending preimages with their record counts.
+pub fn preimages_over_limit<'a>(
+    records: impl IntoIterator<Item = &'a HashRecord>,
+    max_count: usize,
+) -> Vec<(String, usize)> {
+    let mut counts: HashMap<&str, usize> = HashMap::new();
+    for record in records {
+        if !record.preimage.is_empty() {
+            *counts.entry(&record.preimage).or_default() += 1;
+        }
+    }
+    let mut over: Vec<(String, usize)> = counts
+        .into_iter()
+        .filter(|(_, count)| *count > max_count)
+        .map(|(preimage, count)| (preimage.to_string(), count))
+        .collect();
+    over.sort_unstable();
+    over
+}
+
+// Keeps the first record per (hash, algorithm) and returns how many records
+// carried a different preimage for a hash already in the map.
+fn merge_new_records(
+    records: Vec<HashRecord>,
+    records_map: &mut HashMap<RecordKey, HashRecord>,
+) -> usize {
+    let mut collisions = 0;
+    for record in records {
+        let key = (record.hash.clone(), record.algorithm.clone());
+        match records_map.entry(key) {
+            Entry::Occupied(existing) => {
+                if existing.get().preimage != record.preimage {
+                    collisions += 1;
+                }
+            }
+            Entry::Vacant(slot) => {
+                slot.insert(record);
+            }
+        }
+    }
+    collisions
+}
+
+fn hash_words(words: &[String], hashers: &[Box<dyn Hasher>], source_name: &str) -> Vec<HashRecord> {
+    words
+        .par_iter()
+        .flat_map(|word| {
+            hashers
+                .iter()
+                .map(|hasher| HashRecord {
+                    hash: hasher.hash(word.as_bytes()),
+                    preimage: word.clone(),
+                    algorithm: hasher.name().to_string(),
+                    sources: vec![source_name.to_string()],
+                })
+                .collect::<Vec<_>>()
+        })
+        .collect()
+}
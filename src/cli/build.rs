@@ -1,52 +1,287 @@
-use std::collections::{HashMap, HashSet};
-use std::path::PathBuf;
-
-use anyhow::{bail, Result};
-use clap::Args;
+use std::cell::Cell;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::time::{Duration, SystemTime};
+
+use anyhow::{anyhow, bail, Context, Result};
+use clap::{Args, ValueEnum};
 use indicatif::{ProgressBar, ProgressStyle};
-use rayon::prelude::*;
 
+use crate::build::{
+    ingest, ingest_binary, ingest_prehashed, preimages_over_limit, write_records, write_sorted,
+    BuildProgress, Ingested, PrehashedIngested, SortMode,
+};
 use crate::config::{Config, R2Overrides};
-use crate::hasher::{self, Hasher};
-use crate::output;
-use crate::source;
+use crate::dedup::{ensure_writable_dir, ExternalDedup, ExternalSort, HyperLogLog};
+use crate::hasher::{self, Hasher, SaltPosition};
+use crate::output::{self, format_number};
+use crate::rules::{CaseMode, Rules};
+use crate::source::{self, BinaryFormat, InvalidLines, OnInvalid};
 use crate::status;
-use crate::storage::{HashRecord, ParquetStorage, R2Config, R2Storage, Storage};
-
-const BATCH_SIZE: usize = 100_000;
+use crate::storage::{
+    merge_by_key, ArrowStorage, CsvStorage, HashEncoding, HashRecord, Manifest, MergeStrategy,
+    ParquetStorage, R2Config, R2Object, R2Storage, SourceMeta, Storage,
+};
 
-#[derive(Args)]
+#[derive(Args, Clone)]
 pub struct BuildArgs {
-    /// Input file (for backward compatibility)
-    pub input: Option<PathBuf>,
+    /// Input file(s); several are built in order into the same output
+    pub input: Vec<PathBuf>,
 
-    /// Source specification (seclists:path, aspell:lang, file:path, or URL)
+    /// Source specification (seclists:path, aspell:lang, file:path, range:0000-9999, or URL); repeatable
     #[arg(long)]
-    pub from: Option<String>,
+    pub from: Vec<String>,
+
+    /// Extra HTTP header for URL sources, as 'Key: Value' (repeatable)
+    #[arg(long = "header", value_name = "HEADER", value_parser = |s: &str| source::parse_header(s))]
+    pub headers: Vec<(String, String)>,
+
+    /// Bearer token sent as the Authorization header of URL sources
+    #[arg(long, value_name = "TOKEN", env = "SHAHA_BEARER_TOKEN")]
+    pub bearer: Option<String>,
+
+    /// Retries for a URL source after a connection error or 5xx response
+    #[arg(long, value_name = "N", default_value = "3")]
+    pub retries: u32,
+
+    /// Delay before the first retry, doubled after each one
+    #[arg(long, value_name = "MS", default_value = "500")]
+    pub retry_delay_ms: u64,
+
+    /// File listing one source spec per line, built in order into the same output
+    #[arg(
+        long,
+        value_name = "FILE",
+        conflicts_with_all = ["input", "from", "name", "r2", "dry_run", "skip_lines", "max_file_size", "prefix_only_bits", "chunk_output"]
+    )]
+    pub sources_file: Option<PathBuf>,
 
     /// Hash algorithms to use
     #[arg(short, long, default_value = "sha256", value_parser = hasher::algo_value_parser())]
     pub algo: Vec<String>,
 
-    /// Output file
+    /// Output file(s), format inferred from extension (.parquet, .csv, .arrow)
     #[arg(short, long, default_value = "hashes.parquet")]
-    pub output: PathBuf,
+    pub output: Vec<PathBuf>,
 
     /// Source name for metadata (defaults to source name)
     #[arg(short, long)]
     pub name: Option<String>,
 
+    /// Canonicalize the source name before tagging records; with --append,
+    /// names already in the database are canonicalized too
+    #[arg(long, value_enum, default_value = "none")]
+    pub source_name_transform: SourceNameTransform,
+
+    /// Salt (hex) mixed into every word before hashing, for all algorithms
+    #[arg(long, value_name = "HEX")]
+    pub salt: Option<String>,
+
+    /// Whether the salt goes before (hash(salt || word)) or after the word
+    #[arg(long, value_enum, default_value = "prefix")]
+    pub salt_position: SaltPosition,
+
+    /// HMAC key (hex); stores HMAC-<algo>(key, word) as e.g. hmac-sha256
+    #[arg(long, value_name = "HEX", conflicts_with = "salt")]
+    pub hmac_key: Option<String>,
+
+    /// Hash each word this many times, feeding the digest back in (stored as e.g. sha256x5000); a --salt is applied before the first round only
+    #[arg(long, value_name = "N", default_value = "1")]
+    pub rounds: u32,
+
+    /// Hash raw bytes from a directory of files or a u32-length-prefixed stream (preimages stored as base64)
+    #[arg(
+        long,
+        value_enum,
+        value_name = "FORMAT",
+        requires = "input",
+        conflicts_with_all = ["from", "field", "rules_file", "case", "disk_dedup", "dry_run", "sources_file"]
+    )]
+    pub binary_records: Option<BinaryFormat>,
+
     /// Append to existing database (merge sources)
     #[arg(long)]
     pub append: bool,
 
+    /// How an appended record combines with an existing one for the same hash
+    #[arg(long, value_enum, default_value = "union-sources")]
+    pub merge_strategy: MergeStrategy,
+
     /// Force rebuild even if source was already processed
     #[arg(long)]
     pub force: bool,
 
+    /// Skip the build when every output is newer than the source file (file and seclists sources)
+    #[arg(long, conflicts_with_all = ["r2", "sources_file"])]
+    pub if_newer: bool,
+
     #[arg(long)]
     pub dry_run: bool,
 
+    /// With --dry-run, estimate unique words with HyperLogLog in fixed memory
+    #[arg(long, requires = "dry_run")]
+    pub approx: bool,
+
+    /// Record when each source was first added to the database
+    #[arg(long)]
+    pub timestamp_sources: bool,
+
+    /// How hashes are stored in the parquet hash column
+    #[arg(long, value_enum, default_value = "binary")]
+    pub stored_hash_encoding: HashEncoding,
+
+    /// Store a first-byte -> row group index for fast 1-2 byte prefix queries
+    #[arg(long)]
+    pub coarse_index: bool,
+
+    /// Write <name>.manifest.json next to each parquet output, mirroring its metadata
+    #[arg(long, conflicts_with = "r2")]
+    pub write_manifest: bool,
+
+    /// Derive the bloom filter's keys from this string instead of at random,
+    /// so rebuilding the same records gives a byte-identical bloom filter
+    #[arg(long, value_name = "SEED", conflicts_with = "r2")]
+    pub bloom_seed: Option<String>,
+
+    /// Keep only the metadata needed to read hashes back (no bloom filter,
+    /// stats or source hashes); full-hash queries then scan candidate row groups
+    #[arg(
+        long,
+        conflicts_with_all = ["r2", "bloom_seed", "coarse_index", "partition_by_algo", "write_manifest"]
+    )]
+    pub minimal_metadata: bool,
+
+    /// Record the command line of this build (credentials redacted) in the
+    /// metadata, shown by info --verbose
+    #[arg(long, conflicts_with_all = ["r2", "minimal_metadata"])]
+    pub provenance: bool,
+
+    /// Deduplicate words through sorted runs on disk before hashing, keeping at
+    /// most N words in memory (the hash records are still collected in memory)
+    #[arg(
+        long,
+        value_name = "N",
+        num_args = 0..=1,
+        default_missing_value = "1000000"
+    )]
+    pub disk_dedup: Option<usize>,
+
+    /// Sort records on one thread or across all cores (auto: parallel for large builds)
+    #[arg(long, value_enum, default_value = "auto")]
+    pub sort: SortMode,
+
+    /// Sort at most N records in memory, spilling sorted runs to disk and merging them past that
+    #[arg(long, value_name = "N", conflicts_with = "chunk_output")]
+    pub sort_buffer_size: Option<usize>,
+
+    /// Directory for scratch files such as --disk-dedup runs (defaults to the system temp dir)
+    #[arg(long, value_name = "DIR", env = "SHAHA_TMPDIR")]
+    pub temp_dir: Option<PathBuf>,
+
+    /// Omit records whose hash is already stored in this parquet database
+    #[arg(long, value_name = "PATH")]
+    pub exclude_db: Option<PathBuf>,
+
+    /// Skip and count malformed or wrong-length hashes from pre-hashed sources
+    #[arg(long)]
+    pub validate_hex: bool,
+
+    /// Allow --append with a different algorithm set than the existing database
+    #[arg(long, requires = "append")]
+    pub allow_algo_mismatch: bool,
+
+    /// Most records one preimage may produce (default: number of algorithms)
+    #[arg(long, value_name = "N")]
+    pub preimage_max_count: Option<usize>,
+
+    /// Fail instead of warning when a build invariant check trips
+    #[arg(long)]
+    pub strict: bool,
+
+    /// Use only the Nth field (1-based) of each line as the word, e.g. 2 for user:pass
+    #[arg(long, value_name = "N")]
+    pub field: Option<usize>,
+
+    /// Separator between fields for --field
+    #[arg(long, value_name = "CHAR", default_value = ":", requires = "field")]
+    pub field_separator: char,
+
+    /// Stop adding records to parquet outputs before they exceed N bytes
+    #[arg(long, value_name = "BYTES", conflicts_with_all = ["append", "r2"])]
+    pub max_file_size: Option<u64>,
+
+    /// Write each parquet output as <stem>.part-000.parquet, .part-001, ...,
+    /// each a complete database over a contiguous hash range
+    #[arg(
+        long,
+        requires = "max_chunk_size",
+        conflicts_with_all = ["append", "r2", "max_file_size", "partition_by_algo", "write_manifest"]
+    )]
+    pub chunk_output: bool,
+
+    /// Start a new --chunk-output part before the current one exceeds N bytes
+    #[arg(long, value_name = "BYTES", requires = "chunk_output")]
+    pub max_chunk_size: Option<u64>,
+
+    /// Target parquet data page size in bytes (smaller helps lookups, larger helps scans)
+    #[arg(long, value_name = "BYTES")]
+    pub page_size: Option<usize>,
+
+    /// Maximum rows per parquet data page
+    #[arg(long, value_name = "N")]
+    pub page_rows: Option<usize>,
+
+    /// Parquet data page format version (1 or 2)
+    #[arg(long, value_name = "V", value_parser = clap::value_parser!(u8).range(1..=2))]
+    pub data_page_version: Option<u8>,
+
+    /// Group parquet row groups by algorithm so --algo queries skip the others
+    #[arg(long)]
+    pub partition_by_algo: bool,
+
+    /// Generate variants with hashcat-style rules (: l u c t r $X ^X), one rule per line
+    #[arg(long, value_name = "FILE")]
+    pub rules_file: Option<PathBuf>,
+
+    /// Case variants to hash for each word (all = as-is, lower, upper and capitalized)
+    #[arg(long, value_enum, default_value = "as-is")]
+    pub case: CaseMode,
+
+    /// Mangling rules, comma separated: append-digits:A-B, leet, capitalize.
+    /// The word itself is kept; each rule adds variants (e.g. append-digits:0-9)
+    #[arg(long, value_name = "SPEC", conflicts_with = "binary_records")]
+    pub rules: Option<String>,
+
+    /// Keep at most N --case/--rules-file/--rules variants per word (the first N, sorted)
+    #[arg(long, value_name = "N", conflicts_with = "binary_records")]
+    pub max_variants_per_word: Option<usize>,
+
+    /// Skip candidates shorter than N characters (Unicode scalar values, not bytes)
+    #[arg(long, value_name = "N", conflicts_with = "binary_records")]
+    pub min_len: Option<usize>,
+
+    /// Skip candidates longer than N characters (Unicode scalar values, not bytes)
+    #[arg(long, value_name = "N", conflicts_with = "binary_records")]
+    pub max_len: Option<usize>,
+
+    /// Skip candidates with characters outside this set
+    #[arg(long, value_enum, default_value = "any", conflicts_with = "binary_records")]
+    pub charset: Charset,
+
+    /// What to do with a word list line that is not valid UTF-8: replace the
+    /// bad bytes, skip the line, or fail the build naming the line
+    #[arg(long, value_enum, default_value = "lossy")]
+    pub on_invalid: OnInvalid,
+
+    /// Skip the first N non-empty lines of the source (resume with --append)
+    #[arg(long, value_name = "N", default_value = "0")]
+    pub skip_lines: usize,
+
+    /// Store only the top N bits of each hash in parquet outputs (k-anonymity)
+    #[arg(long, value_name = "N", conflicts_with_all = ["append", "r2"])]
+    pub prefix_only_bits: Option<usize>,
+
     /// Upload to R2/S3 storage instead of local file
     #[arg(long)]
     pub r2: bool,
@@ -74,138 +309,551 @@ pub struct BuildArgs {
     /// R2/S3 region (default: "auto" for R2)
     #[arg(long, env = "SHAHA_R2_REGION", default_value = "auto")]
     pub region: String,
+
+    /// Connect to the S3 endpoint over plain HTTP (e.g. local MinIO)
+    #[arg(long)]
+    pub s3_no_ssl: bool,
+}
+
+#[derive(Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum SourceNameTransform {
+    #[default]
+    None,
+    Lower,
+    Slug,
+}
+
+impl SourceNameTransform {
+    fn apply(self, name: &str) -> String {
+        match self {
+            Self::None => name.to_string(),
+            Self::Lower => name.to_lowercase(),
+            Self::Slug => name
+                .to_lowercase()
+                .split(|c: char| !c.is_ascii_alphanumeric())
+                .filter(|part| !part.is_empty())
+                .collect::<Vec<_>>()
+                .join("-"),
+        }
+    }
+
+    // Collapses stored names that only differ in case or punctuation, e.g.
+    // "RockYou" and "rockyou"
+    fn normalize(self, sources: &mut Vec<String>) {
+        if self == Self::None {
+            return;
+        }
+        for source in sources.iter_mut() {
+            *source = self.apply(source);
+        }
+        sources.sort_unstable();
+        sources.dedup();
+    }
+}
+
+#[derive(Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum Charset {
+    #[default]
+    Any,
+    /// ASCII only
+    Ascii,
+    /// ASCII letters and digits
+    Alnum,
+    /// ASCII digits
+    Digits,
+}
+
+impl Charset {
+    fn allows(self, word: &str) -> bool {
+        match self {
+            Self::Any => true,
+            Self::Ascii => word.is_ascii(),
+            Self::Alnum => word.chars().all(|c| c.is_ascii_alphanumeric()),
+            Self::Digits => word.chars().all(|c| c.is_ascii_digit()),
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum OutputKind {
+    Parquet,
+    Csv,
+    Arrow,
+}
+
+impl OutputKind {
+    fn from_path(path: &Path) -> Result<Self> {
+        let ext = path.extension().and_then(|e| e.to_str()).map(str::to_lowercase);
+        match ext.as_deref() {
+            Some("parquet") => Ok(Self::Parquet),
+            Some("csv") => Ok(Self::Csv),
+            Some("arrow") => Ok(Self::Arrow),
+            _ => bail!(
+                "Unsupported output format: {:?}. Use .parquet, .csv, or .arrow",
+                path
+            ),
+        }
+    }
+}
+
+impl BuildArgs {
+    fn database(&self) -> Option<&Path> {
+        self.output
+            .iter()
+            .map(PathBuf::as_path)
+            .find(|p| matches!(OutputKind::from_path(p), Ok(OutputKind::Parquet)))
+    }
+
+    fn existing_database(&self) -> Option<&Path> {
+        if self.r2 {
+            return None;
+        }
+        self.database().filter(|p| p.exists())
+    }
 }
 
-type RecordKey = (Vec<u8>, String);
+pub fn run(mut args: BuildArgs) -> Result<()> {
+    if let Some(path) = args.sources_file.take() {
+        args.from = read_source_specs(&path)?;
+        if args.from.is_empty() {
+            bail!("No sources listed in {}", path.display());
+        }
+    }
+    if args.input.len() + args.from.len() > 1 {
+        check_multiple_sources(&args)?;
+    }
+
+    for path in &args.output {
+        OutputKind::from_path(path)?;
+    }
+    if args.r2 && args.output.len() > 1 {
+        bail!("--r2 supports a single --output");
+    }
+    if args.prefix_only_bits == Some(0) {
+        bail!("--prefix-only-bits must be at least 1");
+    }
+    if args.field == Some(0) {
+        bail!("--field is 1-based and must be at least 1");
+    }
+    if let Some(dir) = &args.temp_dir {
+        ensure_writable_dir(dir)?;
+    }
+    if let Some(reference) = args.exclude_db.as_deref().filter(|p| !p.exists()) {
+        bail!("Reference database not found: {}", reference.display());
+    }
+
+    let salt = match args.salt.as_deref() {
+        Some("") => bail!("--salt must not be empty"),
+        Some(salt) => {
+            Some(hex::decode(salt).with_context(|| format!("--salt is not valid hex: {}", salt))?)
+        }
+        None => None,
+    };
+    let hmac_key = match args.hmac_key.as_deref() {
+        Some("") => bail!("--hmac-key must not be empty"),
+        Some(key) => Some(
+            hex::decode(key).with_context(|| format!("--hmac-key is not valid hex: {}", key))?,
+        ),
+        None => None,
+    };
+
+    if args.rounds == 0 {
+        bail!("--rounds must be at least 1");
+    }
 
-pub fn run(args: BuildArgs) -> Result<()> {
     let hashers: Vec<Box<dyn Hasher>> = args
         .algo
         .iter()
-        .map(|name| hasher::get_hasher(name).expect("algorithm validated by clap"))
-        .collect();
+        .map(|name| {
+            let hasher = match &hmac_key {
+                Some(key) => hasher::get_hmac_hasher(name, key)
+                    .ok_or_else(|| anyhow!("--hmac-key does not support {}", name))?,
+                None => hasher::get_hasher(name).expect("algorithm validated by clap"),
+            };
+            let salt = salt.clone().map(|salt| (salt, args.salt_position));
+            Ok(hasher::salted_and_iterated(hasher, salt, args.rounds))
+        })
+        .collect::<Result<_>>()?;
 
     if hashers.is_empty() {
         bail!("No valid algorithms specified");
     }
 
-    let source_spec = match (&args.input, &args.from) {
-        (None, None) => bail!(
+    if args.input.is_empty() && args.from.is_empty() {
+        bail!(
             "Either INPUT or --from required.\n\
             Examples:\n  \
             shaha build words.txt\n  \
             shaha build --from seclists:Passwords/rockyou.txt\n  \
             shaha build --from aspell:en"
-        ),
-        (Some(_), Some(_)) => bail!("Cannot use both INPUT and --from"),
-        (None, Some(spec)) => spec.clone(),
-        (Some(input), None) => input.to_string_lossy().to_string(),
-    };
+        );
+    }
 
-    let data_source = source::parse(&source_spec)?;
-    let source_name = args.name.clone().unwrap_or_else(|| data_source.name().to_string());
-    let source_hash = data_source.content_hash()?;
+    let url_options = source::UrlOptions {
+        bearer: args.bearer.clone(),
+        headers: args.headers.clone(),
+        retries: args.retries,
+        retry_delay: Duration::from_millis(args.retry_delay_ms),
+    };
+    let specs: Vec<(String, Option<&Path>)> = args
+        .input
+        .iter()
+        .map(|input| (input.to_string_lossy().to_string(), Some(input.as_path())))
+        .chain(args.from.iter().map(|spec| (spec.clone(), None)))
+        .collect();
 
-    if args.dry_run {
-        return run_dry_run(&args, data_source.as_ref(), &hashers, source_hash);
+    if let Some(database) = args.existing_database().filter(|_| args.append) {
+        check_rounds_mismatch(database, args.rounds)?;
+        check_salt_mismatch(database, salt.as_deref(), args.salt_position)?;
     }
+    let mut skip_hashes = match args.existing_database().filter(|_| !args.force) {
+        Some(database) => ParquetStorage::new(database).get_source_hashes()?,
+        None => HashSet::new(),
+    };
 
-    if !args.force && !args.r2 && args.output.exists() {
-        if let Some(ref hash) = source_hash {
-            let existing_storage = ParquetStorage::new(&args.output);
-            let existing_hashes = existing_storage.get_source_hashes()?;
-            if existing_hashes.contains(hash) {
-                status!(
-                    "Source already processed (content hash {}). Use --force to rebuild.",
-                    &hash[..12]
-                );
-                return Ok(());
-            }
+    // Every source is ingested into one map before anything is written, so
+    // the build succeeds or fails as a whole
+    let mut filtered = 0usize;
+    let mut total_words = 0usize;
+    let mut unique_words = 0usize;
+    let mut collisions = 0usize;
+    let mut new_records_map = HashMap::new();
+    let mut ingested_sources: Vec<(String, SourceMeta, Option<String>)> = Vec::new();
+    let mut first_algorithms: Option<(String, BTreeSet<&str>)> = None;
+    for (i, (spec, input)) in specs.iter().enumerate() {
+        if specs.len() > 1 {
+            status!("Source {}/{}: {}", i + 1, specs.len(), spec);
+        }
+        let data_source = match input.filter(|input| input.to_str().is_none()) {
+            // Not valid UTF-8, so it can only be a plain file path
+            Some(input) => Box::new(source::FileSource::new(input).with_on_invalid(args.on_invalid)),
+            None => source::parse_with(spec, &url_options, args.on_invalid)?,
+        };
+        let source_name = args
+            .source_name_transform
+            .apply(args.name.as_deref().unwrap_or(data_source.name()));
+        if source_name.is_empty() {
+            bail!("Source name is empty after --source-name-transform");
+        }
+        let source_hash = data_source.content_hash()?;
+        let now = humantime::format_rfc3339_seconds(SystemTime::now()).to_string();
+        let source_meta = SourceMeta {
+            retrieved_at: Some(now.clone()),
+            first_seen: args.timestamp_sources.then_some(now),
+            rules: args.rules.clone(),
+            ..data_source.metadata()
+        };
+
+        if args.dry_run {
+            return run_dry_run(&args, data_source.as_ref(), &hashers, &source_name, source_hash);
         }
-    }
-
-    status!("Reading words from {}...", data_source.name());
 
-    let words_iter = data_source.words()?;
+        if args.if_newer && !args.force && outputs_newer_than(&args.output, data_source.as_ref())? {
+            status!("Output is newer than the source. Use --force to rebuild.");
+            return Ok(());
+        }
 
-    let mut total_words = 0usize;
-    let mut unique_words = 0usize;
-    let mut batch: Vec<String> = Vec::with_capacity(BATCH_SIZE);
-    let mut seen: HashSet<String> = HashSet::new();
-    let mut new_records_map: HashMap<RecordKey, HashRecord> = HashMap::new();
+        if let Some(hash) = source_hash.as_ref().filter(|hash| skip_hashes.contains(*hash)) {
+            status!(
+                "Source already processed (content hash {}). Use --force to rebuild.",
+                &hash[..12]
+            );
+            continue;
+        }
 
-    let pb = if output::is_quiet() {
-        ProgressBar::hidden()
-    } else {
-        let pb = ProgressBar::new_spinner();
-        pb.set_style(
-            ProgressStyle::default_spinner()
-                .template("{spinner:.green} [{elapsed_precise}] {msg}")
-                .unwrap(),
-        );
-        pb
-    };
+        let pb = progress_bar(None);
 
-    for word in words_iter {
-        total_words += 1;
+        let prehashed = data_source.prehashed()?;
 
-        if seen.insert(word.clone()) {
-            batch.push(word);
+        let new_algorithms: BTreeSet<&str> = match &prehashed {
+            Some(prehashed) => BTreeSet::from([prehashed.algorithm]),
+            None => hashers.iter().map(|h| h.name()).collect(),
+        };
+        if let Some(database) = args.existing_database().filter(|_| args.append) {
+            check_algorithm_mismatch(database, &new_algorithms, args.allow_algo_mismatch)?;
+        }
+        match &first_algorithms {
+            Some((first, algorithms)) if *algorithms != new_algorithms && !args.allow_algo_mismatch => bail!(
+                "Algorithm mismatch: {} uses [{}] but {} uses [{}].\n\
+                Mixing them would leave sources with uneven coverage; pass --allow-algo-mismatch to proceed.",
+                first,
+                algorithms.iter().copied().collect::<Vec<_>>().join(", "),
+                spec,
+                new_algorithms.iter().copied().collect::<Vec<_>>().join(", ")
+            ),
+            Some(_) => {}
+            None => first_algorithms = Some((spec.clone(), new_algorithms)),
+        }
 
-            if batch.len() >= BATCH_SIZE {
-                process_new_words(&batch, &hashers, &source_name, &mut new_records_map);
-                unique_words += batch.len();
+        let Ingested {
+            total_words: source_words,
+            unique_words: source_unique,
+            records: mut source_records,
+            collisions: source_collisions,
+        } = match (prehashed, args.binary_records) {
+            (_, Some(format)) => {
+                let input = input.expect("clap requires INPUT for --binary-records");
+                status!("Reading binary records from {}...", input.display());
+                let mut report = |p: BuildProgress| {
+                    pb.set_message(format!(
+                        "{} records ({} unique), {} hashes",
+                        format_number(p.total_words),
+                        format_number(p.unique_words),
+                        format_number(p.records)
+                    ))
+                };
+                let blobs = source::read_binary_records(input, format)?;
+                ingest_binary(blobs, &hashers, &source_name, &mut report)?
+            }
+            (Some(mut prehashed), None) => {
+                if args.field.is_some() || args.rules_file.is_some() || args.rules.is_some() {
+                    bail!("--field, --rules-file and --rules apply to word lists, not pre-hashed sources");
+                }
+                if args.case != CaseMode::AsIs {
+                    bail!("--case applies to word lists, not pre-hashed sources");
+                }
+                if args.max_variants_per_word.is_some() {
+                    bail!("--max-variants-per-word applies to word lists, not pre-hashed sources");
+                }
+                if salt.is_some() {
+                    bail!("--salt applies to word lists, not pre-hashed sources");
+                }
+                if hmac_key.is_some() {
+                    bail!("--hmac-key applies to word lists, not pre-hashed sources");
+                }
+                if args.rounds > 1 {
+                    bail!("--rounds applies to word lists, not pre-hashed sources");
+                }
+                status!(
+                    "Reading pre-hashed {} records from {}...",
+                    prehashed.algorithm,
+                    data_source.name()
+                );
+                if args.skip_lines > 0 {
+                    let skipped = prehashed.entries.by_ref().take(args.skip_lines).count();
+                    status!("Skipped first {} lines", format_number(skipped));
+                }
+                let PrehashedIngested {
+                    ingested,
+                    occurrences,
+                    invalid,
+                    first_invalid,
+                } = ingest_prehashed(
+                    prehashed,
+                    &source_name,
+                    &mut |p| {
+                        pb.set_message(format!(
+                            "{} hashes ({} unique)",
+                            format_number(p.total_words),
+                            format_number(p.records)
+                        ))
+                    },
+                    args.validate_hex,
+                )?;
+                if let Some(first) = first_invalid {
+                    status!("Skipped {} invalid lines (first: {})", format_number(invalid), first);
+                }
+                status!(
+                    "Counted {} occurrences (counts are not stored)",
+                    format_number(occurrences as usize)
+                );
+                ingested
+            }
+            (None, None) => {
+                status!("Reading words from {}...", data_source.name());
+                let mut words_iter = data_source.words()?;
+                if args.skip_lines > 0 {
+                    let skipped = words_iter.by_ref().take(args.skip_lines).count();
+                    status!("Skipped first {} lines", format_number(skipped));
+                }
+                let (words_iter, shaped) = shape_words(&args, words_iter)?;
+                let mut report = |p: BuildProgress| {
+                    pb.set_message(format!(
+                        "{} words ({} unique), {} hashes",
+                        format_number(p.total_words),
+                        format_number(p.unique_words),
+                        format_number(p.records)
+                    ))
+                };
+                let ingested =
+                    ingest(words_iter, &hashers, &source_name, &mut report, disk_dedup(&args))?;
+                check_invalid_lines(args.on_invalid, data_source.invalid_lines())?;
+                if shaped.missing.get() > 0 {
+                    status!(
+                        "Skipped {} lines without field {}",
+                        format_number(shaped.missing.get()),
+                        args.field.unwrap_or_default()
+                    );
+                }
+                if shaped.dropped_variants.get() > 0 {
+                    status!(
+                        "Dropped {} variants over --max-variants-per-word {}",
+                        format_number(shaped.dropped_variants.get()),
+                        args.max_variants_per_word.unwrap_or_default()
+                    );
+                }
+                filtered += shaped.filtered.get();
+                ingested
+            }
+        };
 
-                pb.set_message(format!(
-                    "{} words ({} unique), {} hashes",
-                    total_words, unique_words, new_records_map.len()
-                ));
+        pb.finish_and_clear();
 
-                batch.clear();
+        total_words += source_words;
+        unique_words += source_unique;
+        collisions += source_collisions;
+        // Earlier sources keep their preimage, as an existing database does
+        for record in new_records_map.values_mut() {
+            if let Some(merged) = merge_by_key(record, &mut source_records, args.merge_strategy)? {
+                collisions += usize::from(merged.collision);
             }
         }
+        new_records_map.extend(source_records);
+        if let Some(hash) = &source_hash {
+            skip_hashes.insert(hash.clone());
+        }
+        ingested_sources.push((source_name, source_meta, source_hash));
     }
 
-    if !batch.is_empty() {
-        process_new_words(&batch, &hashers, &source_name, &mut new_records_map);
-        unique_words += batch.len();
+    if ingested_sources.is_empty() {
+        return Ok(());
     }
 
-    pb.finish_and_clear();
+    // The upload is conditional on the object still being at this version,
+    // so a concurrent writer fails this build instead of being overwritten.
+    let r2_version = if args.r2 {
+        Some(R2Object::new(build_r2_config(&args)?).version()?)
+    } else {
+        None
+    };
+
+    check_preimage_counts(&args, &new_records_map)?;
+
+    if let Some(reference) = &args.exclude_db {
+        status!(
+            "Checking {} records against {}...",
+            format_number(new_records_map.len()),
+            reference.display()
+        );
+        let excluded = ParquetStorage::new(reference).retain_absent(&mut new_records_map)?;
+        status!(
+            "Excluded {} records already in {}",
+            format_number(excluded),
+            reference.display()
+        );
+    }
 
     let mut existing_count = 0usize;
     let mut merged_count = 0usize;
-    let mut final_records: Vec<HashRecord> = Vec::new();
+    let mut existing_collisions = 0usize;
+    let mut final_records = ExternalSort::new(args.sort_buffer_size, args.sort);
+    if args.partition_by_algo {
+        final_records = final_records.by_algorithm();
+    }
+    if let Some(dir) = &args.temp_dir {
+        final_records = final_records.with_temp_dir(dir);
+    }
+    let mut source_hashes = HashSet::new();
+    let mut sources_meta = BTreeMap::new();
+    // The existing database and the records this append adds or changes,
+    // for extending its metadata instead of recomputing it from every row
+    let mut carried_from: Option<(ParquetStorage, Vec<HashRecord>)> = None;
 
-    if args.append && !args.r2 && args.output.exists() {
+    if let Some(database) = args.existing_database().filter(|_| args.append) {
         status!("Streaming existing database for merge...");
-        let existing_storage = ParquetStorage::new(&args.output);
-        
+        let existing_storage = ParquetStorage::new(database);
+        source_hashes = existing_storage.get_source_hashes()?;
+        sources_meta = existing_storage.get_source_meta()?;
+        if args.source_name_transform != SourceNameTransform::None {
+            // The first name to map to a canonical one keeps its provenance
+            let mut canonical = BTreeMap::new();
+            for (name, meta) in sources_meta {
+                canonical.entry(args.source_name_transform.apply(&name)).or_insert(meta);
+            }
+            sources_meta = canonical;
+        }
+        let existing_stats = existing_storage.stats()?;
+        existing_collisions = existing_stats.collisions.unwrap_or(0);
+
+        // Renamed sources would leave the old names in the carried metadata
+        let mut touched = (args.source_name_transform == SourceNameTransform::None).then(Vec::new);
+
+        let expected = existing_stats.total_records;
+        let merge_pb = progress_bar(Some(expected as u64));
+        // Without a terminal the bar draws nothing, so log every 10% instead
+        let log_every = (!output::is_quiet() && merge_pb.is_hidden() && expected > 0)
+            .then(|| expected.div_ceil(10));
+
         existing_storage.for_each_record(|mut record| {
             existing_count += 1;
-            let key = (record.hash.clone(), record.algorithm.clone());
-            
-            if let Some(new_record) = new_records_map.remove(&key) {
-                for source in new_record.sources {
-                    if !record.sources.contains(&source) {
-                        record.sources.push(source);
-                        merged_count += 1;
-                    }
+            if existing_count.is_multiple_of(MERGE_PROGRESS_INTERVAL) || existing_count == expected {
+                merge_pb.set_position(existing_count as u64);
+                merge_pb.set_message(format!(
+                    "{} existing records, {} sources merged",
+                    format_number(existing_count),
+                    format_number(merged_count)
+                ));
+            }
+            if log_every.is_some_and(|step| existing_count.is_multiple_of(step) || existing_count == expected) {
+                eprintln!(
+                    "Merging existing records: {} of {} ({}%), {} sources merged",
+                    format_number(existing_count),
+                    format_number(expected),
+                    existing_count * 100 / expected,
+                    format_number(merged_count)
+                );
+            }
+            args.source_name_transform.normalize(&mut record.sources);
+            let merged = merge_by_key(&mut record, &mut new_records_map, args.merge_strategy)?;
+            if let Some(merged) = merged {
+                collisions += usize::from(merged.collision);
+                merged_count += merged.sources_added;
+                if let Some(touched) = touched.as_mut() {
+                    touched.push(record.clone());
                 }
             }
-            final_records.push(record);
-            Ok(())
+            final_records.push(record)
+
         })?;
-        
-        status!("Processed {} existing records, {} sources merged", existing_count, merged_count);
+        merge_pb.finish_and_clear();
+
+        status!(
+            "Processed {} existing records, {} sources merged",
+            format_number(existing_count),
+            format_number(merged_count)
+        );
+        if let Some(mut touched) = touched {
+            touched.extend(new_records_map.values().cloned());
+            carried_from = Some((existing_storage, touched));
+        }
     }
 
-    let new_records = new_records_map.len();
-    final_records.extend(new_records_map.into_values());
+    for (source_name, mut source_meta, source_hash) in ingested_sources {
+        if let Some(previous) = sources_meta.get(&source_name) {
+            if previous.first_seen.is_some() {
+                source_meta.first_seen = previous.first_seen.clone();
+            }
+        }
+        sources_meta.insert(source_name, source_meta);
+        if let Some(hash) = source_hash {
+            source_hashes.insert(hash);
+        }
+    }
 
-    status!("Sorting and writing {} total records...", final_records.len());
+    let new_records = new_records_map.len();
+    for record in new_records_map.into_values() {
+        final_records.push(record)?;
+    }
 
-    final_records.sort_by(|a, b| a.hash.cmp(&b.hash));
+    let total_records = final_records.len();
+    status!("Sorting and writing {} total records...", format_number(total_records));
+    if final_records.spilled_runs() > 0 {
+        status!(
+            "Merging {} sorted runs spilled past --sort-buffer-size",
+            final_records.spilled_runs() + 1
+        );
+    }
+    let final_records = final_records.into_sorted()?;
 
     let output_location: String;
     
@@ -215,45 +863,520 @@ pub fn run(args: BuildArgs) -> Result<()> {
         
         status!("Uploading to {}...", output_location);
         let mut storage = R2Storage::new(r2_config)?;
-        for chunk in final_records.chunks(BATCH_SIZE) {
-            storage.write_batch(chunk.to_vec())?;
+        if let Some(version) = r2_version {
+            storage = storage.with_expected_version(version);
         }
-        storage.finish()?;
+        if let Some(dir) = &args.temp_dir {
+            storage = storage.with_temp_dir(dir);
+        }
+        write_sorted(&mut [&mut storage], final_records)?;
     } else {
-        output_location = args.output.display().to_string();
-        let mut storage = ParquetStorage::with_expected_capacity(&args.output, final_records.len());
-        if let Some(ref hash) = source_hash {
-            storage.add_source_hash(hash);
+        let parquet_output = |path: &Path, capacity: usize| {
+            let mut storage = ParquetStorage::with_expected_capacity(path, capacity)
+                .with_hash_encoding(args.stored_hash_encoding);
+            if args.minimal_metadata {
+                storage = storage.with_minimal_metadata();
+            }
+            if args.provenance {
+                storage = storage.with_build_command(output::command_line());
+            }
+            if let Some(seed) = &args.bloom_seed {
+                storage = storage.with_bloom_seed(blake3::hash(seed.as_bytes()).as_bytes());
+            }
+            if let Some(bits) = args.prefix_only_bits {
+                storage = storage.with_prefix_bits(bits);
+            }
+            if args.coarse_index {
+                storage = storage.with_coarse_index();
+            }
+            if args.partition_by_algo {
+                storage = storage.with_algorithm_partitions();
+            }
+            if args.rounds > 1 {
+                storage = storage.with_rounds(args.rounds);
+            }
+            if let Some(salt) = &salt {
+                storage = storage.with_salt(salt, args.salt_position);
+            }
+            if let Some(bytes) = args.page_size {
+                storage = storage.with_page_size(bytes);
+            }
+            if let Some(rows) = args.page_rows {
+                storage = storage.with_page_rows(rows);
+            }
+            if let Some(version) = args.data_page_version {
+                storage = storage.with_data_page_version(version);
+            }
+            if let Some(bytes) = args.max_file_size {
+                storage = storage.with_max_file_size(bytes);
+            }
+            for hash in &source_hashes {
+                storage.add_source_hash(hash);
+            }
+            for (name, meta) in &sources_meta {
+                storage.add_source_meta(name, meta.clone());
+            }
+            storage.add_collisions(existing_collisions + collisions);
+            storage
+        };
+
+        let mut parquet_sinks: Vec<(&Path, ParquetStorage)> = Vec::new();
+        let mut chunked: Vec<&Path> = Vec::new();
+        let mut sinks: Vec<Box<dyn Storage>> = Vec::new();
+        for path in &args.output {
+            match OutputKind::from_path(path)? {
+                OutputKind::Parquet if args.chunk_output => chunked.push(path),
+                OutputKind::Parquet => {
+                    let mut storage = parquet_output(path, total_records);
+                    if let Some((existing, touched)) = &carried_from {
+                        if storage.extend_metadata(existing, touched)? {
+                            status!(
+                                "Extending metadata of {} with {} changed records",
+                                path.display(),
+                                format_number(touched.len())
+                            );
+                        }
+                    }
+                    parquet_sinks.push((path, storage))
+                }
+                OutputKind::Csv => sinks.push(Box::new(CsvStorage::new(path))),
+                OutputKind::Arrow => sinks.push(Box::new(ArrowStorage::new(path))),
+            }
         }
-        for chunk in final_records.chunks(BATCH_SIZE) {
-            storage.write_batch(chunk.to_vec())?;
+
+        let mut chunk_parts: Vec<(&Path, Vec<PathBuf>)> = Vec::new();
+        for path in chunked {
+            let max_bytes = args.max_chunk_size.expect("clap requires --max-chunk-size");
+            let records = final_records
+                .as_slice()
+                .expect("clap keeps --chunk-output records in memory");
+            let parts = write_chunks(path, records, max_bytes, &parquet_output)?;
+            status!(
+                "Split {} records into {} parts of at most {} bytes: {} .. {}",
+                format_number(total_records),
+                parts.len(),
+                format_number(max_bytes as usize),
+                parts.first().map_or(String::new(), |p| p.display().to_string()),
+                parts.last().map_or(String::new(), |p| p.display().to_string())
+            );
+            chunk_parts.push((path, parts));
         }
-        storage.finish()?;
+
+        let mut streamed: Vec<&mut dyn Storage> = parquet_sinks
+            .iter_mut()
+            .map(|(_, sink)| sink as &mut dyn Storage)
+            .chain(sinks.iter_mut().map(|sink| sink.as_mut() as &mut dyn Storage))
+            .collect();
+        write_sorted(&mut streamed, final_records)?;
+        for (path, sink) in &parquet_sinks {
+            if sink.dropped_records() > 0 {
+                status!(
+                    "Stopped {} at --max-file-size: wrote {} records, dropped {}",
+                    path.display(),
+                    format_number(total_records - sink.dropped_records()),
+                    format_number(sink.dropped_records())
+                );
+            }
+            if args.write_manifest {
+                write_manifest(path)?;
+            }
+        }
+
+        // Chunked outputs are listed by the parts actually written
+        output_location = args
+            .output
+            .iter()
+            .flat_map(|path| match chunk_parts.iter().find(|(chunked, _)| *chunked == path) {
+                Some((_, parts)) => parts.iter().map(|p| p.display().to_string()).collect(),
+                None => vec![path.display().to_string()],
+            })
+            .collect::<Vec<_>>()
+            .join(", ");
     }
 
     let duplicates = total_words - unique_words;
     status!(
-        "Processed {} words ({} unique, {} duplicates skipped)",
-        total_words, unique_words, duplicates
+        "Processed {} words ({} unique, {} duplicates skipped, {} filtered)",
+        format_number(total_words + filtered),
+        format_number(unique_words),
+        format_number(duplicates),
+        format_number(filtered)
     );
     if args.append && existing_count > 0 {
         status!(
             "Records: {} existing + {} new ({} sources merged) = {} total",
-            existing_count, new_records, merged_count, 
-            final_records.len()
+            format_number(existing_count),
+            format_number(new_records),
+            format_number(merged_count),
+            format_number(total_records)
         );
     } else {
-        status!("Generated {} hash records", final_records.len());
+        status!("Generated {} hash records", format_number(total_records));
+    }
+    if collisions > 0 {
+        eprintln!(
+            "warning: {} hash collision{} (distinct preimages, same hash); kept the first preimage of each",
+            format_number(collisions),
+            if collisions == 1 { "" } else { "s" }
+        );
     }
     status!("Wrote to {}", output_location);
 
     Ok(())
 }
 
+// Read back from the finished file so the manifest cannot drift from the
+// parquet metadata.
+fn write_manifest(database: &Path) -> Result<()> {
+    let manifest = ParquetStorage::new(database).manifest()?;
+    let path = Manifest::path_for(database);
+    std::fs::write(&path, serde_json::to_string_pretty(&manifest)? + "\n")
+        .with_context(|| format!("Failed to write manifest {}", path.display()))?;
+    status!("Wrote manifest {}", path.display());
+    Ok(())
+}
+
+type Words = Box<dyn Iterator<Item = String>>;
+
+fn check_preimage_counts(
+    args: &BuildArgs,
+    records: &HashMap<(Vec<u8>, String), HashRecord>,
+) -> Result<()> {
+    let max_count = args.preimage_max_count.unwrap_or(args.algo.len());
+    let over = preimages_over_limit(records.values(), max_count);
+    let Some((preimage, count)) = over.first() else {
+        return Ok(());
+    };
+
+    let message = format!(
+        "{} preimage(s) produced more than {} records (e.g. '{}' has {}); this is a bug in the build pipeline",
+        format_number(over.len()),
+        max_count,
+        preimage,
+        count
+    );
+    if args.strict {
+        bail!(message);
+    }
+    eprintln!("warning: {}", message);
+    Ok(())
+}
+
+// Lines dropped, variants capped and candidates filtered while shaping a
+// word stream
+struct Shaped {
+    missing: Rc<Cell<usize>>,
+    dropped_variants: Rc<Cell<usize>>,
+    filtered: Rc<Cell<usize>>,
+}
+
+// Applies --field, then --case, then --rules-file, then --rules, then the
+// length and charset filters, to a raw word stream. The case, file and spec
+// rules run as one composed rule set so that --max-variants-per-word sees
+// every variant of a word at once.
+fn shape_words(args: &BuildArgs, words: Words) -> Result<(Words, Shaped)> {
+    if args.max_variants_per_word == Some(0) {
+        bail!("--max-variants-per-word must be at least 1");
+    }
+    if let (Some(min), Some(max)) = (args.min_len, args.max_len) {
+        if min > max {
+            bail!("--min-len {} is greater than --max-len {}", min, max);
+        }
+    }
+
+    let (words, missing) = select_field(words, args.field, args.field_separator);
+    let shaped = Shaped {
+        missing,
+        dropped_variants: Rc::new(Cell::new(0)),
+        filtered: Rc::new(Cell::new(0)),
+    };
+
+    let file_rules = match &args.rules_file {
+        Some(path) => {
+            let rules = Rules::from_file(path)?;
+            if rules.is_empty() {
+                bail!("Rules file {} contains no rules", path.display());
+            }
+            status!("Applying {} rules from {}", rules.len(), path.display());
+            Some(rules)
+        }
+        None => None,
+    };
+    let spec_rules = match &args.rules {
+        Some(spec) => Some(Rules::from_spec(spec).context("Invalid --rules")?),
+        None => None,
+    };
+    let rules = match (args.case, file_rules) {
+        (CaseMode::AsIs, None) => None,
+        (CaseMode::AsIs, Some(rules)) => Some(rules),
+        (case, None) => Some(case.rules()),
+        (case, Some(rules)) => Some(case.rules().then(&rules)),
+    };
+    let rules = match (rules, spec_rules) {
+        (rules, None) => rules,
+        (None, Some(spec)) => Some(spec),
+        (Some(rules), Some(spec)) => Some(rules.then(&spec)),
+    };
+
+    let words = match (rules, args.max_variants_per_word) {
+        (None, _) => words,
+        (Some(rules), None) => rules.expand(words),
+        (Some(rules), Some(max)) => {
+            let dropped = Rc::clone(&shaped.dropped_variants);
+            Box::new(words.flat_map(move |word| {
+                let mut variants = rules.variants(&word);
+                if variants.len() > max {
+                    variants.sort();
+                    variants.dedup();
+                    dropped.set(dropped.get() + variants.len().saturating_sub(max));
+                    variants.truncate(max);
+                }
+                variants
+            }))
+        }
+    };
+    let words = filter_candidates(args, words, Rc::clone(&shaped.filtered));
+    Ok((words, shaped))
+}
+
+// Drops candidates a password policy rules out before they are counted or
+// deduplicated. Length is in chars, so "pässword" is 8 long, not 9.
+fn filter_candidates(args: &BuildArgs, words: Words, filtered: Rc<Cell<usize>>) -> Words {
+    let (min, max, charset) = (args.min_len, args.max_len, args.charset);
+    if min.is_none() && max.is_none() && charset == Charset::Any {
+        return words;
+    }
+    Box::new(words.filter(move |word| {
+        let len = word.chars().count();
+        let keep = min.is_none_or(|min| len >= min)
+            && max.is_none_or(|max| len <= max)
+            && charset.allows(word);
+        if !keep {
+            filtered.set(filtered.get() + 1);
+        }
+        keep
+    }))
+}
+
+// Sources decode lines themselves, so invalid UTF-8 is tallied there and
+// looked at once the words have been read. Nothing has been written yet
+// when --on-invalid fail aborts.
+fn check_invalid_lines(mode: OnInvalid, invalid: InvalidLines) -> Result<()> {
+    let InvalidLines { count, first } = invalid;
+    let Some((location, line)) = first else {
+        return Ok(());
+    };
+    match mode {
+        OnInvalid::Fail => bail!("{} line {} is not valid UTF-8 (--on-invalid fail)", location, line),
+        OnInvalid::Skip => status!(
+            "Skipped {} lines that are not valid UTF-8 (first: {} line {})",
+            format_number(count),
+            location,
+            line
+        ),
+        OnInvalid::Lossy => eprintln!(
+            "warning: {} lines are not valid UTF-8, invalid bytes replaced with U+FFFD (first: {} line {})",
+            format_number(count),
+            location,
+            line
+        ),
+    }
+    Ok(())
+}
+
+// Lines that lack the field (or have it empty) are dropped and counted.
+fn select_field(
+    words: Words,
+    field: Option<usize>,
+    separator: char,
+) -> (Words, Rc<Cell<usize>>) {
+    let missing = Rc::new(Cell::new(0usize));
+    let Some(field) = field else {
+        return (words, missing);
+    };
+
+    let counter = Rc::clone(&missing);
+    let words = words.filter_map(move |line| {
+        match line.split(separator).nth(field - 1).filter(|f| !f.is_empty()) {
+            Some(value) => Some(value.to_string()),
+            None => {
+                counter.set(counter.get() + 1);
+                None
+            }
+        }
+    });
+    (Box::new(words), missing)
+}
+
+// Records arrive sorted by hash, so consecutive slices cover contiguous
+// hash ranges. Parts left over from an earlier, larger build are removed so
+// `query --db-dir` does not pick them up.
+fn write_chunks(
+    path: &Path,
+    records: &[HashRecord],
+    max_bytes: u64,
+    parquet_output: &dyn Fn(&Path, usize) -> ParquetStorage,
+) -> Result<Vec<PathBuf>> {
+    let mut parts = Vec::new();
+    let mut rest = records;
+    while !rest.is_empty() {
+        let part = chunk_path(path, parts.len());
+        let storage = parquet_output(&part, rest.len());
+        let len = storage.records_within(rest, max_bytes);
+        if len == 0 {
+            bail!(
+                "--max-chunk-size {} is too small for a single record plus metadata",
+                max_bytes
+            );
+        }
+        let mut storage = storage.with_bloom_capacity(len);
+        write_records(&mut storage, &rest[..len])?;
+        rest = &rest[len..];
+        parts.push(part);
+    }
+
+    for index in parts.len().. {
+        let stale = chunk_path(path, index);
+        if !stale.exists() {
+            break;
+        }
+        std::fs::remove_file(&stale)
+            .with_context(|| format!("Failed to remove stale part {}", stale.display()))?;
+    }
+    Ok(parts)
+}
+
+fn chunk_path(path: &Path, index: usize) -> PathBuf {
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    path.with_file_name(format!("{}.part-{:03}.parquet", stem, index))
+}
+
+// A cheaper staleness check than the content hash for cron rebuilds: the
+// source is not read at all, only stat'ed. A missing output always builds.
+fn outputs_newer_than(outputs: &[PathBuf], source: &dyn crate::source::Source) -> Result<bool> {
+    let Some(path) = source.local_path() else {
+        bail!("--if-newer needs a file or seclists source, got {}", source.name());
+    };
+    let modified = |path: &Path| -> Result<SystemTime> {
+        std::fs::metadata(path)
+            .and_then(|meta| meta.modified())
+            .with_context(|| format!("Failed to read modification time of {}", path.display()))
+    };
+    let source_mtime = modified(path)?;
+    for output in outputs {
+        if !output.exists() || modified(output)? <= source_mtime {
+            return Ok(false);
+        }
+    }
+    Ok(true)
+}
+
+// Salted and unsalted hashes (or two different salts) in one database could
+// not be told apart at query time.
+fn check_salt_mismatch(database: &Path, salt: Option<&[u8]>, position: SaltPosition) -> Result<()> {
+    let existing = ParquetStorage::new(database).get_salt()?;
+    let describe = |salt: Option<(&[u8], SaltPosition)>| match salt {
+        Some((salt, position)) => format!("salt {} ({})", hex::encode(salt), position.as_str()),
+        None => "no salt".to_string(),
+    };
+    let new = salt.map(|salt| (salt, position));
+    let old = existing.as_ref().map(|(salt, position)| (salt.as_slice(), *position));
+    if old != new {
+        bail!(
+            "{} was built with {} but this build uses {}; append with the same --salt",
+            database.display(),
+            describe(old),
+            describe(new)
+        );
+    }
+    Ok(())
+}
+
+// Checked before the algorithm names, which also differ ("sha256x2" vs
+// "sha256x3"), so the error names the actual cause; shaha:rounds holds one count.
+fn check_rounds_mismatch(database: &Path, rounds: u32) -> Result<()> {
+    let existing = ParquetStorage::new(database).get_rounds()?.unwrap_or(1);
+    if existing != rounds {
+        bail!(
+            "{} was built with --rounds {} but this build uses --rounds {}",
+            database.display(),
+            existing,
+            rounds
+        );
+    }
+    Ok(())
+}
+
+fn check_algorithm_mismatch(
+    database: &Path,
+    new_algorithms: &BTreeSet<&str>,
+    allow: bool,
+) -> Result<()> {
+    let existing: BTreeSet<String> = ParquetStorage::new(database)
+        .stats()?
+        .algorithms
+        .into_iter()
+        .collect();
+    let matches = existing
+        .iter()
+        .map(String::as_str)
+        .eq(new_algorithms.iter().copied());
+    if existing.is_empty() || matches {
+        return Ok(());
+    }
+
+    let existing = existing.into_iter().collect::<Vec<_>>().join(", ");
+    let new = new_algorithms.iter().copied().collect::<Vec<_>>().join(", ");
+    if !allow {
+        bail!(
+            "Algorithm mismatch: {} has [{}] but this build uses [{}].\n\
+            Appending would leave sources with uneven coverage; pass --allow-algo-mismatch to proceed.",
+            database.display(),
+            existing,
+            new
+        );
+    }
+    eprintln!(
+        "warning: algorithm mismatch: {} has [{}] but this build uses [{}]",
+        database.display(),
+        existing,
+        new
+    );
+    Ok(())
+}
+
+// ~0.8% standard error in 16 KiB of registers
+const HLL_PRECISION: u32 = 14;
+
+enum DryRunCounter {
+    Exact(ExternalDedup),
+    Approx(HyperLogLog),
+}
+
+impl DryRunCounter {
+    fn insert(&mut self, word: String) -> Result<()> {
+        match self {
+            Self::Exact(dedup) => dedup.insert(word),
+            Self::Approx(hll) => {
+                hll.insert(&word);
+                Ok(())
+            }
+        }
+    }
+
+    fn count_unique(self) -> Result<usize> {
+        match self {
+            Self::Exact(dedup) => dedup.count_unique(),
+            Self::Approx(hll) => Ok(hll.estimate()),
+        }
+    }
+}
+
 fn run_dry_run(
     args: &BuildArgs,
     source: &dyn crate::source::Source,
     hashers: &[Box<dyn Hasher>],
+    source_name: &str,
     source_hash: Option<String>,
 ) -> Result<()> {
     eprintln!("[dry-run] Would process: {}", source.name());
@@ -263,9 +1386,9 @@ fn run_dry_run(
 
     let mut already_processed = false;
 
-    if !args.r2 && args.output.exists() {
+    if let Some(database) = args.existing_database() {
         if let Some(ref hash) = source_hash {
-            let existing_storage = ParquetStorage::new(&args.output);
+            let existing_storage = ParquetStorage::new(database);
             let existing_hashes = existing_storage.get_source_hashes()?;
             if existing_hashes.contains(hash) {
                 already_processed = true;
@@ -277,57 +1400,249 @@ fn run_dry_run(
         }
     }
 
-    if args.append && !args.r2 && args.output.exists() {
-        let existing_storage = ParquetStorage::new(&args.output);
+    if let Some(database) = args.existing_database().filter(|_| args.append) {
+        let existing_storage = ParquetStorage::new(database);
         let stats = existing_storage.stats()?;
         eprintln!(
             "[dry-run] Append mode: would merge with {} existing records",
             format_number(stats.total_records)
         );
+        // The merge preview needs exact keys, so --approx skips it
+        if !args.approx {
+            return run_dry_run_merge(
+                args,
+                source,
+                hashers,
+                source_name,
+                &existing_storage,
+                already_processed,
+            );
+        }
     }
 
-    let words_iter = source.words()?;
-    let mut seen: HashSet<String> = HashSet::new();
+    let mut seen = if args.approx {
+        DryRunCounter::Approx(HyperLogLog::new(HLL_PRECISION))
+    } else {
+        DryRunCounter::Exact(external_dedup(args, args.disk_dedup.unwrap_or(usize::MAX)))
+    };
     let mut total = 0usize;
 
-    for word in words_iter {
-        total += 1;
-        seen.insert(word);
+    let prehashed = source.prehashed()?;
+    let per_word = if prehashed.is_some() { 1 } else { hashers.len() };
+    match prehashed {
+        Some(prehashed) => {
+            for entry in prehashed.entries.skip(args.skip_lines) {
+                total += 1;
+                seen.insert(entry?.hash)?;
+            }
+        }
+        None => {
+            let words = Box::new(source.words()?.skip(args.skip_lines));
+            let (words, shaped) = shape_words(args, words)?;
+            for word in words {
+                total += 1;
+                seen.insert(word)?;
+            }
+            check_invalid_lines(args.on_invalid, source.invalid_lines())?;
+            if shaped.missing.get() > 0 {
+                eprintln!(
+                    "[dry-run] Lines without field {}: {}",
+                    args.field.unwrap_or_default(),
+                    format_number(shaped.missing.get())
+                );
+            }
+            if shaped.dropped_variants.get() > 0 {
+                eprintln!(
+                    "[dry-run] Variants dropped by --max-variants-per-word: {}",
+                    format_number(shaped.dropped_variants.get())
+                );
+            }
+            if shaped.filtered.get() > 0 {
+                eprintln!(
+                    "[dry-run] Candidates filtered by length or charset: {}",
+                    format_number(shaped.filtered.get())
+                );
+            }
+        }
     }
-
-    let unique = seen.len();
-    let record_count = unique * hashers.len();
+    let approx = match &seen {
+        DryRunCounter::Approx(hll) => {
+            format!(" (approximate, ±{:.1}%)", hll.standard_error() * 100.0)
+        }
+        DryRunCounter::Exact(_) => String::new(),
+    };
+    let tilde = if args.approx { "~" } else { "" };
+    let unique = seen.count_unique()?;
+    let record_count = unique * per_word;
 
     eprintln!("[dry-run] Total words: {}", format_number(total));
-    eprintln!("[dry-run] Unique words: {}", format_number(unique));
+    eprintln!("[dry-run] Unique words: {}{}{}", tilde, format_number(unique), approx);
     eprintln!(
-        "[dry-run] Records to generate: {}",
+        "[dry-run] Records to generate: {}{}",
+        tilde,
         format_number(record_count)
     );
 
-    let output_location = if args.r2 {
-        let r2_config = build_r2_config(args)?;
-        r2_config.s3_url()
-    } else {
-        args.output.display().to_string()
+    eprintln!("[dry-run] Output: {}", dry_run_output_location(args)?);
+    print_dry_run_result(args, already_processed, record_count);
+
+    Ok(())
+}
+
+// Mirrors the --sources-file restrictions, which clap cannot express for a
+// repeated positional
+fn check_multiple_sources(args: &BuildArgs) -> Result<()> {
+    let unsupported = [
+        ("--name", args.name.is_some()),
+        ("--r2", args.r2),
+        ("--dry-run", args.dry_run),
+        ("--skip-lines", args.skip_lines > 0),
+        ("--max-file-size", args.max_file_size.is_some()),
+        ("--prefix-only-bits", args.prefix_only_bits.is_some()),
+        ("--chunk-output", args.chunk_output),
+        // Compares the outputs against a single source
+        ("--if-newer", args.if_newer),
+    ];
+    if let Some((flag, _)) = unsupported.iter().find(|(_, set)| *set) {
+        bail!("{} cannot be used with several sources", flag);
+    }
+    Ok(())
+}
+
+fn read_source_specs(path: &Path) -> Result<Vec<String>> {
+    let content = std::fs::read_to_string(path)
+        .with_context(|| format!("Failed to read sources file {}", path.display()))?;
+    Ok(content
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(String::from)
+        .collect())
+}
+
+fn external_dedup(args: &BuildArgs, threshold: usize) -> ExternalDedup {
+    let dedup = ExternalDedup::new(threshold);
+    match &args.temp_dir {
+        Some(dir) => dedup.with_temp_dir(dir),
+        None => dedup,
+    }
+}
+
+const MERGE_PROGRESS_INTERVAL: usize = 10_000;
+
+// A spinner, or a bar when the total is known; hidden under --quiet
+fn progress_bar(len: Option<u64>) -> ProgressBar {
+    if output::is_quiet() {
+        return ProgressBar::hidden();
+    }
+    let (pb, template) = match len {
+        Some(len) => (
+            ProgressBar::new(len),
+            "{spinner:.green} [{elapsed_precise}] [{bar:30}] {percent}% {msg}",
+        ),
+        None => (
+            ProgressBar::new_spinner(),
+            "{spinner:.green} [{elapsed_precise}] {msg}",
+        ),
+    };
+    pb.set_style(ProgressStyle::default_spinner().template(template).unwrap());
+    pb
+}
+
+fn disk_dedup(args: &BuildArgs) -> Option<ExternalDedup> {
+    args.disk_dedup.map(|threshold| external_dedup(args, threshold))
+}
+
+// Runs the real ingest and merges it against the existing records in a
+// scratch map, so the preview matches what --append would write.
+fn run_dry_run_merge(
+    args: &BuildArgs,
+    source: &dyn crate::source::Source,
+    hashers: &[Box<dyn Hasher>],
+    source_name: &str,
+    existing_storage: &ParquetStorage,
+    already_processed: bool,
+) -> Result<()> {
+    let Ingested {
+        total_words,
+        unique_words,
+        records: mut new_records,
+        ..
+    } = match source.prehashed()? {
+        Some(mut prehashed) => {
+            prehashed.entries = Box::new(prehashed.entries.skip(args.skip_lines));
+            ingest_prehashed(prehashed, source_name, &mut |_| {}, args.validate_hex)?.ingested
+        }
+        None => {
+            let words = Box::new(source.words()?.skip(args.skip_lines));
+            let (words, _) = shape_words(args, words)?;
+            let ingested = ingest(words, hashers, source_name, &mut |_| {}, disk_dedup(args))?;
+            check_invalid_lines(args.on_invalid, source.invalid_lines())?;
+            ingested
+        }
     };
+    let generated = new_records.len();
+
+    let mut existing_count = 0usize;
+    let mut gaining_source = 0usize;
+    existing_storage.for_each_record(|mut record| {
+        existing_count += 1;
+        args.source_name_transform.normalize(&mut record.sources);
+        let merged = merge_by_key(&mut record, &mut new_records, args.merge_strategy)?;
+        if merged.is_some_and(|m| m.sources_added > 0) {
+            gaining_source += 1;
+        }
+        Ok(())
+    })?;
+    let brand_new = new_records.len();
+    let total = existing_count + brand_new;
 
-    eprintln!("[dry-run] Output: {}", output_location);
+    eprintln!("[dry-run] Total words: {}", format_number(total_words));
+    eprintln!("[dry-run] Unique words: {}", format_number(unique_words));
+    eprintln!(
+        "[dry-run] Records to generate: {}",
+        format_number(generated)
+    );
+    eprintln!(
+        "[dry-run] Merge preview: {} existing records would gain source '{}', {} new records, {} total",
+        format_number(gaining_source),
+        source_name,
+        format_number(brand_new),
+        format_number(total)
+    );
+    eprintln!("[dry-run] Output: {}", dry_run_output_location(args)?);
+    print_dry_run_result(args, already_processed, total);
 
+    Ok(())
+}
+
+fn dry_run_output_location(args: &BuildArgs) -> Result<String> {
+    if args.r2 {
+        return Ok(build_r2_config(args)?.s3_url());
+    }
+    Ok(args
+        .output
+        .iter()
+        .map(|p| p.display().to_string())
+        .collect::<Vec<_>>()
+        .join(", "))
+}
+
+fn print_dry_run_result(args: &BuildArgs, already_processed: bool, record_count: usize) {
     if already_processed && !args.force {
         eprintln!("[dry-run] Result: Would skip (use --force to rebuild)");
     } else {
         eprintln!(
-            "[dry-run] Result: Would write {} records",
+            "[dry-run] Result: Would write {}{} records",
+            if args.approx { "~" } else { "" },
             format_number(record_count)
         );
     }
-
-    Ok(())
 }
 
 fn build_r2_config(args: &BuildArgs) -> Result<R2Config> {
-    let default_path = args.output.file_name()
+    let default_path = args.output.first()
+        .and_then(|p| p.file_name())
         .map(|n| n.to_string_lossy().to_string())
         .unwrap_or_else(|| "hashes.parquet".to_string());
 
@@ -339,49 +1654,8 @@ fn build_r2_config(args: &BuildArgs) -> Result<R2Config> {
         path: args.r2_path.as_deref(),
         region: &args.region,
         default_path: &default_path,
+        no_ssl: args.s3_no_ssl,
     };
 
     Config::load().unwrap_or_default().build_r2_config(overrides)
 }
-
-fn process_new_words(
-    words: &[String],
-    hashers: &[Box<dyn Hasher>],
-    source_name: &str,
-    records_map: &mut HashMap<RecordKey, HashRecord>,
-) {
-    let new_records: Vec<HashRecord> = words
-        .par_iter()
-        .flat_map(|word| {
-            hashers
-                .iter()
-                .map(|hasher| HashRecord {
-                    hash: hasher.hash(word.as_bytes()),
-                    preimage: word.clone(),
-                    algorithm: hasher.name().to_string(),
-                    sources: vec![source_name.to_string()],
-                })
-                .collect::<Vec<_>>()
-        })
-        .collect();
-
-    for record in new_records {
-        let key = (record.hash.clone(), record.algorithm.clone());
-        records_map.entry(key).or_insert(record);
-    }
-}
-
-fn format_number(n: usize) -> String {
-    let s = n.to_string();
-    let bytes = s.as_bytes();
-    let len = bytes.len();
-
-    let mut result = String::with_capacity(len + (len - 1) / 3);
-    for (i, &byte) in bytes.iter().enumerate() {
-        if i > 0 && (len - i).is_multiple_of(3) {
-            result.push(',');
-        }
-        result.push(byte as char);
-    }
-    result
-}
@@ -0,0 +1,89 @@
+use std::collections::HashSet;
+use std::path::PathBuf;
+
+use anyhow::{bail, Context, Result};
+use clap::Args;
+
+use crate::hasher::{self, Hasher};
+use crate::output::format_number;
+use crate::storage::ParquetStorage;
+
+#[derive(Args)]
+pub struct CheckArgs {
+    /// File with one candidate plaintext per line
+    pub candidates_file: PathBuf,
+
+    /// Database file
+    #[arg(short, long, default_value = "hashes.parquet")]
+    pub database: PathBuf,
+
+    /// Algorithm to hash candidates with
+    #[arg(short, long, default_value = "sha256", value_parser = hasher::algo_value_parser())]
+    pub algo: String,
+
+    /// Print a found/missing line for every candidate
+    #[arg(short, long)]
+    pub verbose: bool,
+}
+
+// Wraps `hasher` in the database's salt and rounds, so candidates are
+// hashed the way the database was built
+pub(super) fn as_built(storage: &ParquetStorage, hasher: Box<dyn Hasher>) -> Result<Box<dyn Hasher>> {
+    Ok(hasher::salted_and_iterated(
+        hasher,
+        storage.get_salt()?,
+        storage.get_rounds()?.unwrap_or(1),
+    ))
+}
+
+pub fn run(args: CheckArgs) -> Result<()> {
+    if !args.database.exists() {
+        bail!("Database not found: {}", args.database.display());
+    }
+
+    let content = std::fs::read_to_string(&args.candidates_file).with_context(|| {
+        format!(
+            "Failed to read candidates file {}",
+            args.candidates_file.display()
+        )
+    })?;
+    let mut candidates: Vec<&str> = content
+        .lines()
+        .map(|line| line.trim_end_matches('\r'))
+        .filter(|line| !line.is_empty())
+        .collect();
+    let mut seen = HashSet::new();
+    candidates.retain(|candidate| seen.insert(*candidate));
+
+    let storage = ParquetStorage::new(&args.database);
+    let hasher = hasher::get_hasher(&args.algo).expect("algorithm validated by clap");
+    let hasher = as_built(&storage, hasher)?;
+    let keys: Vec<(Vec<u8>, String)> = candidates
+        .iter()
+        .map(|candidate| (hasher.hash(candidate.as_bytes()), hasher.name().to_string()))
+        .collect();
+
+    let present = storage.present_keys(&keys)?;
+
+    if args.verbose {
+        for (candidate, key) in candidates.iter().zip(&keys) {
+            let status = if present.contains(key) {
+                "found"
+            } else {
+                "missing"
+            };
+            println!("{}\t{}", status, candidate);
+        }
+    }
+
+    let found = keys.iter().filter(|key| present.contains(*key)).count();
+    println!(
+        "{} of {} candidates found in {} ({})",
+        format_number(found),
+        format_number(candidates.len()),
+        args.database.display(),
+        args.algo
+    );
+
+    Ok(())
+}
@@ -0,0 +1,150 @@
+use std::borrow::Cow;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::PathBuf;
+
+use anyhow::{bail, Context, Result};
+use clap::{Args, ValueEnum};
+
+use crate::hasher;
+use crate::output::format_number;
+use crate::status;
+use crate::storage::{write_csv_record, HashRecord, ParquetStorage, Storage, CSV_HEADER};
+
+#[derive(Clone, ValueEnum)]
+pub enum ExportFormat {
+    /// hash,preimage,algorithm,sources with sources joined by `;`
+    Csv,
+    /// Hashcat potfile: one hash:plain (hash:salt:plain if salted) per line
+    Potfile,
+}
+
+#[derive(Args)]
+pub struct ExportArgs {
+    /// Parquet database to export
+    pub database: PathBuf,
+
+    #[arg(short, long, default_value = "csv")]
+    pub format: ExportFormat,
+
+    /// Output file, or `-` for stdout
+    #[arg(short, long, default_value = "-")]
+    pub output: PathBuf,
+
+    /// Only export records of these algorithms
+    #[arg(short, long, value_parser = hasher::algo_value_parser())]
+    pub algo: Vec<String>,
+}
+
+pub fn run(args: ExportArgs) -> Result<()> {
+    if !args.database.is_file() {
+        bail!("Database not found: {}", args.database.display());
+    }
+
+    let salt = match args.format {
+        ExportFormat::Potfile => potfile_salt(&args)?,
+        ExportFormat::Csv => None,
+    };
+
+    let to_stdout = args.output.as_os_str() == "-";
+    let out: Box<dyn Write> = if to_stdout {
+        Box::new(io::stdout().lock())
+    } else {
+        let file = File::create(&args.output)
+            .with_context(|| format!("Failed to create file: {}", args.output.display()))?;
+        Box::new(file)
+    };
+
+    let mut out = BufWriter::new(out);
+    let exported = match args.format {
+        ExportFormat::Csv => {
+            let mut writer = csv::Writer::from_writer(out);
+            writer.write_record(CSV_HEADER)?;
+            let exported = export_records(&args, |record| write_csv_record(&mut writer, record))?;
+            writer.flush()?;
+            exported
+        }
+        ExportFormat::Potfile => {
+            let exported = export_records(&args, |record| {
+                write!(out, "{}:", hex::encode(&record.hash))?;
+                if let Some(salt) = &salt {
+                    write!(out, "{}:", salt)?;
+                }
+                writeln!(out, "{}", potfile_plain(&record.preimage))?;
+                Ok(())
+            })?;
+            out.flush()?;
+            exported
+        }
+    };
+
+    status!(
+        "Exported {} records to {}",
+        format_number(exported),
+        if to_stdout { "stdout".to_string() } else { args.output.display().to_string() }
+    );
+    Ok(())
+}
+
+fn export_records(
+    args: &ExportArgs,
+    mut write: impl FnMut(&HashRecord) -> Result<()>,
+) -> Result<usize> {
+    let mut exported = 0usize;
+    ParquetStorage::new(&args.database).for_each_record(|record| {
+        if args.algo.is_empty() || args.algo.contains(&record.algorithm) {
+            write(&record)?;
+            exported += 1;
+        }
+        Ok(())
+    })?;
+    Ok(exported)
+}
+
+// A potfile line carries the hash, an optional salt and the plaintext; it
+// cannot say that a hash was truncated, iterated or keyed, so such
+// databases are refused rather than exported as lines hashcat cannot use.
+fn potfile_salt(args: &ExportArgs) -> Result<Option<String>> {
+    let storage = ParquetStorage::new(&args.database);
+    let database = args.database.display();
+    if let Some(bits) = storage.get_prefix_bits()? {
+        bail!(
+            "{} stores only the first {} bits of each hash (--prefix-only-bits); a potfile needs full hashes",
+            database,
+            bits
+        );
+    }
+    if let Some(rounds) = storage.get_rounds()?.filter(|&rounds| rounds > 1) {
+        bail!("{} was built with --rounds {}; a potfile cannot express iterated hashes", database, rounds);
+    }
+    let exported = |algorithm: &String| args.algo.is_empty() || args.algo.contains(algorithm);
+    if let Some(algorithm) = storage
+        .stats()?
+        .algorithms
+        .iter()
+        .find(|algorithm| algorithm.starts_with("hmac-") && exported(algorithm))
+    {
+        bail!("{} holds {} hashes; a potfile cannot carry the HMAC key", database, algorithm);
+    }
+
+    let Some((salt, _)) = storage.get_salt()? else {
+        return Ok(None);
+    };
+    match std::str::from_utf8(&salt) {
+        Ok(text) if !text.contains(|c: char| c == ':' || c.is_control()) => Ok(Some(text.to_string())),
+        _ => {
+            status!("Salt is not printable; writing it as hex (use hashcat --hex-salt)");
+            Ok(Some(hex::encode(salt)))
+        }
+    }
+}
+
+// Same escaping as hashcat: plaintexts that would break the line (or look
+// escaped already) are written as $HEX[...]
+fn potfile_plain(preimage: &str) -> Cow<'_, str> {
+    if preimage.chars().any(char::is_control) || preimage.starts_with("$HEX[") {
+        Cow::Owned(format!("$HEX[{}]", hex::encode(preimage)))
+    } else {
+        Cow::Borrowed(preimage)
+    }
+}
@@ -0,0 +1,76 @@
+use std::fmt::Write;
+
+use anyhow::{bail, Context, Result};
+use clap::Args;
+
+use crate::hasher::{self, Hasher, SaltPosition, SaltedHasher};
+use crate::source::{Source, StdinSource};
+
+#[derive(Args)]
+pub struct HashArgs {
+    /// Word(s) to hash, or `-` to read one word per line from stdin
+    #[arg(required = true)]
+    pub words: Vec<String>,
+
+    /// Hash algorithms to use
+    #[arg(short, long, default_value = "sha256", value_parser = hasher::algo_value_parser())]
+    pub algo: Vec<String>,
+
+    /// Salt (hex) mixed into every word before hashing, as in build --salt
+    #[arg(long, value_name = "HEX")]
+    pub salt: Option<String>,
+
+    /// Whether the salt goes before (hash(salt || word)) or after the word
+    #[arg(long, value_enum, default_value = "prefix")]
+    pub salt_position: SaltPosition,
+
+    /// Print hashes as uppercase hex
+    #[arg(long)]
+    pub uppercase: bool,
+}
+
+pub fn run(args: HashArgs) -> Result<()> {
+    let salt = match args.salt.as_deref() {
+        Some("") => bail!("--salt must not be empty"),
+        Some(salt) => {
+            Some(hex::decode(salt).with_context(|| format!("--salt is not valid hex: {}", salt))?)
+        }
+        None => None,
+    };
+
+    let hashers: Vec<Box<dyn Hasher>> = args
+        .algo
+        .iter()
+        .map(|name| {
+            let hasher = hasher::get_hasher(name).expect("algorithm validated by clap");
+            match &salt {
+                Some(salt) => {
+                    Box::new(SaltedHasher::new(hasher, salt.clone(), args.salt_position)) as _
+                }
+                None => hasher,
+            }
+        })
+        .collect();
+
+    let words: Box<dyn Iterator<Item = String>> = if args.words == ["-"] {
+        StdinSource::new().words()?
+    } else {
+        Box::new(args.words.into_iter())
+    };
+
+    let mut out = String::new();
+    for word in words {
+        for hasher in &hashers {
+            let hash = hasher.hash(word.as_bytes());
+            let hash = if args.uppercase {
+                hex::encode_upper(hash)
+            } else {
+                hex::encode(hash)
+            };
+            writeln!(out, "{}  {}  {}", hasher.name(), hash, word)?;
+        }
+        print!("{}", out);
+        out.clear();
+    }
+    Ok(())
+}
@@ -0,0 +1,240 @@
+use std::collections::hash_map::Entry;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Context, Result};
+use clap::{Args, ValueEnum};
+
+use super::merge::Settings;
+use crate::build::{sort_records, write_records, SortMode};
+use crate::hasher;
+use crate::output::format_number;
+use crate::status;
+use crate::storage::{merge_by_key, HashRecord, MergeStrategy, ParquetStorage, RecordKey, Storage};
+
+#[derive(Clone, ValueEnum)]
+pub enum ImportFormat {
+    /// hash,preimage,algorithm,sources with a header row, as written by `export`
+    Csv,
+    /// Hashcat potfile: one hash:plain per line
+    Potfile,
+}
+
+#[derive(Args)]
+pub struct ImportArgs {
+    /// File to import
+    pub input: PathBuf,
+
+    #[arg(short, long, default_value = "csv")]
+    pub format: ImportFormat,
+
+    /// Potfile only: algorithms a hash may be, picked by its length (default: any known)
+    #[arg(short, long, value_parser = hasher::algo_value_parser())]
+    pub algo: Vec<String>,
+
+    /// Output parquet file
+    #[arg(short, long, default_value = "hashes.parquet")]
+    pub output: PathBuf,
+
+    /// Merge into the existing output database instead of replacing it
+    #[arg(long)]
+    pub append: bool,
+
+    /// How an imported record combines with an existing one for the same hash
+    #[arg(long, value_enum, default_value = "union-sources")]
+    pub merge_strategy: MergeStrategy,
+}
+
+pub fn run(args: ImportArgs) -> Result<()> {
+    if !args.output.extension().is_some_and(|ext| ext.eq_ignore_ascii_case("parquet")) {
+        bail!("Import output must be a .parquet file: {:?}", args.output);
+    }
+
+    status!("Reading {}...", args.input.display());
+    let parsed = match args.format {
+        ImportFormat::Csv if !args.algo.is_empty() => {
+            bail!("--algo only applies to --format potfile; CSV rows name their algorithm")
+        }
+        ImportFormat::Csv => read_csv(&args.input)?,
+        ImportFormat::Potfile => read_potfile(&args.input, &args.algo)?,
+    };
+
+    let mut incoming: HashMap<RecordKey, HashRecord> = HashMap::new();
+    let mut read = 0usize;
+    for record in parsed {
+        read += 1;
+        match incoming.entry((record.hash.clone(), record.algorithm.clone())) {
+            Entry::Occupied(mut existing) => {
+                args.merge_strategy.merge(existing.get_mut(), record)?;
+            }
+            Entry::Vacant(slot) => {
+                slot.insert(record);
+            }
+        }
+    }
+
+    let mut records: Vec<HashRecord> = Vec::new();
+    let mut output = ParquetStorage::with_expected_capacity(&args.output, incoming.len());
+    let mut collisions = 0usize;
+    let mut merged_count = 0usize;
+
+    if args.append && args.output.exists() {
+        status!("Streaming existing database for merge...");
+        let existing = ParquetStorage::new(&args.output);
+        existing.for_each_record(|mut record| {
+            if let Some(merged) = merge_by_key(&mut record, &mut incoming, args.merge_strategy)? {
+                collisions += usize::from(merged.collision);
+                merged_count += merged.sources_added;
+            }
+            records.push(record);
+            Ok(())
+        })?;
+
+        output = Settings::read(&existing)?.apply(output);
+        for hash in &existing.get_source_hashes()? {
+            output.add_source_hash(hash);
+        }
+        for (name, meta) in existing.get_source_meta()? {
+            output.add_source_meta(&name, meta);
+        }
+        collisions += existing.stats()?.collisions.unwrap_or(0);
+    }
+
+    let added = incoming.len();
+    records.extend(incoming.into_values());
+    sort_records(&mut records, SortMode::Auto);
+    output.add_collisions(collisions);
+    write_records(&mut output, &records)?;
+
+    status!(
+        "Imported {} rows into {}: {} new records, {} sources merged ({} total)",
+        format_number(read),
+        args.output.display(),
+        format_number(added),
+        format_number(merged_count),
+        format_number(records.len())
+    );
+    Ok(())
+}
+
+fn read_csv(path: &Path) -> Result<Vec<HashRecord>> {
+    let mut reader = csv::ReaderBuilder::new()
+        .flexible(true)
+        .from_path(path)
+        .with_context(|| format!("Failed to open {}", path.display()))?;
+
+    let mut records = Vec::new();
+    for row in reader.records() {
+        let row = row.with_context(|| format!("Failed to read {}", path.display()))?;
+        let line = row.position().map_or(0, |position| position.line());
+        if row.len() != 4 {
+            bail!(
+                "{} line {}: expected hash,preimage,algorithm,sources, got {} columns",
+                path.display(),
+                line,
+                row.len()
+            );
+        }
+
+        let hash = decode_hash(&row[0], &row[2])
+            .with_context(|| format!("{} line {}", path.display(), line))?;
+        records.push(HashRecord {
+            hash,
+            preimage: row[1].to_string(),
+            algorithm: row[2].to_string(),
+            sources: row[3].split(';').filter(|s| !s.is_empty()).map(String::from).collect(),
+        });
+    }
+    Ok(records)
+}
+
+// Hashcat writes `hash:plain`; the hash never contains a colon, so
+// everything after the first one is the plaintext. Records are named after
+// the file, like any other source.
+fn read_potfile(path: &Path, algos: &[String]) -> Result<Vec<HashRecord>> {
+    let file = File::open(path).with_context(|| format!("Failed to open {}", path.display()))?;
+    let source = path.file_stem().unwrap_or_default().to_string_lossy().to_string();
+    let candidates: Vec<(&str, usize)> = match algos {
+        [] => hasher::available_algorithms().to_vec(),
+        algos => algos.iter().map(String::as_str).collect(),
+    }
+    .into_iter()
+    .filter_map(|name| Some((name, hasher::algorithm_output_len(name)?)))
+    .collect();
+
+    let mut records = Vec::new();
+    for (i, line) in BufReader::new(file).lines().enumerate() {
+        let line = line.with_context(|| format!("Failed to read {}", path.display()))?;
+        let line = line.trim_end_matches('\r');
+        if line.is_empty() {
+            continue;
+        }
+        let record = parse_potfile_line(line, &candidates)
+            .with_context(|| format!("{} line {}", path.display(), i + 1))?;
+        records.push(HashRecord {
+            sources: vec![source.clone()],
+            ..record
+        });
+    }
+    Ok(records)
+}
+
+fn parse_potfile_line(line: &str, candidates: &[(&str, usize)]) -> Result<HashRecord> {
+    let Some((hash, plain)) = line.split_once(':') else {
+        bail!("expected hash:plain, got {:?}", line);
+    };
+    let hash = hex::decode(hash).with_context(|| format!("hash is not valid hex: {}", hash))?;
+
+    let matching: Vec<&str> = candidates
+        .iter()
+        .filter(|(_, len)| *len == hash.len())
+        .map(|(name, _)| *name)
+        .collect();
+    let algorithm = match matching[..] {
+        [algorithm] => algorithm,
+        [] => bail!(
+            "no algorithm among {} has {}-byte hashes",
+            candidates.iter().map(|(name, _)| *name).collect::<Vec<_>>().join(", "),
+            hash.len()
+        ),
+        _ => bail!(
+            "a {}-byte hash could be {}; pass --algo to choose",
+            hash.len(),
+            matching.join(", ")
+        ),
+    };
+
+    Ok(HashRecord {
+        hash,
+        preimage: decode_potfile_plain(plain)?,
+        algorithm: algorithm.to_string(),
+        sources: Vec::new(),
+    })
+}
+
+// Hashcat stores plaintexts with unprintable bytes as $HEX[...]
+fn decode_potfile_plain(plain: &str) -> Result<String> {
+    let Some(encoded) = plain.strip_prefix("$HEX[").and_then(|rest| rest.strip_suffix(']')) else {
+        return Ok(plain.to_string());
+    };
+    let bytes = hex::decode(encoded).with_context(|| format!("invalid {}", plain))?;
+    String::from_utf8(bytes).with_context(|| format!("{} is not valid UTF-8", plain))
+}
+
+fn decode_hash(hash: &str, algorithm: &str) -> Result<Vec<u8>> {
+    let Some(expected) = hasher::algorithm_output_len(algorithm) else {
+        bail!("unknown algorithm {:?}", algorithm);
+    };
+    let hash = hex::decode(hash).with_context(|| format!("hash is not valid hex: {}", hash))?;
+    if hash.len() != expected {
+        bail!(
+            "{} hash should be {} bytes, got {}",
+            algorithm,
+            expected,
+            hash.len()
+        );
+    }
+    Ok(hash)
+}
@@ -1,15 +1,26 @@
+use std::collections::{BTreeMap, BTreeSet};
 use std::path::PathBuf;
 
 use anyhow::Result;
 use clap::{Args, ValueEnum};
+use comfy_table::{presets::UTF8_FULL, Table};
 
 use crate::config::{Config, R2Overrides};
-use crate::storage::{ParquetStorage, R2Config, R2Storage, Storage};
+use crate::hasher::{self, SaltPosition};
+use crate::output::format_number;
+use crate::storage::{
+    BloomStats, ParquetStorage, Provenance, R2Config, R2Storage, SourceMeta, Storage,
+};
+
+// An optimally sized bloom filter is about half full at its design capacity.
+const BLOOM_SATURATED_FILL: f64 = 0.6;
 
 #[derive(Clone, ValueEnum)]
 pub enum OutputFormat {
     Plain,
     Json,
+    /// One line: records, algorithms, sources and size
+    Summary,
 }
 
 #[derive(Args)]
@@ -20,6 +31,22 @@ pub struct InfoArgs {
     #[arg(short, long, default_value = "plain")]
     pub format: OutputFormat,
 
+    /// Show per-source provenance details and how the database was built
+    #[arg(short, long)]
+    pub verbose: bool,
+
+    /// List the content hashes of already processed sources (always in --format json)
+    #[arg(long, conflicts_with = "compare")]
+    pub show_source_hashes: bool,
+
+    /// Fail if the database lists an algorithm this version does not know
+    #[arg(long)]
+    pub strict_algo: bool,
+
+    /// Compare against another database (metadata only)
+    #[arg(long, value_name = "OTHER", conflicts_with = "verbose")]
+    pub compare: Option<PathBuf>,
+
     #[arg(long)]
     pub r2: bool,
 
@@ -40,30 +67,109 @@ pub struct InfoArgs {
 
     #[arg(long, env = "SHAHA_R2_REGION", default_value = "auto")]
     pub region: String,
+
+    #[arg(long)]
+    pub s3_no_ssl: bool,
 }
 
 pub fn run(args: InfoArgs) -> Result<()> {
-    let (stats, location) = if args.r2 {
+    let (stats, location, source_meta, bloom, provenance, hashing, source_hashes) = if args.r2 {
         let r2_config = build_r2_config(&args)?;
         let url = r2_config.s3_url();
         let storage = R2Storage::new(r2_config)?;
-        (storage.stats()?, url)
+        let stats = storage.stats()?;
+        (stats, url, BTreeMap::new(), None, None, Hashing::default(), BTreeSet::new())
     } else {
         let storage = ParquetStorage::new(&args.database);
-        (storage.stats()?, args.database.display().to_string())
+        let (source_meta, bloom, provenance) = if args.verbose {
+            (storage.get_source_meta()?, storage.bloom_stats()?, Some(storage.get_provenance()?))
+        } else {
+            (BTreeMap::new(), None, None)
+        };
+        let location = args.database.display().to_string();
+        let hashing = Hashing {
+            salt: storage.get_salt()?,
+            rounds: storage.get_rounds()?,
+        };
+        let source_hashes = storage.get_source_hashes()?.into_iter().collect();
+        (storage.stats()?, location, source_meta, bloom, provenance, hashing, source_hashes)
     };
 
+    if args.strict_algo {
+        hasher::ensure_known_algorithms(stats.algorithms.iter().map(String::as_str))?;
+    }
+
+    if let Some(other) = &args.compare {
+        let other_stats = ParquetStorage::new(other).stats()?;
+        let other_location = other.display().to_string();
+        match args.format {
+            OutputFormat::Plain | OutputFormat::Summary => print_compare_plain(&location, &stats, &other_location, &other_stats),
+            OutputFormat::Json => print_compare_json(&location, &stats, &other_location, &other_stats)?,
+        }
+        return Ok(());
+    }
+
+    let source_meta = args.verbose.then_some(&source_meta);
+
     match args.format {
-        OutputFormat::Plain => print_plain(&location, &stats),
-        OutputFormat::Json => print_json(&location, &stats)?,
+        OutputFormat::Plain => {
+            print_plain(&location, &stats, source_meta, bloom.as_ref(), provenance.as_ref(), &hashing);
+            if args.show_source_hashes {
+                print_source_hashes(&source_hashes);
+            }
+        }
+        OutputFormat::Json => {
+            let provenance = provenance.as_ref();
+            print_json(&location, &stats, source_meta, bloom.as_ref(), provenance, &hashing, &source_hashes)?
+        }
+        OutputFormat::Summary => println!("{}", format_summary(&location, &stats)),
+    }
+
+    if let Some(bloom) = bloom.filter(|b| b.fill_ratio() > BLOOM_SATURATED_FILL) {
+        eprintln!(
+            "warning: bloom filter is {:.0}% full; queries will see elevated false positives \
+            (~{:.2}%). Rebuild with --force to resize it.",
+            bloom.fill_ratio() * 100.0,
+            bloom.false_positive_rate() * 100.0
+        );
     }
 
     Ok(())
 }
 
-fn print_plain(location: &str, stats: &crate::storage::Stats) {
+// Plain summary of a local database, as printed by `info` without flags
+pub(super) fn print_parquet_info(storage: &ParquetStorage, location: &str) -> Result<()> {
+    let hashing = Hashing {
+        salt: storage.get_salt()?,
+        rounds: storage.get_rounds()?,
+    };
+    print_plain(location, &storage.stats()?, None, None, None, &hashing);
+    Ok(())
+}
+
+// How each stored hash was derived from its preimage
+#[derive(Default)]
+struct Hashing {
+    salt: Option<(Vec<u8>, SaltPosition)>,
+    rounds: Option<u32>,
+}
+
+fn print_plain(
+    location: &str,
+    stats: &crate::storage::Stats,
+    source_meta: Option<&BTreeMap<String, SourceMeta>>,
+    bloom: Option<&BloomStats>,
+    provenance: Option<&Provenance>,
+    hashing: &Hashing,
+) {
     println!("Database:   {}", location);
-    println!("Records:    {}", stats.total_records);
+    println!("Records:    {}", format_number(stats.total_records));
+    if let Some(preimages) = stats.distinct_preimages {
+        println!("Preimages:  {}", format_number(preimages));
+    }
+    if let Some(collisions) = stats.collisions {
+        println!("Collisions: {}", format_number(collisions));
+    }
     if stats.file_size_bytes > 0 {
         println!("Size:       {}", format_bytes(stats.file_size_bytes));
     }
@@ -83,22 +189,144 @@ fn print_plain(location: &str, stats: &crate::storage::Stats) {
             stats.sources.join(", ")
         }
     );
+    if let Some((salt, position)) = &hashing.salt {
+        println!("Salt:       {} ({})", hex::encode(salt), position.as_str());
+    }
+    if let Some(rounds) = hashing.rounds {
+        println!("Rounds:     {}", format_number(rounds as usize));
+    }
+
+    if let Some(provenance) = provenance {
+        println!();
+        let fields = [
+            ("Version:", &provenance.shaha_version),
+            ("Built at:", &provenance.built_at),
+            ("Command:", &provenance.build_command),
+        ];
+        for (label, value) in fields {
+            println!("{:<12}{}", label, value.as_deref().unwrap_or("-"));
+        }
+    }
+
+    if let Some(bloom) = bloom {
+        println!();
+        println!(
+            "Bloom:      {} bits, {} hash functions",
+            format_number(bloom.bits as usize),
+            bloom.hash_functions
+        );
+        if let Some(items) = bloom.items {
+            println!("  Items:    {}", format_number(items));
+        }
+        println!("  Fill:     {:.1}%", bloom.fill_ratio() * 100.0);
+        println!("  Est. FPR: {:.4}%", bloom.false_positive_rate() * 100.0);
+    }
+
+    if let Some(source_meta) = source_meta {
+        for (source, meta) in source_meta {
+            println!();
+            println!("Source:     {}", source);
+            let fields = [
+                ("URL", &meta.url),
+                ("Path", &meta.path),
+                ("Commit", &meta.commit),
+                ("License", &meta.license),
+                ("Retrieved", &meta.retrieved_at),
+                ("First seen", &meta.first_seen),
+                ("Rules", &meta.rules),
+            ];
+            for (label, value) in fields {
+                if let Some(value) = value {
+                    println!("  {:<10}{}", format!("{}:", label), value);
+                }
+            }
+        }
+    }
 }
 
-fn print_json(location: &str, stats: &crate::storage::Stats) -> Result<()> {
+// Content hashes of every source built into the database; a rebuild from a
+// source whose hash is listed here is skipped as "already processed".
+fn print_source_hashes(source_hashes: &BTreeSet<String>) {
+    println!();
+    if source_hashes.is_empty() {
+        println!("Source hashes: -");
+        return;
+    }
+    println!("Source hashes:");
+    for hash in source_hashes {
+        println!("  {}", hash);
+    }
+}
+
+fn format_summary(location: &str, stats: &crate::storage::Stats) -> String {
+    let count = |n: usize, noun: &str| {
+        format!("{} {}{}", format_number(n), noun, if n == 1 { "" } else { "s" })
+    };
+    let mut parts = vec![
+        count(stats.total_records, "record"),
+        count(stats.algorithms.len(), "algo"),
+        count(stats.sources.len(), "source"),
+    ];
+    if stats.file_size_bytes > 0 {
+        parts.push(format_bytes(stats.file_size_bytes));
+    }
+    format!("{}: {}", location, parts.join(", "))
+}
+
+fn print_json(
+    location: &str,
+    stats: &crate::storage::Stats,
+    source_meta: Option<&BTreeMap<String, SourceMeta>>,
+    bloom: Option<&BloomStats>,
+    provenance: Option<&Provenance>,
+    hashing: &Hashing,
+    source_hashes: &BTreeSet<String>,
+) -> Result<()> {
+    #[derive(serde::Serialize)]
+    struct JsonSalt {
+        salt: String,
+        position: &'static str,
+    }
+
+    #[derive(serde::Serialize)]
+    struct JsonBloom<'a> {
+        #[serde(flatten)]
+        stats: &'a BloomStats,
+        fill_ratio: f64,
+        false_positive_rate: f64,
+    }
+
     #[derive(serde::Serialize)]
-    struct JsonInfo {
+    struct JsonInfo<'a> {
         database: String,
         total_records: usize,
         #[serde(skip_serializing_if = "Option::is_none")]
+        distinct_preimages: Option<usize>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        collisions: Option<usize>,
+        #[serde(skip_serializing_if = "Option::is_none")]
         file_size_bytes: Option<u64>,
         algorithms: Vec<String>,
         sources: Vec<String>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        salt: Option<JsonSalt>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        rounds: Option<u32>,
+        #[serde(skip_serializing_if = "BTreeSet::is_empty")]
+        source_hashes: &'a BTreeSet<String>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        source_meta: Option<&'a BTreeMap<String, SourceMeta>>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        bloom: Option<JsonBloom<'a>>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        provenance: Option<&'a Provenance>,
     }
 
     let info = JsonInfo {
         database: location.to_string(),
         total_records: stats.total_records,
+        distinct_preimages: stats.distinct_preimages,
+        collisions: stats.collisions,
         file_size_bytes: if stats.file_size_bytes > 0 {
             Some(stats.file_size_bytes)
         } else {
@@ -106,12 +334,131 @@ fn print_json(location: &str, stats: &crate::storage::Stats) -> Result<()> {
         },
         algorithms: stats.algorithms.clone(),
         sources: stats.sources.clone(),
+        salt: hashing.salt.as_ref().map(|(salt, position)| JsonSalt {
+            salt: hex::encode(salt),
+            position: position.as_str(),
+        }),
+        rounds: hashing.rounds,
+        source_hashes,
+        source_meta,
+        bloom: bloom.map(|stats| JsonBloom {
+            stats,
+            fill_ratio: stats.fill_ratio(),
+            false_positive_rate: stats.false_positive_rate(),
+        }),
+        provenance,
     };
 
     println!("{}", serde_json::to_string_pretty(&info)?);
     Ok(())
 }
 
+struct SetDelta<'a> {
+    added: Vec<&'a str>,
+    removed: Vec<&'a str>,
+}
+
+impl<'a> SetDelta<'a> {
+    fn between(base: &'a [String], other: &'a [String]) -> Self {
+        let base: BTreeSet<&str> = base.iter().map(String::as_str).collect();
+        let other: BTreeSet<&str> = other.iter().map(String::as_str).collect();
+        Self {
+            added: other.difference(&base).copied().collect(),
+            removed: base.difference(&other).copied().collect(),
+        }
+    }
+
+    fn describe(&self) -> String {
+        let parts: Vec<String> = self
+            .added
+            .iter()
+            .map(|s| format!("+{}", s))
+            .chain(self.removed.iter().map(|s| format!("-{}", s)))
+            .collect();
+        if parts.is_empty() {
+            "=".to_string()
+        } else {
+            parts.join(", ")
+        }
+    }
+}
+
+fn print_compare_plain(
+    location: &str,
+    stats: &crate::storage::Stats,
+    other_location: &str,
+    other_stats: &crate::storage::Stats,
+) {
+    let records_delta = other_stats.total_records as i64 - stats.total_records as i64;
+    let size_delta = other_stats.file_size_bytes as i64 - stats.file_size_bytes as i64;
+
+    let mut table = Table::new();
+    table.load_preset(UTF8_FULL);
+    table.set_header(vec!["", location, other_location, "Delta"]);
+    table.add_row(vec![
+        "Records".to_string(),
+        format_number(stats.total_records),
+        format_number(other_stats.total_records),
+        format!(
+            "{}{}",
+            if records_delta < 0 { "-" } else { "+" },
+            format_number(records_delta.unsigned_abs() as usize)
+        ),
+    ]);
+    table.add_row(vec![
+        "Size".to_string(),
+        format_bytes(stats.file_size_bytes),
+        format_bytes(other_stats.file_size_bytes),
+        format!(
+            "{}{}",
+            if size_delta < 0 { "-" } else { "+" },
+            format_bytes(size_delta.unsigned_abs())
+        ),
+    ]);
+    table.add_row(vec![
+        "Algorithms".to_string(),
+        stats.algorithms.join(", "),
+        other_stats.algorithms.join(", "),
+        SetDelta::between(&stats.algorithms, &other_stats.algorithms).describe(),
+    ]);
+    table.add_row(vec![
+        "Sources".to_string(),
+        stats.sources.join(", "),
+        other_stats.sources.join(", "),
+        SetDelta::between(&stats.sources, &other_stats.sources).describe(),
+    ]);
+
+    println!("{table}");
+}
+
+fn print_compare_json(
+    location: &str,
+    stats: &crate::storage::Stats,
+    other_location: &str,
+    other_stats: &crate::storage::Stats,
+) -> Result<()> {
+    let algorithms = SetDelta::between(&stats.algorithms, &other_stats.algorithms);
+    let sources = SetDelta::between(&stats.sources, &other_stats.sources);
+
+    let info = serde_json::json!({
+        "database": location,
+        "compare": other_location,
+        "total_records": [stats.total_records, other_stats.total_records],
+        "file_size_bytes": [stats.file_size_bytes, other_stats.file_size_bytes],
+        "delta": {
+            "records": other_stats.total_records as i64 - stats.total_records as i64,
+            "file_size_bytes": other_stats.file_size_bytes as i64 - stats.file_size_bytes as i64,
+            "added_algorithms": algorithms.added,
+            "removed_algorithms": algorithms.removed,
+            "added_sources": sources.added,
+            "removed_sources": sources.removed,
+        },
+    });
+
+    println!("{}", serde_json::to_string_pretty(&info)?);
+    Ok(())
+}
+
 fn build_r2_config(args: &InfoArgs) -> Result<R2Config> {
     let default_path = args.database.file_name()
         .map(|n| n.to_string_lossy().to_string())
@@ -125,6 +472,7 @@ fn build_r2_config(args: &InfoArgs) -> Result<R2Config> {
         path: args.r2_path.as_deref(),
         region: &args.region,
         default_path: &default_path,
+        no_ssl: args.s3_no_ssl,
     };
 
     Config::load().unwrap_or_default().build_r2_config(overrides)
@@ -0,0 +1,183 @@
+use std::collections::btree_map::Entry;
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Result};
+use clap::Args;
+
+use crate::build::{sort_records, write_records, SortMode};
+use crate::hasher::SaltPosition;
+use crate::output::format_number;
+use crate::status;
+use crate::storage::{
+    merge_by_key, HashEncoding, HashRecord, MergeStrategy, ParquetStorage, SourceMeta, Storage,
+};
+
+#[derive(Args)]
+pub struct MergeArgs {
+    /// Parquet databases to merge; on conflicts the earlier database's record is kept
+    #[arg(required = true, num_args = 2..)]
+    pub databases: Vec<PathBuf>,
+
+    /// Output parquet file
+    #[arg(short, long)]
+    pub output: PathBuf,
+
+    /// How a record combines with one already merged for the same hash
+    #[arg(long, value_enum, default_value = "union-sources")]
+    pub merge_strategy: MergeStrategy,
+}
+
+pub fn run(args: MergeArgs) -> Result<()> {
+    if !args.output.extension().is_some_and(|ext| ext.eq_ignore_ascii_case("parquet")) {
+        bail!("Merge output must be a .parquet file: {:?}", args.output);
+    }
+    for database in &args.databases {
+        if !database.is_file() {
+            bail!("Database not found: {}", database.display());
+        }
+    }
+
+    let first = ParquetStorage::new(&args.databases[0]);
+    let settings = Settings::read(&first)?;
+    for database in &args.databases[1..] {
+        let other = Settings::read(&ParquetStorage::new(database))?;
+        settings.ensure_matches(&other, &args.databases[0], database)?;
+    }
+
+    status!("Reading {}...", args.databases[0].display());
+    let mut records: Vec<HashRecord> = Vec::new();
+    first.for_each_record(|record| {
+        records.push(record);
+        Ok(())
+    })?;
+    let mut source_hashes = first.get_source_hashes()?;
+    let mut source_meta = first.get_source_meta()?;
+    let mut collisions = first.stats()?.collisions.unwrap_or(0);
+    let mut sources_merged = 0usize;
+
+    for database in &args.databases[1..] {
+        status!("Merging {}...", database.display());
+        let storage = ParquetStorage::new(database);
+        let mut incoming = HashMap::new();
+        storage.for_each_record(|record| {
+            incoming.insert((record.hash.clone(), record.algorithm.clone()), record);
+            Ok(())
+        })?;
+        let incoming_count = incoming.len();
+
+        for record in &mut records {
+            if let Some(merged) = merge_by_key(record, &mut incoming, args.merge_strategy)? {
+                collisions += usize::from(merged.collision);
+                sources_merged += merged.sources_added;
+            }
+        }
+        let added = incoming.len();
+        records.extend(incoming.into_values());
+        status!(
+            "{}: {} records, {} new, {} already present",
+            database.display(),
+            format_number(incoming_count),
+            format_number(added),
+            format_number(incoming_count - added)
+        );
+
+        source_hashes.extend(storage.get_source_hashes()?);
+        merge_source_meta(&mut source_meta, storage.get_source_meta()?);
+        collisions += storage.stats()?.collisions.unwrap_or(0);
+    }
+
+    status!("Sorting and writing {} total records...", format_number(records.len()));
+    sort_records(&mut records, SortMode::Auto);
+
+    let output = ParquetStorage::with_expected_capacity(&args.output, records.len());
+    let mut output = settings.apply(output);
+    for hash in &source_hashes {
+        output.add_source_hash(hash);
+    }
+    for (name, meta) in source_meta {
+        output.add_source_meta(&name, meta);
+    }
+    output.add_collisions(collisions);
+    write_records(&mut output, &records)?;
+
+    status!(
+        "Merged {} databases into {}: {} records ({} sources merged)",
+        args.databases.len(),
+        args.output.display(),
+        format_number(records.len()),
+        format_number(sources_merged)
+    );
+    Ok(())
+}
+
+// Metadata that changes what a stored hash means; records built with
+// different settings cannot share one database.
+pub(super) struct Settings {
+    salt: Option<(Vec<u8>, SaltPosition)>,
+    rounds: Option<u32>,
+    prefix_bits: Option<usize>,
+    encoding: HashEncoding,
+}
+
+impl Settings {
+    pub(super) fn read(storage: &ParquetStorage) -> Result<Self> {
+        Ok(Self {
+            salt: storage.get_salt()?,
+            rounds: storage.get_rounds()?,
+            prefix_bits: storage.get_prefix_bits()?,
+            encoding: storage.get_hash_encoding()?,
+        })
+    }
+
+    // The hash encoding only affects storage, so it may differ; the output
+    // uses the first database's.
+    fn ensure_matches(&self, other: &Self, first: &Path, database: &Path) -> Result<()> {
+        let mismatch = if self.salt != other.salt {
+            "salt"
+        } else if self.rounds.unwrap_or(1) != other.rounds.unwrap_or(1) {
+            "--rounds"
+        } else if self.prefix_bits != other.prefix_bits {
+            "--prefix-only-bits"
+        } else {
+            return Ok(());
+        };
+        bail!(
+            "{} and {} were built with a different {}; they cannot be merged",
+            first.display(),
+            database.display(),
+            mismatch
+        )
+    }
+
+    pub(super) fn apply(&self, mut storage: ParquetStorage) -> ParquetStorage {
+        storage = storage.with_hash_encoding(self.encoding);
+        if let Some((salt, position)) = &self.salt {
+            storage = storage.with_salt(salt, *position);
+        }
+        if let Some(rounds) = self.rounds.filter(|&rounds| rounds > 1) {
+            storage = storage.with_rounds(rounds);
+        }
+        if let Some(bits) = self.prefix_bits {
+            storage = storage.with_prefix_bits(bits);
+        }
+        storage
+    }
+}
+
+// Provenance from the earlier database wins; a later one only fills in a
+// missing first-seen time.
+fn merge_source_meta(into: &mut BTreeMap<String, SourceMeta>, other: BTreeMap<String, SourceMeta>) {
+    for (name, meta) in other {
+        match into.entry(name) {
+            Entry::Vacant(slot) => {
+                slot.insert(meta);
+            }
+            Entry::Occupied(mut slot) => {
+                if slot.get().first_seen.is_none() {
+                    slot.get_mut().first_seen = meta.first_seen;
+                }
+            }
+        }
+    }
+}
@@ -1,6 +1,12 @@
 pub mod build;
+pub mod check;
+pub mod export;
+pub mod hash;
+pub mod import;
 pub mod info;
+pub mod merge;
 pub mod query;
+pub mod repl;
 pub mod source;
 
 use clap::{Parser, Subcommand};
@@ -21,11 +27,23 @@ pub struct Cli {
 #[derive(Subcommand)]
 pub enum Commands {
     /// Build hash database from input file
-    Build(build::BuildArgs),
+    Build(Box<build::BuildArgs>),
     /// Query hash database for preimage
     Query(query::QueryArgs),
     /// Show database statistics
     Info(info::InfoArgs),
+    /// Report which candidate plaintexts are present in a database
+    Check(check::CheckArgs),
+    /// Load records from CSV into a parquet database
+    Import(import::ImportArgs),
+    /// Interactive session over one database: lookups, word checks, grep
+    Repl(repl::ReplArgs),
+    /// Combine parquet databases into one, merging sources of shared hashes
+    Merge(merge::MergeArgs),
+    /// Write every record of a parquet database to CSV
+    Export(export::ExportArgs),
+    /// Print the hashes of words without a database
+    Hash(hash::HashArgs),
     /// Manage source providers (seclists, aspell)
     Source(source::SourceArgs),
 }
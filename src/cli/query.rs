@@ -1,21 +1,56 @@
-use std::path::PathBuf;
-
-use anyhow::{bail, Result};
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::fmt::Write;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write as _};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use anyhow::{anyhow, bail, Context, Result};
 use clap::{Args, ValueEnum};
 use comfy_table::{presets::UTF8_FULL, Table};
+use rayon::prelude::*;
 
 use crate::config::{Config, R2Overrides};
 use crate::hasher;
-use crate::storage::{HashRecord, ParquetStorage, R2Config, R2Storage, Storage};
+use crate::output::format_number;
+use crate::storage::{
+    CachedStorage, FallbackStorage, HashRecord, ParquetStorage, QueryTimings, R2Config,
+    R2Storage, SharedTimings, Storage,
+};
 
 #[derive(Args)]
 pub struct QueryArgs {
-    /// Hash to search for (hex string, can be prefix)
-    pub hash: String,
+    /// Hash(es) to search for (hex string, can be prefix)
+    #[arg(required_unless_present = "mask")]
+    pub hash: Vec<String>,
+
+    /// Hex prefix with `?` for unknown nibbles (expands to 16^n prefixes)
+    #[arg(long, conflicts_with = "hash")]
+    pub mask: Option<String>,
+
+    /// Maximum number of prefixes a --mask may expand to
+    #[arg(long, default_value = "256")]
+    pub max_expansion: usize,
+
+    /// Database file (repeat to query several databases or shards)
+    #[arg(short, long, default_value = "hashes.parquet", conflicts_with = "db_dir")]
+    pub database: Vec<PathBuf>,
+
+    /// Query every .parquet file in this directory
+    #[arg(long)]
+    pub db_dir: Option<PathBuf>,
+
+    /// Maximum number of database files open at the same time
+    #[arg(long, default_value = "64")]
+    pub max_open_files: usize,
 
-    /// Database file
-    #[arg(short, long, default_value = "hashes.parquet")]
-    pub database: PathBuf,
+    /// Fail if a matched record uses an algorithm this version does not know
+    #[arg(long)]
+    pub strict_algo: bool,
+
+    /// Cache up to N recent lookups, invalidated when the database changes (0 = off)
+    #[arg(long, default_value = "0")]
+    pub query_cache_size: usize,
 
     /// Filter by algorithm
     #[arg(short, long, value_parser = hasher::algo_value_parser())]
@@ -25,6 +60,14 @@ pub struct QueryArgs {
     #[arg(short, long, default_value = "plain")]
     pub format: OutputFormat,
 
+    /// Print hashes as uppercase hex (JSON, JSONL and --only hash; plain and table show no hash)
+    #[arg(long)]
+    pub uppercase: bool,
+
+    /// Print only this field, one line per match (overrides --format)
+    #[arg(long, value_enum, conflicts_with = "split_output")]
+    pub only: Option<OnlyField>,
+
     /// Query from R2/S3 storage instead of local file
     #[arg(long)]
     pub r2: bool,
@@ -53,58 +96,473 @@ pub struct QueryArgs {
     #[arg(long, env = "SHAHA_R2_REGION", default_value = "auto")]
     pub region: String,
 
+    /// Connect to the S3 endpoint over plain HTTP (e.g. local MinIO)
+    #[arg(long)]
+    pub s3_no_ssl: bool,
+
+    /// Query R2/S3 for hashes the local database does not contain
+    #[arg(long, conflicts_with_all = ["r2", "db_dir"])]
+    pub fallback_r2: bool,
+
+    /// Write one file per algorithm (e.g. sha256.txt) into this directory instead of stdout
+    #[arg(long, value_name = "DIR")]
+    pub split_output: Option<PathBuf>,
+
+    /// Write results to this file instead of stdout
+    #[arg(long, value_name = "PATH", conflicts_with = "split_output")]
+    pub output_file: Option<PathBuf>,
+
     /// Maximum number of results to return
     #[arg(short, long)]
     pub limit: Option<usize>,
+
+    /// Skip this many matching results first; with --limit, pages through large result sets
+    #[arg(long, default_value_t = 0)]
+    pub offset: usize,
+
+    /// Print only the number of matching records for each query; on a single
+    /// database only the hash and algorithm columns are read
+    #[arg(
+        long,
+        visible_alias = "count-only",
+        conflicts_with_all = ["only", "split_output", "output_file", "offset", "limit", "strict_algo"]
+    )]
+    pub count: bool,
+
+    /// Print time spent in metadata, bloom, row group selection and scan to stderr
+    #[arg(long, conflicts_with = "r2")]
+    pub timing: bool,
 }
 
 #[derive(Clone, ValueEnum)]
 pub enum OutputFormat {
     Plain,
     Json,
+    Jsonl,
     Table,
 }
 
+#[derive(Clone, ValueEnum)]
+pub enum OnlyField {
+    Preimage,
+    Hash,
+}
+
+type Prefixes = Vec<(Vec<u8>, usize)>;
+
 pub fn run(args: QueryArgs) -> Result<()> {
-    let hash_bytes = hex::decode(&args.hash)
-        .map_err(|_| anyhow::anyhow!("Invalid hex string: {}", args.hash))?;
+    let queries: Vec<(String, Prefixes)> = match &args.mask {
+        Some(mask) => {
+            let prefixes = expand_mask(mask, args.max_expansion)?
+                .into_iter()
+                .map(|prefix| {
+                    let bits = prefix.len() * 8;
+                    (prefix, bits)
+                })
+                .collect();
+            vec![(mask.clone(), prefixes)]
+        }
+        None if args.hash.is_empty() => bail!("Either HASH or --mask required"),
+        None => args
+            .hash
+            .iter()
+            .map(|hash| Ok((hash.clone(), vec![parse_hex_prefix(hash)?])))
+            .collect::<Result<_>>()?,
+    };
 
-    let results = if args.r2 {
-        let r2_config = build_r2_config(&args)?;
-        let storage = R2Storage::new(r2_config)?;
-        storage.query(&hash_bytes, args.algo.as_deref(), args.limit)?
-    } else {
-        let storage = ParquetStorage::new(&args.database);
-        storage.query(&hash_bytes, args.algo.as_deref(), args.limit)?
+    if args.max_open_files == 0 {
+        bail!("--max-open-files must be at least 1");
+    }
+
+    let databases = if args.r2 { Vec::new() } else { database_paths(&args)? };
+    if args.fallback_r2 && databases.len() != 1 {
+        bail!("--fallback-r2 needs exactly one local --database");
+    }
+    let timings = args.timing.then(SharedTimings::default);
+    let storage = open_storage(&args, &databases, timings.as_ref())?;
+    let (storage, fallback) = match storage {
+        Some(local) if args.fallback_r2 => {
+            let r2_config = build_r2_config(&args)?;
+            let fallback = FallbackStorage::new(local, move || {
+                Ok(Box::new(R2Storage::new(r2_config.clone())?) as Box<dyn Storage>)
+            });
+            (None, Some(fallback))
+        }
+        storage => (storage, None),
+    };
+    let storage: Option<&dyn Storage> = match &fallback {
+        Some(fallback) => Some(fallback),
+        None => storage.as_deref(),
     };
 
-    if results.is_empty() {
+    if args.count {
+        let mut out = String::new();
+        for (_, prefixes) in &queries {
+            let count = match storage {
+                Some(storage) => count_prefixes(storage, prefixes, args.algo.as_deref())?,
+                // The same hash can sit in several databases, so merge before counting
+                None => query_databases(
+                    &databases,
+                    prefixes,
+                    args.algo.as_deref(),
+                    0,
+                    None,
+                    args.max_open_files,
+                    timings.as_ref(),
+                )?
+                .len(),
+            };
+            writeln!(out, "{}", count)?;
+        }
+        report_timings(timings.as_ref())?;
+        print!("{}", out);
+        return Ok(());
+    }
+
+    let mut groups: Vec<(&str, Vec<HashRecord>)> = Vec::new();
+    for (input, prefixes) in &queries {
+        let results = match storage {
+            Some(storage) => {
+                query_prefixes(storage, prefixes, args.algo.as_deref(), args.offset, args.limit)?
+            }
+            None => query_databases(
+                &databases,
+                prefixes,
+                args.algo.as_deref(),
+                args.offset,
+                args.limit,
+                args.max_open_files,
+                timings.as_ref(),
+            )?,
+        };
+
+        if args.strict_algo {
+            hasher::ensure_known_algorithms(results.iter().map(|r| r.algorithm.as_str()))?;
+        }
+        groups.push((input.as_str(), results));
+    }
+
+    report_timings(timings.as_ref())?;
+
+    let count: usize = groups.iter().map(|(_, results)| results.len()).sum();
+    if count == 0 && args.offset > 0 {
+        bail!("No matches found at offset {}", args.offset);
+    }
+    if count == 0 {
         bail!("No matches found");
     }
 
-    match args.format {
-        OutputFormat::Plain => print_plain(&results),
-        OutputFormat::Json => print_json(&results)?,
-        OutputFormat::Table => print_table(&results),
+    let write_output = |out: &mut dyn io::Write| match &args.only {
+        Some(field) => write_only(out, field, &groups, args.uppercase),
+        None => write_rendered(out, &args.format, &groups, args.uppercase),
+    };
+    match (&args.split_output, &args.output_file) {
+        (Some(dir), _) => write_split(dir, &args.format, &groups, args.uppercase)?,
+        (None, Some(path)) => write_file(path, write_output)?,
+        (None, None) => write_output(&mut io::stdout().lock())?,
     }
 
-    let count = results.len();
     let prefix = match args.format {
-        OutputFormat::Json => "",
+        _ if args.only.is_some() => "",
+        OutputFormat::Json | OutputFormat::Jsonl => "",
+        _ if args.split_output.is_some() || args.output_file.is_some() => "",
         _ => "\n",
     };
+    let found = groups.iter().filter(|(_, results)| !results.is_empty()).count();
     crate::status!(
-        "{}Found {} {}",
+        "{}Found {} {}{}",
         prefix,
-        count,
-        if count == 1 { "result" } else { "results" }
+        format_number(count),
+        if count == 1 { "result" } else { "results" },
+        if groups.len() > 1 {
+            format!(
+                " for {} of {} hashes",
+                format_number(found),
+                format_number(groups.len())
+            )
+        } else {
+            String::new()
+        }
+    );
+    if let Some(fallback) = fallback.filter(|f| f.fallback_hits() > 0) {
+        crate::status!(
+            "{} of {} lookups answered from R2 fallback",
+            format_number(fallback.fallback_hits()),
+            format_number(groups.len())
+        );
+    }
+
+    Ok(())
+}
+
+// Plain, JSONL and --only output is written record by record; pretty JSON
+// and tables are laid out as a whole first.
+fn write_rendered(
+    out: &mut dyn io::Write,
+    format: &OutputFormat,
+    groups: &[(&str, Vec<HashRecord>)],
+    uppercase: bool,
+) -> Result<()> {
+    let rendered = match (format, groups) {
+        (OutputFormat::Plain, [(_, results)]) => return write_plain(out, results),
+        (OutputFormat::Plain, _) => return write_plain_grouped(out, groups),
+        (OutputFormat::Jsonl, _) => return write_jsonl(out, groups, uppercase),
+        (OutputFormat::Json, [(_, results)]) => render_json(results, uppercase)?,
+        (OutputFormat::Table, [(_, results)]) => render_table(results)?,
+        (OutputFormat::Json, _) => render_json_grouped(groups, uppercase)?,
+        (OutputFormat::Table, _) => render_table_grouped(groups)?,
+    };
+    out.write_all(rendered.as_bytes())?;
+    Ok(())
+}
+
+fn write_file(path: &Path, write: impl FnOnce(&mut dyn io::Write) -> Result<()>) -> Result<()> {
+    let file = File::create(path).with_context(|| format!("Failed to create {}", path.display()))?;
+    let mut out = BufWriter::new(file);
+    write(&mut out)
+        .and_then(|()| out.flush().map_err(Into::into))
+        .with_context(|| format!("Failed to write {}", path.display()))
+}
+
+// One file per algorithm, each keeping the same per-query layout as stdout.
+fn write_split(
+    dir: &Path,
+    format: &OutputFormat,
+    groups: &[(&str, Vec<HashRecord>)],
+    uppercase: bool,
+) -> Result<()> {
+    let algorithms: BTreeSet<&str> = groups
+        .iter()
+        .flat_map(|(_, results)| results.iter().map(|r| r.algorithm.as_str()))
+        .collect();
+
+    fs::create_dir_all(dir).with_context(|| format!("Failed to create {}", dir.display()))?;
+
+    let extension = match format {
+        OutputFormat::Json => "json",
+        OutputFormat::Jsonl => "jsonl",
+        OutputFormat::Plain | OutputFormat::Table => "txt",
+    };
+
+    for algorithm in algorithms {
+        let bucket: Vec<(&str, Vec<HashRecord>)> = groups
+            .iter()
+            .map(|(query, results)| {
+                let matching = results
+                    .iter()
+                    .filter(|r| r.algorithm == algorithm)
+                    .cloned()
+                    .collect();
+                (*query, matching)
+            })
+            .collect();
+        let count: usize = bucket.iter().map(|(_, results)| results.len()).sum();
+
+        let path = dir.join(format!("{}.{}", algorithm, extension));
+        write_file(&path, |out| write_rendered(out, format, &bucket, uppercase))?;
+        crate::status!(
+            "Wrote {} {} results to {}",
+            format_number(count),
+            algorithm,
+            path.display()
+        );
+    }
+
+    Ok(())
+}
+
+fn print_timings(timings: &QueryTimings) {
+    let ms = |d: Duration| format!("{:.3} ms", d.as_secs_f64() * 1000.0);
+    eprintln!(
+        "Timing ({} {}):",
+        format_number(timings.lookups),
+        if timings.lookups == 1 { "lookup" } else { "lookups" }
     );
+    eprintln!("  metadata    {}", ms(timings.metadata));
+    eprintln!("  bloom       {}", ms(timings.bloom));
+    eprintln!("  row groups  {}", ms(timings.row_groups));
+    eprintln!("  scan        {}", ms(timings.scan));
+    eprintln!("  total       {}", ms(timings.total()));
+}
 
+fn report_timings(timings: Option<&SharedTimings>) -> Result<()> {
+    if let Some(timings) = timings {
+        let timings = *timings
+            .lock()
+            .map_err(|_| anyhow!("Query timings lock poisoned"))?;
+        print_timings(&timings);
+    }
     Ok(())
 }
 
+fn parquet_storage(database: &Path, timings: Option<&SharedTimings>) -> ParquetStorage {
+    let storage = ParquetStorage::new(database);
+    match timings {
+        Some(timings) => storage.with_timings(timings.clone()),
+        None => storage,
+    }
+}
+
+fn open_storage(
+    args: &QueryArgs,
+    databases: &[PathBuf],
+    timings: Option<&SharedTimings>,
+) -> Result<Option<Box<dyn Storage>>> {
+    let cache_size = args.query_cache_size;
+
+    if args.r2 {
+        let storage = R2Storage::new(build_r2_config(args)?)?;
+        return Ok(Some(if cache_size > 0 {
+            Box::new(CachedStorage::new(storage, cache_size))
+        } else {
+            Box::new(storage)
+        }));
+    }
+
+    if let [database] = databases {
+        let storage = parquet_storage(database, timings);
+        return Ok(Some(if cache_size > 0 {
+            Box::new(CachedStorage::new(storage, cache_size).with_watch_path(database))
+        } else {
+            Box::new(storage)
+        }));
+    }
+
+    Ok(None)
+}
+
+fn database_paths(args: &QueryArgs) -> Result<Vec<PathBuf>> {
+    let Some(dir) = &args.db_dir else {
+        return Ok(args.database.clone());
+    };
+
+    let mut paths = Vec::new();
+    for entry in std::fs::read_dir(dir)
+        .with_context(|| format!("Failed to read directory {}", dir.display()))?
+    {
+        let path = entry?.path();
+        if path.is_file() && path.extension().is_some_and(|e| e == "parquet") {
+            paths.push(path);
+        }
+    }
+    if paths.is_empty() {
+        bail!("No .parquet files found in {}", dir.display());
+    }
+    paths.sort();
+    Ok(paths)
+}
+
+fn query_databases(
+    databases: &[PathBuf],
+    prefixes: &[(Vec<u8>, usize)],
+    algo: Option<&str>,
+    offset: usize,
+    limit: Option<usize>,
+    max_open_files: usize,
+    timings: Option<&SharedTimings>,
+) -> Result<Vec<HashRecord>> {
+    let mut results: Vec<HashRecord> = Vec::new();
+    let mut index: HashMap<(Vec<u8>, String), usize> = HashMap::new();
+
+    // Records for one hash can be spread over several databases, so the
+    // offset only goes to the storage when there is a single database.
+    let (db_offset, db_limit) = match databases {
+        [_] => (offset, limit),
+        _ => (0, limit.map(|l| l + offset)),
+    };
+
+    // Each wave opens at most `max_open_files` databases; their readers are
+    // dropped before the next wave starts.
+    for wave in databases.chunks(max_open_files) {
+        let per_db: Vec<Result<Vec<HashRecord>>> = wave
+            .par_iter()
+            .map(|db| {
+                query_prefixes(&parquet_storage(db, timings), prefixes, algo, db_offset, db_limit)
+                    .with_context(|| format!("Failed to query {}", db.display()))
+            })
+            .collect();
+
+        for records in per_db {
+            for record in records? {
+                let key = (record.hash.clone(), record.algorithm.clone());
+                match index.get(&key) {
+                    Some(&i) => {
+                        let sources = &mut results[i].sources;
+                        for source in record.sources {
+                            if !sources.contains(&source) {
+                                sources.push(source);
+                            }
+                        }
+                    }
+                    None => {
+                        index.insert(key, results.len());
+                        results.push(record);
+                    }
+                }
+            }
+        }
+
+        if db_limit.is_some_and(|l| results.len() >= l) {
+            break;
+        }
+    }
+
+    results.drain(..(offset - db_offset).min(results.len()));
+    if let Some(l) = limit {
+        results.truncate(l);
+    }
+    Ok(results)
+}
+
+fn query_prefixes(
+    storage: &dyn Storage,
+    prefixes: &[(Vec<u8>, usize)],
+    algo: Option<&str>,
+    offset: usize,
+    limit: Option<usize>,
+) -> Result<Vec<HashRecord>> {
+    let mut results = Vec::new();
+    let mut seen = HashSet::new();
+
+    // Mask expansions are paged as one result set, so the storage only
+    // skips rows itself when there is a single prefix.
+    let storage_offset = if prefixes.len() == 1 { offset } else { 0 };
+    let mut to_skip = offset - storage_offset;
+
+    for (prefix, bits) in prefixes {
+        let remaining = limit.map(|l| l - results.len() + to_skip);
+        for record in storage.query_bits(prefix, *bits, algo, storage_offset, remaining)? {
+            if !seen.insert((record.hash.clone(), record.algorithm.clone())) {
+                continue;
+            }
+            if to_skip > 0 {
+                to_skip -= 1;
+            } else {
+                results.push(record);
+            }
+        }
+        if limit.is_some_and(|l| results.len() >= l) {
+            break;
+        }
+    }
+
+    Ok(results)
+}
+
+fn count_prefixes(
+    storage: &dyn Storage,
+    prefixes: &[(Vec<u8>, usize)],
+    algo: Option<&str>,
+) -> Result<usize> {
+    let mut total = 0;
+    for (prefix, bits) in prefixes {
+        total += storage.count_bits(prefix, *bits, algo)?;
+    }
+    Ok(total)
+}
+
 fn build_r2_config(args: &QueryArgs) -> Result<R2Config> {
-    let default_path = args.database.file_name()
+    let default_path = args.database[0].file_name()
         .map(|n| n.to_string_lossy().to_string())
         .unwrap_or_else(|| "hashes.parquet".to_string());
 
@@ -116,11 +574,72 @@ fn build_r2_config(args: &QueryArgs) -> Result<R2Config> {
         path: args.r2_path.as_deref(),
         region: &args.region,
         default_path: &default_path,
+        no_ssl: args.s3_no_ssl,
     };
 
     Config::load().unwrap_or_default().build_r2_config(overrides)
 }
 
+// An odd number of hex digits leaves a dangling nibble, matched as a
+// 4-bit prefix of the next byte.
+pub fn parse_hex_prefix(hash: &str) -> Result<(Vec<u8>, usize)> {
+    let bits = hash.len() * 4;
+    let padded = if hash.len().is_multiple_of(2) {
+        hash.to_string()
+    } else {
+        format!("{}0", hash)
+    };
+    let bytes = hex::decode(&padded).map_err(|_| anyhow::anyhow!("Invalid hex string: {}", hash))?;
+    Ok((bytes, bits))
+}
+
+pub fn expand_mask(mask: &str, max_expansion: usize) -> Result<Vec<Vec<u8>>> {
+    let mask = mask.trim_end_matches('?');
+
+    if !mask.len().is_multiple_of(2) {
+        bail!("Mask must cover whole bytes (even number of hex digits): {}", mask);
+    }
+    if let Some(c) = mask.chars().find(|c| *c != '?' && !c.is_ascii_hexdigit()) {
+        bail!("Invalid character in mask: '{}'", c);
+    }
+
+    let wildcards = mask.chars().filter(|c| *c == '?').count() as u32;
+    let expansion = 16usize.checked_pow(wildcards).unwrap_or(usize::MAX);
+    if expansion > max_expansion {
+        bail!(
+            "Mask expands to {} prefixes (limit {}). Use fewer '?' or raise --max-expansion",
+            expansion, max_expansion
+        );
+    }
+
+    let mut candidates = vec![String::new()];
+    for c in mask.chars() {
+        if c == '?' {
+            candidates = candidates
+                .iter()
+                .flat_map(|p| "0123456789abcdef".chars().map(move |n| format!("{}{}", p, n)))
+                .collect();
+        } else {
+            for p in &mut candidates {
+                p.push(c);
+            }
+        }
+    }
+
+    candidates
+        .iter()
+        .map(|p| hex::decode(p).map_err(|_| anyhow::anyhow!("Invalid hex string: {}", p)))
+        .collect()
+}
+
+fn format_preimage(record: &HashRecord) -> &str {
+    if record.preimage.is_empty() {
+        "(hash only)"
+    } else {
+        &record.preimage
+    }
+}
+
 fn format_sources(sources: &[String]) -> String {
     if sources.is_empty() {
         "-".to_string()
@@ -129,51 +648,171 @@ fn format_sources(sources: &[String]) -> String {
     }
 }
 
-fn print_plain(results: &[HashRecord]) {
+pub(super) fn render_plain(results: &[HashRecord]) -> Result<String> {
+    let mut out = Vec::new();
+    write_plain(&mut out, results)?;
+    Ok(String::from_utf8(out)?)
+}
+
+fn write_plain(out: &mut dyn io::Write, results: &[HashRecord]) -> Result<()> {
     for r in results {
-        println!(
+        writeln!(
+            out,
             "{} ({}, {})",
-            r.preimage, r.algorithm, format_sources(&r.sources)
-        );
+            format_preimage(r), r.algorithm, format_sources(&r.sources)
+        )?;
     }
+    Ok(())
 }
 
-fn print_json(results: &[HashRecord]) -> Result<()> {
-    #[derive(serde::Serialize)]
-    struct JsonRecord {
-        hash: String,
-        preimage: String,
-        algorithm: String,
-        sources: Vec<String>,
+// Bare values for piping into other tools; hash-only records have no
+// preimage to print and are skipped in preimage mode.
+fn write_only(
+    out: &mut dyn io::Write,
+    field: &OnlyField,
+    groups: &[(&str, Vec<HashRecord>)],
+    uppercase: bool,
+) -> Result<()> {
+    for r in groups.iter().flat_map(|(_, results)| results) {
+        match field {
+            OnlyField::Preimage if r.preimage.is_empty() => {}
+            OnlyField::Preimage => writeln!(out, "{}", r.preimage)?,
+            OnlyField::Hash => writeln!(out, "{}", encode_hash(&r.hash, uppercase))?,
+        }
     }
+    Ok(())
+}
 
+#[derive(serde::Serialize)]
+struct JsonRecord<'a> {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    query: Option<&'a str>,
+    hash: String,
+    preimage: &'a str,
+    algorithm: &'a str,
+    sources: &'a [String],
+}
+
+fn encode_hash(hash: &[u8], uppercase: bool) -> String {
+    if uppercase {
+        hex::encode_upper(hash)
+    } else {
+        hex::encode(hash)
+    }
+}
+
+impl<'a> JsonRecord<'a> {
+    fn new(query: Option<&'a str>, record: &'a HashRecord, uppercase: bool) -> Self {
+        Self {
+            query,
+            hash: encode_hash(&record.hash, uppercase),
+            preimage: &record.preimage,
+            algorithm: &record.algorithm,
+            sources: &record.sources,
+        }
+    }
+}
+
+fn render_json(results: &[HashRecord], uppercase: bool) -> Result<String> {
     let json_results: Vec<JsonRecord> = results
         .iter()
-        .map(|r| JsonRecord {
-            hash: hex::encode(&r.hash),
-            preimage: r.preimage.clone(),
-            algorithm: r.algorithm.clone(),
-            sources: r.sources.clone(),
+        .map(|r| JsonRecord::new(None, r, uppercase))
+        .collect();
+
+    Ok(format!("{}\n", serde_json::to_string_pretty(&json_results)?))
+}
+
+fn render_json_grouped(groups: &[(&str, Vec<HashRecord>)], uppercase: bool) -> Result<String> {
+    #[derive(serde::Serialize)]
+    struct JsonGroup<'a> {
+        query: &'a str,
+        results: Vec<JsonRecord<'a>>,
+    }
+
+    let json_groups: Vec<JsonGroup> = groups
+        .iter()
+        .map(|(query, results)| JsonGroup {
+            query,
+            results: results.iter().map(|r| JsonRecord::new(None, r, uppercase)).collect(),
         })
         .collect();
 
-    let json = serde_json::to_string_pretty(&json_results)?;
-    println!("{}", json);
+    Ok(format!("{}\n", serde_json::to_string_pretty(&json_groups)?))
+}
+
+fn write_jsonl(
+    out: &mut dyn io::Write,
+    groups: &[(&str, Vec<HashRecord>)],
+    uppercase: bool,
+) -> Result<()> {
+    for (query, results) in groups {
+        for r in results {
+            serde_json::to_writer(&mut *out, &JsonRecord::new(Some(query), r, uppercase))?;
+            writeln!(out)?;
+        }
+    }
+    Ok(())
+}
+
+fn write_plain_grouped(out: &mut dyn io::Write, groups: &[(&str, Vec<HashRecord>)]) -> Result<()> {
+    for (i, (query, results)) in groups.iter().enumerate() {
+        if i > 0 {
+            writeln!(out)?;
+        }
+        writeln!(out, "{}:", query)?;
+        if results.is_empty() {
+            writeln!(out, "  (no matches)")?;
+        }
+        for r in results {
+            writeln!(
+                out,
+                "  {} ({}, {})",
+                format_preimage(r), r.algorithm, format_sources(&r.sources)
+            )?;
+        }
+    }
     Ok(())
 }
 
-fn print_table(results: &[HashRecord]) {
+fn render_table_grouped(groups: &[(&str, Vec<HashRecord>)]) -> Result<String> {
+    let mut table = Table::new();
+    table.load_preset(UTF8_FULL);
+    table.set_header(vec!["Query", "Preimage", "Algorithm", "Sources"]);
+
+    for (query, results) in groups {
+        if results.is_empty() {
+            table.add_row(vec![
+                query.to_string(),
+                "(no matches)".to_string(),
+                "-".to_string(),
+                "-".to_string(),
+            ]);
+        }
+        for r in results {
+            table.add_row(vec![
+                query.to_string(),
+                format_preimage(r).to_string(),
+                r.algorithm.clone(),
+                format_sources(&r.sources),
+            ]);
+        }
+    }
+
+    Ok(format!("{table}\n"))
+}
+
+fn render_table(results: &[HashRecord]) -> Result<String> {
     let mut table = Table::new();
     table.load_preset(UTF8_FULL);
     table.set_header(vec!["Preimage", "Algorithm", "Sources"]);
 
     for r in results {
         table.add_row(vec![
-            r.preimage.clone(),
+            format_preimage(r).to_string(),
             r.algorithm.clone(),
             format_sources(&r.sources),
         ]);
     }
 
-    println!("{table}");
+    Ok(format!("{table}\n"))
 }
@@ -0,0 +1,149 @@
+use std::io::{self, BufRead, IsTerminal, Write};
+use std::path::PathBuf;
+
+use anyhow::{bail, Result};
+use clap::Args;
+
+use super::check::as_built;
+use super::info::print_parquet_info;
+use super::query::{parse_hex_prefix, render_plain};
+use crate::hasher::{self, Hasher};
+use crate::output::format_number;
+use crate::storage::{HashRecord, ParquetStorage, Storage};
+
+const HELP: &str = "\
+q <hex>        look up a hash or hex prefix
+p <word>       hash a word with every algorithm in the database and check it is stored
+grep <text>    list records whose preimage contains text
+info           database summary
+quit           exit (or Ctrl-D)";
+
+#[derive(Args)]
+pub struct ReplArgs {
+    /// Database file, kept open for the whole session
+    #[arg(default_value = "hashes.parquet")]
+    pub database: PathBuf,
+}
+
+pub fn run(args: ReplArgs) -> Result<()> {
+    if !args.database.exists() {
+        bail!("Database not found: {}", args.database.display());
+    }
+
+    let storage = ParquetStorage::new(&args.database).with_cached_bloom();
+    let algorithms = storage.stats()?.algorithms;
+    let hashers = algorithms
+        .iter()
+        .filter_map(|name| {
+            // Iterated hashes are stored as e.g. "sha256x5000"; HMAC ones
+            // cannot be recomputed without the key
+            let base = name.rsplit_once('x').map_or(name.as_str(), |(base, _)| base);
+            let hasher = hasher::get_hasher(name).or_else(|| hasher::get_hasher(base))?;
+            Some(as_built(&storage, hasher))
+        })
+        .collect::<Result<Vec<_>>>()?;
+    let session = Session {
+        storage,
+        location: args.database.display().to_string(),
+        hashers,
+    };
+
+    let interactive = io::stdin().is_terminal();
+    let mut lines = io::stdin().lock().lines();
+    loop {
+        if interactive {
+            eprint!("shaha> ");
+            io::stderr().flush()?;
+        }
+        let Some(line) = lines.next() else {
+            if interactive {
+                eprintln!();
+            }
+            return Ok(());
+        };
+        let line = line?;
+        let (command, rest) = line.trim().split_once(' ').unwrap_or((line.trim(), ""));
+        let rest = rest.trim();
+        let result = match command {
+            "" => Ok(()),
+            "quit" | "exit" => return Ok(()),
+            "help" => {
+                println!("{}", HELP);
+                Ok(())
+            }
+            "q" | "query" => session.query(rest),
+            "p" | "verify" => session.verify(rest),
+            "grep" => session.grep(rest),
+            "info" => print_parquet_info(&session.storage, &session.location),
+            other => Err(anyhow::anyhow!("Unknown command: {} (try help)", other)),
+        };
+        if let Err(err) = result {
+            eprintln!("error: {:#}", err);
+        }
+    }
+}
+
+struct Session {
+    storage: ParquetStorage,
+    location: String,
+    hashers: Vec<Box<dyn Hasher>>,
+}
+
+impl Session {
+    fn query(&self, hash: &str) -> Result<()> {
+        if hash.is_empty() {
+            bail!("usage: q <hex>");
+        }
+        let (prefix, bits) = parse_hex_prefix(hash)?;
+        let results = self.storage.query_bits(&prefix, bits, None, 0, None)?;
+        print_results(&results)
+    }
+
+    fn verify(&self, word: &str) -> Result<()> {
+        if word.is_empty() {
+            bail!("usage: p <word>");
+        }
+        if self.hashers.is_empty() {
+            bail!("No algorithm in this database can be recomputed here");
+        }
+        for hasher in &self.hashers {
+            let hash = hasher.hash(word.as_bytes());
+            let found = self
+                .storage
+                .query(&hash, Some(hasher.name()), 0, None)?
+                .iter()
+                .any(|record| record.preimage == word || record.preimage.is_empty());
+            let status = if found { "found" } else { "missing" };
+            println!("{:<8}{}  {}", status, hasher.name(), hex::encode(&hash));
+        }
+        Ok(())
+    }
+
+    fn grep(&self, needle: &str) -> Result<()> {
+        if needle.is_empty() {
+            bail!("usage: grep <text>");
+        }
+        let mut results = Vec::new();
+        self.storage.for_each_record(|record| {
+            if record.preimage.contains(needle) {
+                results.push(record);
+            }
+            Ok(())
+        })?;
+        print_results(&results)
+    }
+}
+
+fn print_results(results: &[HashRecord]) -> Result<()> {
+    if results.is_empty() {
+        println!("No matches found");
+    } else {
+        print!("{}", render_plain(results)?);
+        println!(
+            "{} {}",
+            format_number(results.len()),
+            if results.len() == 1 { "result" } else { "results" }
+        );
+    }
+    Ok(())
+}
@@ -1,7 +1,8 @@
 use anyhow::{bail, Result};
 use clap::{Args, Subcommand};
 
-use crate::source::{aspell, seclists};
+use crate::output::format_number;
+use crate::source::{aspell, seclists, AspellSource};
 use crate::status;
 
 #[derive(Args)]
@@ -16,6 +17,9 @@ pub enum SourceCommands {
     Pull {
         /// Provider name (seclists)
         provider: String,
+        /// Pin to a specific commit or tag (seclists only)
+        #[arg(long)]
+        rev: Option<String>,
     },
     /// List available files from a provider
     List {
@@ -23,6 +27,15 @@ pub enum SourceCommands {
         provider: String,
         /// Optional subpath to filter
         path: Option<String>,
+        /// Dump each dictionary and show its word count (aspell only, slow)
+        #[arg(short, long)]
+        verbose: bool,
+        /// List files of every extension, not just .txt (seclists only)
+        #[arg(long)]
+        all_extensions: bool,
+        /// File extension to list, repeatable; "" for files without one (seclists only, default: txt)
+        #[arg(long = "extension", value_name = "EXT", conflicts_with = "all_extensions")]
+        extensions: Vec<String>,
     },
     /// Show cache path for a provider
     Path {
@@ -33,15 +46,22 @@ pub enum SourceCommands {
 
 pub fn run(args: SourceArgs) -> Result<()> {
     match args.command {
-        SourceCommands::Pull { provider } => pull(&provider),
-        SourceCommands::List { provider, path } => list(&provider, path.as_deref()),
+        SourceCommands::Pull { provider, rev } => pull(&provider, rev.as_deref()),
+        SourceCommands::List {
+            provider,
+            path,
+            verbose,
+            all_extensions,
+            extensions,
+        } => list(&provider, path.as_deref(), verbose, all_extensions, &extensions),
         SourceCommands::Path { provider } => path(&provider),
     }
 }
 
-fn pull(provider: &str) -> Result<()> {
+fn pull(provider: &str, rev: Option<&str>) -> Result<()> {
     match provider {
-        "seclists" => seclists::pull(),
+        "seclists" => seclists::pull(rev),
+        "aspell" if rev.is_some() => bail!("--rev is only supported for seclists"),
         "aspell" => {
             if aspell::is_available() {
                 status!("aspell is installed and ready.");
@@ -57,19 +77,41 @@ fn pull(provider: &str) -> Result<()> {
     }
 }
 
-fn list(provider: &str, subpath: Option<&str>) -> Result<()> {
+fn list(
+    provider: &str,
+    subpath: Option<&str>,
+    verbose: bool,
+    all_extensions: bool,
+    extensions: &[String],
+) -> Result<()> {
     match provider {
+        "seclists" if verbose => bail!("--verbose is only supported for aspell"),
         "seclists" => {
-            let files = seclists::list(subpath)?;
+            let txt = ["txt".to_string()];
+            let allowed = match extensions {
+                _ if all_extensions => None,
+                [] => Some(&txt[..]),
+                extensions => Some(extensions),
+            };
+            let files = seclists::list(subpath, allowed)?;
             for file in files {
                 println!("{}", file);
             }
             Ok(())
         }
+        "aspell" if all_extensions || !extensions.is_empty() => {
+            bail!("--all-extensions and --extension are only supported for seclists")
+        }
         "aspell" => {
             let langs = aspell::list_languages()?;
             for lang in langs {
-                println!("{}", lang);
+                if verbose {
+                    let words = AspellSource::new(&lang)?.word_count();
+                    let noun = if words == 1 { "word" } else { "words" };
+                    println!("{}\t{} {}", lang, format_number(words), noun);
+                } else {
+                    println!("{}", lang);
+                }
             }
             Ok(())
         }
@@ -84,6 +126,9 @@ fn path(provider: &str) -> Result<()> {
     match provider {
         "seclists" => {
             println!("{}", seclists::path().display());
+            if let Some(commit) = seclists::current_commit() {
+                status!("Commit: {}", commit);
+            }
             Ok(())
         }
         "aspell" => {
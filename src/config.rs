@@ -44,6 +44,7 @@ pub struct R2Overrides<'a> {
     pub path: Option<&'a str>,
     pub region: &'a str,
     pub default_path: &'a str,
+    pub no_ssl: bool,
 }
 
 impl<'a> R2Overrides<'a> {
@@ -137,7 +138,8 @@ impl Config {
             r2.region.clone().unwrap_or_else(|| "auto".to_string())
         };
 
-        let mut config = R2Config::new(endpoint, access_key_id, secret_access_key, bucket, path);
+        let mut config = R2Config::new(endpoint, access_key_id, secret_access_key, bucket, path)
+            .with_ssl(!overrides.no_ssl);
         config.region = region;
 
         Ok(config)
@@ -0,0 +1,445 @@
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashSet};
+use std::fs::{self, File};
+use std::hash::{DefaultHasher, Hash, Hasher};
+use std::io::{BufRead, BufReader, BufWriter, Lines, Write};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::{Context, Result};
+
+use crate::build::{sort_records, sort_records_by_algorithm, SortMode};
+use crate::storage::HashRecord;
+
+// Deduplicates lines with bounded memory: at most `threshold` words are
+// held in memory, then written out as a sorted run. Runs are k-way merged at
+// the end. Words must not contain '\n'.
+pub struct ExternalDedup {
+    threshold: usize,
+    temp_dir: PathBuf,
+    buffer: HashSet<String>,
+    dir: Option<PathBuf>,
+    runs: Vec<PathBuf>,
+}
+
+impl ExternalDedup {
+    pub fn new(threshold: usize) -> Self {
+        Self {
+            threshold: threshold.max(1),
+            temp_dir: std::env::temp_dir(),
+            buffer: HashSet::new(),
+            dir: None,
+            runs: Vec::new(),
+        }
+    }
+
+    pub fn with_temp_dir(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.temp_dir = dir.into();
+        self
+    }
+
+    pub fn insert(&mut self, word: String) -> Result<()> {
+        self.buffer.insert(word);
+        if self.buffer.len() >= self.threshold {
+            self.spill()?;
+        }
+        Ok(())
+    }
+
+    pub fn spilled_runs(&self) -> usize {
+        self.runs.len()
+    }
+
+    pub fn count_unique(self) -> Result<usize> {
+        if self.runs.is_empty() {
+            return Ok(self.buffer.len());
+        }
+        self.into_unique()?.try_fold(0, |unique, word| word.map(|_| unique + 1))
+    }
+
+    // Every distinct word once, in sorted order, reading the runs back one
+    // line at a time
+    pub fn into_unique(mut self) -> Result<Box<dyn Iterator<Item = Result<String>>>> {
+        if self.runs.is_empty() {
+            let mut words: Vec<String> = self.buffer.drain().collect();
+            words.sort_unstable();
+            return Ok(Box::new(words.into_iter().map(Ok)));
+        }
+        self.spill()?;
+
+        let mut readers = self
+            .runs
+            .iter()
+            .map(|path| {
+                File::open(path)
+                    .map(|f| BufReader::new(f).lines())
+                    .with_context(|| format!("Failed to open dedup run {}", path.display()))
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        let mut heap = BinaryHeap::new();
+        for (i, reader) in readers.iter_mut().enumerate() {
+            if let Some(line) = reader.next() {
+                heap.push(Reverse((line?, i)));
+            }
+        }
+
+        Ok(Box::new(MergedRuns {
+            _dedup: self,
+            readers,
+            heap,
+            last: None,
+        }))
+    }
+
+    fn spill(&mut self) -> Result<()> {
+        if self.buffer.is_empty() {
+            return Ok(());
+        }
+
+        let dir = scratch_dir(&mut self.dir, &self.temp_dir, "dedup")?;
+
+        let mut words: Vec<String> = self.buffer.drain().collect();
+        words.sort_unstable();
+
+        let path = dir.join(format!("run-{:05}", self.runs.len()));
+        let mut writer = BufWriter::new(
+            File::create(&path).with_context(|| format!("Failed to create {}", path.display()))?,
+        );
+        for word in &words {
+            writer.write_all(word.as_bytes())?;
+            writer.write_all(b"\n")?;
+        }
+        writer.flush()?;
+
+        self.runs.push(path);
+        Ok(())
+    }
+}
+
+// K-way merge of the sorted runs; holds the dedup so its runs are removed
+// only once the merge is dropped
+struct MergedRuns {
+    _dedup: ExternalDedup,
+    readers: Vec<Lines<BufReader<File>>>,
+    heap: BinaryHeap<Reverse<(String, usize)>>,
+    last: Option<String>,
+}
+
+impl Iterator for MergedRuns {
+    type Item = Result<String>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while let Some(Reverse((word, i))) = self.heap.pop() {
+            match self.readers[i].next() {
+                Some(Ok(line)) => self.heap.push(Reverse((line, i))),
+                Some(Err(err)) => return Some(Err(err.into())),
+                None => {}
+            }
+            if self.last.as_ref() != Some(&word) {
+                self.last = Some(word.clone());
+                return Some(Ok(word));
+            }
+        }
+        None
+    }
+}
+
+// The run directory, created under `temp_dir` on first use
+fn scratch_dir(dir: &mut Option<PathBuf>, temp_dir: &Path, kind: &str) -> Result<PathBuf> {
+    if let Some(dir) = dir {
+        return Ok(dir.clone());
+    }
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_nanos())
+        .unwrap_or_default();
+    let created = temp_dir.join(format!("shaha-{}-{}-{}", kind, std::process::id(), nanos));
+    fs::create_dir_all(&created)
+        .with_context(|| format!("Failed to create {}", created.display()))?;
+    *dir = Some(created.clone());
+    Ok(created)
+}
+
+// Creates `dir` if needed and probes it with a throwaway file, so a bad
+// --temp-dir fails before any work instead of at the first spill.
+pub fn ensure_writable_dir(dir: &Path) -> Result<()> {
+    fs::create_dir_all(dir)
+        .with_context(|| format!("Temp directory {} cannot be created", dir.display()))?;
+    let probe = dir.join(format!(".shaha-probe-{}", std::process::id()));
+    File::create(&probe)
+        .with_context(|| format!("Temp directory {} is not writable", dir.display()))?;
+    let _ = fs::remove_file(&probe);
+    Ok(())
+}
+
+impl Drop for ExternalDedup {
+    fn drop(&mut self) {
+        if let Some(dir) = &self.dir {
+            let _ = fs::remove_dir_all(dir);
+        }
+    }
+}
+
+// Sorts hash records with bounded memory: past `buffer_size` records the
+// buffer is sorted and written out as a run of JSON lines, and the runs are
+// k-way merged when read back. Without a buffer size it is a plain in-memory
+// sort.
+pub struct ExternalSort {
+    buffer_size: Option<usize>,
+    mode: SortMode,
+    by_algorithm: bool,
+    temp_dir: PathBuf,
+    buffer: Vec<HashRecord>,
+    len: usize,
+    dir: Option<PathBuf>,
+    runs: Vec<PathBuf>,
+}
+
+// (hash, algorithm), or (algorithm, hash) when sorting by algorithm
+type SortKey = (Vec<u8>, Vec<u8>);
+
+pub enum SortedRecords {
+    Memory(Vec<HashRecord>),
+    Merged(Box<dyn Iterator<Item = Result<HashRecord>>>),
+}
+
+impl SortedRecords {
+    // None once the records were spilled to disk
+    pub fn as_slice(&self) -> Option<&[HashRecord]> {
+        match self {
+            SortedRecords::Memory(records) => Some(records),
+            SortedRecords::Merged(_) => None,
+        }
+    }
+}
+
+impl IntoIterator for SortedRecords {
+    type Item = Result<HashRecord>;
+    type IntoIter = Box<dyn Iterator<Item = Result<HashRecord>>>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        match self {
+            SortedRecords::Memory(records) => Box::new(records.into_iter().map(Ok)),
+            SortedRecords::Merged(records) => records,
+        }
+    }
+}
+
+impl ExternalSort {
+    pub fn new(buffer_size: Option<usize>, mode: SortMode) -> Self {
+        Self {
+            buffer_size: buffer_size.map(|size| size.max(1)),
+            mode,
+            by_algorithm: false,
+            temp_dir: std::env::temp_dir(),
+            buffer: Vec::new(),
+            len: 0,
+            dir: None,
+            runs: Vec::new(),
+        }
+    }
+
+    // Order by algorithm first, as --partition-by-algo writes
+    pub fn by_algorithm(mut self) -> Self {
+        self.by_algorithm = true;
+        self
+    }
+
+    pub fn with_temp_dir(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.temp_dir = dir.into();
+        self
+    }
+
+    pub fn push(&mut self, record: HashRecord) -> Result<()> {
+        self.buffer.push(record);
+        self.len += 1;
+        if self.buffer_size.is_some_and(|size| self.buffer.len() >= size) {
+            self.spill()?;
+        }
+        Ok(())
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn spilled_runs(&self) -> usize {
+        self.runs.len()
+    }
+
+    pub fn into_sorted(mut self) -> Result<SortedRecords> {
+        if self.runs.is_empty() {
+            let mut records = std::mem::take(&mut self.buffer);
+            self.sort(&mut records);
+            return Ok(SortedRecords::Memory(records));
+        }
+        self.spill()?;
+
+        let mut readers = self
+            .runs
+            .iter()
+            .map(|path| {
+                File::open(path)
+                    .map(|f| BufReader::new(f).lines())
+                    .with_context(|| format!("Failed to open sort run {}", path.display()))
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        let mut heap = BinaryHeap::new();
+        let mut heads = Vec::with_capacity(readers.len());
+        for (i, reader) in readers.iter_mut().enumerate() {
+            let head = match reader.next() {
+                Some(line) => Some(serde_json::from_str::<HashRecord>(&line?)?),
+                None => None,
+            };
+            if let Some(record) = &head {
+                heap.push(Reverse((self.key(record), i)));
+            }
+            heads.push(head);
+        }
+
+        Ok(SortedRecords::Merged(Box::new(MergedRecordRuns {
+            sort: self,
+            readers,
+            heads,
+            heap,
+        })))
+    }
+
+    fn sort(&self, records: &mut [HashRecord]) {
+        if self.by_algorithm {
+            sort_records_by_algorithm(records, self.mode);
+        } else {
+            sort_records(records, self.mode);
+        }
+    }
+
+    fn key(&self, record: &HashRecord) -> SortKey {
+        let algorithm = record.algorithm.as_bytes().to_vec();
+        if self.by_algorithm {
+            (algorithm, record.hash.clone())
+        } else {
+            (record.hash.clone(), algorithm)
+        }
+    }
+
+    fn spill(&mut self) -> Result<()> {
+        if self.buffer.is_empty() {
+            return Ok(());
+        }
+
+        let dir = scratch_dir(&mut self.dir, &self.temp_dir, "sort")?;
+
+        let mut records = std::mem::take(&mut self.buffer);
+        self.sort(&mut records);
+
+        let path = dir.join(format!("run-{:05}", self.runs.len()));
+        let mut writer = BufWriter::new(
+            File::create(&path).with_context(|| format!("Failed to create {}", path.display()))?,
+        );
+        for record in &records {
+            serde_json::to_writer(&mut writer, record)?;
+            writer.write_all(b"\n")?;
+        }
+        writer.flush()?;
+
+        self.runs.push(path);
+        Ok(())
+    }
+}
+
+impl Drop for ExternalSort {
+    fn drop(&mut self) {
+        if let Some(dir) = &self.dir {
+            let _ = fs::remove_dir_all(dir);
+        }
+    }
+}
+
+// K-way merge of the sorted record runs; (hash, algorithm) is unique, so
+// no two runs hold the same key
+struct MergedRecordRuns {
+    sort: ExternalSort,
+    readers: Vec<Lines<BufReader<File>>>,
+    // The next record of each run, keyed in the heap by its run index
+    heads: Vec<Option<HashRecord>>,
+    heap: BinaryHeap<Reverse<(SortKey, usize)>>,
+}
+
+impl Iterator for MergedRecordRuns {
+    type Item = Result<HashRecord>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let Reverse((_, i)) = self.heap.pop()?;
+        let record = self.heads[i].take()?;
+        match self.readers[i].next() {
+            Some(Ok(line)) => match serde_json::from_str::<HashRecord>(&line) {
+                Ok(next) => {
+                    self.heap.push(Reverse((self.sort.key(&next), i)));
+                    self.heads[i] = Some(next);
+                }
+                Err(err) => return Some(Err(err.into())),
+            },
+            Some(Err(err)) => return Some(Err(err.into())),
+            None => {}
+        }
+        Some(Ok(record))
+    }
+}
+
+// Approximate distinct counting in fixed memory (2^precision one-byte
+// registers). The standard error is about 1.04 / sqrt(2^precision).
+pub struct HyperLogLog {
+    precision: u32,
+    registers: Vec<u8>,
+}
+
+impl HyperLogLog {
+    pub fn new(precision: u32) -> Self {
+        let precision = precision.clamp(4, 18);
+        Self {
+            precision,
+            registers: vec![0; 1 << precision],
+        }
+    }
+
+    pub fn insert(&mut self, item: &impl Hash) {
+        let mut hasher = DefaultHasher::new();
+        item.hash(&mut hasher);
+        let hash = hasher.finish();
+
+        let index = (hash >> (64 - self.precision)) as usize;
+        // The guard bit caps the rank when the remaining bits are all zero
+        let rest = (hash << self.precision) | (1 << (self.precision - 1));
+        let rank = rest.leading_zeros() as u8 + 1;
+        self.registers[index] = self.registers[index].max(rank);
+    }
+
+    pub fn standard_error(&self) -> f64 {
+        1.04 / (self.registers.len() as f64).sqrt()
+    }
+
+    pub fn estimate(&self) -> usize {
+        let m = self.registers.len() as f64;
+        let alpha = 0.7213 / (1.0 + 1.079 / m);
+        let sum: f64 = self
+            .registers
+            .iter()
+            .map(|&r| 2f64.powi(-i32::from(r)))
+            .sum();
+        let raw = alpha * m * m / sum;
+
+        let zeros = self.registers.iter().filter(|&&r| r == 0).count();
+        // Linear counting is more accurate while many registers are empty
+        if raw <= 2.5 * m && zeros > 0 {
+            (m * (m / zeros as f64).ln()).round() as usize
+        } else {
+            raw.round() as usize
+        }
+    }
+}
@@ -1,12 +1,24 @@
+use std::collections::HashSet;
+use std::sync::{Mutex, OnceLock};
+
+use blake2::{Blake2b512, Blake2s256};
+use clap::builder::{PossibleValue, PossibleValuesParser, TypedValueParser};
+use hmac::digest::core_api::BlockSizeUser;
+use hmac::{Mac, SimpleHmac};
+use md4::Md4;
 use md5::Md5;
 use ripemd::Ripemd160;
 use sha1::Sha1;
 use sha2::{Digest, Sha256, Sha512};
-use sha3::Keccak256;
+use sha3::{Keccak256, Sha3_256, Sha3_512};
 
 pub trait Hasher: Send + Sync {
     fn name(&self) -> &'static str;
     fn hash(&self, input: &[u8]) -> Vec<u8>;
+
+    fn output_len(&self) -> usize {
+        self.hash(b"").len()
+    }
 }
 
 /// Standard hashers using the Digest trait
@@ -31,7 +43,20 @@ impl_digest_hasher!(Sha1Hasher, Sha1, "sha1");
 impl_digest_hasher!(Sha256Hasher, Sha256, "sha256");
 impl_digest_hasher!(Sha512Hasher, Sha512, "sha512");
 impl_digest_hasher!(Keccak256Hasher, Keccak256, "keccak256");
+impl_digest_hasher!(Sha3_256Hasher, Sha3_256, "sha3-256");
+impl_digest_hasher!(Sha3_512Hasher, Sha3_512, "sha3-512");
 impl_digest_hasher!(Ripemd160Hasher, Ripemd160, "ripemd160");
+impl_digest_hasher!(Blake2b512Hasher, Blake2b512, "blake2b");
+impl_digest_hasher!(Blake2s256Hasher, Blake2s256, "blake2s");
+
+// Alternate spellings accepted on the command line, with the canonical name
+// that gets stored
+const ALIASES: &[(&str, &str)] = &[
+    ("blake2b512", "blake2b"),
+    ("blake2s256", "blake2s"),
+    ("sha3_256", "sha3-256"),
+    ("sha3_512", "sha3-512"),
+];
 
 // BLAKE3 - different API (not Digest trait)
 pub struct Blake3Hasher;
@@ -74,6 +99,214 @@ impl Hasher for Hash256Hasher {
     }
 }
 
+// NTLM = MD4(UTF-16LE(x)) - Windows SAM/NTDS password hashes. Input is
+// decoded as UTF-8 first, so "é" becomes the single code unit 0x00E9.
+pub struct NtlmHasher;
+
+impl Hasher for NtlmHasher {
+    fn name(&self) -> &'static str {
+        "ntlm"
+    }
+
+    fn hash(&self, input: &[u8]) -> Vec<u8> {
+        let utf16le: Vec<u8> = String::from_utf8_lossy(input)
+            .encode_utf16()
+            .flat_map(u16::to_le_bytes)
+            .collect();
+        Md4::digest(utf16le).to_vec()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum SaltPosition {
+    Prefix,
+    Suffix,
+}
+
+impl SaltPosition {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            SaltPosition::Prefix => "prefix",
+            SaltPosition::Suffix => "suffix",
+        }
+    }
+
+    pub fn parse(s: &str) -> Option<Self> {
+        match s {
+            "prefix" => Some(SaltPosition::Prefix),
+            "suffix" => Some(SaltPosition::Suffix),
+            _ => None,
+        }
+    }
+}
+
+// hash(salt || input) or hash(input || salt) with any inner algorithm; the
+// stored algorithm name stays the inner one.
+pub struct SaltedHasher {
+    inner: Box<dyn Hasher>,
+    salt: Vec<u8>,
+    position: SaltPosition,
+}
+
+impl SaltedHasher {
+    pub fn new(inner: Box<dyn Hasher>, salt: Vec<u8>, position: SaltPosition) -> Self {
+        Self {
+            inner,
+            salt,
+            position,
+        }
+    }
+}
+
+impl Hasher for SaltedHasher {
+    fn name(&self) -> &'static str {
+        self.inner.name()
+    }
+
+    fn hash(&self, input: &[u8]) -> Vec<u8> {
+        self.inner.hash(&salted(&self.salt, self.position, input))
+    }
+
+    fn output_len(&self) -> usize {
+        self.inner.output_len()
+    }
+}
+
+fn salted(salt: &[u8], position: SaltPosition, input: &[u8]) -> Vec<u8> {
+    match position {
+        SaltPosition::Prefix => [salt, input].concat(),
+        SaltPosition::Suffix => [input, salt].concat(),
+    }
+}
+
+// hash^N(input): the digest is fed back into the same hasher `rounds` times.
+// A salt is applied to the input of the first round only, hash^N(salt ||
+// input). Stored as e.g. "sha256x5000".
+pub struct IteratedHasher {
+    inner: Box<dyn Hasher>,
+    rounds: u32,
+    salt: Option<(Vec<u8>, SaltPosition)>,
+    name: &'static str,
+}
+
+impl IteratedHasher {
+    pub fn new(inner: Box<dyn Hasher>, rounds: u32) -> Self {
+        let name = intern_name(format!("{}x{}", inner.name(), rounds));
+        Self {
+            inner,
+            rounds,
+            salt: None,
+            name,
+        }
+    }
+
+    pub fn with_salt(mut self, salt: Vec<u8>, position: SaltPosition) -> Self {
+        self.salt = Some((salt, position));
+        self
+    }
+}
+
+// The hasher a database built with this salt and --rounds stores
+pub fn salted_and_iterated(
+    hasher: Box<dyn Hasher>,
+    salt: Option<(Vec<u8>, SaltPosition)>,
+    rounds: u32,
+) -> Box<dyn Hasher> {
+    match (salt, rounds) {
+        (None, 0 | 1) => hasher,
+        (Some((salt, position)), 0 | 1) => Box::new(SaltedHasher::new(hasher, salt, position)),
+        (None, rounds) => Box::new(IteratedHasher::new(hasher, rounds)),
+        (Some((salt, position)), rounds) => {
+            Box::new(IteratedHasher::new(hasher, rounds).with_salt(salt, position))
+        }
+    }
+}
+
+// Hasher names are 'static; each distinct generated name is leaked once and
+// reused by every later hasher that needs it
+fn intern_name(name: String) -> &'static str {
+    static NAMES: OnceLock<Mutex<HashSet<&'static str>>> = OnceLock::new();
+    let mut names = NAMES
+        .get_or_init(Mutex::default)
+        .lock()
+        .unwrap_or_else(|e| e.into_inner());
+    if let Some(interned) = names.get(name.as_str()) {
+        return interned;
+    }
+    let interned: &'static str = Box::leak(name.into_boxed_str());
+    names.insert(interned);
+    interned
+}
+
+impl Hasher for IteratedHasher {
+    fn name(&self) -> &'static str {
+        self.name
+    }
+
+    fn hash(&self, input: &[u8]) -> Vec<u8> {
+        let mut digest = match &self.salt {
+            Some((salt, position)) => self.inner.hash(&salted(salt, *position, input)),
+            None => self.inner.hash(input),
+        };
+        for _ in 1..self.rounds {
+            digest = self.inner.hash(&digest);
+        }
+        digest
+    }
+
+    fn output_len(&self) -> usize {
+        self.inner.output_len()
+    }
+}
+
+type MacFn = fn(&[u8], &[u8]) -> Vec<u8>;
+
+fn hmac<D: Digest + BlockSizeUser>(key: &[u8], input: &[u8]) -> Vec<u8> {
+    let mut mac =
+        <SimpleHmac<D> as Mac>::new_from_slice(key).expect("HMAC accepts keys of any length");
+    Mac::update(&mut mac, input);
+    mac.finalize().into_bytes().to_vec()
+}
+
+// HMAC(key, input) over a Digest algorithm, stored as e.g. "hmac-sha256"
+pub struct HmacHasher {
+    name: &'static str,
+    key: Vec<u8>,
+    mac: MacFn,
+}
+
+impl Hasher for HmacHasher {
+    fn name(&self) -> &'static str {
+        self.name
+    }
+
+    fn hash(&self, input: &[u8]) -> Vec<u8> {
+        (self.mac)(&self.key, input)
+    }
+}
+
+// None for algorithms that are not a plain Digest (blake3, hash160, ntlm, ...)
+pub fn get_hmac_hasher(algo: &str, key: &[u8]) -> Option<Box<dyn Hasher>> {
+    let (name, mac): (&'static str, MacFn) = match algo.to_lowercase().as_str() {
+        "md5" => ("hmac-md5", hmac::<Md5>),
+        "sha1" => ("hmac-sha1", hmac::<Sha1>),
+        "sha256" => ("hmac-sha256", hmac::<Sha256>),
+        "sha512" => ("hmac-sha512", hmac::<Sha512>),
+        "keccak256" => ("hmac-keccak256", hmac::<Keccak256>),
+        "sha3-256" | "sha3_256" => ("hmac-sha3-256", hmac::<Sha3_256>),
+        "sha3-512" | "sha3_512" => ("hmac-sha3-512", hmac::<Sha3_512>),
+        "ripemd160" => ("hmac-ripemd160", hmac::<Ripemd160>),
+        "blake2b" | "blake2b512" => ("hmac-blake2b", hmac::<Blake2b512>),
+        "blake2s" | "blake2s256" => ("hmac-blake2s", hmac::<Blake2s256>),
+        _ => return None,
+    };
+    Some(Box::new(HmacHasher {
+        name,
+        key: key.to_vec(),
+        mac,
+    }))
+}
+
 pub fn get_hasher(name: &str) -> Option<Box<dyn Hasher>> {
     match name.to_lowercase().as_str() {
         "md5" => Some(Box::new(Md5Hasher)),
@@ -83,8 +316,13 @@ pub fn get_hasher(name: &str) -> Option<Box<dyn Hasher>> {
         "hash160" => Some(Box::new(Hash160Hasher)),
         "hash256" => Some(Box::new(Hash256Hasher)),
         "keccak256" => Some(Box::new(Keccak256Hasher)),
+        "sha3-256" | "sha3_256" => Some(Box::new(Sha3_256Hasher)),
+        "sha3-512" | "sha3_512" => Some(Box::new(Sha3_512Hasher)),
         "blake3" => Some(Box::new(Blake3Hasher)),
         "ripemd160" => Some(Box::new(Ripemd160Hasher)),
+        "ntlm" => Some(Box::new(NtlmHasher)),
+        "blake2b" | "blake2b512" => Some(Box::new(Blake2b512Hasher)),
+        "blake2s" | "blake2s256" => Some(Box::new(Blake2s256Hasher)),
         _ => None,
     }
 }
@@ -98,11 +336,68 @@ pub fn available_algorithms() -> &'static [&'static str] {
         "hash160",
         "hash256",
         "keccak256",
+        "sha3-256",
+        "sha3-512",
         "blake3",
         "ripemd160",
+        "ntlm",
+        "blake2b",
+        "blake2s",
     ]
 }
 
-pub fn algo_value_parser() -> clap::builder::PossibleValuesParser {
-    clap::builder::PossibleValuesParser::new(available_algorithms())
+fn is_known_algorithm(name: &str) -> bool {
+    algorithm_output_len(name).is_some()
+}
+
+// Digest size of a stored algorithm name, or None if it is unknown
+pub fn algorithm_output_len(name: &str) -> Option<usize> {
+    // "sha256x5000" is sha256 iterated 5000 times
+    let name = match name.rsplit_once('x') {
+        Some((inner, rounds))
+            if !rounds.is_empty() && rounds.bytes().all(|b| b.is_ascii_digit()) =>
+        {
+            inner
+        }
+        _ => name,
+    };
+    let hasher = match name.strip_prefix("hmac-") {
+        Some(inner) => get_hmac_hasher(inner, b"")?,
+        None if available_algorithms().contains(&name) => get_hasher(name)?,
+        None => return None,
+    };
+    Some(hasher.output_len())
+}
+
+pub fn ensure_known_algorithms<'a>(names: impl IntoIterator<Item = &'a str>) -> anyhow::Result<()> {
+    let mut unknown: Vec<&str> = names
+        .into_iter()
+        .filter(|name| !is_known_algorithm(name))
+        .collect();
+    unknown.sort_unstable();
+    unknown.dedup();
+
+    if !unknown.is_empty() {
+        anyhow::bail!(
+            "Unknown algorithm(s) in database: {} (known: {})",
+            unknown.join(", "),
+            available_algorithms().join(", ")
+        );
+    }
+    Ok(())
+}
+
+pub fn algo_value_parser() -> impl TypedValueParser<Value = String> {
+    let values = available_algorithms().iter().map(|&name| {
+        ALIASES
+            .iter()
+            .filter(|(_, canonical)| *canonical == name)
+            .fold(PossibleValue::new(name), |value, (alias, _)| value.alias(alias))
+    });
+    PossibleValuesParser::new(values).map(|name| {
+        match ALIASES.iter().find(|(alias, _)| *alias == name) {
+            Some((_, canonical)) => canonical.to_string(),
+            None => name,
+        }
+    })
 }
@@ -1,11 +1,15 @@
+pub mod build;
 pub mod cli;
 pub mod config;
+pub mod dedup;
 pub mod hasher;
 pub mod output;
+pub mod rules;
 pub mod source;
 pub mod storage;
 
+pub use build::{BuildOptions, BuildProgress, BuildSummary};
 pub use config::Config;
 pub use hasher::Hasher;
 pub use source::Source;
-pub use storage::{HashRecord, Storage};
+pub use storage::{HashRecord, SourceMeta, Storage};
@@ -4,13 +4,21 @@ use clap::Parser;
 use shaha::cli::{Cli, Commands};
 
 fn main() -> Result<()> {
+    shaha::output::install_panic_hook();
+
     let cli = Cli::parse();
     shaha::output::set_quiet(cli.quiet);
 
     match cli.command {
-        Commands::Build(args) => shaha::cli::build::run(args),
+        Commands::Build(args) => shaha::cli::build::run(*args),
         Commands::Query(args) => shaha::cli::query::run(args),
         Commands::Info(args) => shaha::cli::info::run(args),
+        Commands::Check(args) => shaha::cli::check::run(args),
+        Commands::Import(args) => shaha::cli::import::run(args),
+        Commands::Repl(args) => shaha::cli::repl::run(args),
+        Commands::Merge(args) => shaha::cli::merge::run(args),
+        Commands::Export(args) => shaha::cli::export::run(args),
+        Commands::Hash(args) => shaha::cli::hash::run(args),
         Commands::Source(args) => shaha::cli::source::run(args),
     }
 }
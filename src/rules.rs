@@ -0,0 +1,245 @@
+use std::path::Path;
+
+use anyhow::{bail, Context, Result};
+
+// A subset of hashcat rule syntax. Each non-empty, non-comment line is one
+// rule; its operations apply left to right and produce one variant per word.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RuleOp {
+    Noop,
+    Lowercase,
+    Uppercase,
+    Capitalize,
+    ToggleCase,
+    Reverse,
+    Append(char),
+    Prepend(char),
+    // Only reachable through `build --rules`; hashcat has no single op for it
+    Leet,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Rules {
+    rules: Vec<Vec<RuleOp>>,
+}
+
+// Case passes for `build --case`; `all` keeps the word and adds its lower,
+// upper and capitalized forms. Identical variants are dropped by the
+// build's word dedup before hashing.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum CaseMode {
+    #[default]
+    AsIs,
+    Lower,
+    Upper,
+    Capitalize,
+    All,
+}
+
+impl CaseMode {
+    pub fn rules(self) -> Rules {
+        let rules = match self {
+            CaseMode::AsIs => vec![vec![RuleOp::Noop]],
+            CaseMode::Lower => vec![vec![RuleOp::Lowercase]],
+            CaseMode::Upper => vec![vec![RuleOp::Uppercase]],
+            CaseMode::Capitalize => vec![vec![RuleOp::Capitalize]],
+            CaseMode::All => vec![
+                vec![RuleOp::Noop],
+                vec![RuleOp::Lowercase],
+                vec![RuleOp::Uppercase],
+                vec![RuleOp::Capitalize],
+            ],
+        };
+        Rules { rules }
+    }
+}
+
+impl Rules {
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let content = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read rules file {}", path.display()))?;
+        Self::parse(&content).with_context(|| format!("Invalid rules file {}", path.display()))
+    }
+
+    pub fn parse(content: &str) -> Result<Self> {
+        let mut rules = Vec::new();
+        for (i, line) in content.lines().enumerate() {
+            let line = line.trim_end_matches('\r');
+            if line.trim().is_empty() || line.starts_with('#') {
+                continue;
+            }
+            rules.push(parse_rule(line).with_context(|| format!("line {}", i + 1))?);
+        }
+        Ok(Self { rules })
+    }
+
+    // `build --rules`: a comma list of named mangling rules. The word itself
+    // is always kept and each rule adds its own variants of it:
+    //   append-digits:A-B  the word followed by each number from A to B
+    //   leet               a->4 e->3 i->1 o->0 s->5 t->7
+    //   capitalize         first letter upper, the rest lower
+    pub fn from_spec(spec: &str) -> Result<Self> {
+        let mut rules = vec![vec![RuleOp::Noop]];
+        for item in spec.split(',').map(str::trim) {
+            let (name, arg) = match item.split_once(':') {
+                Some((name, arg)) => (name, Some(arg)),
+                None => (item, None),
+            };
+            match (name, arg) {
+                ("append-digits", Some(range)) => {
+                    let (start, end) = parse_range(range)
+                        .with_context(|| format!("invalid append-digits range '{}'", range))?;
+                    rules.extend((start..=end).map(|n| n.to_string().chars().map(RuleOp::Append).collect()));
+                }
+                ("append-digits", None) => bail!("append-digits needs a range, e.g. append-digits:0-99"),
+                ("leet", None) => rules.push(vec![RuleOp::Leet]),
+                ("capitalize", None) => rules.push(vec![RuleOp::Capitalize]),
+                ("leet" | "capitalize", Some(_)) => bail!("{} takes no argument", name),
+                ("", None) => bail!("empty rule in '{}'", spec),
+                _ => bail!(
+                    "unknown rule '{}' (supported: append-digits:A-B, leet, capitalize)",
+                    item
+                ),
+            }
+        }
+        Ok(Self { rules })
+    }
+
+    pub fn len(&self) -> usize {
+        self.rules.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    pub fn apply<'a>(&'a self, word: &'a str) -> impl Iterator<Item = String> + 'a {
+        self.rules.iter().map(move |ops| apply_rule(ops, word))
+    }
+
+    // Non-empty variants of one word, in rule order
+    pub fn variants(&self, word: &str) -> Vec<String> {
+        self.apply(word)
+            .filter(|variant| !variant.is_empty())
+            .collect()
+    }
+
+    // Every rule of `self` followed by every rule of `next`, so one pass
+    // yields what expanding with `self` and then `next` would.
+    pub fn then(self, next: &Rules) -> Rules {
+        let rules = self
+            .rules
+            .iter()
+            .flat_map(|first| {
+                next.rules
+                    .iter()
+                    .map(move |second| first.iter().chain(second).copied().collect())
+            })
+            .collect();
+        Rules { rules }
+    }
+
+    pub fn expand(
+        self,
+        words: Box<dyn Iterator<Item = String>>,
+    ) -> Box<dyn Iterator<Item = String>> {
+        Box::new(words.flat_map(move |word| self.variants(&word)))
+    }
+}
+
+fn parse_range(range: &str) -> Result<(u32, u32)> {
+    let Some((start, end)) = range.split_once('-') else {
+        bail!("expected A-B");
+    };
+    let (start, end): (u32, u32) = (start.trim().parse()?, end.trim().parse()?);
+    if start > end {
+        bail!("{} is greater than {}", start, end);
+    }
+    Ok((start, end))
+}
+
+fn parse_rule(line: &str) -> Result<Vec<RuleOp>> {
+    let mut ops = Vec::new();
+    let mut chars = line.chars();
+    while let Some(c) = chars.next() {
+        let op = match c {
+            ' ' => continue,
+            ':' => RuleOp::Noop,
+            'l' => RuleOp::Lowercase,
+            'u' => RuleOp::Uppercase,
+            'c' => RuleOp::Capitalize,
+            't' => RuleOp::ToggleCase,
+            'r' => RuleOp::Reverse,
+            '$' | '^' => {
+                let Some(arg) = chars.next() else {
+                    bail!("'{}' needs a character", c);
+                };
+                if c == '$' {
+                    RuleOp::Append(arg)
+                } else {
+                    RuleOp::Prepend(arg)
+                }
+            }
+            other => bail!(
+                "unsupported rule operation '{}' (supported: : l u c t r $X ^X)",
+                other
+            ),
+        };
+        ops.push(op);
+    }
+    Ok(ops)
+}
+
+fn apply_rule(ops: &[RuleOp], word: &str) -> String {
+    let mut word = word.to_string();
+    for op in ops {
+        word = match *op {
+            RuleOp::Noop => word,
+            RuleOp::Lowercase => word.to_lowercase(),
+            RuleOp::Uppercase => word.to_uppercase(),
+            RuleOp::Capitalize => {
+                let mut chars = word.chars();
+                match chars.next() {
+                    Some(first) => first
+                        .to_uppercase()
+                        .chain(chars.as_str().to_lowercase().chars())
+                        .collect(),
+                    None => word,
+                }
+            }
+            RuleOp::ToggleCase => word
+                .chars()
+                .map(|c| {
+                    if c.is_lowercase() {
+                        c.to_uppercase().collect::<String>()
+                    } else {
+                        c.to_lowercase().collect::<String>()
+                    }
+                })
+                .collect(),
+            RuleOp::Reverse => word.chars().rev().collect(),
+            RuleOp::Append(c) => {
+                word.push(c);
+                word
+            }
+            RuleOp::Prepend(c) => {
+                word.insert(0, c);
+                word
+            }
+            RuleOp::Leet => word
+                .chars()
+                .map(|c| match c.to_ascii_lowercase() {
+                    'a' => '4',
+                    'e' => '3',
+                    'i' => '1',
+                    'o' => '0',
+                    's' => '5',
+                    't' => '7',
+                    _ => c,
+                })
+                .collect(),
+        };
+    }
+    word
+}
@@ -46,6 +46,13 @@ impl AspellSource {
     fn get_dump(&self) -> &[u8] {
         self.cached_dump.get().expect("dump initialized in new()")
     }
+
+    pub fn word_count(&self) -> usize {
+        self.get_dump()
+            .split(|&b| b == b'\n')
+            .filter(|line| !line.is_empty())
+            .count()
+    }
 }
 
 impl Source for AspellSource {
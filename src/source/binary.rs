@@ -0,0 +1,96 @@
+use std::fs::File;
+use std::io::{BufReader, ErrorKind, Read};
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Context, Result};
+
+pub type BinaryRecords = Box<dyn Iterator<Item = Result<Vec<u8>>>>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum BinaryFormat {
+    // Every regular file in a directory is one record, in file name order
+    Files,
+    // A stream of records, each preceded by its length as a big-endian u32
+    LengthPrefixed,
+}
+
+pub fn read_records(path: &Path, format: BinaryFormat) -> Result<BinaryRecords> {
+    match format {
+        BinaryFormat::Files => read_files(path),
+        BinaryFormat::LengthPrefixed => {
+            let file =
+                File::open(path).with_context(|| format!("Failed to open file: {:?}", path))?;
+            Ok(Box::new(LengthPrefixed {
+                reader: BufReader::new(file),
+                index: 0,
+                done: false,
+            }))
+        }
+    }
+}
+
+fn read_files(dir: &Path) -> Result<BinaryRecords> {
+    if !dir.is_dir() {
+        bail!(
+            "--binary-records files needs a directory, got {}",
+            dir.display()
+        );
+    }
+    let mut paths: Vec<PathBuf> = std::fs::read_dir(dir)
+        .with_context(|| format!("Failed to read directory {}", dir.display()))?
+        .map(|entry| entry.map(|e| e.path()))
+        .collect::<std::io::Result<_>>()?;
+    paths.retain(|path| path.is_file());
+    paths.sort();
+
+    Ok(Box::new(paths.into_iter().map(|path| {
+        std::fs::read(&path).with_context(|| format!("Failed to read {}", path.display()))
+    })))
+}
+
+struct LengthPrefixed {
+    reader: BufReader<File>,
+    index: usize,
+    done: bool,
+}
+
+impl LengthPrefixed {
+    fn read_record(&mut self) -> Result<Option<Vec<u8>>> {
+        let mut prefix = [0u8; 4];
+        let mut filled = 0;
+        while filled < prefix.len() {
+            match self.reader.read(&mut prefix[filled..]) {
+                Ok(0) if filled == 0 => return Ok(None),
+                Ok(0) => bail!("Record {} has a truncated length prefix", self.index + 1),
+                Ok(n) => filled += n,
+                Err(e) if e.kind() == ErrorKind::Interrupted => {}
+                Err(e) => return Err(e.into()),
+            }
+        }
+
+        let len = u32::from_be_bytes(prefix) as usize;
+        let mut record = vec![0u8; len];
+        self.reader.read_exact(&mut record).with_context(|| {
+            format!(
+                "Record {} is truncated (expected {} bytes)",
+                self.index + 1,
+                len
+            )
+        })?;
+        self.index += 1;
+        Ok(Some(record))
+    }
+}
+
+impl Iterator for LengthPrefixed {
+    type Item = Result<Vec<u8>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        let record = self.read_record().transpose();
+        self.done = !matches!(record, Some(Ok(_)));
+        record
+    }
+}
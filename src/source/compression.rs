@@ -0,0 +1,158 @@
+use std::io::{BufRead, BufReader, Cursor, Read};
+use std::path::Path;
+
+use anyhow::{bail, Context, Result};
+
+const MAGIC_LEN: usize = 6;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Codec {
+    Gzip,
+    Zstd,
+    Xz,
+}
+
+impl Codec {
+    const ALL: [Codec; 3] = [Codec::Gzip, Codec::Zstd, Codec::Xz];
+
+    fn name(self) -> &'static str {
+        match self {
+            Codec::Gzip => "gzip",
+            Codec::Zstd => "zstd",
+            Codec::Xz => "xz",
+        }
+    }
+
+    fn magic(self) -> &'static [u8] {
+        match self {
+            Codec::Gzip => &[0x1f, 0x8b],
+            Codec::Zstd => &[0x28, 0xb5, 0x2f, 0xfd],
+            Codec::Xz => &[0xfd, b'7', b'z', b'X', b'Z', 0x00],
+        }
+    }
+
+    fn extensions(self) -> &'static [&'static str] {
+        match self {
+            Codec::Gzip => &["gz", "gzip"],
+            Codec::Zstd => &["zst", "zstd"],
+            Codec::Xz => &["xz"],
+        }
+    }
+
+    fn from_magic(magic: &[u8]) -> Option<Self> {
+        Self::ALL.into_iter().find(|codec| magic.starts_with(codec.magic()))
+    }
+
+    fn from_extension(hint: &str) -> Option<Self> {
+        let ext = Path::new(hint).extension()?.to_str()?.to_lowercase();
+        Self::ALL
+            .into_iter()
+            .find(|codec| codec.extensions().contains(&ext.as_str()))
+    }
+
+    fn decoder(self, stream: impl Read + 'static) -> Result<Box<dyn Read>> {
+        Ok(match self {
+            Codec::Gzip => Box::new(flate2::read::MultiGzDecoder::new(stream)),
+            Codec::Zstd => {
+                Box::new(zstd::Decoder::new(stream).context("Failed to start zstd decoder")?)
+            }
+            Codec::Xz => Box::new(liblzma::read::XzDecoder::new_multi_decoder(stream)),
+        })
+    }
+}
+
+// The magic bytes decide the codec; `hint` (a file name or URL) only catches
+// inputs whose extension promises a codec the data does not start with.
+// The magic is read up front and chained back in front of the stream, so
+// this works on pipes and other readers that cannot be rewound.
+pub fn wrap_decompressor(
+    mut reader: impl Read + 'static,
+    hint: Option<&str>,
+) -> Result<Box<dyn BufRead>> {
+    let mut magic = Vec::with_capacity(MAGIC_LEN);
+    reader
+        .by_ref()
+        .take(MAGIC_LEN as u64)
+        .read_to_end(&mut magic)
+        .context("Failed to read input")?;
+    let stream = Cursor::new(magic.clone()).chain(reader);
+
+    let detected = Codec::from_magic(&magic);
+    if let Some((hint, expected)) = hint.and_then(|h| Some((h, Codec::from_extension(h)?))) {
+        if detected != Some(expected) {
+            let codec = expected.name();
+            bail!("{} has a {} extension but is not {} data", hint, codec, codec);
+        }
+    }
+
+    Ok(match detected {
+        Some(codec) => Box::new(BufReader::new(codec.decoder(stream)?)),
+        None => Box::new(BufReader::new(stream)),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    const WORDS: &[u8] = b"hello\nworld\n";
+
+    fn compressed(codec: Codec) -> Vec<u8> {
+        match codec {
+            Codec::Gzip => {
+                let mut gzip =
+                    flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+                gzip.write_all(WORDS).unwrap();
+                gzip.finish().unwrap()
+            }
+            Codec::Zstd => zstd::encode_all(WORDS, 0).unwrap(),
+            Codec::Xz => {
+                let mut xz = Vec::new();
+                liblzma::read::XzEncoder::new(WORDS, 6).read_to_end(&mut xz).unwrap();
+                xz
+            }
+        }
+    }
+
+    fn read_all(reader: Box<dyn BufRead>) -> Vec<String> {
+        reader.lines().map(Result::unwrap).collect()
+    }
+
+    #[test]
+    fn test_detects_each_codec_by_magic() {
+        for codec in Codec::ALL {
+            let bytes = compressed(codec);
+            assert!(bytes.starts_with(codec.magic()), "{}", codec.name());
+            let reader = wrap_decompressor(Cursor::new(bytes), None).unwrap();
+            assert_eq!(read_all(reader), ["hello", "world"], "{}", codec.name());
+        }
+    }
+
+    #[test]
+    fn test_passes_plain_and_short_input_through() {
+        let reader = wrap_decompressor(Cursor::new(WORDS.to_vec()), Some("words.txt")).unwrap();
+        assert_eq!(read_all(reader), ["hello", "world"]);
+        let reader = wrap_decompressor(Cursor::new(b"a\n".to_vec()), None).unwrap();
+        assert_eq!(read_all(reader), ["a"]);
+        let reader = wrap_decompressor(Cursor::new(Vec::new()), None).unwrap();
+        assert!(read_all(reader).is_empty());
+    }
+
+    #[test]
+    fn test_extension_hint_must_match_magic() {
+        let reader =
+            wrap_decompressor(Cursor::new(compressed(Codec::Xz)), Some("words.XZ")).unwrap();
+        assert_eq!(read_all(reader), ["hello", "world"]);
+
+        let err = wrap_decompressor(Cursor::new(WORDS.to_vec()), Some("words.txt.gz"))
+            .err()
+            .unwrap();
+        assert_eq!(err.to_string(), "words.txt.gz has a gzip extension but is not gzip data");
+
+        let err = wrap_decompressor(Cursor::new(compressed(Codec::Gzip)), Some("words.zst"))
+            .err()
+            .unwrap();
+        assert!(err.to_string().contains("is not zstd data"));
+    }
+}
@@ -0,0 +1,108 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Context, Result};
+
+use super::{FileSource, InvalidLines, LineTally, OnInvalid, Source};
+use crate::storage::SourceMeta;
+
+// Every .txt file under a directory, read in sorted path order as one source
+pub struct DirSource {
+    path: PathBuf,
+    name: String,
+    // Shared by the file sources it reads
+    lines: LineTally,
+}
+
+impl DirSource {
+    pub fn new(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        if !path.is_dir() {
+            bail!("Directory not found: {:?}", path);
+        }
+        let name = path
+            .canonicalize()
+            .unwrap_or_else(|_| path.clone())
+            .file_name()
+            .and_then(|s| s.to_str())
+            .unwrap_or("dir")
+            .to_string();
+        Ok(Self {
+            path,
+            name,
+            lines: LineTally::default(),
+        })
+    }
+
+    pub fn with_on_invalid(mut self, mode: OnInvalid) -> Self {
+        self.lines = LineTally::new(mode);
+        self
+    }
+
+    fn files(&self) -> Result<Vec<PathBuf>> {
+        let mut files = Vec::new();
+        collect_txt_files(&self.path, &mut files)?;
+        files.sort();
+        Ok(files)
+    }
+}
+
+fn collect_txt_files(dir: &Path, files: &mut Vec<PathBuf>) -> Result<()> {
+    let entries =
+        std::fs::read_dir(dir).with_context(|| format!("Failed to read directory: {:?}", dir))?;
+    for entry in entries {
+        let path = entry?.path();
+        if path.file_name().is_some_and(|name| name.to_string_lossy().starts_with('.')) {
+            continue;
+        }
+        if path.is_dir() {
+            collect_txt_files(&path, files)?;
+        } else if path.extension().is_some_and(|ext| ext == "txt") {
+            files.push(path);
+        }
+    }
+    Ok(())
+}
+
+impl Source for DirSource {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn words(&self) -> Result<Box<dyn Iterator<Item = String>>> {
+        let files = self.files()?;
+        if files.is_empty() {
+            bail!("No .txt files found in {:?}", self.path);
+        }
+        // Files are opened one at a time as the previous one runs out
+        let lines = self.lines.clone();
+        Ok(Box::new(files.into_iter().flat_map(move |file| {
+            let source = FileSource::new(&file).with_line_tally(lines.clone());
+            source.words().unwrap_or_else(|err| {
+                eprintln!("warning: skipping {}: {:#}", file.display(), err);
+                Box::new(std::iter::empty())
+            })
+        })))
+    }
+
+    fn content_hash(&self) -> Result<Option<String>> {
+        let mut hasher = blake3::Hasher::new();
+        for file in self.files()? {
+            if let Some(hash) = FileSource::new(&file).content_hash()? {
+                hasher.update(hash.as_bytes());
+            }
+        }
+        Ok(Some(hasher.finalize().to_hex().to_string()))
+    }
+
+    fn invalid_lines(&self) -> InvalidLines {
+        self.lines.invalid_lines()
+    }
+
+    fn metadata(&self) -> SourceMeta {
+        let path = self.path.canonicalize().unwrap_or_else(|_| self.path.clone());
+        SourceMeta {
+            path: Some(path.display().to_string()),
+            ..Default::default()
+        }
+    }
+}
@@ -1,14 +1,16 @@
 use std::fs::File;
-use std::io::{BufRead, BufReader, Read};
+use std::io::Read;
 use std::path::{Path, PathBuf};
 
 use anyhow::{Context, Result};
 
-use super::Source;
+use super::{decode_lines, wrap_decompressor, InvalidLines, LineTally, OnInvalid, Source};
+use crate::storage::SourceMeta;
 
 pub struct FileSource {
     path: PathBuf,
     name: String,
+    lines: LineTally,
 }
 
 impl FileSource {
@@ -16,10 +18,22 @@ impl FileSource {
         let path = path.as_ref().to_path_buf();
         let name = path
             .file_stem()
-            .and_then(|s| s.to_str())
-            .unwrap_or("unknown")
-            .to_string();
-        Self { path, name }
+            .map(|s| s.to_string_lossy().to_string())
+            .unwrap_or_else(|| "unknown".to_string());
+        Self {
+            path,
+            name,
+            lines: LineTally::default(),
+        }
+    }
+
+    pub fn with_on_invalid(self, mode: OnInvalid) -> Self {
+        self.with_line_tally(LineTally::new(mode))
+    }
+
+    pub(crate) fn with_line_tally(mut self, lines: LineTally) -> Self {
+        self.lines = lines;
+        self
     }
 }
 
@@ -28,19 +42,33 @@ impl Source for FileSource {
         &self.name
     }
 
+    fn local_path(&self) -> Option<&Path> {
+        Some(&self.path)
+    }
+
     fn words(&self) -> Result<Box<dyn Iterator<Item = String>>> {
         let file = File::open(&self.path)
             .with_context(|| format!("Failed to open file: {:?}", self.path))?;
-        let reader = BufReader::new(file);
+        let reader = wrap_decompressor(file, self.path.to_str())?;
         Ok(Box::new(
-            reader
-                .lines()
-                .map_while(Result::ok)
+            decode_lines(reader, self.path.display().to_string(), &self.lines)
                 .filter(|line| !line.is_empty()),
         ))
     }
 
+    fn invalid_lines(&self) -> InvalidLines {
+        self.lines.invalid_lines()
+    }
+
     fn content_hash(&self) -> Result<Option<String>> {
+        // Pipes and other special files can only be read once; leave them
+        // for words() and skip the hash, like stdin.
+        let metadata = std::fs::metadata(&self.path)
+            .with_context(|| format!("Failed to open file: {:?}", self.path))?;
+        if !metadata.is_file() {
+            return Ok(None);
+        }
+
         let mut file = File::open(&self.path)
             .with_context(|| format!("Failed to open file: {:?}", self.path))?;
         let mut hasher = blake3::Hasher::new();
@@ -54,4 +82,12 @@ impl Source for FileSource {
         }
         Ok(Some(hasher.finalize().to_hex().to_string()))
     }
+
+    fn metadata(&self) -> SourceMeta {
+        let path = self.path.canonicalize().unwrap_or_else(|_| self.path.clone());
+        SourceMeta {
+            path: Some(path.display().to_string()),
+            ..Default::default()
+        }
+    }
 }
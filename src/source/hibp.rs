@@ -0,0 +1,111 @@
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::path::{Path, PathBuf};
+
+use anyhow::{anyhow, bail, Context, Result};
+
+use super::{FileSource, Prehashed, PrehashedEntry, Source};
+use crate::storage::SourceMeta;
+
+const HIBP_URL: &str = "https://haveibeenpwned.com/Passwords";
+const HIBP_LICENSE: &str = "CC-BY-4.0";
+const SHA1_HEX_LEN: usize = 40;
+const RANGE_PREFIX_LEN: usize = 5;
+
+pub struct HibpSource {
+    path: PathBuf,
+    name: String,
+    prefix: Option<String>,
+}
+
+impl HibpSource {
+    pub fn new(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        if !path.exists() {
+            bail!("HIBP file not found: {:?}", path);
+        }
+
+        let stem = path
+            .file_stem()
+            .and_then(|s| s.to_str())
+            .unwrap_or("hibp")
+            .to_string();
+        let prefix = (stem.len() == RANGE_PREFIX_LEN && stem.chars().all(|c| c.is_ascii_hexdigit()))
+            .then(|| stem.to_ascii_lowercase());
+        let name = match prefix {
+            Some(_) => format!("hibp-{}", stem.to_ascii_lowercase()),
+            None => stem,
+        };
+
+        Ok(Self { path, name, prefix })
+    }
+}
+
+impl Source for HibpSource {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn words(&self) -> Result<Box<dyn Iterator<Item = String>>> {
+        bail!("hibp: sources contain pre-hashed sha1 records, not words")
+    }
+
+    fn content_hash(&self) -> Result<Option<String>> {
+        FileSource::new(&self.path).content_hash()
+    }
+
+    fn metadata(&self) -> SourceMeta {
+        SourceMeta {
+            url: Some(HIBP_URL.to_string()),
+            license: Some(HIBP_LICENSE.to_string()),
+            ..FileSource::new(&self.path).metadata()
+        }
+    }
+
+    fn prehashed(&self) -> Result<Option<Prehashed>> {
+        let file = File::open(&self.path)
+            .with_context(|| format!("Failed to open file: {:?}", self.path))?;
+        let prefix = self.prefix.clone();
+
+        let entries = BufReader::new(file)
+            .lines()
+            .enumerate()
+            .filter(|(_, line)| line.as_ref().map_or(true, |l| !l.trim().is_empty()))
+            .map(move |(i, line)| {
+                let line = line?;
+                parse_line(line.trim(), prefix.as_deref())
+                    .with_context(|| format!("line {}: {:?}", i + 1, line))
+            });
+
+        Ok(Some(Prehashed {
+            algorithm: "sha1",
+            entries: Box::new(entries),
+        }))
+    }
+}
+
+// Range files hold `SUFFIX:COUNT` (35 hex chars, prefix in the file name);
+// the full downloads hold `HASH:COUNT` with all 40 hex chars.
+fn parse_line(line: &str, prefix: Option<&str>) -> Result<PrehashedEntry> {
+    let (hash, count) = match line.split_once(':') {
+        Some((hash, count)) => (hash, Some(count)),
+        None => (line, None),
+    };
+
+    let hash = match (hash.len(), prefix) {
+        (SHA1_HEX_LEN, _) => hash.to_ascii_lowercase(),
+        (len, Some(prefix)) if len == SHA1_HEX_LEN - RANGE_PREFIX_LEN => {
+            format!("{}{}", prefix, hash.to_ascii_lowercase())
+        }
+        (len, None) if len == SHA1_HEX_LEN - RANGE_PREFIX_LEN => {
+            bail!("hash suffix without a range prefix (name the file after its 5-char prefix)")
+        }
+        _ => hash.to_ascii_lowercase(),
+    };
+
+    let count = count
+        .map(|c| c.trim().parse::<u64>().map_err(|_| anyhow!("invalid count: {:?}", c)))
+        .transpose()?;
+
+    Ok(PrehashedEntry { hash, count })
+}
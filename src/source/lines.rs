@@ -0,0 +1,107 @@
+use std::io::BufRead;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+
+// What a word list line that is not valid UTF-8 turns into
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum OnInvalid {
+    // Replace invalid bytes with U+FFFD and keep the line
+    #[default]
+    Lossy,
+    // Drop the line
+    Skip,
+    // Stop reading; the build reports the first offending line
+    Fail,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct InvalidLines {
+    pub count: usize,
+    // Where the first invalid line was found, and its 1-based line number
+    pub first: Option<(String, usize)>,
+}
+
+// A source's --on-invalid mode and the invalid lines it has read so far.
+// Clones share the tally, so a dir: source reports (and under Fail stops)
+// across all of its files.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct LineTally {
+    mode: OnInvalid,
+    shared: Arc<TallyState>,
+}
+
+#[derive(Debug, Default)]
+struct TallyState {
+    // Checked per line, so kept apart from the mutex
+    failed: AtomicBool,
+    invalid: Mutex<InvalidLines>,
+}
+
+impl LineTally {
+    pub(crate) fn new(mode: OnInvalid) -> Self {
+        Self {
+            mode,
+            shared: Arc::default(),
+        }
+    }
+
+    pub(crate) fn invalid_lines(&self) -> InvalidLines {
+        self.lock().clone()
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, InvalidLines> {
+        self.shared.invalid.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn record(&self, label: &str, line_number: usize) {
+        let mut invalid = self.lock();
+        invalid.count += 1;
+        if invalid.first.is_none() {
+            invalid.first = Some((label.to_string(), line_number));
+        }
+    }
+}
+
+// Like BufRead::lines(), but invalid UTF-8 is handled per --on-invalid
+// instead of silently ending the stream. Empty lines are kept.
+pub(crate) fn decode_lines<R: BufRead + 'static>(
+    reader: R,
+    label: impl Into<String>,
+    tally: &LineTally,
+) -> Box<dyn Iterator<Item = String>> {
+    let label = label.into();
+    let tally = tally.clone();
+    let mut reader = reader;
+    let mut line_number = 0usize;
+    let mut buffer = Vec::new();
+    Box::new(std::iter::from_fn(move || loop {
+        if tally.shared.failed.load(Ordering::Relaxed) {
+            return None;
+        }
+        buffer.clear();
+        match reader.read_until(b'\n', &mut buffer) {
+            Ok(0) | Err(_) => return None,
+            Ok(_) => {}
+        }
+        line_number += 1;
+        if buffer.last() == Some(&b'\n') {
+            buffer.pop();
+            if buffer.last() == Some(&b'\r') {
+                buffer.pop();
+            }
+        }
+
+        if let Ok(line) = std::str::from_utf8(&buffer) {
+            return Some(line.to_string());
+        }
+        tally.record(&label, line_number);
+        match tally.mode {
+            OnInvalid::Lossy => return Some(String::from_utf8_lossy(&buffer).into_owned()),
+            OnInvalid::Skip => continue,
+            OnInvalid::Fail => {
+                tally.shared.failed.store(true, Ordering::Relaxed);
+                return None;
+            }
+        }
+    }))
+}
@@ -1,43 +1,96 @@
+mod binary;
+mod compression;
+mod dir;
 mod file;
+mod hibp;
+mod lines;
+mod range;
 mod stdin;
 mod url;
 pub mod aspell;
 pub mod seclists;
 
 pub use aspell::AspellSource;
+pub use binary::{read_records as read_binary_records, BinaryFormat, BinaryRecords};
+pub use compression::wrap_decompressor;
+pub use dir::DirSource;
 pub use file::FileSource;
+pub use hibp::HibpSource;
+use lines::{decode_lines, LineTally};
+pub use lines::{InvalidLines, OnInvalid};
+pub use range::RangeSource;
 pub use seclists::SecListsSource;
 pub use stdin::StdinSource;
-pub use url::UrlSource;
+pub use url::{parse_header, UrlOptions, UrlSource};
+
+use std::path::Path;
 
 use anyhow::{bail, Result};
 
+use crate::storage::SourceMeta;
+
 pub trait Source {
     fn name(&self) -> &str;
     fn words(&self) -> Result<Box<dyn Iterator<Item = String>>>;
     fn content_hash(&self) -> Result<Option<String>>;
+
+    fn metadata(&self) -> SourceMeta {
+        SourceMeta::default()
+    }
+
+    fn prehashed(&self) -> Result<Option<Prehashed>> {
+        Ok(None)
+    }
+
+    // The file on disk backing this source, for mtime comparisons
+    fn local_path(&self) -> Option<&Path> {
+        None
+    }
+
+    // Lines that were not valid UTF-8, over every words() iterator so far
+    fn invalid_lines(&self) -> InvalidLines {
+        InvalidLines::default()
+    }
+}
+
+pub struct Prehashed {
+    pub algorithm: &'static str,
+    pub entries: Box<dyn Iterator<Item = Result<PrehashedEntry>>>,
+}
+
+pub struct PrehashedEntry {
+    pub hash: String,
+    pub count: Option<u64>,
 }
 
 pub fn parse(spec: &str) -> Result<Box<dyn Source>> {
+    parse_with(spec, &UrlOptions::default(), OnInvalid::default())
+}
+
+// `url` only applies to http(s) specs, `on_invalid` to line-based word lists
+pub fn parse_with(spec: &str, url: &UrlOptions, on_invalid: OnInvalid) -> Result<Box<dyn Source>> {
     if spec == "-" {
-        return Ok(Box::new(StdinSource::new()));
+        return Ok(Box::new(StdinSource::new().with_on_invalid(on_invalid)));
     }
 
     if spec.starts_with("http://") || spec.starts_with("https://") {
-        return Ok(Box::new(UrlSource::new(spec)?));
+        return Ok(Box::new(UrlSource::with_options(spec, url)?.with_on_invalid(on_invalid)));
     }
 
     if let Some((provider, path)) = spec.split_once(':') {
         match provider {
-            "seclists" => Ok(Box::new(SecListsSource::new(path)?)),
+            "seclists" => Ok(Box::new(SecListsSource::new(path)?.with_on_invalid(on_invalid))),
             "aspell" => Ok(Box::new(AspellSource::new(path)?)),
-            "file" => Ok(Box::new(FileSource::new(path))),
+            "file" => Ok(Box::new(FileSource::new(path).with_on_invalid(on_invalid))),
+            "hibp" => Ok(Box::new(HibpSource::new(path)?)),
+            "dir" => Ok(Box::new(DirSource::new(path)?.with_on_invalid(on_invalid))),
+            "range" => Ok(Box::new(RangeSource::new(path)?)),
             _ => bail!(
-                "Unknown source provider: '{}'. Available: seclists, aspell, file",
+                "Unknown source provider: '{}'. Available: seclists, aspell, file, hibp, dir, range",
                 provider
             ),
         }
     } else {
-        Ok(Box::new(FileSource::new(spec)))
+        Ok(Box::new(FileSource::new(spec).with_on_invalid(on_invalid)))
     }
 }
@@ -0,0 +1,60 @@
+use anyhow::{bail, Context, Result};
+
+use super::Source;
+
+// Every integer from start to end, zero-padded to the width of the start
+// token: range:0000-9999 yields 0000..9999, range:1-100 yields 1..100.
+pub struct RangeSource {
+    spec: String,
+    name: String,
+    start: u64,
+    end: u64,
+    width: usize,
+}
+
+impl RangeSource {
+    pub fn new(spec: &str) -> Result<Self> {
+        let Some((start_token, end_token)) = spec.split_once('-') else {
+            bail!("Invalid range '{}': expected START-END, e.g. range:0000-9999", spec);
+        };
+        let parse = |token: &str| {
+            if token.is_empty() || !token.bytes().all(|b| b.is_ascii_digit()) {
+                bail!("Invalid range '{}': '{}' is not a non-negative integer", spec, token);
+            }
+            token
+                .parse::<u64>()
+                .with_context(|| format!("Invalid range '{}': '{}' is too large", spec, token))
+        };
+        let (start, end) = (parse(start_token)?, parse(end_token)?);
+        if start > end {
+            bail!("Invalid range '{}': {} is greater than {}", spec, start, end);
+        }
+
+        Ok(Self {
+            spec: spec.to_string(),
+            name: format!("range_{}_{}", start_token, end_token),
+            start,
+            end,
+            width: start_token.len(),
+        })
+    }
+}
+
+impl Source for RangeSource {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn words(&self) -> Result<Box<dyn Iterator<Item = String>>> {
+        let width = self.width;
+        Ok(Box::new(
+            (self.start..=self.end).map(move |n| format!("{:0width$}", n, width = width)),
+        ))
+    }
+
+    // The words follow from the spec alone
+    fn content_hash(&self) -> Result<Option<String>> {
+        let hash = blake3::hash(format!("range:{}", self.spec).as_bytes());
+        Ok(Some(hash.to_hex().to_string()))
+    }
+}
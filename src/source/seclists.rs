@@ -1,18 +1,21 @@
 use std::fs::File;
-use std::io::{BufRead, BufReader, Read};
+use std::io::Read;
 use std::path::{Path, PathBuf};
 use std::process::Command;
 
 use anyhow::{bail, Context, Result};
 
-use super::Source;
+use super::{decode_lines, wrap_decompressor, InvalidLines, LineTally, OnInvalid, Source};
 use crate::status;
+use crate::storage::SourceMeta;
 
 const SECLISTS_REPO: &str = "https://github.com/danielmiessler/SecLists.git";
+const SECLISTS_LICENSE: &str = "MIT";
 
 pub struct SecListsSource {
     path: String,
     full_path: PathBuf,
+    lines: LineTally,
 }
 
 impl SecListsSource {
@@ -35,8 +38,14 @@ impl SecListsSource {
         Ok(Self {
             path: path.to_string(),
             full_path,
+            lines: LineTally::default(),
         })
     }
+
+    pub fn with_on_invalid(mut self, mode: OnInvalid) -> Self {
+        self.lines = LineTally::new(mode);
+        self
+    }
 }
 
 impl Source for SecListsSource {
@@ -44,22 +53,31 @@ impl Source for SecListsSource {
         &self.path
     }
 
+    fn local_path(&self) -> Option<&Path> {
+        Some(&self.full_path)
+    }
+
     fn words(&self) -> Result<Box<dyn Iterator<Item = String>>> {
         let file = File::open(&self.full_path)
             .with_context(|| format!("Failed to open: {:?}", self.full_path))?;
-        let reader = BufReader::new(file);
+        let reader = wrap_decompressor(file, self.full_path.to_str())?;
         Ok(Box::new(
-            reader
-                .lines()
-                .map_while(Result::ok)
+            decode_lines(reader, self.full_path.display().to_string(), &self.lines)
                 .filter(|line| !line.is_empty()),
         ))
     }
 
+    fn invalid_lines(&self) -> InvalidLines {
+        self.lines.invalid_lines()
+    }
+
     fn content_hash(&self) -> Result<Option<String>> {
         let mut file = File::open(&self.full_path)
             .with_context(|| format!("Failed to open: {:?}", self.full_path))?;
         let mut hasher = blake3::Hasher::new();
+        if let Some(commit) = current_commit() {
+            hasher.update(commit.as_bytes());
+        }
         let mut buffer = [0u8; 65536];
         loop {
             let bytes_read = file.read(&mut buffer)?;
@@ -70,6 +88,20 @@ impl Source for SecListsSource {
         }
         Ok(Some(hasher.finalize().to_hex().to_string()))
     }
+
+    fn metadata(&self) -> SourceMeta {
+        SourceMeta {
+            url: Some(seclists_repo()),
+            path: Some(self.path.clone()),
+            commit: current_commit(),
+            license: Some(SECLISTS_LICENSE.to_string()),
+            ..Default::default()
+        }
+    }
+}
+
+fn seclists_repo() -> String {
+    std::env::var("SHAHA_SECLISTS_REPO").unwrap_or_else(|_| SECLISTS_REPO.to_string())
 }
 
 fn seclists_dir() -> PathBuf {
@@ -83,20 +115,27 @@ pub fn is_pulled() -> bool {
     seclists_dir().join(".git").exists()
 }
 
-pub fn pull() -> Result<()> {
+pub fn current_commit() -> Option<String> {
+    let output = Command::new("git")
+        .args(["rev-parse", "HEAD"])
+        .current_dir(seclists_dir())
+        .output()
+        .ok()?;
+
+    if !output.status.success() {
+        return None;
+    }
+
+    let commit = String::from_utf8_lossy(&output.stdout).trim().to_string();
+    (!commit.is_empty()).then_some(commit)
+}
+
+pub fn pull(rev: Option<&str>) -> Result<()> {
     let dir = seclists_dir();
 
     if dir.join(".git").exists() {
         status!("Updating SecLists...");
-        let status = Command::new("git")
-            .args(["pull", "--ff-only"])
-            .current_dir(&dir)
-            .status()
-            .context("Failed to run git pull")?;
-
-        if !status.success() {
-            bail!("git pull failed");
-        }
+        checkout(&dir, rev.unwrap_or("HEAD"))?;
         status!("SecLists updated.");
     } else {
         if let Some(parent) = dir.parent() {
@@ -106,20 +145,54 @@ pub fn pull() -> Result<()> {
 
         status!("Cloning SecLists (this may take a while)...");
         let status = Command::new("git")
-            .args(["clone", "--depth", "1", SECLISTS_REPO, dir.to_str().unwrap()])
+            .args(["clone", "--depth", "1", &seclists_repo(), dir.to_str().unwrap()])
             .status()
             .context("Failed to run git clone")?;
 
         if !status.success() {
             bail!("git clone failed");
         }
+
+        if let Some(rev) = rev {
+            checkout(&dir, rev)?;
+        }
         status!("SecLists cloned to {:?}", dir);
     }
 
+    if let Some(commit) = current_commit() {
+        status!("SecLists at commit {}", commit);
+    }
+
+    Ok(())
+}
+
+fn checkout(dir: &Path, rev: &str) -> Result<()> {
+    let status = Command::new("git")
+        .args(["fetch", "--depth", "1", "origin", rev])
+        .current_dir(dir)
+        .status()
+        .context("Failed to run git fetch")?;
+
+    if !status.success() {
+        bail!("git fetch failed for revision '{}'", rev);
+    }
+
+    let status = Command::new("git")
+        .args(["checkout", "--quiet", "--detach", "FETCH_HEAD"])
+        .current_dir(dir)
+        .status()
+        .context("Failed to run git checkout")?;
+
+    if !status.success() {
+        bail!("git checkout failed for revision '{}'", rev);
+    }
+
     Ok(())
 }
 
-pub fn list(subpath: Option<&str>) -> Result<Vec<String>> {
+// `extensions` is an allowlist compared without the dot ("" matches files
+// with no extension); None lists every file.
+pub fn list(subpath: Option<&str>, extensions: Option<&[String]>) -> Result<Vec<String>> {
     let base = seclists_dir();
     if !base.exists() {
         bail!("SecLists not found. Run `shaha source pull seclists` first.");
@@ -135,24 +208,30 @@ pub fn list(subpath: Option<&str>) -> Result<Vec<String>> {
     }
 
     let mut files = Vec::new();
-    collect_txt_files(&search_dir, &seclists_dir(), &mut files)?;
+    collect_files(&search_dir, &seclists_dir(), extensions, &mut files)?;
     files.sort();
     Ok(files)
 }
 
-fn collect_txt_files(dir: &Path, base: &Path, files: &mut Vec<String>) -> Result<()> {
+fn collect_files(
+    dir: &Path,
+    base: &Path,
+    extensions: Option<&[String]>,
+    files: &mut Vec<String>,
+) -> Result<()> {
     for entry in std::fs::read_dir(dir)? {
         let entry = entry?;
         let path = entry.path();
+        let name = path.file_name().unwrap().to_string_lossy();
+        if name.starts_with('.') {
+            continue;
+        }
         
         if path.is_dir() {
-            let name = path.file_name().unwrap().to_string_lossy();
-            if name.starts_with('.') {
-                continue;
-            }
-            collect_txt_files(&path, base, files)?;
-        } else if let Some(ext) = path.extension() {
-            if ext == "txt" {
+            collect_files(&path, base, extensions, files)?;
+        } else {
+            let ext = path.extension().map(|e| e.to_string_lossy()).unwrap_or_default();
+            if extensions.is_none_or(|allowed| allowed.iter().any(|a| *a == ext)) {
                 let relative = path.strip_prefix(base)
                     .unwrap()
                     .to_string_lossy()
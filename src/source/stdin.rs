@@ -1,14 +1,23 @@
-use std::io::{self, BufRead, BufReader};
+use std::io;
 
 use anyhow::Result;
 
-use super::Source;
+use super::{decode_lines, wrap_decompressor, InvalidLines, LineTally, OnInvalid, Source};
 
-pub struct StdinSource;
+pub struct StdinSource {
+    lines: LineTally,
+}
 
 impl StdinSource {
     pub fn new() -> Self {
-        Self
+        Self {
+            lines: LineTally::default(),
+        }
+    }
+
+    pub fn with_on_invalid(mut self, mode: OnInvalid) -> Self {
+        self.lines = LineTally::new(mode);
+        self
     }
 }
 
@@ -24,15 +33,16 @@ impl Source for StdinSource {
     }
 
     fn words(&self) -> Result<Box<dyn Iterator<Item = String>>> {
-        let reader = BufReader::new(io::stdin());
+        let reader = wrap_decompressor(io::stdin(), None)?;
         Ok(Box::new(
-            reader
-                .lines()
-                .map_while(Result::ok)
-                .filter(|line| !line.is_empty()),
+            decode_lines(reader, "stdin", &self.lines).filter(|line| !line.is_empty()),
         ))
     }
 
+    fn invalid_lines(&self) -> InvalidLines {
+        self.lines.invalid_lines()
+    }
+
     fn content_hash(&self) -> Result<Option<String>> {
         Ok(None)
     }
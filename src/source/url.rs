@@ -1,17 +1,84 @@
-use std::io::{BufRead, BufReader, Cursor};
+use std::io::{Cursor, Read};
 use std::sync::OnceLock;
+use std::time::Duration;
 
-use anyhow::{Context, Result};
+use anyhow::{bail, Context, Result};
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue, AUTHORIZATION};
 
-use super::Source;
+use super::{decode_lines, wrap_decompressor, InvalidLines, LineTally, OnInvalid, Source};
+use crate::status;
+use crate::storage::SourceMeta;
 
 pub struct UrlSource {
+    url: String,
     name: String,
-    cached_content: OnceLock<String>,
+    cached_content: OnceLock<Vec<u8>>,
+    lines: LineTally,
+}
+
+// Extra request settings for private word list endpoints and flaky networks
+#[derive(Debug, Clone)]
+pub struct UrlOptions {
+    pub bearer: Option<String>,
+    pub headers: Vec<(String, String)>,
+    // Further attempts after a connection error or 5xx response; the delay
+    // doubles after each one
+    pub retries: u32,
+    pub retry_delay: Duration,
+}
+
+impl Default for UrlOptions {
+    fn default() -> Self {
+        Self {
+            bearer: None,
+            headers: Vec::new(),
+            retries: 3,
+            retry_delay: Duration::from_millis(500),
+        }
+    }
+}
+
+impl UrlOptions {
+    fn header_map(&self) -> Result<HeaderMap> {
+        let mut map = HeaderMap::new();
+        for (name, value) in &self.headers {
+            let name = HeaderName::from_bytes(name.as_bytes())
+                .with_context(|| format!("Invalid header name: {:?}", name))?;
+            let value = HeaderValue::from_str(value)
+                .with_context(|| format!("Invalid value for header {}", name))?;
+            map.append(name, value);
+        }
+        if let Some(token) = &self.bearer {
+            if map.contains_key(AUTHORIZATION) {
+                bail!("--bearer and an Authorization --header cannot be combined");
+            }
+            let mut value = HeaderValue::from_str(&format!("Bearer {}", token))
+                .context("Invalid --bearer token")?;
+            value.set_sensitive(true);
+            map.insert(AUTHORIZATION, value);
+        }
+        Ok(map)
+    }
+}
+
+// `Key: Value`, as given to --header; both sides are trimmed
+pub fn parse_header(header: &str) -> Result<(String, String)> {
+    let Some((name, value)) = header.split_once(':') else {
+        bail!("expected 'Key: Value', got {:?}", header);
+    };
+    let (name, value) = (name.trim(), value.trim());
+    HeaderName::from_bytes(name.as_bytes())
+        .with_context(|| format!("invalid header name {:?}", name))?;
+    HeaderValue::from_str(value).with_context(|| format!("invalid value for header {}", name))?;
+    Ok((name.to_string(), value.to_string()))
 }
 
 impl UrlSource {
     pub fn new(url: impl Into<String>) -> Result<Self> {
+        Self::with_options(url, &UrlOptions::default())
+    }
+
+    pub fn with_options(url: impl Into<String>, options: &UrlOptions) -> Result<Self> {
         let url = url.into();
         let name = url
             .rsplit('/')
@@ -20,46 +87,98 @@ impl UrlSource {
             .unwrap_or("url")
             .to_string();
 
-        let response = reqwest::blocking::get(&url)
-            .with_context(|| format!("Failed to fetch URL: {}", url))?;
-        let content = response
-            .text()
+        let headers = options.header_map()?;
+        let response = fetch(&url, headers, options)?;
+        let body = response
+            .bytes()
             .with_context(|| format!("Failed to read response from: {}", url))?;
-
+        let mut decoded = Vec::new();
+        wrap_decompressor(Cursor::new(body), Some(&url))?
+            .read_to_end(&mut decoded)
+            .with_context(|| format!("Failed to decompress response from: {}", url))?;
         let source = Self {
+            url,
             name,
             cached_content: OnceLock::new(),
+            lines: LineTally::default(),
         };
-        let _ = source.cached_content.set(content);
+        let _ = source.cached_content.set(decoded);
 
         Ok(source)
     }
 
-    fn get_content(&self) -> &str {
+    pub fn with_on_invalid(mut self, mode: OnInvalid) -> Self {
+        self.lines = LineTally::new(mode);
+        self
+    }
+
+    fn get_content(&self) -> &[u8] {
         self.cached_content.get().expect("content initialized in new()")
     }
 }
 
+// An error status on the final attempt fails the fetch, so an error page is
+// never read as a word list.
+fn fetch(
+    url: &str,
+    headers: HeaderMap,
+    options: &UrlOptions,
+) -> Result<reqwest::blocking::Response> {
+    let client = reqwest::blocking::Client::new();
+    let mut delay = options.retry_delay;
+    let mut attempt = 0;
+    loop {
+        let result = client.get(url).headers(headers.clone()).send();
+        let failure = match &result {
+            Ok(response) if response.status().is_server_error() => Some(response.status().to_string()),
+            Err(err) if err.is_connect() || err.is_timeout() => Some(err.to_string()),
+            _ => None,
+        };
+        let Some(failure) = failure.filter(|_| attempt < options.retries) else {
+            return result
+                .and_then(|response| response.error_for_status())
+                .with_context(|| format!("Failed to fetch URL: {}", url));
+        };
+        attempt += 1;
+        status!(
+            "Fetching {} failed ({}), retrying in {}ms ({}/{})",
+            url,
+            failure,
+            delay.as_millis(),
+            attempt,
+            options.retries
+        );
+        std::thread::sleep(delay);
+        delay = delay.saturating_mul(2);
+    }
+}
+
 impl Source for UrlSource {
     fn name(&self) -> &str {
         &self.name
     }
 
     fn words(&self) -> Result<Box<dyn Iterator<Item = String>>> {
-        let content = self.get_content();
-        let reader = BufReader::new(Cursor::new(content));
-        let lines: Vec<String> = reader
-            .lines()
-            .map_while(Result::ok)
-            .filter(|line| !line.is_empty())
-            .collect();
-
-        Ok(Box::new(lines.into_iter()))
+        let reader = Cursor::new(self.get_content().to_vec());
+        Ok(Box::new(
+            decode_lines(reader, self.url.clone(), &self.lines).filter(|line| !line.is_empty()),
+        ))
+    }
+
+    fn invalid_lines(&self) -> InvalidLines {
+        self.lines.invalid_lines()
     }
 
     fn content_hash(&self) -> Result<Option<String>> {
         let content = self.get_content();
-        let hash = blake3::hash(content.as_bytes());
+        let hash = blake3::hash(content);
         Ok(Some(hash.to_hex().to_string()))
     }
+
+    fn metadata(&self) -> SourceMeta {
+        SourceMeta {
+            url: Some(self.url.clone()),
+            ..Default::default()
+        }
+    }
 }
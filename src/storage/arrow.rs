@@ -0,0 +1,62 @@
+use std::fs::File;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use anyhow::{bail, Context, Result};
+use arrow::datatypes::Schema;
+use arrow::ipc::writer::FileWriter;
+
+use super::parquet::{record_batch, record_schema};
+use super::{HashEncoding, HashRecord, Stats, Storage};
+
+pub struct ArrowStorage {
+    path: PathBuf,
+    writer: Option<FileWriter<File>>,
+    schema: Arc<Schema>,
+}
+
+impl ArrowStorage {
+    pub fn new(path: impl AsRef<Path>) -> Self {
+        Self {
+            path: path.as_ref().to_path_buf(),
+            writer: None,
+            schema: record_schema(HashEncoding::Binary),
+        }
+    }
+
+    fn ensure_writer(&mut self) -> Result<&mut FileWriter<File>> {
+        if self.writer.is_none() {
+            let file = File::create(&self.path)
+                .with_context(|| format!("Failed to create file: {:?}", self.path))?;
+            self.writer = Some(FileWriter::try_new(file, &self.schema)?);
+        }
+        Ok(self.writer.as_mut().expect("writer initialized above"))
+    }
+}
+
+impl Storage for ArrowStorage {
+    fn write_batch(&mut self, records: Vec<HashRecord>) -> Result<()> {
+        if records.is_empty() {
+            return Ok(());
+        }
+
+        let batch = record_batch(&self.schema, &records)?;
+        self.ensure_writer()?.write(&batch)?;
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<()> {
+        if let Some(mut writer) = self.writer.take() {
+            writer.finish()?;
+        }
+        Ok(())
+    }
+
+    fn query(&self, _hash_prefix: &[u8], _algo: Option<&str>, _offset: usize, _limit: Option<usize>) -> Result<Vec<HashRecord>> {
+        bail!("Arrow output is write-only: query the parquet database instead")
+    }
+
+    fn stats(&self) -> Result<Stats> {
+        bail!("Arrow output is write-only: use the parquet database for stats")
+    }
+}
@@ -0,0 +1,120 @@
+use std::fs;
+use std::num::NonZeroUsize;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::time::SystemTime;
+
+use anyhow::{anyhow, Result};
+use lru::LruCache;
+
+use super::{HashRecord, Stats, Storage};
+
+type CacheKey = (Vec<u8>, usize, Option<String>, usize, Option<usize>);
+
+pub struct CachedStorage<S> {
+    inner: S,
+    path: Option<PathBuf>,
+    cache: Mutex<Cache>,
+    hits: AtomicUsize,
+    misses: AtomicUsize,
+}
+
+struct Cache {
+    entries: LruCache<CacheKey, Vec<HashRecord>>,
+    file_stamp: Option<(SystemTime, u64)>,
+}
+
+impl<S: Storage> CachedStorage<S> {
+    pub fn new(inner: S, capacity: usize) -> Self {
+        let capacity = NonZeroUsize::new(capacity).unwrap_or(NonZeroUsize::MIN);
+        Self {
+            inner,
+            path: None,
+            cache: Mutex::new(Cache {
+                entries: LruCache::new(capacity),
+                file_stamp: None,
+            }),
+            hits: AtomicUsize::new(0),
+            misses: AtomicUsize::new(0),
+        }
+    }
+
+    pub fn with_watch_path(mut self, path: impl AsRef<Path>) -> Self {
+        self.path = Some(path.as_ref().to_path_buf());
+        self
+    }
+
+    pub fn hits(&self) -> usize {
+        self.hits.load(Ordering::Relaxed)
+    }
+
+    pub fn misses(&self) -> usize {
+        self.misses.load(Ordering::Relaxed)
+    }
+
+    fn file_stamp(&self) -> Option<(SystemTime, u64)> {
+        let metadata = fs::metadata(self.path.as_ref()?).ok()?;
+        Some((metadata.modified().ok()?, metadata.len()))
+    }
+}
+
+impl<S: Storage> Storage for CachedStorage<S> {
+    fn write_batch(&mut self, records: Vec<HashRecord>) -> Result<()> {
+        self.inner.write_batch(records)
+    }
+
+    fn finish(&mut self) -> Result<()> {
+        self.inner.finish()
+    }
+
+    fn query(&self, hash_prefix: &[u8], algo: Option<&str>, offset: usize, limit: Option<usize>) -> Result<Vec<HashRecord>> {
+        self.query_bits(hash_prefix, hash_prefix.len() * 8, algo, offset, limit)
+    }
+
+    fn query_bits(
+        &self,
+        hash_prefix: &[u8],
+        bits: usize,
+        algo: Option<&str>,
+        offset: usize,
+        limit: Option<usize>,
+    ) -> Result<Vec<HashRecord>> {
+        let key = (hash_prefix.to_vec(), bits, algo.map(String::from), offset, limit);
+        let stamp = self.file_stamp();
+
+        {
+            let mut cache = self.cache.lock().map_err(|_| anyhow!("Query cache poisoned"))?;
+            if cache.file_stamp != stamp {
+                cache.entries.clear();
+                cache.file_stamp = stamp;
+            }
+            if let Some(records) = cache.entries.get(&key) {
+                self.hits.fetch_add(1, Ordering::Relaxed);
+                return Ok(records.clone());
+            }
+        }
+
+        self.misses.fetch_add(1, Ordering::Relaxed);
+        let records = self.inner.query_bits(hash_prefix, bits, algo, offset, limit)?;
+
+        let mut cache = self.cache.lock().map_err(|_| anyhow!("Query cache poisoned"))?;
+        if cache.file_stamp == stamp {
+            cache.entries.put(key, records.clone());
+        }
+        Ok(records)
+    }
+
+    // Counts are not cached; they go straight to the inner storage
+    fn count(&self, hash_prefix: &[u8], algo: Option<&str>) -> Result<usize> {
+        self.inner.count(hash_prefix, algo)
+    }
+
+    fn count_bits(&self, hash_prefix: &[u8], bits: usize, algo: Option<&str>) -> Result<usize> {
+        self.inner.count_bits(hash_prefix, bits, algo)
+    }
+
+    fn stats(&self) -> Result<Stats> {
+        self.inner.stats()
+    }
+}
@@ -0,0 +1,69 @@
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Context, Result};
+
+use super::{HashRecord, Stats, Storage};
+
+pub const CSV_HEADER: [&str; 4] = ["hash", "preimage", "algorithm", "sources"];
+
+pub fn write_csv_record<W: Write>(writer: &mut csv::Writer<W>, record: &HashRecord) -> Result<()> {
+    writer.write_record([
+        hex::encode(&record.hash).as_str(),
+        record.preimage.as_str(),
+        record.algorithm.as_str(),
+        record.sources.join(";").as_str(),
+    ])?;
+    Ok(())
+}
+
+pub struct CsvStorage {
+    path: PathBuf,
+    writer: Option<csv::Writer<BufWriter<File>>>,
+}
+
+impl CsvStorage {
+    pub fn new(path: impl AsRef<Path>) -> Self {
+        Self {
+            path: path.as_ref().to_path_buf(),
+            writer: None,
+        }
+    }
+
+    fn ensure_writer(&mut self) -> Result<&mut csv::Writer<BufWriter<File>>> {
+        if self.writer.is_none() {
+            let file = File::create(&self.path)
+                .with_context(|| format!("Failed to create file: {:?}", self.path))?;
+            let mut writer = csv::Writer::from_writer(BufWriter::new(file));
+            writer.write_record(CSV_HEADER)?;
+            self.writer = Some(writer);
+        }
+        Ok(self.writer.as_mut().expect("writer initialized above"))
+    }
+}
+
+impl Storage for CsvStorage {
+    fn write_batch(&mut self, records: Vec<HashRecord>) -> Result<()> {
+        let writer = self.ensure_writer()?;
+        for record in &records {
+            write_csv_record(writer, record)?;
+        }
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<()> {
+        if let Some(mut writer) = self.writer.take() {
+            writer.flush()?;
+        }
+        Ok(())
+    }
+
+    fn query(&self, _hash_prefix: &[u8], _algo: Option<&str>, _offset: usize, _limit: Option<usize>) -> Result<Vec<HashRecord>> {
+        bail!("CSV output is write-only: query the parquet database instead")
+    }
+
+    fn stats(&self) -> Result<Stats> {
+        bail!("CSV output is write-only: use the parquet database for stats")
+    }
+}
@@ -0,0 +1,120 @@
+use std::cell::{Cell, OnceCell};
+
+use anyhow::Result;
+
+use super::{HashRecord, Stats, Storage};
+
+type OpenFallback = Box<dyn Fn() -> Result<Box<dyn Storage>>>;
+
+// Answers from `primary` and only consults the fallback for lookups that
+// found nothing there. The fallback is opened on the first miss, so a run
+// where every hash is found locally never connects to it.
+pub struct FallbackStorage {
+    primary: Box<dyn Storage>,
+    open_fallback: OpenFallback,
+    fallback: OnceCell<Box<dyn Storage>>,
+    fallback_hits: Cell<usize>,
+}
+
+impl FallbackStorage {
+    pub fn new(
+        primary: Box<dyn Storage>,
+        open_fallback: impl Fn() -> Result<Box<dyn Storage>> + 'static,
+    ) -> Self {
+        Self {
+            primary,
+            open_fallback: Box::new(open_fallback),
+            fallback: OnceCell::new(),
+            fallback_hits: Cell::new(0),
+        }
+    }
+
+    // Lookups answered by the fallback rather than the primary
+    pub fn fallback_hits(&self) -> usize {
+        self.fallback_hits.get()
+    }
+
+    fn fallback(&self) -> Result<&dyn Storage> {
+        if let Some(fallback) = self.fallback.get() {
+            return Ok(fallback.as_ref());
+        }
+        let opened = (self.open_fallback)()?;
+        Ok(self.fallback.get_or_init(|| opened).as_ref())
+    }
+
+    // `query` runs with the caller's offset and limit; an empty page past the
+    // end of the primary's matches is a real answer, not a miss, so a paged
+    // miss is confirmed with an unpaged probe before falling back.
+    fn or_fallback(
+        &self,
+        offset: usize,
+        limit: Option<usize>,
+        query: impl Fn(&dyn Storage, usize, Option<usize>) -> Result<Vec<HashRecord>>,
+    ) -> Result<Vec<HashRecord>> {
+        let primary = query(self.primary.as_ref(), offset, limit)?;
+        if !primary.is_empty() {
+            return Ok(primary);
+        }
+        if offset > 0 && !query(self.primary.as_ref(), 0, Some(1))?.is_empty() {
+            return Ok(primary);
+        }
+        let results = query(self.fallback()?, offset, limit)?;
+        if !results.is_empty() {
+            self.fallback_hits.set(self.fallback_hits.get() + 1);
+        }
+        Ok(results)
+    }
+}
+
+impl Storage for FallbackStorage {
+    fn write_batch(&mut self, records: Vec<HashRecord>) -> Result<()> {
+        self.primary.write_batch(records)
+    }
+
+    fn finish(&mut self) -> Result<()> {
+        self.primary.finish()
+    }
+
+    fn query(
+        &self,
+        hash_prefix: &[u8],
+        algo: Option<&str>,
+        offset: usize,
+        limit: Option<usize>,
+    ) -> Result<Vec<HashRecord>> {
+        self.or_fallback(offset, limit, |storage, offset, limit| {
+            storage.query(hash_prefix, algo, offset, limit)
+        })
+    }
+
+    fn query_bits(
+        &self,
+        hash_prefix: &[u8],
+        bits: usize,
+        algo: Option<&str>,
+        offset: usize,
+        limit: Option<usize>,
+    ) -> Result<Vec<HashRecord>> {
+        self.or_fallback(offset, limit, |storage, offset, limit| {
+            storage.query_bits(hash_prefix, bits, algo, offset, limit)
+        })
+    }
+
+    fn count(&self, hash_prefix: &[u8], algo: Option<&str>) -> Result<usize> {
+        match self.primary.count(hash_prefix, algo)? {
+            0 => self.fallback()?.count(hash_prefix, algo),
+            count => Ok(count),
+        }
+    }
+
+    fn count_bits(&self, hash_prefix: &[u8], bits: usize, algo: Option<&str>) -> Result<usize> {
+        match self.primary.count_bits(hash_prefix, bits, algo)? {
+            0 => self.fallback()?.count_bits(hash_prefix, bits, algo),
+            count => Ok(count),
+        }
+    }
+
+    fn stats(&self) -> Result<Stats> {
+        self.primary.stats()
+    }
+}
@@ -0,0 +1,67 @@
+use std::collections::BTreeSet;
+
+use anyhow::Result;
+
+use super::{HashRecord, Stats, Storage};
+
+#[derive(Debug, Default)]
+pub struct MemoryStorage {
+    records: Vec<HashRecord>,
+}
+
+impl MemoryStorage {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn records(&self) -> &[HashRecord] {
+        &self.records
+    }
+
+    pub fn into_records(self) -> Vec<HashRecord> {
+        self.records
+    }
+}
+
+impl Storage for MemoryStorage {
+    fn write_batch(&mut self, records: Vec<HashRecord>) -> Result<()> {
+        self.records.extend(records);
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<()> {
+        Ok(())
+    }
+
+    fn query(&self, hash_prefix: &[u8], algo: Option<&str>, offset: usize, limit: Option<usize>) -> Result<Vec<HashRecord>> {
+        Ok(self
+            .records
+            .iter()
+            .filter(|r| r.hash.starts_with(hash_prefix))
+            .filter(|r| algo.is_none_or(|a| r.algorithm == a))
+            .skip(offset)
+            .take(limit.unwrap_or(usize::MAX))
+            .cloned()
+            .collect())
+    }
+
+    fn stats(&self) -> Result<Stats> {
+        let algorithms: BTreeSet<&str> = self.records.iter().map(|r| r.algorithm.as_str()).collect();
+        let sources: BTreeSet<&str> = self
+            .records
+            .iter()
+            .flat_map(|r| r.sources.iter().map(String::as_str))
+            .collect();
+
+        let preimages: BTreeSet<&str> = self.records.iter().map(|r| r.preimage.as_str()).collect();
+
+        Ok(Stats {
+            total_records: self.records.len(),
+            distinct_preimages: Some(preimages.len()),
+            collisions: None,
+            algorithms: algorithms.into_iter().map(String::from).collect(),
+            sources: sources.into_iter().map(String::from).collect(),
+            file_size_bytes: 0,
+        })
+    }
+}
@@ -0,0 +1,70 @@
+use std::collections::HashMap;
+
+use anyhow::{bail, Result};
+
+use super::HashRecord;
+
+pub type RecordKey = (Vec<u8>, String);
+
+#[derive(Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum MergeStrategy {
+    // Add the new record's sources to the existing one
+    #[default]
+    UnionSources,
+    // Leave the existing record untouched
+    First,
+    // Union sources, but abort when the preimages differ (a real collision)
+    Fail,
+}
+
+impl MergeStrategy {
+    // Folds `incoming` into `existing` and returns how many sources it gained
+    pub fn merge(self, existing: &mut HashRecord, incoming: HashRecord) -> Result<usize> {
+        if self == Self::First {
+            return Ok(0);
+        }
+        if self == Self::Fail && existing.preimage != incoming.preimage {
+            bail!(
+                "Hash collision: {} {} is both '{}' and '{}'",
+                existing.algorithm,
+                hex::encode(&existing.hash),
+                existing.preimage,
+                incoming.preimage
+            );
+        }
+
+        // Kept as a sorted set, so long source lists merge without a
+        // linear contains() per source
+        let before = existing.sources.len();
+        existing.sources.extend(incoming.sources);
+        existing.sources.sort_unstable();
+        existing.sources.dedup();
+        Ok(existing.sources.len().saturating_sub(before))
+    }
+}
+
+pub struct Merged {
+    // The two records carried different preimages for the same hash
+    pub collision: bool,
+    pub sources_added: usize,
+}
+
+// Used while streaming a stored database: takes the incoming record with the
+// same (hash, algorithm) as `existing` out of `incoming` and folds it in.
+// Whatever is left in `incoming` afterwards is new to the database.
+pub fn merge_by_key(
+    existing: &mut HashRecord,
+    incoming: &mut HashMap<RecordKey, HashRecord>,
+    strategy: MergeStrategy,
+) -> Result<Option<Merged>> {
+    let key = (existing.hash.clone(), existing.algorithm.clone());
+    let Some(record) = incoming.remove(&key) else {
+        return Ok(None);
+    };
+    let collision = record.preimage != existing.preimage;
+    let sources_added = strategy.merge(existing, record)?;
+    Ok(Some(Merged {
+        collision,
+        sources_added,
+    }))
+}
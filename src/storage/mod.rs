@@ -1,8 +1,27 @@
+mod arrow;
+mod cache;
+mod csv;
+mod fallback;
+mod memory;
+mod merge;
 mod parquet;
 mod r2;
+mod s3;
 
+pub use self::arrow::ArrowStorage;
+pub use self::cache::CachedStorage;
+pub use self::csv::{write_csv_record, CsvStorage, CSV_HEADER};
+pub use self::fallback::FallbackStorage;
+pub use self::memory::MemoryStorage;
+pub use self::merge::{merge_by_key, MergeStrategy, Merged, RecordKey};
 pub use self::parquet::ParquetStorage;
 pub use self::r2::{R2Config, R2Storage};
+pub use self::s3::{ObjectVersion, R2Object};
+
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
 
 use anyhow::Result;
 use serde::{Deserialize, Serialize};
@@ -15,17 +34,221 @@ pub struct HashRecord {
     pub sources: Vec<String>,
 }
 
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum HashEncoding {
+    #[default]
+    Binary,
+    Hex,
+}
+
+impl HashEncoding {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            HashEncoding::Binary => "binary",
+            HashEncoding::Hex => "hex",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct SourceMeta {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub url: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub path: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub commit: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub license: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub retrieved_at: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub first_seen: Option<String>,
+    // The build --rules spec the source's words were mangled with
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rules: Option<String>,
+}
+
 #[derive(Debug, Default)]
 pub struct Stats {
     pub total_records: usize,
+    // None for databases written before the count was stored
+    pub distinct_preimages: Option<usize>,
+    // Preimages dropped because their hash was already stored; None when unknown
+    pub collisions: Option<usize>,
     pub algorithms: Vec<String>,
     pub sources: Vec<String>,
     pub file_size_bytes: u64,
 }
 
+// Sidecar description of a parquet database, built from its KV metadata
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Manifest {
+    pub database: String,
+    pub total_records: usize,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub distinct_preimages: Option<usize>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub collisions: Option<usize>,
+    pub algorithms: Vec<String>,
+    pub sources: BTreeMap<String, SourceMeta>,
+    pub source_hashes: Vec<String>,
+    pub file_size_bytes: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub built_at: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub shaha_version: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub build_command: Option<String>,
+}
+
+// How a database was produced; each field is None when it was not recorded
+#[derive(Debug, Default, Serialize)]
+pub struct Provenance {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub shaha_version: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub built_at: Option<String>,
+    // Command line of the build, with credentials redacted
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub build_command: Option<String>,
+}
+
+impl Manifest {
+    // hashes.parquet -> hashes.manifest.json
+    pub fn path_for(database: &Path) -> PathBuf {
+        let stem = database
+            .file_stem()
+            .map(|s| s.to_string_lossy().to_string())
+            .unwrap_or_default();
+        database.with_file_name(format!("{}.manifest.json", stem))
+    }
+}
+
+// Time spent in each phase of a parquet lookup, summed over `lookups`
+#[derive(Debug, Clone, Copy, Default)]
+pub struct QueryTimings {
+    pub lookups: usize,
+    pub metadata: Duration,
+    pub bloom: Duration,
+    pub row_groups: Duration,
+    pub scan: Duration,
+}
+
+pub type SharedTimings = Arc<Mutex<QueryTimings>>;
+
+impl QueryTimings {
+    pub fn add(&mut self, other: &QueryTimings) {
+        self.lookups += other.lookups;
+        self.metadata += other.metadata;
+        self.bloom += other.bloom;
+        self.row_groups += other.row_groups;
+        self.scan += other.scan;
+    }
+
+    pub fn total(&self) -> Duration {
+        self.metadata + self.bloom + self.row_groups + self.scan
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct BloomStats {
+    pub items: Option<usize>,
+    pub bits: u64,
+    pub hash_functions: u32,
+    pub set_bits: u64,
+}
+
+impl BloomStats {
+    pub fn fill_ratio(&self) -> f64 {
+        if self.bits == 0 {
+            return 0.0;
+        }
+        self.set_bits as f64 / self.bits as f64
+    }
+
+    // A lookup for an absent key hits k set bits by chance.
+    pub fn false_positive_rate(&self) -> f64 {
+        self.fill_ratio().powi(self.hash_functions as i32)
+    }
+}
+
 pub trait Storage {
     fn write_batch(&mut self, records: Vec<HashRecord>) -> Result<()>;
     fn finish(&mut self) -> Result<()>;
-    fn query(&self, hash_prefix: &[u8], algo: Option<&str>, limit: Option<usize>) -> Result<Vec<HashRecord>>;
+    fn query(&self, hash_prefix: &[u8], algo: Option<&str>, offset: usize, limit: Option<usize>) -> Result<Vec<HashRecord>>;
     fn stats(&self) -> Result<Stats>;
+
+    // Matches the first `bits` bits of `hash_prefix`. Backends without
+    // bit-level matching expand the dangling bits into whole-byte queries.
+    fn query_bits(
+        &self,
+        hash_prefix: &[u8],
+        bits: usize,
+        algo: Option<&str>,
+        offset: usize,
+        limit: Option<usize>,
+    ) -> Result<Vec<HashRecord>> {
+        let whole = bits / 8;
+        let spare = bits % 8;
+        if spare == 0 {
+            return self.query(&hash_prefix[..whole], algo, offset, limit);
+        }
+
+        // The offset spans all candidates, so each one is fetched from its
+        // start and the skipped rows are dropped here.
+        let fixed = hash_prefix[whole] & (0xFFu8 << (8 - spare));
+        let mut candidate = hash_prefix[..=whole].to_vec();
+        let mut results = Vec::new();
+        let mut to_skip = offset;
+        for low in 0..(1u16 << (8 - spare)) {
+            candidate[whole] = fixed | low as u8;
+            let remaining = limit.map(|l| l - results.len() + to_skip);
+            let mut records = self.query(&candidate, algo, 0, remaining)?;
+            let skipped = to_skip.min(records.len());
+            records.drain(..skipped);
+            to_skip -= skipped;
+            results.extend(records);
+            if limit.is_some_and(|l| results.len() >= l) {
+                break;
+            }
+        }
+        Ok(results)
+    }
+
+    // Number of records `query` would return; backends that can count
+    // without building each HashRecord override this.
+    fn count(&self, hash_prefix: &[u8], algo: Option<&str>) -> Result<usize> {
+        Ok(self.query(hash_prefix, algo, 0, None)?.len())
+    }
+
+    fn count_bits(&self, hash_prefix: &[u8], bits: usize, algo: Option<&str>) -> Result<usize> {
+        let whole = bits / 8;
+        let spare = bits % 8;
+        if spare == 0 {
+            return self.count(&hash_prefix[..whole], algo);
+        }
+
+        let fixed = hash_prefix[whole] & (0xFFu8 << (8 - spare));
+        let mut candidate = hash_prefix[..=whole].to_vec();
+        let mut total = 0;
+        for low in 0..(1u16 << (8 - spare)) {
+            candidate[whole] = fixed | low as u8;
+            total += self.count(&candidate, algo)?;
+        }
+        Ok(total)
+    }
+}
+
+pub(crate) fn matches_bits(hash: &[u8], prefix: &[u8], bits: usize) -> bool {
+    let whole = bits / 8;
+    let spare = bits % 8;
+    if hash.len() < bits.div_ceil(8) || hash[..whole] != prefix[..whole] {
+        return false;
+    }
+    if spare == 0 {
+        return true;
+    }
+    let mask = 0xFFu8 << (8 - spare);
+    hash[whole] & mask == prefix[whole] & mask
 }
@@ -1,46 +1,108 @@
-use std::collections::HashSet;
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::fs::File;
+use std::hash::{Hash, Hasher};
 use std::path::{Path, PathBuf};
-use std::sync::Arc;
+use std::sync::{Arc, OnceLock};
+use std::time::{Instant, SystemTime};
 
 use anyhow::{anyhow, Context, Result};
 use arrow::array::{Array, ArrayRef, BinaryArray, ListArray, RecordBatch, StringArray};
 use arrow::buffer::OffsetBuffer;
 use arrow::datatypes::{DataType, Field, Schema};
 use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use bloomfilter::reexports::bit_vec::BitVec;
+use bloomfilter::reexports::siphasher::sip::SipHasher13;
 use bloomfilter::Bloom;
-use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
-use parquet::arrow::ArrowWriter;
+use parquet::arrow::arrow_reader::{ParquetRecordBatchReader, ParquetRecordBatchReaderBuilder};
+use parquet::arrow::{ArrowWriter, ProjectionMask};
 use parquet::basic::Compression;
-use parquet::file::properties::WriterProperties;
+use parquet::file::metadata::{ParquetMetaData, RowGroupMetaData};
+use parquet::file::properties::{WriterProperties, WriterVersion, DEFAULT_WRITE_BATCH_SIZE};
 use parquet::file::statistics::Statistics;
+use rayon::prelude::*;
 
-use super::{HashRecord, Stats, Storage};
+use crate::hasher::{self, SaltPosition};
+
+use super::{
+    matches_bits, BloomStats, HashEncoding, HashRecord, Manifest, Provenance, QueryTimings,
+    SharedTimings, SourceMeta, Stats, Storage,
+};
 
 const META_TOTAL_RECORDS: &str = "shaha:total_records";
+const META_DISTINCT_PREIMAGES: &str = "shaha:distinct_preimages";
+const META_COLLISIONS: &str = "shaha:collisions";
+const META_SALT: &str = "shaha:salt";
+const META_SALT_POSITION: &str = "shaha:salt_position";
+const META_ROUNDS: &str = "shaha:rounds";
+const META_BUILT_AT: &str = "shaha:built_at";
+const META_VERSION: &str = "shaha:version";
+const META_BUILD_COMMAND: &str = "shaha:build_command";
 const META_ALGORITHMS: &str = "shaha:algorithms";
 const META_SOURCES: &str = "shaha:sources";
 const META_SOURCE_HASHES: &str = "shaha:source_hashes";
+const META_SOURCE_META: &str = "shaha:source_meta";
+const META_HASH_ENCODING: &str = "shaha:hash_encoding";
+const META_PREFIX_BITS: &str = "shaha:prefix_bits";
+const META_PAGE_SIZE: &str = "shaha:page_size";
+const META_PAGE_ROWS: &str = "shaha:page_rows";
+const META_DATA_PAGE_VERSION: &str = "shaha:data_page_version";
 const META_BLOOM_BITMAP: &str = "shaha:bloom_bitmap";
 const META_BLOOM_KEYS: &str = "shaha:bloom_keys";
 const META_BLOOM_ITEMS: &str = "shaha:bloom_items";
+const META_BLOOM_HASHES: &str = "shaha:bloom_hashes";
+const META_FIRST_BYTE_INDEX: &str = "shaha:first_byte_index";
+const META_ROW_GROUP_ALGORITHMS: &str = "shaha:row_group_algorithms";
 
 const DEFAULT_BLOOM_CAPACITY: usize = 1_000_000;
 const BLOOM_FP_RATE: f64 = 0.01;
+const DEFAULT_MAX_ROW_GROUP_BYTES: usize = 64 * 1024 * 1024;
+const FIRST_BYTE_BITMAP_LEN: usize = 32;
+const COARSE_INDEX_MAX_PREFIX: usize = 2;
+// Size budgeting: length prefixes, levels and offsets per record, plus room
+// for schema, row group metadata and page headers in the footer.
+const RECORD_OVERHEAD_BYTES: u64 = 16;
+const FOOTER_SLACK_BYTES: u64 = 32 * 1024;
+
+type SharedBloom = Arc<Bloom<Vec<u8>>>;
 
 pub struct ParquetStorage {
     path: PathBuf,
     writer: Option<ArrowWriter<File>>,
     schema: Arc<Schema>,
     write_stats: WriteStats,
+    max_row_group_bytes: usize,
+    hash_encoding: HashEncoding,
+    prefix_bits: Option<usize>,
+    coarse_index: bool,
+    max_file_size: Option<u64>,
+    estimated_bytes: u64,
+    dropped_records: usize,
+    partition_by_algorithm: bool,
+    current_algorithm: Option<String>,
+    page_size: Option<usize>,
+    page_rows: Option<usize>,
+    data_page_version: Option<u8>,
+    salt: Option<(Vec<u8>, SaltPosition)>,
+    rounds: Option<u32>,
+    minimal_metadata: bool,
+    build_command: Option<String>,
+    bloom_cache: Option<OnceLock<Option<SharedBloom>>>,
+    timings: Option<SharedTimings>,
 }
 
 struct WriteStats {
     total_records: usize,
+    preimages: PreimageSet,
+    collisions: usize,
     algorithms: HashSet<String>,
     sources: HashSet<String>,
     source_hashes: HashSet<String>,
-    bloom: Bloom<Vec<u8>>,
+    source_meta: BTreeMap<String, SourceMeta>,
+    bloom: BloomBuilder,
+    first_byte_runs: Vec<(u8, usize)>,
+    // Bloom, algorithms and sources were carried over from the database
+    // being appended to, so written rows only add counts and preimages
+    carried: bool,
 }
 
 impl WriteStats {
@@ -48,10 +110,15 @@ impl WriteStats {
         let bloom_capacity = expected_records.max(DEFAULT_BLOOM_CAPACITY);
         Self {
             total_records: 0,
+            preimages: PreimageSet::default(),
+            collisions: 0,
             algorithms: HashSet::new(),
             sources: HashSet::new(),
             source_hashes: HashSet::new(),
-            bloom: Bloom::new_for_fp_rate(bloom_capacity, BLOOM_FP_RATE),
+            source_meta: BTreeMap::new(),
+            bloom: BloomBuilder::new(bloom_capacity),
+            first_byte_runs: Vec::new(),
+            carried: false,
         }
     }
 }
@@ -62,6 +129,207 @@ impl Default for WriteStats {
     }
 }
 
+// Distinct preimages tracked as 64-bit fingerprints rather than strings, so
+// memory per word is a u64 slot plus HashSet overhead (roughly 10-20 bytes
+// depending on load) whatever the word length; a collision undercounts by one.
+#[derive(Default)]
+struct PreimageSet(HashSet<u64>);
+
+impl PreimageSet {
+    fn insert(&mut self, preimage: &str) {
+        let mut hasher = std::hash::DefaultHasher::new();
+        preimage.hash(&mut hasher);
+        self.0.insert(hasher.finish());
+    }
+
+    fn len(&self) -> usize {
+        self.0.len()
+    }
+}
+
+// Fills a bloom filter from bit positions hashed in parallel. Positions use
+// the same SipHash-1-3 double hashing as Bloom::set in bloomfilter 1.0.16
+// (pinned in Cargo.toml), so the bitmap is identical to inserting every hash
+// serially into a Bloom with these keys.
+struct BloomBuilder {
+    capacity: usize,
+    seed: Option<[u8; 32]>,
+    bits: BitVec,
+    bitmap_bits: u64,
+    k_num: u32,
+    sips: [SipHasher13; 2],
+}
+
+impl BloomBuilder {
+    fn new(capacity: usize) -> Self {
+        // Sized and keyed by the library; only its parameters are kept
+        Self::from_bloom(capacity, None, Bloom::new_for_fp_rate(capacity, BLOOM_FP_RATE))
+    }
+
+    // Same size, but sip keys derived from `seed` instead of random ones,
+    // so identical records give an identical bitmap
+    fn with_seed(capacity: usize, seed: &[u8; 32]) -> Self {
+        let bloom = Bloom::new_for_fp_rate_with_seed(capacity, BLOOM_FP_RATE, seed);
+        Self::from_bloom(capacity, Some(*seed), bloom)
+    }
+
+    fn resized(&self, capacity: usize) -> Self {
+        match &self.seed {
+            Some(seed) => Self::with_seed(capacity, seed),
+            None => Self::new(capacity),
+        }
+    }
+
+    // Keeps the bits already set in `bloom`, to add more hashes to it
+    fn extending(capacity: usize, seed: Option<[u8; 32]>, bloom: &Bloom<Vec<u8>>) -> Self {
+        let mut builder = Self::from_bloom(capacity, seed, bloom.clone());
+        builder.bits = BitVec::from_bytes(&bloom.bitmap());
+        builder.bits.truncate(builder.bitmap_bits as usize);
+        builder
+    }
+
+    fn from_bloom(capacity: usize, seed: Option<[u8; 32]>, bloom: Bloom<Vec<u8>>) -> Self {
+        let keys = bloom.sip_keys();
+        Self {
+            capacity,
+            seed,
+            bits: BitVec::from_elem(bloom.number_of_bits() as usize, false),
+            bitmap_bits: bloom.number_of_bits(),
+            k_num: bloom.number_of_hash_functions(),
+            sips: keys.map(|(k0, k1)| SipHasher13::new_with_keys(k0, k1)),
+        }
+    }
+
+    fn positions(&self, item: &Vec<u8>) -> impl Iterator<Item = usize> + '_ {
+        let [h0, h1] = self.sips.map(|mut sip| {
+            item.hash(&mut sip);
+            sip.finish()
+        });
+        (0..self.k_num).map(move |k_i| {
+            let hash = match k_i {
+                0 => h0,
+                1 => h1,
+                _ => h0.wrapping_add((k_i as u64).wrapping_mul(h1)) % 0xFFFF_FFFF_FFFF_FFC5,
+            };
+            (hash % self.bitmap_bits) as usize
+        })
+    }
+
+    fn insert_all(&mut self, records: &[HashRecord]) {
+        let positions: Vec<usize> = records
+            .par_iter()
+            .flat_map_iter(|record| self.positions(&record.hash))
+            .collect();
+        for position in positions {
+            self.bits.set(position, true);
+        }
+    }
+
+    fn bitmap(&self) -> Vec<u8> {
+        self.bits.to_bytes()
+    }
+
+    fn number_of_bits(&self) -> u64 {
+        self.bitmap_bits
+    }
+
+    fn number_of_hash_functions(&self) -> u32 {
+        self.k_num
+    }
+
+    fn sip_keys(&self) -> [(u64, u64); 2] {
+        [self.sips[0].keys(), self.sips[1].keys()]
+    }
+}
+
+enum HashColumn<'a> {
+    Binary(&'a BinaryArray),
+    Hex(&'a StringArray),
+}
+
+impl<'a> HashColumn<'a> {
+    fn from_batch(batch: &'a RecordBatch, encoding: HashEncoding) -> Result<Self> {
+        let column = batch.column(0).as_any();
+        match encoding {
+            HashEncoding::Binary => column
+                .downcast_ref::<BinaryArray>()
+                .map(HashColumn::Binary)
+                .ok_or_else(|| anyhow!("Invalid schema: expected binary hash column")),
+            HashEncoding::Hex => column
+                .downcast_ref::<StringArray>()
+                .map(HashColumn::Hex)
+                .ok_or_else(|| anyhow!("Invalid schema: expected hex string hash column")),
+        }
+    }
+
+    fn matches(&self, i: usize, prefix: &[u8], bits: usize, hex_prefix: &str) -> bool {
+        match self {
+            HashColumn::Binary(array) => matches_bits(array.value(i), prefix, bits),
+            HashColumn::Hex(array) if bits.is_multiple_of(4) => array.value(i).starts_with(hex_prefix),
+            HashColumn::Hex(array) => hex::decode(array.value(i))
+                .is_ok_and(|hash| matches_bits(&hash, prefix, bits)),
+        }
+    }
+
+    fn value(&self, i: usize) -> Result<Vec<u8>> {
+        match self {
+            HashColumn::Binary(array) => Ok(array.value(i).to_vec()),
+            HashColumn::Hex(array) => hex::decode(array.value(i))
+                .map_err(|_| anyhow!("Invalid hex hash: {}", array.value(i))),
+        }
+    }
+}
+
+fn truncate_bits(hash: &[u8], bits: usize) -> Vec<u8> {
+    let mut truncated = hash[..bits.div_ceil(8).min(hash.len())].to_vec();
+    let spare = bits % 8;
+    if spare != 0 && bits < hash.len() * 8 {
+        if let Some(last) = truncated.last_mut() {
+            *last &= 0xFFu8 << (8 - spare);
+        }
+    }
+    truncated
+}
+
+pub(super) fn record_schema(encoding: HashEncoding) -> Arc<Schema> {
+    let hash_type = match encoding {
+        HashEncoding::Binary => DataType::Binary,
+        HashEncoding::Hex => DataType::Utf8,
+    };
+    Arc::new(Schema::new(vec![
+        Field::new("hash", hash_type, false),
+        Field::new("preimage", DataType::Utf8, false),
+        Field::new("algorithm", DataType::Utf8, false),
+        Field::new(
+            "sources",
+            DataType::List(Arc::new(Field::new("item", DataType::Utf8, false))),
+            false,
+        ),
+    ]))
+}
+
+pub(super) fn record_batch(schema: &Arc<Schema>, records: &[HashRecord]) -> Result<RecordBatch> {
+    let hashes: ArrayRef = match schema.field(0).data_type() {
+        DataType::Utf8 => Arc::new(StringArray::from_iter_values(
+            records.iter().map(|r| hex::encode(&r.hash)),
+        )),
+        _ => Arc::new(BinaryArray::from_iter_values(records.iter().map(|r| r.hash.as_slice()))),
+    };
+    let preimages: Vec<&str> = records.iter().map(|r| r.preimage.as_str()).collect();
+    let algorithms: Vec<&str> = records.iter().map(|r| r.algorithm.as_str()).collect();
+    let sources_array = ParquetStorage::build_sources_array(records);
+
+    Ok(RecordBatch::try_new(
+        schema.clone(),
+        vec![
+            hashes,
+            Arc::new(StringArray::from(preimages)),
+            Arc::new(StringArray::from(algorithms)),
+            sources_array,
+        ],
+    )?)
+}
+
 impl ParquetStorage {
     pub fn new(path: impl AsRef<Path>) -> Self {
         Self::with_expected_capacity(path, DEFAULT_BLOOM_CAPACITY)
@@ -71,18 +339,307 @@ impl ParquetStorage {
         Self {
             path: path.as_ref().to_path_buf(),
             writer: None,
-            schema: Arc::new(Schema::new(vec![
-                Field::new("hash", DataType::Binary, false),
-                Field::new("preimage", DataType::Utf8, false),
-                Field::new("algorithm", DataType::Utf8, false),
-                Field::new(
-                    "sources",
-                    DataType::List(Arc::new(Field::new("item", DataType::Utf8, false))),
-                    false,
-                ),
-            ])),
+            schema: record_schema(HashEncoding::Binary),
             write_stats: WriteStats::with_capacity(expected_records),
+            max_row_group_bytes: DEFAULT_MAX_ROW_GROUP_BYTES,
+            hash_encoding: HashEncoding::Binary,
+            prefix_bits: None,
+            coarse_index: false,
+            max_file_size: None,
+            estimated_bytes: 0,
+            dropped_records: 0,
+            partition_by_algorithm: false,
+            current_algorithm: None,
+            page_size: None,
+            page_rows: None,
+            data_page_version: None,
+            salt: None,
+            rounds: None,
+            minimal_metadata: false,
+            build_command: None,
+            bloom_cache: None,
+            timings: None,
+        }
+    }
+
+    // Decodes the bloom filter once instead of on every lookup, for handles
+    // that stay open across many queries. Rewrites of the file are not seen.
+    pub fn with_cached_bloom(mut self) -> Self {
+        self.bloom_cache = Some(OnceLock::new());
+        self
+    }
+
+    // Adds the per-phase time of every lookup to `timings`
+    pub fn with_timings(mut self, timings: SharedTimings) -> Self {
+        self.timings = Some(timings);
+        self
+    }
+
+    pub fn with_hash_encoding(mut self, encoding: HashEncoding) -> Self {
+        self.hash_encoding = encoding;
+        self.schema = record_schema(encoding);
+        self
+    }
+
+    // Sizes the bloom filter for exactly `items` records, without the usual
+    // minimum capacity. Must be set before the first write.
+    pub fn with_bloom_capacity(mut self, items: usize) -> Self {
+        self.write_stats.bloom = self.write_stats.bloom.resized(items.max(1));
+        self
+    }
+
+    // Must be set before the first write
+    pub fn with_bloom_seed(mut self, seed: &[u8; 32]) -> Self {
+        self.write_stats.bloom = BloomBuilder::with_seed(self.write_stats.bloom.capacity, seed);
+        self
+    }
+
+    pub fn with_prefix_bits(mut self, bits: usize) -> Self {
+        self.prefix_bits = Some(bits);
+        self
+    }
+
+    pub fn with_coarse_index(mut self) -> Self {
+        self.coarse_index = true;
+        self
+    }
+
+    // Starts a new row group whenever the algorithm changes and records each
+    // group's algorithm, so --algo queries skip other algorithms' groups.
+    // Records should arrive sorted by algorithm first.
+    pub fn with_algorithm_partitions(mut self) -> Self {
+        self.partition_by_algorithm = true;
+        self
+    }
+
+    // Records past the cap are dropped rather than written. The estimate
+    // uses uncompressed sizes, so the final file lands under the cap.
+    pub fn with_max_file_size(mut self, bytes: u64) -> Self {
+        self.max_file_size = Some(bytes);
+        self
+    }
+
+    // Skips the bloom filter, stats, sources and other optional metadata;
+    // only what is needed to read the hashes back is kept. Queries then rely
+    // on parquet's own column statistics, so full-hash lookups always scan
+    // the candidate row groups.
+    pub fn with_minimal_metadata(mut self) -> Self {
+        self.minimal_metadata = true;
+        self
+    }
+
+    // Stored as-is, so callers must redact credentials first
+    pub fn with_build_command(mut self, command: impl Into<String>) -> Self {
+        self.build_command = Some(command.into());
+        self
+    }
+
+    pub fn dropped_records(&self) -> usize {
+        self.dropped_records
+    }
+
+    // How many leading records fit in a file of `max_bytes`, with a bloom
+    // filter sized for just those records (see with_bloom_capacity)
+    pub fn records_within(&self, records: &[HashRecord], max_bytes: u64) -> usize {
+        let mut estimated = 0u64;
+        for (i, record) in records.iter().enumerate() {
+            estimated += Self::record_size(record) as u64 + RECORD_OVERHEAD_BYTES;
+            let bloom_bits = Bloom::<Vec<u8>>::compute_bitmap_size(i + 1, BLOOM_FP_RATE) as u64 * 8;
+            if estimated + self.footer_reserve_for(bloom_bits) > max_bytes {
+                return i;
+            }
         }
+        records.len()
+    }
+
+    fn footer_reserve(&self) -> u64 {
+        self.footer_reserve_for(self.write_stats.bloom.number_of_bits())
+    }
+
+    fn footer_reserve_for(&self, bloom_bits: u64) -> u64 {
+        if self.minimal_metadata {
+            return FOOTER_SLACK_BYTES;
+        }
+        let bloom_bytes = bloom_bits.div_ceil(8);
+        let source_hashes: usize = self.write_stats.source_hashes.iter().map(|h| h.len() + 1).sum();
+        let source_meta = serde_json::to_string(&self.write_stats.source_meta)
+            .map(|json| json.len())
+            .unwrap_or_default();
+        bloom_bytes.div_ceil(3) * 4 + (source_hashes + source_meta) as u64 + FOOTER_SLACK_BYTES
+    }
+
+    fn take_within_budget(&mut self, mut records: Vec<HashRecord>) -> Vec<HashRecord> {
+        let Some(cap) = self.max_file_size else {
+            return records;
+        };
+
+        let budget = cap.saturating_sub(self.footer_reserve());
+        let mut keep = 0;
+        if self.dropped_records == 0 {
+            for record in &records {
+                let size = Self::record_size(record) as u64 + RECORD_OVERHEAD_BYTES;
+                if self.estimated_bytes + size > budget {
+                    break;
+                }
+                self.estimated_bytes += size;
+                keep += 1;
+            }
+        }
+
+        self.dropped_records += records.len() - keep;
+        records.truncate(keep);
+        records
+    }
+
+    fn metadata_value(metadata: &ParquetMetaData, key: &str) -> Option<String> {
+        metadata
+            .file_metadata()
+            .key_value_metadata()
+            .and_then(|kvs| kvs.iter().find(|kv| kv.key == key))
+            .and_then(|kv| kv.value.clone())
+    }
+
+    fn read_prefix_bits(metadata: &ParquetMetaData) -> Option<usize> {
+        metadata
+            .file_metadata()
+            .key_value_metadata()
+            .and_then(|kvs| kvs.iter().find(|kv| kv.key == META_PREFIX_BITS))
+            .and_then(|kv| kv.value.as_deref())
+            .and_then(|v| v.parse().ok())
+    }
+
+    fn effective_prefix(metadata: &ParquetMetaData, hash_prefix: &[u8], bits: usize) -> (Vec<u8>, usize) {
+        let bits = match Self::read_prefix_bits(metadata) {
+            Some(stored_bits) => bits.min(stored_bits),
+            None => bits,
+        };
+        (truncate_bits(hash_prefix, bits), bits)
+    }
+
+    fn read_hash_encoding(metadata: &ParquetMetaData) -> HashEncoding {
+        let value = metadata
+            .file_metadata()
+            .key_value_metadata()
+            .and_then(|kvs| kvs.iter().find(|kv| kv.key == META_HASH_ENCODING))
+            .and_then(|kv| kv.value.as_deref());
+
+        match value {
+            Some("hex") => HashEncoding::Hex,
+            _ => HashEncoding::Binary,
+        }
+    }
+
+    pub fn with_max_row_group_bytes(mut self, bytes: usize) -> Self {
+        self.max_row_group_bytes = bytes.max(1);
+        self
+    }
+
+    // Smaller pages help point lookups, larger ones help scans.
+    pub fn with_page_size(mut self, bytes: usize) -> Self {
+        self.page_size = Some(bytes.max(1));
+        self
+    }
+
+    pub fn with_page_rows(mut self, rows: usize) -> Self {
+        self.page_rows = Some(rows.max(1));
+        self
+    }
+
+    // 1 writes DataPage V1 (parquet 1.0), 2 writes DataPage V2 (parquet 2.0).
+    pub fn with_data_page_version(mut self, version: u8) -> Self {
+        self.data_page_version = Some(version);
+        self
+    }
+
+    // Recorded so readers know the stored hashes are hash(salt || word) or
+    // hash(word || salt)
+    pub fn with_salt(mut self, salt: &[u8], position: SaltPosition) -> Self {
+        self.salt = Some((salt.to_vec(), position));
+        self
+    }
+
+    pub fn get_salt(&self) -> Result<Option<(Vec<u8>, SaltPosition)>> {
+        if !self.path.exists() {
+            return Ok(None);
+        }
+
+        let file = File::open(&self.path)
+            .with_context(|| format!("Failed to open database: {:?}", self.path))?;
+        let builder = ParquetRecordBatchReaderBuilder::try_new(file)?;
+        let value = |key: &str| Self::metadata_value(builder.metadata(), key);
+
+        let Some(salt) = value(META_SALT) else {
+            return Ok(None);
+        };
+        let salt = hex::decode(&salt).with_context(|| format!("Invalid stored salt: {}", salt))?;
+        let position = value(META_SALT_POSITION)
+            .as_deref()
+            .and_then(SaltPosition::parse)
+            .unwrap_or(SaltPosition::Prefix);
+        Ok(Some((salt, position)))
+    }
+
+    // Recorded so readers know each stored hash is the digest iterated N times
+    pub fn with_rounds(mut self, rounds: u32) -> Self {
+        self.rounds = Some(rounds);
+        self
+    }
+
+    pub fn get_rounds(&self) -> Result<Option<u32>> {
+        if !self.path.exists() {
+            return Ok(None);
+        }
+
+        let file = File::open(&self.path)
+            .with_context(|| format!("Failed to open database: {:?}", self.path))?;
+        let builder = ParquetRecordBatchReaderBuilder::try_new(file)?;
+        Self::metadata_value(builder.metadata(), META_ROUNDS)
+            .map(|rounds| {
+                rounds
+                    .parse()
+                    .with_context(|| format!("Invalid stored round count: {}", rounds))
+            })
+            .transpose()
+    }
+
+    pub fn get_hash_encoding(&self) -> Result<HashEncoding> {
+        if !self.path.exists() {
+            return Ok(HashEncoding::default());
+        }
+
+        let file = File::open(&self.path)
+            .with_context(|| format!("Failed to open database: {:?}", self.path))?;
+        let builder = ParquetRecordBatchReaderBuilder::try_new(file)?;
+        Ok(Self::read_hash_encoding(builder.metadata()))
+    }
+
+    pub fn get_prefix_bits(&self) -> Result<Option<usize>> {
+        if !self.path.exists() {
+            return Ok(None);
+        }
+
+        let file = File::open(&self.path)
+            .with_context(|| format!("Failed to open database: {:?}", self.path))?;
+        let builder = ParquetRecordBatchReaderBuilder::try_new(file)?;
+        Ok(Self::read_prefix_bits(builder.metadata()))
+    }
+
+    fn writer_properties(&self) -> WriterProperties {
+        let mut props = WriterProperties::builder()
+            .set_compression(Compression::ZSTD(Default::default()));
+        if let Some(bytes) = self.page_size {
+            props = props.set_data_page_size_limit(bytes);
+        }
+        if let Some(rows) = self.page_rows {
+            // Page limits are only checked between write batches
+            props = props
+                .set_data_page_row_count_limit(rows)
+                .set_write_batch_size(rows.min(DEFAULT_WRITE_BATCH_SIZE));
+        }
+        if self.data_page_version == Some(2) {
+            props = props.set_writer_version(WriterVersion::PARQUET_2_0);
+        }
+        props.build()
     }
 
     fn ensure_writer(&mut self) -> Result<&mut ArrowWriter<File>> {
@@ -90,16 +647,14 @@ impl ParquetStorage {
             let file = File::create(&self.path)
                 .with_context(|| format!("Failed to create file: {:?}", self.path))?;
 
-            let props = WriterProperties::builder()
-                .set_compression(Compression::ZSTD(Default::default()))
-                .build();
+            let props = self.writer_properties();
 
             self.writer = Some(ArrowWriter::try_new(file, self.schema.clone(), Some(props))?);
         }
         Ok(self.writer.as_mut().expect("writer initialized above"))
     }
 
-    fn build_sources_array(records: &[HashRecord]) -> ArrayRef {
+    pub(super) fn build_sources_array(records: &[HashRecord]) -> ArrayRef {
         let mut all_sources: Vec<&str> = Vec::new();
         let mut offsets: Vec<i32> = vec![0];
 
@@ -121,25 +676,57 @@ impl ParquetStorage {
         ))
     }
 
-    fn extract_sources(list_array: &ListArray, index: usize) -> Vec<String> {
-        let start = list_array.value_offsets()[index] as usize;
-        let end = list_array.value_offsets()[index + 1] as usize;
+    // Files written by other tools may omit the sources column or mark it
+    // nullable; both read back as records without sources.
+    fn sources_column(batch: &RecordBatch) -> Result<Option<&ListArray>> {
+        batch
+            .column_by_name("sources")
+            .map(|column| {
+                column
+                    .as_any()
+                    .downcast_ref::<ListArray>()
+                    .ok_or_else(|| anyhow!("Invalid schema: expected list sources column"))
+            })
+            .transpose()
+    }
 
-        let values = list_array
-            .values()
-            .as_any()
-            .downcast_ref::<StringArray>()
-            .unwrap();
+    fn extract_sources(list_array: Option<&ListArray>, index: usize) -> Vec<String> {
+        let Some(list_array) = list_array.filter(|list| list.is_valid(index)) else {
+            return Vec::new();
+        };
+        let Some(values) = list_array.values().as_any().downcast_ref::<StringArray>() else {
+            return Vec::new();
+        };
 
+        let start = list_array.value_offsets()[index] as usize;
+        let end = list_array.value_offsets()[index + 1] as usize;
         (start..end)
+            .filter(|&i| values.is_valid(i))
             .map(|i| values.value(i).to_string())
             .collect()
     }
 
     fn collect_stats(&mut self, records: &[HashRecord]) {
         self.write_stats.total_records += records.len();
+        let carried = self.write_stats.carried;
+        if !self.minimal_metadata && !carried {
+            self.write_stats.bloom.insert_all(records);
+        }
         for record in records {
-            self.write_stats.bloom.set(&record.hash);
+            if self.coarse_index {
+                let first = record.hash.first().copied().unwrap_or(0);
+                match self.write_stats.first_byte_runs.last_mut() {
+                    Some((byte, count)) if *byte == first => *count += 1,
+                    _ => self.write_stats.first_byte_runs.push((first, 1)),
+                }
+            }
+            // Only written to the full metadata
+            if !self.minimal_metadata {
+                self.write_stats.preimages.insert(&record.preimage);
+            }
+            if carried {
+                continue;
+            }
             self.write_stats
                 .algorithms
                 .insert(record.algorithm.clone());
@@ -149,6 +736,39 @@ impl ParquetStorage {
         }
     }
 
+    // For appends: starts from `existing`'s bloom filter, algorithms and
+    // sources and adds only `touched` (the new and merged records), so the
+    // existing rows are rewritten without being hashed into the bloom again.
+    // Returns false and changes nothing when that metadata cannot be reused:
+    // no bloom, a bloom smaller than this write needs, or keys other than
+    // the --bloom-seed ones.
+    pub fn extend_metadata(&mut self, existing: &ParquetStorage, touched: &[HashRecord]) -> Result<bool> {
+        if self.minimal_metadata || self.prefix_bits.is_some() || self.writer.is_some() {
+            return Ok(false);
+        }
+        let (Some(stats), Some(bloom)) = (existing.read_stats_from_metadata()?, existing.read_bloom_filter()?) else {
+            return Ok(false);
+        };
+        let wanted = &self.write_stats.bloom;
+        if bloom.number_of_bits() < wanted.number_of_bits()
+            || (wanted.seed.is_some() && bloom.sip_keys() != wanted.sip_keys())
+        {
+            return Ok(false);
+        }
+
+        let mut builder = BloomBuilder::extending(wanted.capacity, wanted.seed, &bloom);
+        builder.insert_all(touched);
+        self.write_stats.bloom = builder;
+        self.write_stats.algorithms.extend(stats.algorithms);
+        self.write_stats.sources.extend(stats.sources);
+        for record in touched {
+            self.write_stats.algorithms.insert(record.algorithm.clone());
+            self.write_stats.sources.extend(record.sources.iter().cloned());
+        }
+        self.write_stats.carried = true;
+        Ok(true)
+    }
+
     fn read_stats_from_metadata(&self) -> Result<Option<Stats>> {
         let file = File::open(&self.path)
             .with_context(|| format!("Failed to open database: {:?}", self.path))?;
@@ -162,6 +782,8 @@ impl ParquetStorage {
         };
 
         let mut total_records = None;
+        let mut distinct_preimages = None;
+        let mut collisions = None;
         let mut algorithms = None;
         let mut sources = None;
 
@@ -170,6 +792,12 @@ impl ParquetStorage {
                 META_TOTAL_RECORDS => {
                     total_records = kv.value.as_ref().and_then(|v| v.parse().ok());
                 }
+                META_DISTINCT_PREIMAGES => {
+                    distinct_preimages = kv.value.as_ref().and_then(|v| v.parse().ok());
+                }
+                META_COLLISIONS => {
+                    collisions = kv.value.as_ref().and_then(|v| v.parse().ok());
+                }
                 META_ALGORITHMS => {
                     algorithms = kv.value.as_ref().map(|v| {
                         v.split(',')
@@ -193,6 +821,8 @@ impl ParquetStorage {
         match (total_records, algorithms, sources) {
             (Some(total_records), Some(algorithms), Some(sources)) => Ok(Some(Stats {
                 total_records,
+                distinct_preimages,
+                collisions,
                 algorithms,
                 sources,
                 file_size_bytes: file_size,
@@ -201,7 +831,18 @@ impl ParquetStorage {
         }
     }
 
-    fn load_bloom_filter(&self) -> Result<Option<Bloom<Vec<u8>>>> {
+    fn load_bloom_filter(&self) -> Result<Option<SharedBloom>> {
+        let Some(cache) = &self.bloom_cache else {
+            return Ok(self.read_bloom_filter()?.map(Arc::new));
+        };
+        if let Some(bloom) = cache.get() {
+            return Ok(bloom.clone());
+        }
+        let bloom = self.read_bloom_filter()?.map(Arc::new);
+        Ok(cache.get_or_init(|| bloom).clone())
+    }
+
+    fn read_bloom_filter(&self) -> Result<Option<Bloom<Vec<u8>>>> {
         let file = File::open(&self.path)?;
         let builder = ParquetRecordBatchReaderBuilder::try_new(file)?;
         let metadata = builder.metadata().file_metadata().key_value_metadata();
@@ -212,14 +853,14 @@ impl ParquetStorage {
 
         let mut bitmap: Option<Vec<u8>> = None;
         let mut keys: Option<[(u64, u64); 2]> = None;
-        let mut items_count: Option<u32> = None;
+        let mut hash_count: Option<u32> = None;
 
         for kv in metadata {
             match kv.key.as_str() {
+                // Corrupt bloom metadata disables the filter; queries
+                // still answer correctly by scanning.
                 META_BLOOM_BITMAP => {
-                    if let Some(ref encoded) = kv.value {
-                        bitmap = Some(BASE64.decode(encoded)?);
-                    }
+                    bitmap = kv.value.as_ref().and_then(|encoded| BASE64.decode(encoded).ok());
                 }
                 META_BLOOM_KEYS => {
                     if let Some(ref keys_str) = kv.value {
@@ -232,17 +873,17 @@ impl ParquetStorage {
                         }
                     }
                 }
-                META_BLOOM_ITEMS => {
+                META_BLOOM_HASHES => {
                     if let Some(ref count_str) = kv.value {
-                        items_count = count_str.parse().ok();
+                        hash_count = count_str.parse().ok();
                     }
                 }
                 _ => {}
             }
         }
 
-        match (bitmap, keys, items_count) {
-            (Some(bytes), Some(sip_keys), Some(count)) => {
+        match (bitmap, keys, hash_count) {
+            (Some(bytes), Some(sip_keys), Some(count)) if !bytes.is_empty() && count > 0 => {
                 let bloom = Bloom::from_existing(
                     &bytes,
                     (bytes.len() * 8) as u64,
@@ -255,8 +896,170 @@ impl ParquetStorage {
         }
     }
 
-    fn is_full_hash_length(len: usize) -> bool {
-        matches!(len, 16 | 20 | 32 | 64)
+    fn first_byte_bitmaps(runs: &[(u8, usize)], row_groups: &[RowGroupMetaData]) -> Vec<u8> {
+        let mut bitmaps = vec![0u8; row_groups.len() * FIRST_BYTE_BITMAP_LEN];
+        let mut runs = runs.iter().copied();
+        let mut current = runs.next();
+
+        for (group, rg) in row_groups.iter().enumerate() {
+            let mut remaining = rg.num_rows() as usize;
+            while remaining > 0 {
+                let Some((byte, count)) = current.as_mut() else {
+                    break;
+                };
+                bitmaps[group * FIRST_BYTE_BITMAP_LEN + *byte as usize / 8] |= 1 << (*byte % 8);
+                let taken = (*count).min(remaining);
+                *count -= taken;
+                remaining -= taken;
+                if *count == 0 {
+                    current = runs.next();
+                }
+            }
+        }
+
+        bitmaps
+    }
+
+    // Single-algorithm groups are named; mixed or unknown groups stay empty.
+    fn row_group_algorithms(row_groups: &[RowGroupMetaData]) -> Vec<String> {
+        row_groups
+            .iter()
+            .map(|rg| match rg.column(2).statistics() {
+                Some(Statistics::ByteArray(stats)) => match (stats.min_opt(), stats.max_opt()) {
+                    (Some(min), Some(max)) if min == max => {
+                        String::from_utf8_lossy(min.data()).into_owned()
+                    }
+                    _ => String::new(),
+                },
+                _ => String::new(),
+            })
+            .collect()
+    }
+
+    fn filter_row_groups_by_algorithm(
+        metadata: &ParquetMetaData,
+        row_groups: Vec<usize>,
+        algo: Option<&str>,
+    ) -> Vec<usize> {
+        let Some(algo) = algo else {
+            return row_groups;
+        };
+        let Some(value) = metadata
+            .file_metadata()
+            .key_value_metadata()
+            .and_then(|kvs| kvs.iter().find(|kv| kv.key == META_ROW_GROUP_ALGORITHMS))
+            .and_then(|kv| kv.value.as_deref())
+        else {
+            return row_groups;
+        };
+
+        let algorithms: Vec<&str> = value.split(',').collect();
+        if algorithms.len() != metadata.num_row_groups() {
+            return row_groups;
+        }
+        row_groups
+            .into_iter()
+            .filter(|&group| algorithms[group].is_empty() || algorithms[group] == algo)
+            .collect()
+    }
+
+    fn read_bloom_items(metadata: &ParquetMetaData) -> Option<usize> {
+        metadata
+            .file_metadata()
+            .key_value_metadata()
+            .and_then(|kvs| kvs.iter().find(|kv| kv.key == META_BLOOM_ITEMS))
+            .and_then(|kv| kv.value.as_deref())
+            .and_then(|v| v.parse().ok())
+    }
+
+    pub fn bloom_stats(&self) -> Result<Option<BloomStats>> {
+        if !self.path.exists() {
+            return Ok(None);
+        }
+        let Some(bloom) = self.load_bloom_filter()? else {
+            return Ok(None);
+        };
+
+        let file = File::open(&self.path)?;
+        let builder = ParquetRecordBatchReaderBuilder::try_new(file)?;
+        let set_bits = bloom
+            .bitmap()
+            .iter()
+            .map(|byte| u64::from(byte.count_ones()))
+            .sum();
+
+        Ok(Some(BloomStats {
+            items: Self::read_bloom_items(builder.metadata()),
+            bits: bloom.number_of_bits(),
+            hash_functions: bloom.number_of_hash_functions(),
+            set_bits,
+        }))
+    }
+
+    fn read_first_byte_index(metadata: &ParquetMetaData) -> Option<Vec<u8>> {
+        let encoded = metadata
+            .file_metadata()
+            .key_value_metadata()?
+            .iter()
+            .find(|kv| kv.key == META_FIRST_BYTE_INDEX)?
+            .value
+            .as_ref()?;
+        let bitmaps = BASE64.decode(encoded).ok()?;
+        (bitmaps.len() == metadata.num_row_groups() * FIRST_BYTE_BITMAP_LEN).then_some(bitmaps)
+    }
+
+    fn select_row_groups(metadata: &ParquetMetaData, hash_prefix: &[u8], stats_prefix: &[u8]) -> Vec<usize> {
+        if let Some(&first) = hash_prefix.first().filter(|_| hash_prefix.len() <= COARSE_INDEX_MAX_PREFIX) {
+            if let Some(bitmaps) = Self::read_first_byte_index(metadata) {
+                let (offset, bit) = (first as usize / 8, 1u8 << (first % 8));
+                return (0..metadata.num_row_groups())
+                    .filter(|group| bitmaps[group * FIRST_BYTE_BITMAP_LEN + offset] & bit != 0)
+                    .collect();
+            }
+        }
+
+        let mut matching_row_groups = Vec::new();
+
+        for (i, rg) in metadata.row_groups().iter().enumerate() {
+            let dominated_by_statistics = rg.column(0).statistics().and_then(|stats| {
+                if let Statistics::ByteArray(byte_stats) = stats {
+                    let min = byte_stats.min_opt()?;
+                    let max = byte_stats.max_opt()?;
+                    Some(Self::prefix_might_be_in_range(stats_prefix, min.data(), max.data()))
+                } else {
+                    None
+                }
+            });
+
+            if dominated_by_statistics.unwrap_or(true) {
+                matching_row_groups.push(i);
+            }
+        }
+
+        matching_row_groups
+    }
+
+    pub fn candidate_row_groups(&self, hash_prefix: &[u8], algo: Option<&str>) -> Result<Vec<usize>> {
+        let file = File::open(&self.path)
+            .with_context(|| format!("Failed to open database: {:?}", self.path))?;
+        let builder = ParquetRecordBatchReaderBuilder::try_new(file)?;
+        let metadata = builder.metadata();
+        let (prefix, bits) = Self::effective_prefix(metadata, hash_prefix, hash_prefix.len() * 8);
+        let whole = &prefix[..bits / 8];
+        let hex_prefix = hex::encode(&prefix);
+        let stats_prefix = match Self::read_hash_encoding(metadata) {
+            HashEncoding::Binary => whole,
+            HashEncoding::Hex => &hex_prefix.as_bytes()[..bits / 4],
+        };
+        let row_groups = Self::select_row_groups(metadata, whole, stats_prefix);
+        Ok(Self::filter_row_groups_by_algorithm(metadata, row_groups, algo))
+    }
+
+    // The bloom filter holds whole hashes, so it can only reject a query that
+    // is a whole hash of the requested algorithm. Without --algo a 16-byte
+    // query may be an md5 or the prefix of a longer hash in the same file.
+    fn is_full_hash_for(len: usize, algo: Option<&str>) -> bool {
+        algo.and_then(hasher::algorithm_output_len) == Some(len)
     }
 
     fn prefix_might_be_in_range(prefix: &[u8], min: &[u8], max: &[u8]) -> bool {
@@ -264,17 +1067,93 @@ impl ParquetStorage {
             return true;
         }
 
+        // Pad to the longer bound: with mixed hash lengths `min` can be a
+        // longer hash than `max`
         let prefix_low = prefix;
         let mut prefix_high: Vec<u8> = prefix.to_vec();
-        prefix_high.resize(max.len().max(prefix.len()), 0xFF);
+        prefix_high.resize(min.len().max(max.len()).max(prefix.len()), 0xFF);
 
         max >= prefix_low && min <= prefix_high.as_slice()
     }
 
+    fn record_size(record: &HashRecord) -> usize {
+        record.hash.len()
+            + record.preimage.len()
+            + record.algorithm.len()
+            + record.sources.iter().map(String::len).sum::<usize>()
+    }
+
+    fn split_by_size(records: &[HashRecord], max_bytes: usize) -> Vec<&[HashRecord]> {
+        let mut chunks = Vec::new();
+        let mut start = 0;
+        let mut size = 0;
+
+        for (i, record) in records.iter().enumerate() {
+            let record_size = Self::record_size(record);
+            if i > start && size + record_size > max_bytes {
+                chunks.push(&records[start..i]);
+                start = i;
+                size = 0;
+            }
+            size += record_size;
+        }
+
+        chunks.push(&records[start..]);
+        chunks
+    }
+
+    fn to_record_batch(&self, records: &[HashRecord]) -> Result<RecordBatch> {
+        record_batch(&self.schema, records)
+    }
+
+    // Returns which of the given (hash, algorithm) keys are stored in this
+    // database. The bloom filter clears most keys without reading rows; the
+    // remaining candidates are verified in one scan.
+    pub fn present_keys<'a>(
+        &self,
+        keys: impl IntoIterator<Item = &'a (Vec<u8>, String)>,
+    ) -> Result<HashSet<(Vec<u8>, String)>> {
+        let bloom = self.load_bloom_filter().ok().flatten();
+        let candidates: HashSet<(Vec<u8>, String)> = keys
+            .into_iter()
+            .filter(|(hash, _)| bloom.as_ref().is_none_or(|bloom| bloom.check(hash)))
+            .cloned()
+            .collect();
+        let mut present = HashSet::new();
+        if candidates.is_empty() {
+            return Ok(present);
+        }
+
+        self.for_each_record(|record| {
+            let key = (record.hash, record.algorithm);
+            if candidates.contains(&key) {
+                present.insert(key);
+            }
+            Ok(())
+        })?;
+        Ok(present)
+    }
+
+    // Drops every key already stored in this database and returns how many
+    // were dropped.
+    pub fn retain_absent<V>(&self, records: &mut HashMap<(Vec<u8>, String), V>) -> Result<usize> {
+        let present = self.present_keys(records.keys())?;
+        records.retain(|key, _| !present.contains(key));
+        Ok(present.len())
+    }
+
     pub fn add_source_hash(&mut self, hash: &str) {
         self.write_stats.source_hashes.insert(hash.to_string());
     }
 
+    pub fn add_source_meta(&mut self, source: &str, meta: SourceMeta) {
+        self.write_stats.source_meta.insert(source.to_string(), meta);
+    }
+
+    pub fn add_collisions(&mut self, collisions: usize) {
+        self.write_stats.collisions += collisions;
+    }
+
     pub fn for_each_record<F>(&self, mut callback: F) -> Result<()>
     where
         F: FnMut(HashRecord) -> Result<()>,
@@ -286,16 +1165,13 @@ impl ParquetStorage {
         let file = File::open(&self.path)
             .with_context(|| format!("Failed to open database: {:?}", self.path))?;
         let builder = ParquetRecordBatchReaderBuilder::try_new(file)?;
+        let encoding = Self::read_hash_encoding(builder.metadata());
         let reader = builder.build()?;
 
         for batch_result in reader {
             let batch = batch_result?;
 
-            let hashes = batch
-                .column(0)
-                .as_any()
-                .downcast_ref::<BinaryArray>()
-                .ok_or_else(|| anyhow!("Invalid schema: expected binary hash column"))?;
+            let hashes = HashColumn::from_batch(&batch, encoding)?;
             let preimages = batch
                 .column(1)
                 .as_any()
@@ -306,15 +1182,11 @@ impl ParquetStorage {
                 .as_any()
                 .downcast_ref::<StringArray>()
                 .ok_or_else(|| anyhow!("Invalid schema: expected string algorithm column"))?;
-            let sources = batch
-                .column(3)
-                .as_any()
-                .downcast_ref::<ListArray>()
-                .ok_or_else(|| anyhow!("Invalid schema: expected list sources column"))?;
+            let sources = Self::sources_column(&batch)?;
 
             for i in 0..batch.num_rows() {
                 let record = HashRecord {
-                    hash: hashes.value(i).to_vec(),
+                    hash: hashes.value(i)?,
                     preimage: preimages.value(i).to_string(),
                     algorithm: algorithms.value(i).to_string(),
                     sources: Self::extract_sources(sources, i),
@@ -350,6 +1222,79 @@ impl ParquetStorage {
         Ok(HashSet::new())
     }
 
+    pub fn get_source_meta(&self) -> Result<BTreeMap<String, SourceMeta>> {
+        if !self.path.exists() {
+            return Ok(BTreeMap::new());
+        }
+
+        let file = File::open(&self.path)?;
+        let builder = ParquetRecordBatchReaderBuilder::try_new(file)?;
+        let metadata = builder.metadata().file_metadata().key_value_metadata();
+
+        let Some(metadata) = metadata else {
+            return Ok(BTreeMap::new());
+        };
+
+        for kv in metadata {
+            if kv.key == META_SOURCE_META {
+                if let Some(ref json) = kv.value {
+                    return Ok(serde_json::from_str(json).unwrap_or_default());
+                }
+            }
+        }
+
+        Ok(BTreeMap::new())
+    }
+
+    pub fn manifest(&self) -> Result<Manifest> {
+        let stats = self.stats()?;
+        let source_meta = self.get_source_meta()?;
+        let mut source_hashes: Vec<String> = self.get_source_hashes()?.into_iter().collect();
+        source_hashes.sort();
+
+        let file = File::open(&self.path)
+            .with_context(|| format!("Failed to open database: {:?}", self.path))?;
+        let builder = ParquetRecordBatchReaderBuilder::try_new(file)?;
+        let value = |key: &str| Self::metadata_value(builder.metadata(), key);
+
+        Ok(Manifest {
+            database: self.path.file_name().unwrap_or_default().to_string_lossy().to_string(),
+            total_records: stats.total_records,
+            distinct_preimages: stats.distinct_preimages,
+            collisions: stats.collisions,
+            algorithms: stats.algorithms,
+            sources: stats
+                .sources
+                .into_iter()
+                .map(|name| {
+                    let meta = source_meta.get(&name).cloned().unwrap_or_default();
+                    (name, meta)
+                })
+                .collect(),
+            source_hashes,
+            file_size_bytes: stats.file_size_bytes,
+            built_at: value(META_BUILT_AT),
+            shaha_version: value(META_VERSION),
+            build_command: value(META_BUILD_COMMAND),
+        })
+    }
+
+    pub fn get_provenance(&self) -> Result<Provenance> {
+        if !self.path.exists() {
+            return Ok(Provenance::default());
+        }
+
+        let file = File::open(&self.path)
+            .with_context(|| format!("Failed to open database: {:?}", self.path))?;
+        let builder = ParquetRecordBatchReaderBuilder::try_new(file)?;
+        let value = |key: &str| Self::metadata_value(builder.metadata(), key);
+        Ok(Provenance {
+            shaha_version: value(META_VERSION),
+            built_at: value(META_BUILT_AT),
+            build_command: value(META_BUILD_COMMAND),
+        })
+    }
+
     fn scan_stats(&self) -> Result<Stats> {
         let file = File::open(&self.path)
             .with_context(|| format!("Failed to open database: {:?}", self.path))?;
@@ -359,6 +1304,7 @@ impl ParquetStorage {
         let reader = builder.build()?;
 
         let mut total_records = 0;
+        let mut preimages = PreimageSet::default();
         let mut algorithms = HashSet::new();
         let mut sources = HashSet::new();
 
@@ -366,18 +1312,21 @@ impl ParquetStorage {
             let batch = batch_result?;
             total_records += batch.num_rows();
 
+            let preimage_array = batch
+                .column(1)
+                .as_any()
+                .downcast_ref::<StringArray>()
+                .ok_or_else(|| anyhow!("Invalid schema: expected string preimage column"))?;
+
             let algo_array = batch
                 .column(2)
                 .as_any()
                 .downcast_ref::<StringArray>()
                 .ok_or_else(|| anyhow!("Invalid schema: expected string algorithm column"))?;
-            let sources_array = batch
-                .column(3)
-                .as_any()
-                .downcast_ref::<ListArray>()
-                .ok_or_else(|| anyhow!("Invalid schema: expected list sources column"))?;
+            let sources_array = Self::sources_column(&batch)?;
 
             for i in 0..batch.num_rows() {
+                preimages.insert(preimage_array.value(i));
                 algorithms.insert(algo_array.value(i).to_string());
                 for source in Self::extract_sources(sources_array, i) {
                     sources.insert(source);
@@ -387,6 +1336,8 @@ impl ParquetStorage {
 
         Ok(Stats {
             total_records,
+            distinct_preimages: Some(preimages.len()),
+            collisions: None,
             algorithms: algorithms.into_iter().collect(),
             sources: sources.into_iter().collect(),
             file_size_bytes: file_size,
@@ -400,25 +1351,46 @@ impl Storage for ParquetStorage {
             return Ok(());
         }
 
-        self.collect_stats(&records);
+        let records = match self.prefix_bits {
+            Some(bits) => records
+                .into_iter()
+                .map(|mut record| {
+                    record.hash = truncate_bits(&record.hash, bits);
+                    record
+                })
+                .collect(),
+            None => records,
+        };
+
+        let records = self.take_within_budget(records);
+        if records.is_empty() {
+            self.ensure_writer()?;
+            return Ok(());
+        }
 
-        let hashes: Vec<&[u8]> = records.iter().map(|r| r.hash.as_slice()).collect();
-        let preimages: Vec<&str> = records.iter().map(|r| r.preimage.as_str()).collect();
-        let algorithms: Vec<&str> = records.iter().map(|r| r.algorithm.as_str()).collect();
-        let sources_array = Self::build_sources_array(&records);
+        self.collect_stats(&records);
 
-        let batch = RecordBatch::try_new(
-            self.schema.clone(),
-            vec![
-                Arc::new(BinaryArray::from(hashes)),
-                Arc::new(StringArray::from(preimages)),
-                Arc::new(StringArray::from(algorithms)),
-                sources_array,
-            ],
-        )?;
+        let max_bytes = self.max_row_group_bytes;
+        let partitioned = self.partition_by_algorithm;
+        for chunk in Self::split_by_size(&records, max_bytes) {
+            for run in chunk.chunk_by(|a, b| !partitioned || a.algorithm == b.algorithm) {
+                let run_bytes: usize = run.iter().map(Self::record_size).sum();
+                let batch = self.to_record_batch(run)?;
+
+                let new_partition = partitioned
+                    && self.current_algorithm.as_deref() != Some(run[0].algorithm.as_str());
+                if new_partition {
+                    self.current_algorithm = Some(run[0].algorithm.clone());
+                }
 
-        let writer = self.ensure_writer()?;
-        writer.write(&batch)?;
+                let writer = self.ensure_writer()?;
+                let too_big = writer.in_progress_size() + run_bytes > max_bytes;
+                if writer.in_progress_rows() > 0 && (too_big || new_partition) {
+                    writer.flush()?;
+                }
+                writer.write(&batch)?;
+            }
+        }
 
         Ok(())
     }
@@ -428,10 +1400,98 @@ impl Storage for ParquetStorage {
             let algorithms: Vec<_> = self.write_stats.algorithms.iter().collect();
             let sources: Vec<_> = self.write_stats.sources.iter().collect();
 
+            writer.append_key_value_metadata(parquet::format::KeyValue {
+                key: META_HASH_ENCODING.to_string(),
+                value: Some(self.hash_encoding.as_str().to_string()),
+            });
+            if self.coarse_index {
+                writer.flush()?;
+                let bitmaps = Self::first_byte_bitmaps(
+                    &self.write_stats.first_byte_runs,
+                    writer.flushed_row_groups(),
+                );
+                writer.append_key_value_metadata(parquet::format::KeyValue {
+                    key: META_FIRST_BYTE_INDEX.to_string(),
+                    value: Some(BASE64.encode(bitmaps)),
+                });
+            }
+            if self.partition_by_algorithm {
+                writer.flush()?;
+                let algorithms = Self::row_group_algorithms(writer.flushed_row_groups());
+                writer.append_key_value_metadata(parquet::format::KeyValue {
+                    key: META_ROW_GROUP_ALGORITHMS.to_string(),
+                    value: Some(algorithms.join(",")),
+                });
+            }
+            if let Some((salt, position)) = &self.salt {
+                writer.append_key_value_metadata(parquet::format::KeyValue {
+                    key: META_SALT.to_string(),
+                    value: Some(hex::encode(salt)),
+                });
+                writer.append_key_value_metadata(parquet::format::KeyValue {
+                    key: META_SALT_POSITION.to_string(),
+                    value: Some(position.as_str().to_string()),
+                });
+            }
+            if let Some(rounds) = self.rounds {
+                writer.append_key_value_metadata(parquet::format::KeyValue {
+                    key: META_ROUNDS.to_string(),
+                    value: Some(rounds.to_string()),
+                });
+            }
+            if let Some(bits) = self.prefix_bits {
+                writer.append_key_value_metadata(parquet::format::KeyValue {
+                    key: META_PREFIX_BITS.to_string(),
+                    value: Some(bits.to_string()),
+                });
+            }
+            if self.minimal_metadata {
+                writer.append_key_value_metadata(parquet::format::KeyValue {
+                    key: META_VERSION.to_string(),
+                    value: Some(env!("CARGO_PKG_VERSION").to_string()),
+                });
+                writer.close()?;
+                return Ok(());
+            }
+            let page_settings = [
+                (META_PAGE_SIZE, self.page_size),
+                (META_PAGE_ROWS, self.page_rows),
+                (META_DATA_PAGE_VERSION, self.data_page_version.map(usize::from)),
+            ];
+            for (key, value) in page_settings {
+                if let Some(value) = value {
+                    writer.append_key_value_metadata(parquet::format::KeyValue {
+                        key: key.to_string(),
+                        value: Some(value.to_string()),
+                    });
+                }
+            }
+            writer.append_key_value_metadata(parquet::format::KeyValue {
+                key: META_BUILT_AT.to_string(),
+                value: Some(humantime::format_rfc3339_seconds(SystemTime::now()).to_string()),
+            });
+            writer.append_key_value_metadata(parquet::format::KeyValue {
+                key: META_VERSION.to_string(),
+                value: Some(env!("CARGO_PKG_VERSION").to_string()),
+            });
+            if let Some(command) = &self.build_command {
+                writer.append_key_value_metadata(parquet::format::KeyValue {
+                    key: META_BUILD_COMMAND.to_string(),
+                    value: Some(command.clone()),
+                });
+            }
             writer.append_key_value_metadata(parquet::format::KeyValue {
                 key: META_TOTAL_RECORDS.to_string(),
                 value: Some(self.write_stats.total_records.to_string()),
             });
+            writer.append_key_value_metadata(parquet::format::KeyValue {
+                key: META_DISTINCT_PREIMAGES.to_string(),
+                value: Some(self.write_stats.preimages.len().to_string()),
+            });
+            writer.append_key_value_metadata(parquet::format::KeyValue {
+                key: META_COLLISIONS.to_string(),
+                value: Some(self.write_stats.collisions.to_string()),
+            });
             writer.append_key_value_metadata(parquet::format::KeyValue {
                 key: META_ALGORITHMS.to_string(),
                 value: Some(algorithms.iter().map(|s| s.as_str()).collect::<Vec<_>>().join(",")),
@@ -459,6 +1519,10 @@ impl Storage for ParquetStorage {
                 key: META_BLOOM_ITEMS.to_string(),
                 value: Some(self.write_stats.total_records.to_string()),
             });
+            writer.append_key_value_metadata(parquet::format::KeyValue {
+                key: META_BLOOM_HASHES.to_string(),
+                value: Some(self.write_stats.bloom.number_of_hash_functions().to_string()),
+            });
 
             if !self.write_stats.source_hashes.is_empty() {
                 let source_hashes_json = serde_json::to_string(&self.write_stats.source_hashes)?;
@@ -468,63 +1532,165 @@ impl Storage for ParquetStorage {
                 });
             }
 
+            if !self.write_stats.source_meta.is_empty() {
+                let source_meta_json = serde_json::to_string(&self.write_stats.source_meta)?;
+                writer.append_key_value_metadata(parquet::format::KeyValue {
+                    key: META_SOURCE_META.to_string(),
+                    value: Some(source_meta_json),
+                });
+            }
+
             writer.close()?;
         }
         Ok(())
     }
 
-    fn query(&self, hash_prefix: &[u8], algo: Option<&str>, limit: Option<usize>) -> Result<Vec<HashRecord>> {
+    fn query(&self, hash_prefix: &[u8], algo: Option<&str>, offset: usize, limit: Option<usize>) -> Result<Vec<HashRecord>> {
+        self.query_bits(hash_prefix, hash_prefix.len() * 8, algo, offset, limit)
+    }
+
+    fn query_bits(
+        &self,
+        hash_prefix: &[u8],
+        bits: usize,
+        algo: Option<&str>,
+        offset: usize,
+        limit: Option<usize>,
+    ) -> Result<Vec<HashRecord>> {
+        self.timed(|timings| self.scan_prefix(hash_prefix, bits, algo, offset, limit, timings))
+    }
+
+    fn count(&self, hash_prefix: &[u8], algo: Option<&str>) -> Result<usize> {
+        self.count_bits(hash_prefix, hash_prefix.len() * 8, algo)
+    }
+
+    fn count_bits(&self, hash_prefix: &[u8], bits: usize, algo: Option<&str>) -> Result<usize> {
+        self.timed(|timings| self.count_prefix(hash_prefix, bits, algo, timings))
+    }
+
+    fn stats(&self) -> Result<Stats> {
         if !self.path.exists() {
-            return Ok(vec![]);
+            return Ok(Stats::default());
         }
 
-        if Self::is_full_hash_length(hash_prefix.len()) {
-            if let Ok(Some(bloom)) = self.load_bloom_filter() {
-                if !bloom.check(&hash_prefix.to_vec()) {
-                    return Ok(vec![]);
-                }
-            }
+        if let Some(stats) = self.read_stats_from_metadata()? {
+            return Ok(stats);
+        }
+
+        self.scan_stats()
+    }
+}
+
+impl ParquetStorage {
+    fn timed<T>(&self, scan: impl FnOnce(&mut QueryTimings) -> Result<T>) -> Result<T> {
+        let Some(shared) = &self.timings else {
+            return scan(&mut QueryTimings::default());
+        };
+
+        let mut timings = QueryTimings {
+            lookups: 1,
+            ..QueryTimings::default()
+        };
+        let result = scan(&mut timings);
+        shared
+            .lock()
+            .map_err(|_| anyhow!("Query timings lock poisoned"))?
+            .add(&timings);
+        result
+    }
+
+    // Opens a reader over only the row groups that can hold `hash_prefix`,
+    // or None when metadata or the bloom filter already rule it out.
+    // `columns` projects the read to those root columns, in schema order.
+    fn prefix_reader(
+        &self,
+        hash_prefix: &[u8],
+        bits: usize,
+        algo: Option<&str>,
+        columns: Option<&[usize]>,
+        timings: &mut QueryTimings,
+    ) -> Result<Option<PrefixReader>> {
+        if !self.path.exists() {
+            return Ok(None);
         }
 
+        let started = Instant::now();
         let file = File::open(&self.path)
             .with_context(|| format!("Failed to open database: {:?}", self.path))?;
         let builder = ParquetRecordBatchReaderBuilder::try_new(file)?;
         
         let metadata = builder.metadata().clone();
-        let mut matching_row_groups = Vec::new();
-        
-        for (i, rg) in metadata.row_groups().iter().enumerate() {
-            let dominated_by_statistics = rg.column(0).statistics().and_then(|stats| {
-                if let Statistics::ByteArray(byte_stats) = stats {
-                    let min = byte_stats.min_opt()?;
-                    let max = byte_stats.max_opt()?;
-                    Some(Self::prefix_might_be_in_range(hash_prefix, min.data(), max.data()))
-                } else {
-                    None
-                }
-            });
-            
-            if dominated_by_statistics.unwrap_or(true) {
-                matching_row_groups.push(i);
+        let encoding = Self::read_hash_encoding(&metadata);
+
+        let (prefix, bits) = Self::effective_prefix(&metadata, hash_prefix, bits);
+        let whole = &prefix[..bits / 8];
+        timings.metadata += started.elapsed();
+
+        if bits.is_multiple_of(8) && Self::is_full_hash_for(whole.len(), algo) {
+            let started = Instant::now();
+            let maybe_present = match self.load_bloom_filter() {
+                Ok(Some(bloom)) => bloom.check(&whole.to_vec()),
+                _ => true,
+            };
+            timings.bloom += started.elapsed();
+            if !maybe_present {
+                return Ok(None);
             }
         }
+
+        let started = Instant::now();
+
+        let hex_prefix = hex::encode(&prefix)[..bits / 4].to_string();
+        let stats_prefix = match encoding {
+            HashEncoding::Binary => whole,
+            HashEncoding::Hex => hex_prefix.as_bytes(),
+        };
+        let matching_row_groups = Self::filter_row_groups_by_algorithm(
+            &metadata,
+            Self::select_row_groups(&metadata, whole, stats_prefix),
+            algo,
+        );
+        timings.row_groups += started.elapsed();
         
         if matching_row_groups.is_empty() {
-            return Ok(vec![]);
+            return Ok(None);
         }
-        
-        let reader = builder.with_row_groups(matching_row_groups).build()?;
 
+        let mut builder = builder.with_row_groups(matching_row_groups);
+        if let Some(columns) = columns {
+            let schema = metadata.file_metadata().schema_descr();
+            builder = builder.with_projection(ProjectionMask::roots(schema, columns.iter().copied()));
+        }
+        Ok(Some(PrefixReader {
+            reader: builder.build()?,
+            encoding,
+            prefix,
+            bits,
+            hex_prefix,
+        }))
+    }
+
+    fn scan_prefix(
+        &self,
+        hash_prefix: &[u8],
+        bits: usize,
+        algo: Option<&str>,
+        offset: usize,
+        limit: Option<usize>,
+        timings: &mut QueryTimings,
+    ) -> Result<Vec<HashRecord>> {
+        let Some(scan) = self.prefix_reader(hash_prefix, bits, algo, None, timings)? else {
+            return Ok(vec![]);
+        };
+
+        let started = Instant::now();
         let mut results = Vec::new();
+        let mut skipped = 0;
 
-        'outer: for batch_result in reader {
+        'outer: for batch_result in scan.reader {
             let batch = batch_result?;
 
-            let hashes = batch
-                .column(0)
-                .as_any()
-                .downcast_ref::<BinaryArray>()
-                .ok_or_else(|| anyhow!("Invalid schema: expected binary hash column"))?;
+            let hashes = HashColumn::from_batch(&batch, scan.encoding)?;
             let preimages = batch
                 .column(1)
                 .as_any()
@@ -535,16 +1701,10 @@ impl Storage for ParquetStorage {
                 .as_any()
                 .downcast_ref::<StringArray>()
                 .ok_or_else(|| anyhow!("Invalid schema: expected string algorithm column"))?;
-            let sources = batch
-                .column(3)
-                .as_any()
-                .downcast_ref::<ListArray>()
-                .ok_or_else(|| anyhow!("Invalid schema: expected list sources column"))?;
+            let sources = Self::sources_column(&batch)?;
 
             for i in 0..batch.num_rows() {
-                let hash = hashes.value(i);
-
-                if !hash.starts_with(hash_prefix) {
+                if !hashes.matches(i, &scan.prefix, scan.bits, &scan.hex_prefix) {
                     continue;
                 }
 
@@ -553,8 +1713,13 @@ impl Storage for ParquetStorage {
                     continue;
                 }
 
+                if skipped < offset {
+                    skipped += 1;
+                    continue;
+                }
+
                 results.push(HashRecord {
-                    hash: hash.to_vec(),
+                    hash: hashes.value(i)?,
                     preimage: preimages.value(i).to_string(),
                     algorithm: algorithm.to_string(),
                     sources: Self::extract_sources(sources, i),
@@ -565,19 +1730,97 @@ impl Storage for ParquetStorage {
                 }
             }
         }
+        timings.scan += started.elapsed();
 
         Ok(results)
     }
 
-    fn stats(&self) -> Result<Stats> {
-        if !self.path.exists() {
-            return Ok(Stats::default());
+    // Only the hash and algorithm columns are decoded; preimages and
+    // sources are never read.
+    fn count_prefix(
+        &self,
+        hash_prefix: &[u8],
+        bits: usize,
+        algo: Option<&str>,
+        timings: &mut QueryTimings,
+    ) -> Result<usize> {
+        let Some(scan) = self.prefix_reader(hash_prefix, bits, algo, Some(&[0, 2]), timings)? else {
+            return Ok(0);
+        };
+
+        let started = Instant::now();
+        let mut count = 0;
+        for batch_result in scan.reader {
+            let batch = batch_result?;
+
+            let hashes = HashColumn::from_batch(&batch, scan.encoding)?;
+            let algorithms = batch
+                .column(1)
+                .as_any()
+                .downcast_ref::<StringArray>()
+                .ok_or_else(|| anyhow!("Invalid schema: expected string algorithm column"))?;
+
+            count += (0..batch.num_rows())
+                .filter(|&i| hashes.matches(i, &scan.prefix, scan.bits, &scan.hex_prefix))
+                .filter(|&i| algo.is_none_or(|filter| algorithms.value(i) == filter))
+                .count();
         }
+        timings.scan += started.elapsed();
 
-        if let Some(stats) = self.read_stats_from_metadata()? {
-            return Ok(stats);
+        Ok(count)
+    }
+}
+
+struct PrefixReader {
+    reader: ParquetRecordBatchReader,
+    encoding: HashEncoding,
+    prefix: Vec<u8>,
+    bits: usize,
+    hex_prefix: String,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parallel_bloom_matches_serial() {
+        let records: Vec<HashRecord> = (0..50_000u32)
+            .map(|i| HashRecord {
+                hash: blake3::hash(&i.to_le_bytes()).as_bytes().to_vec(),
+                preimage: i.to_string(),
+                algorithm: "blake3".to_string(),
+                sources: Vec::new(),
+            })
+            .collect();
+
+        let mut builder = BloomBuilder::new(records.len());
+        for chunk in records.chunks(7_000) {
+            builder.insert_all(chunk);
         }
 
-        self.scan_stats()
+        let empty = vec![0u8; builder.number_of_bits().div_ceil(8) as usize];
+        let mut serial: Bloom<Vec<u8>> = Bloom::from_existing(
+            &empty,
+            builder.number_of_bits(),
+            builder.number_of_hash_functions(),
+            builder.sip_keys(),
+        );
+        for record in &records {
+            serial.set(&record.hash);
+        }
+
+        assert_eq!(builder.bitmap(), serial.bitmap());
+        assert!(records.iter().all(|r| serial.check(&r.hash)));
+    }
+
+    #[test]
+    fn test_seeded_bloom_keys_are_stable() {
+        let seed = [7u8; 32];
+        let first = BloomBuilder::with_seed(1_000, &seed);
+        let second = BloomBuilder::with_seed(1_000, &seed);
+        assert_eq!(first.sip_keys(), second.sip_keys());
+        assert_eq!(first.number_of_bits(), BloomBuilder::new(1_000).number_of_bits());
+        assert_ne!(first.sip_keys(), BloomBuilder::with_seed(1_000, &[8u8; 32]).sip_keys());
     }
 }
@@ -1,6 +1,9 @@
+use std::path::PathBuf;
+
 use anyhow::{Context, Result};
 use duckdb::{params, Connection};
 
+use super::s3::{ObjectVersion, R2Object};
 use super::{HashRecord, Stats, Storage};
 
 /// Configuration for R2/S3 storage
@@ -18,6 +21,8 @@ pub struct R2Config {
     pub path: String,
     /// Region (default: "auto" for R2)
     pub region: String,
+    /// Connect over HTTPS (false for local MinIO on http://)
+    pub use_ssl: bool,
 }
 
 impl R2Config {
@@ -35,14 +40,61 @@ impl R2Config {
             bucket: bucket.into(),
             path: path.into(),
             region: "auto".to_string(),
+            use_ssl: true,
         }
     }
 
+    pub fn with_ssl(mut self, use_ssl: bool) -> Self {
+        self.use_ssl = use_ssl;
+        self
+    }
+
+    /// Endpoint as DuckDB expects it: host[:port], no scheme
+    pub fn endpoint_host(&self) -> &str {
+        self.endpoint
+            .trim_start_matches("https://")
+            .trim_start_matches("http://")
+            .trim_end_matches('/')
+    }
+
+    // An explicit http:// endpoint can only be reached without TLS
+    fn tls(&self) -> bool {
+        self.use_ssl && !self.endpoint.starts_with("http://")
+    }
+
+    /// DuckDB settings for the httpfs S3 client
+    pub fn settings_sql(&self) -> String {
+        format!(
+            "SET s3_endpoint = '{}';
+             SET s3_access_key_id = '{}';
+             SET s3_secret_access_key = '{}';
+             SET s3_region = '{}';
+             SET s3_url_style = 'path';
+             SET s3_use_ssl = {};",
+            self.endpoint_host(),
+            self.access_key_id,
+            self.secret_access_key,
+            self.region,
+            self.tls(),
+        )
+    }
+
     /// Build S3 URL for the parquet file
     pub fn s3_url(&self) -> String {
         format!("s3://{}/{}", self.bucket, self.path)
     }
 
+    /// Path-style HTTP(S) URL of the parquet file
+    pub fn http_url(&self) -> String {
+        format!(
+            "{}://{}/{}/{}",
+            if self.tls() { "https" } else { "http" },
+            self.endpoint_host(),
+            self.bucket,
+            self.path
+        )
+    }
+
     /// Load config from environment variables
     pub fn from_env() -> Result<Self> {
         Ok(Self {
@@ -60,6 +112,7 @@ impl R2Config {
                 .unwrap_or_else(|_| "hashes.parquet".to_string()),
             region: std::env::var("SHAHA_R2_REGION")
                 .unwrap_or_else(|_| "auto".to_string()),
+            use_ssl: true,
         })
     }
 }
@@ -68,6 +121,8 @@ pub struct R2Storage {
     conn: Connection,
     config: R2Config,
     pending_records: Vec<HashRecord>,
+    expected_version: Option<ObjectVersion>,
+    temp_dir: PathBuf,
 }
 
 impl R2Storage {
@@ -82,17 +137,8 @@ impl R2Storage {
         ).context("Failed to install/load httpfs extension")?;
 
         // Configure S3/R2 credentials
-        conn.execute_batch(&format!(
-            "SET s3_endpoint = '{}';
-             SET s3_access_key_id = '{}';
-             SET s3_secret_access_key = '{}';
-             SET s3_region = '{}';
-             SET s3_url_style = 'path';",
-            config.endpoint.trim_start_matches("https://").trim_start_matches("http://"),
-            config.access_key_id,
-            config.secret_access_key,
-            config.region,
-        )).context("Failed to configure S3 credentials")?;
+        conn.execute_batch(&config.settings_sql())
+            .context("Failed to configure S3 credentials")?;
 
         conn.execute_batch(
             "CREATE TABLE pending_records (
@@ -107,9 +153,42 @@ impl R2Storage {
             conn,
             config,
             pending_records: Vec::new(),
+            expected_version: None,
+            temp_dir: std::env::temp_dir(),
         })
     }
 
+    // Upload only if the object is still at `version`, as read before the
+    // build started. The parquet is staged in the temp dir and sent with a
+    // conditional PUT instead of DuckDB's unconditional COPY.
+    pub fn with_expected_version(mut self, version: ObjectVersion) -> Self {
+        self.expected_version = Some(version);
+        self
+    }
+
+    pub fn with_temp_dir(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.temp_dir = dir.into();
+        self
+    }
+
+    fn upload_conditionally(&self, expected: &ObjectVersion) -> Result<()> {
+        let staged = self
+            .temp_dir
+            .join(format!("shaha-r2-upload-{}.parquet", std::process::id()));
+        let staged_sql = staged.to_string_lossy().replace('\'', "''");
+        self.conn
+            .execute_batch(&format!(
+                "COPY pending_records TO '{}' (FORMAT PARQUET, COMPRESSION ZSTD);",
+                staged_sql
+            ))
+            .with_context(|| format!("Failed to stage parquet at {}", staged.display()))?;
+        let body = std::fs::read(&staged);
+        let _ = std::fs::remove_file(&staged);
+        let body = body.with_context(|| format!("Failed to read {}", staged.display()))?;
+
+        R2Object::new(self.config.clone()).put_if(body, expected)
+    }
+
     fn insert_pending_to_table(&mut self) -> Result<()> {
         if self.pending_records.is_empty() {
             return Ok(());
@@ -142,6 +221,30 @@ impl R2Storage {
         format!("[{}]", escaped.join(", "))
     }
 
+    // WHERE clause and its positional parameters for a prefix/algorithm filter
+    fn where_clause(hash_prefix: &[u8], algo: Option<&str>) -> (String, Vec<String>) {
+        let mut conditions = Vec::new();
+        let mut param_values: Vec<String> = Vec::new();
+
+        if !hash_prefix.is_empty() {
+            let hex_prefix = hex::encode(hash_prefix);
+            conditions.push("starts_with(encode(hash)::VARCHAR, ?)".to_string());
+            param_values.push(hex_prefix);
+        }
+
+        if let Some(algorithm) = algo {
+            conditions.push("algorithm = ?".to_string());
+            param_values.push(algorithm.to_string());
+        }
+
+        let where_clause = if conditions.is_empty() {
+            String::new()
+        } else {
+            format!(" WHERE {}", conditions.join(" AND "))
+        };
+        (where_clause, param_values)
+    }
+
     fn row_to_record(row: &duckdb::Row<'_>) -> std::result::Result<HashRecord, duckdb::Error> {
         let hash: Vec<u8> = row.get(0)?;
         let preimage: String = row.get(1)?;
@@ -172,11 +275,15 @@ impl Storage for R2Storage {
 
         let s3_url = self.config.s3_url();
 
-        // Write to S3/R2 as parquet with ZSTD compression
-        self.conn.execute_batch(&format!(
-            "COPY pending_records TO '{}' (FORMAT PARQUET, COMPRESSION ZSTD);",
-            s3_url
-        )).with_context(|| format!("Failed to write parquet to {}", s3_url))?;
+        if let Some(expected) = &self.expected_version {
+            self.upload_conditionally(expected)?;
+        } else {
+            // Write to S3/R2 as parquet with ZSTD compression
+            self.conn.execute_batch(&format!(
+                "COPY pending_records TO '{}' (FORMAT PARQUET, COMPRESSION ZSTD);",
+                s3_url
+            )).with_context(|| format!("Failed to write parquet to {}", s3_url))?;
+        }
 
         // Clear the temp table
         self.conn.execute_batch("DELETE FROM pending_records;")?;
@@ -184,36 +291,22 @@ impl Storage for R2Storage {
         Ok(())
     }
 
-    fn query(&self, hash_prefix: &[u8], algo: Option<&str>, limit: Option<usize>) -> Result<Vec<HashRecord>> {
+    fn query(&self, hash_prefix: &[u8], algo: Option<&str>, offset: usize, limit: Option<usize>) -> Result<Vec<HashRecord>> {
         let s3_url = self.config.s3_url();
-
-        let mut conditions = Vec::new();
-        let mut param_values: Vec<String> = Vec::new();
-        
-        if !hash_prefix.is_empty() {
-            let hex_prefix = hex::encode(hash_prefix);
-            conditions.push("starts_with(encode(hash)::VARCHAR, ?)".to_string());
-            param_values.push(hex_prefix);
-        }
-
-        if let Some(algorithm) = algo {
-            conditions.push("algorithm = ?".to_string());
-            param_values.push(algorithm.to_string());
-        }
-
-        let where_clause = if conditions.is_empty() {
-            String::new()
-        } else {
-            format!(" WHERE {}", conditions.join(" AND "))
-        };
+        let (where_clause, param_values) = Self::where_clause(hash_prefix, algo);
 
         let limit_clause = limit
             .map(|l| format!(" LIMIT {}", l))
             .unwrap_or_default();
+        let offset_clause = if offset > 0 {
+            format!(" OFFSET {}", offset)
+        } else {
+            String::new()
+        };
 
         let query = format!(
-            "SELECT hash, preimage, algorithm, to_json(sources)::VARCHAR FROM read_parquet('{}'){}{};",
-            s3_url, where_clause, limit_clause
+            "SELECT hash, preimage, algorithm, coalesce(to_json(sources)::VARCHAR, '[]') FROM read_parquet('{}'){}{}{};",
+            s3_url, where_clause, limit_clause, offset_clause
         );
 
         let mut stmt = self.conn.prepare(&query)
@@ -231,15 +324,28 @@ impl Storage for R2Storage {
         records
     }
 
+    fn count(&self, hash_prefix: &[u8], algo: Option<&str>) -> Result<usize> {
+        let s3_url = self.config.s3_url();
+        let (where_clause, param_values) = Self::where_clause(hash_prefix, algo);
+
+        let query = format!("SELECT COUNT(*) FROM read_parquet('{}'){};", s3_url, where_clause);
+        let count: i64 = self
+            .conn
+            .query_row(&query, duckdb::params_from_iter(&param_values), |row| row.get(0))
+            .with_context(|| format!("Failed to count records in {}", s3_url))?;
+        Ok(count as usize)
+    }
+
     fn stats(&self) -> Result<Stats> {
         let s3_url = self.config.s3_url();
 
         let stats_query = format!(
-            "WITH data AS (SELECT algorithm, sources FROM read_parquet('{}'))
+            "WITH data AS (SELECT preimage, algorithm, sources FROM read_parquet('{}'))
              SELECT 
                  (SELECT COUNT(*) FROM data) as total,
                  (SELECT string_agg(DISTINCT algorithm, ',') FROM data) as algorithms,
-                 (SELECT string_agg(DISTINCT s, ',') FROM data, unnest(sources) as t(s)) as sources",
+                 (SELECT string_agg(DISTINCT s, ',') FROM data, unnest(sources) as t(s)) as sources,
+                 (SELECT COUNT(DISTINCT preimage) FROM data) as preimages",
             s3_url
         );
 
@@ -247,11 +353,12 @@ impl Storage for R2Storage {
             let total: usize = row.get(0)?;
             let algos: Option<String> = row.get(1)?;
             let srcs: Option<String> = row.get(2)?;
-            Ok((total, algos, srcs))
+            let preimages: usize = row.get(3)?;
+            Ok((total, algos, srcs, preimages))
         });
 
         match result {
-            Ok((total_records, algos_str, sources_str)) => {
+            Ok((total_records, algos_str, sources_str, distinct_preimages)) => {
                 let algorithms = algos_str
                     .map(|s| s.split(',').map(String::from).collect())
                     .unwrap_or_default();
@@ -261,6 +368,8 @@ impl Storage for R2Storage {
                 
                 Ok(Stats {
                     total_records,
+                    distinct_preimages: Some(distinct_preimages),
+                    collisions: None,
                     algorithms,
                     sources,
                     file_size_bytes: 0,
@@ -287,6 +396,21 @@ mod tests {
         assert_eq!(config.s3_url(), "s3://my-bucket/path/to/hashes.parquet");
     }
 
+    #[test]
+    fn test_r2_config_settings_for_local_minio() {
+        let config = R2Config::new("http://localhost:9000/", "key", "secret", "bucket", "db.parquet");
+        let sql = config.settings_sql();
+        assert!(sql.contains("SET s3_endpoint = 'localhost:9000';"));
+        assert!(sql.contains("SET s3_use_ssl = false;"));
+        assert_eq!(config.http_url(), "http://localhost:9000/bucket/db.parquet");
+
+        let config = R2Config::new("localhost:9000", "key", "secret", "bucket", "db.parquet");
+        assert!(config.settings_sql().contains("SET s3_use_ssl = true;"));
+        let sql = config.with_ssl(false).settings_sql();
+        assert!(sql.contains("SET s3_endpoint = 'localhost:9000';"));
+        assert!(sql.contains("SET s3_use_ssl = false;"));
+    }
+
     #[test]
     fn test_r2_config_from_env_missing() {
         unsafe { std::env::remove_var("SHAHA_R2_ENDPOINT") };
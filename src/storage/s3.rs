@@ -0,0 +1,171 @@
+use std::time::SystemTime;
+
+use anyhow::{bail, Context, Result};
+use hmac::{Hmac, Mac};
+use reqwest::blocking::{Client, RequestBuilder};
+use reqwest::StatusCode;
+use sha2::{Digest, Sha256};
+
+use super::R2Config;
+
+// State of the remote object when a build started. Uploads are conditional
+// on it still holding, so a concurrent writer makes the build fail instead
+// of being silently overwritten.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ObjectVersion {
+    Absent,
+    ETag(String),
+}
+
+// Minimal signed S3 client for the requests DuckDB's httpfs cannot make:
+// HEAD for the ETag and PUT with If-Match / If-None-Match.
+pub struct R2Object {
+    config: R2Config,
+    client: Client,
+}
+
+impl R2Object {
+    pub fn new(config: R2Config) -> Self {
+        Self {
+            config,
+            client: Client::new(),
+        }
+    }
+
+    pub fn version(&self) -> Result<ObjectVersion> {
+        let url = self.config.http_url();
+        let response = self
+            .signed(reqwest::Method::HEAD, &[])
+            .send()
+            .with_context(|| format!("Failed to read ETag of {}", url))?;
+
+        match response.status() {
+            StatusCode::NOT_FOUND => Ok(ObjectVersion::Absent),
+            status if status.is_success() => {
+                let etag = response
+                    .headers()
+                    .get(reqwest::header::ETAG)
+                    .and_then(|v| v.to_str().ok())
+                    .with_context(|| format!("No ETag returned for {}", url))?;
+                Ok(ObjectVersion::ETag(etag.to_string()))
+            }
+            status => bail!("Failed to read ETag of {}: HTTP {}", url, status),
+        }
+    }
+
+    pub fn put_if(&self, body: Vec<u8>, expected: &ObjectVersion) -> Result<()> {
+        let url = self.config.http_url();
+        let request = self.signed(reqwest::Method::PUT, &body);
+        let request = match expected {
+            ObjectVersion::Absent => request.header(reqwest::header::IF_NONE_MATCH, "*"),
+            ObjectVersion::ETag(etag) => request.header(reqwest::header::IF_MATCH, etag),
+        };
+        let response = request
+            .body(body)
+            .send()
+            .with_context(|| format!("Failed to upload {}", url))?;
+
+        match response.status() {
+            status if status.is_success() => Ok(()),
+            StatusCode::PRECONDITION_FAILED | StatusCode::CONFLICT => bail!(
+                "{} was modified by another writer since this build started (expected {}); \
+                 nothing was uploaded, rerun the build",
+                self.config.s3_url(),
+                match expected {
+                    ObjectVersion::Absent => "no object".to_string(),
+                    ObjectVersion::ETag(etag) => format!("ETag {}", etag),
+                }
+            ),
+            status => bail!("Failed to upload {}: HTTP {}", url, status),
+        }
+    }
+
+    fn signed(&self, method: reqwest::Method, body: &[u8]) -> RequestBuilder {
+        let amz_date = humantime::format_rfc3339_seconds(SystemTime::now())
+            .to_string()
+            .replace(['-', ':'], "");
+        let payload_hash = hex::encode(Sha256::digest(body));
+        let authorization = self.authorization(method.as_str(), &amz_date, &payload_hash);
+
+        self.client
+            .request(method, self.config.http_url())
+            .header("x-amz-date", amz_date)
+            .header("x-amz-content-sha256", payload_hash)
+            .header(reqwest::header::AUTHORIZATION, authorization)
+    }
+
+    // AWS Signature Version 4 over host, x-amz-content-sha256 and x-amz-date.
+    fn authorization(&self, method: &str, amz_date: &str, payload_hash: &str) -> String {
+        let date = &amz_date[..8];
+        let scope = format!("{}/{}/s3/aws4_request", date, self.config.region);
+        let signed_headers = "host;x-amz-content-sha256;x-amz-date";
+        let canonical_request = format!(
+            "{}\n{}\n\nhost:{}\nx-amz-content-sha256:{}\nx-amz-date:{}\n\n{}\n{}",
+            method,
+            uri_encode_path(&format!("/{}/{}", self.config.bucket, self.config.path)),
+            self.config.endpoint_host(),
+            payload_hash,
+            amz_date,
+            signed_headers,
+            payload_hash,
+        );
+        let string_to_sign = format!(
+            "AWS4-HMAC-SHA256\n{}\n{}\n{}",
+            amz_date,
+            scope,
+            hex::encode(Sha256::digest(canonical_request.as_bytes()))
+        );
+
+        let mut key = hmac_sha256(
+            format!("AWS4{}", self.config.secret_access_key).as_bytes(),
+            date.as_bytes(),
+        );
+        for part in [self.config.region.as_str(), "s3", "aws4_request"] {
+            key = hmac_sha256(&key, part.as_bytes());
+        }
+        let signature = hex::encode(hmac_sha256(&key, string_to_sign.as_bytes()));
+
+        format!(
+            "AWS4-HMAC-SHA256 Credential={}/{}, SignedHeaders={}, Signature={}",
+            self.config.access_key_id, scope, signed_headers, signature
+        )
+    }
+}
+
+fn hmac_sha256(key: &[u8], message: &[u8]) -> Vec<u8> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC accepts keys of any length");
+    mac.update(message);
+    mac.finalize().into_bytes().to_vec()
+}
+
+fn uri_encode_path(path: &str) -> String {
+    let mut encoded = String::with_capacity(path.len());
+    for byte in path.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' | b'/' => {
+                encoded.push(byte as char)
+            }
+            _ => encoded.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    encoded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hmac_sha256_rfc4231_case_2() {
+        let mac = hmac_sha256(b"Jefe", b"what do ya want for nothing?");
+        assert_eq!(
+            hex::encode(mac),
+            "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"
+        );
+    }
+
+    #[test]
+    fn test_uri_encode_path_keeps_slashes() {
+        assert_eq!(uri_encode_path("/bucket/my db.parquet"), "/bucket/my%20db.parquet");
+    }
+}
@@ -1,9 +1,14 @@
 use std::fs;
 use std::io::Write;
 
+use shaha::build::{build, BuildOptions};
+use shaha::dedup::{ExternalDedup, ExternalSort};
 use shaha::hasher;
+use shaha::rules::Rules;
 use shaha::source::{FileSource, Source, UrlSource};
-use shaha::storage::{HashRecord, ParquetStorage, Storage};
+use shaha::storage::{
+    CachedStorage, FallbackStorage, HashRecord, MemoryStorage, ParquetStorage, Storage,
+};
 
 #[test]
 fn test_sha256_known_vector() {
@@ -39,6 +44,26 @@ fn test_keccak256_known_vector() {
     );
 }
 
+#[test]
+fn test_sha3_known_vectors_differ_from_keccak() {
+    let sha3_256 = hasher::get_hasher("sha3-256").unwrap();
+    let hash = sha3_256.hash(b"hello");
+    assert_eq!(
+        hex::encode(&hash),
+        "3338be694f50c5f338814986cdf0686453a888b84f424d792af4b9202398f392"
+    );
+    // Same sponge, different padding: FIPS-202 SHA3 is not Ethereum's Keccak
+    assert_ne!(hash, hasher::get_hasher("keccak256").unwrap().hash(b"hello"));
+
+    let sha3_512 = hasher::get_hasher("sha3_512").unwrap();
+    assert_eq!(sha3_512.name(), "sha3-512");
+    assert_eq!(
+        hex::encode(sha3_512.hash(b"hello")),
+        "75d527c368f2efe848ecf6b073a36767800805e9eef2b1857d5f984f036eb6df\
+         891d75f72d9b154518c1cd58835286d1da9a38deba3de98b5a53e5ed78a84976"
+    );
+}
+
 #[test]
 fn test_hash160_known_vector() {
     let hasher = hasher::get_hasher("hash160").unwrap();
@@ -85,6 +110,103 @@ fn test_ripemd160_known_vector() {
     assert_eq!(hex::encode(&hash), "108f07b8382412612c048d07d13f814118445acd");
 }
 
+#[test]
+fn test_blake2_known_vectors() {
+    let blake2b = hasher::get_hasher("blake2b").unwrap();
+    assert_eq!(
+        hex::encode(blake2b.hash(b"hello")),
+        "e4cfa39a3d37be31c59609e807970799caa68a19bfaa15135f165085e01d41a6\
+         5ba1e1b146aeb6bd0092b49eac214c103ccfa3a365954bbbe52f74a2b3620c94"
+    );
+    let blake2s = hasher::get_hasher("blake2s").unwrap();
+    assert_eq!(
+        hex::encode(blake2s.hash(b"hello")),
+        "19213bacc58dee6dbde3ceb9a47cbb330b3d86f8cca8997eb00be456f140ca25"
+    );
+
+    assert_eq!(hasher::get_hasher("blake2b512").unwrap().name(), "blake2b");
+    assert_eq!(hasher::get_hasher("blake2s256").unwrap().name(), "blake2s");
+}
+
+#[test]
+fn test_build_accepts_blake2_aliases() {
+    let dir = tempfile::tempdir().unwrap();
+    let words_path = dir.path().join("words.txt");
+    let db_path = dir.path().join("test.parquet");
+    fs::write(&words_path, "hello\n").unwrap();
+
+    let status = std::process::Command::new(env!("CARGO_BIN_EXE_shaha"))
+        .args(["build", words_path.to_str().unwrap(), "-o", db_path.to_str().unwrap()])
+        .args(["-a", "blake2b512", "-a", "blake2s"])
+        .status()
+        .expect("Failed to build database");
+    assert!(status.success());
+
+    let mut algorithms = ParquetStorage::new(&db_path).stats().unwrap().algorithms;
+    algorithms.sort();
+    assert_eq!(algorithms, vec!["blake2b", "blake2s"]);
+
+    let output = std::process::Command::new(env!("CARGO_BIN_EXE_shaha"))
+        .args(["query", "e4cfa39a", "-d", db_path.to_str().unwrap(), "-a", "blake2b512"])
+        .output()
+        .expect("Failed to run query");
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    assert!(String::from_utf8_lossy(&output.stdout).contains("hello"));
+}
+
+#[test]
+fn test_ntlm_known_vectors() {
+    let hasher = hasher::get_hasher("ntlm").unwrap();
+    assert_eq!(hex::encode(hasher.hash(b"password")), "8846f7eaee8fb117ad06bdd830b7586c");
+    assert_eq!(hex::encode(hasher.hash(b"")), "31d6cfe0d16ae931b73c59d7e0c089c0");
+    // UTF-16LE code unit 0x00E9, not the UTF-8 bytes c3 a9
+    assert_eq!(
+        hex::encode(hasher.hash("café".as_bytes())),
+        "b1db12409c00d1fc586fc48ecadc36a1"
+    );
+}
+
+#[test]
+fn test_hmac_rfc4231_vectors() {
+    // RFC 4231 test case 1
+    let hmac = hasher::get_hmac_hasher("sha256", &[0x0b; 20]).unwrap();
+    assert_eq!(hmac.name(), "hmac-sha256");
+    assert_eq!(
+        hex::encode(hmac.hash(b"Hi There")),
+        "b0344c61d8db38535ca8afceaf0bf12b881dc200c9833da726e9376c2e32cff7"
+    );
+
+    // RFC 4231 test case 2
+    let hmac = hasher::get_hmac_hasher("sha512", b"Jefe").unwrap();
+    assert_eq!(
+        hex::encode(hmac.hash(b"what do ya want for nothing?")),
+        "164b7a7bfcf819e2e395fbe73b56e0a387bd64222e831fd610270cd7ea250554\
+         9758bf75c05a994a6d034f65f8f0e6fdcaeab1a34d4a6b4b636e070a38bce737"
+    );
+
+    assert!(hasher::get_hmac_hasher("blake3", b"Jefe").is_none());
+}
+
+#[test]
+fn test_iterated_hasher_feeds_digest_back() {
+    use shaha::hasher::Hasher;
+
+    let sha256 = hasher::get_hasher("sha256").unwrap();
+    let twice = hasher::IteratedHasher::new(hasher::get_hasher("sha256").unwrap(), 2);
+    assert_eq!(twice.name(), "sha256x2");
+    assert_eq!(twice.hash(b"hello"), sha256.hash(&sha256.hash(b"hello")));
+    assert_eq!(twice.output_len(), 32);
+
+    let salted = hasher::IteratedHasher::new(hasher::get_hasher("sha256").unwrap(), 2)
+        .with_salt(b"NaCl".to_vec(), hasher::SaltPosition::Prefix);
+    assert_eq!(salted.name(), "sha256x2");
+    assert_eq!(salted.hash(b"hello"), sha256.hash(&sha256.hash(b"NaClhello")));
+
+    // Rebuilding the same hasher reuses its name instead of allocating another
+    let again = hasher::IteratedHasher::new(hasher::get_hasher("sha256").unwrap(), 2);
+    assert!(std::ptr::eq(twice.name(), again.name()));
+}
+
 #[test]
 fn test_available_algorithms() {
     let algos = hasher::available_algorithms();
@@ -93,6 +215,7 @@ fn test_available_algorithms() {
     assert!(algos.contains(&"keccak256"));
     assert!(algos.contains(&"hash160"));
     assert!(algos.contains(&"hash256"));
+    assert!(algos.contains(&"ntlm"));
 }
 
 #[test]
@@ -139,13 +262,13 @@ fn test_roundtrip_write_query() {
     storage.write_batch(records).unwrap();
     storage.finish().unwrap();
 
-    let results = storage.query(&hash, None, None).unwrap();
+    let results = storage.query(&hash, None, 0, None).unwrap();
     assert_eq!(results.len(), 1);
     assert_eq!(results[0].preimage, "password");
     assert_eq!(results[0].algorithm, "sha256");
 
     let prefix = &hash[..4];
-    let results = storage.query(prefix, None, None).unwrap();
+    let results = storage.query(prefix, None, 0, None).unwrap();
     assert_eq!(results.len(), 1);
     assert_eq!(results[0].preimage, "password");
 }
@@ -178,14 +301,14 @@ fn test_query_with_algorithm_filter() {
     storage.finish().unwrap();
 
     let sha256_hash = sha256.hash(b"hello");
-    let results = storage.query(&sha256_hash[..4], None, None).unwrap();
+    let results = storage.query(&sha256_hash[..4], None, 0, None).unwrap();
     assert_eq!(results.len(), 1);
 
-    let results = storage.query(&sha256_hash[..4], Some("sha256"), None).unwrap();
+    let results = storage.query(&sha256_hash[..4], Some("sha256"), 0, None).unwrap();
     assert_eq!(results.len(), 1);
     assert_eq!(results[0].algorithm, "sha256");
 
-    let results = storage.query(&sha256_hash[..4], Some("md5"), None).unwrap();
+    let results = storage.query(&sha256_hash[..4], Some("md5"), 0, None).unwrap();
     assert_eq!(results.len(), 0);
 }
 
@@ -263,7 +386,7 @@ fn test_append_mode_merges_sources() {
     storage.finish().unwrap();
 
     let storage = ParquetStorage::new(&db_path);
-    let existing = storage.query(&[], None, None).unwrap();
+    let existing = storage.query(&[], None, 0, None).unwrap();
     
     let mut records_map: HashMap<(Vec<u8>, String), HashRecord> = HashMap::new();
     for record in existing {
@@ -302,7 +425,7 @@ fn test_append_mode_merges_sources() {
     let storage = ParquetStorage::new(&db_path);
     
     let hello_hash = sha256.hash(b"hello");
-    let results = storage.query(&hello_hash, Some("sha256"), None).unwrap();
+    let results = storage.query(&hello_hash, Some("sha256"), 0, None).unwrap();
     assert_eq!(results.len(), 1);
     assert_eq!(results[0].preimage, "hello");
     assert!(results[0].sources.contains(&"wordlist1".to_string()));
@@ -310,12 +433,12 @@ fn test_append_mode_merges_sources() {
     assert_eq!(results[0].sources.len(), 2);
 
     let world_hash = sha256.hash(b"world");
-    let results = storage.query(&world_hash, Some("sha256"), None).unwrap();
+    let results = storage.query(&world_hash, Some("sha256"), 0, None).unwrap();
     assert_eq!(results.len(), 1);
     assert_eq!(results[0].sources, vec!["wordlist1".to_string()]);
 
     let test_hash = sha256.hash(b"test");
-    let results = storage.query(&test_hash, Some("sha256"), None).unwrap();
+    let results = storage.query(&test_hash, Some("sha256"), 0, None).unwrap();
     assert_eq!(results.len(), 1);
     assert_eq!(results[0].sources, vec!["wordlist2".to_string()]);
 
@@ -324,6 +447,48 @@ fn test_append_mode_merges_sources() {
     assert_eq!(stats.total_records, 3);
 }
 
+#[test]
+fn test_full_length_query_without_algo_is_not_bloom_rejected() {
+    let dir = tempfile::tempdir().unwrap();
+    let db_path = dir.path().join("test.parquet");
+
+    let md5 = hasher::get_hasher("md5").unwrap();
+    let sha256 = hasher::get_hasher("sha256").unwrap();
+    let mut records = vec![
+        HashRecord {
+            hash: md5.hash(b"hello"),
+            preimage: "hello".to_string(),
+            algorithm: "md5".to_string(),
+            sources: vec!["test".to_string()],
+        },
+        HashRecord {
+            hash: sha256.hash(b"world"),
+            preimage: "world".to_string(),
+            algorithm: "sha256".to_string(),
+            sources: vec!["test".to_string()],
+        },
+    ];
+    records.sort_by(|a, b| a.hash.cmp(&b.hash));
+
+    let mut storage = ParquetStorage::new(&db_path);
+    storage.write_batch(records).unwrap();
+    storage.finish().unwrap();
+    let storage = ParquetStorage::new(&db_path);
+
+    // md5-length prefix of a stored sha256 hash: not in the bloom filter as a
+    // whole hash, but still a match
+    let prefix = &sha256.hash(b"world")[..16];
+    let results = storage.query(prefix, None, 0, None).unwrap();
+    assert_eq!(results.len(), 1);
+    assert_eq!(results[0].preimage, "world");
+
+    let results = storage.query(prefix, Some("md5"), 0, None).unwrap();
+    assert!(results.is_empty());
+
+    let results = storage.query(&md5.hash(b"hello"), Some("md5"), 0, None).unwrap();
+    assert_eq!(results[0].preimage, "hello");
+}
+
 #[test]
 fn test_bloom_filter_rejects_nonexistent_hash() {
     let dir = tempfile::tempdir().unwrap();
@@ -353,16 +518,16 @@ fn test_bloom_filter_rejects_nonexistent_hash() {
     let storage = ParquetStorage::new(&db_path);
 
     let existing_hash = sha256.hash(b"hello");
-    let results = storage.query(&existing_hash, None, None).unwrap();
+    let results = storage.query(&existing_hash, None, 0, None).unwrap();
     assert_eq!(results.len(), 1);
     assert_eq!(results[0].preimage, "hello");
 
     let nonexistent_hash = sha256.hash(b"notindb");
-    let results = storage.query(&nonexistent_hash, None, None).unwrap();
+    let results = storage.query(&nonexistent_hash, None, 0, None).unwrap();
     assert_eq!(results.len(), 0);
 
     let prefix = &existing_hash[..4];
-    let results = storage.query(prefix, None, None).unwrap();
+    let results = storage.query(prefix, None, 0, None).unwrap();
     assert_eq!(results.len(), 1);
     assert_eq!(results[0].preimage, "hello");
 }
@@ -392,19 +557,65 @@ fn test_query_with_limit() {
 
     let storage = ParquetStorage::new(&db_path);
 
-    let results = storage.query(&[], None, None).unwrap();
+    let results = storage.query(&[], None, 0, None).unwrap();
     assert_eq!(results.len(), 100);
 
-    let results = storage.query(&[], None, Some(10)).unwrap();
+    let results = storage.query(&[], None, 0, Some(10)).unwrap();
     assert_eq!(results.len(), 10);
 
-    let results = storage.query(&[], None, Some(1)).unwrap();
+    let results = storage.query(&[], None, 0, Some(1)).unwrap();
     assert_eq!(results.len(), 1);
 
-    let results = storage.query(&[], None, Some(1000)).unwrap();
+    let results = storage.query(&[], None, 0, Some(1000)).unwrap();
     assert_eq!(results.len(), 100);
 }
 
+#[test]
+fn test_query_with_offset() {
+    let dir = tempfile::tempdir().unwrap();
+    let db_path = dir.path().join("test.parquet");
+
+    let sha256 = hasher::get_hasher("sha256").unwrap();
+    let records: Vec<HashRecord> = (0..100)
+        .map(|i| {
+            let word = format!("word{}", i);
+            HashRecord {
+                hash: sha256.hash(word.as_bytes()),
+                preimage: word,
+                algorithm: "sha256".to_string(),
+                sources: vec!["test".to_string()],
+            }
+        })
+        .collect();
+
+    let mut memory = MemoryStorage::new();
+    memory.write_batch(records.clone()).unwrap();
+    let mut storage = ParquetStorage::new(&db_path);
+    storage.write_batch(records).unwrap();
+    storage.finish().unwrap();
+    let storage = ParquetStorage::new(&db_path);
+
+    let preimages = |records: Vec<HashRecord>| -> Vec<String> {
+        records.into_iter().map(|r| r.preimage).collect()
+    };
+    let all = preimages(storage.query(&[], None, 0, None).unwrap());
+
+    assert_eq!(preimages(storage.query(&[], None, 90, Some(5)).unwrap()), all[90..95]);
+    assert_eq!(preimages(storage.query(&[], None, 95, Some(10)).unwrap()), all[95..]);
+    assert_eq!(preimages(storage.query(&[], None, 40, None).unwrap()), all[40..]);
+    assert!(storage.query(&[], None, 100, None).unwrap().is_empty());
+    assert!(storage.query(&[], None, 1000, Some(10)).unwrap().is_empty());
+
+    // MemoryStorage expands a 4-bit prefix into byte queries; the offset
+    // has to span all of them rather than restart at each one.
+    let nibble = [sha256.hash(b"word0")[0] & 0xf0];
+    let expanded = preimages(memory.query_bits(&nibble, 4, None, 0, None).unwrap());
+    assert!(expanded.len() > 2, "{:?}", expanded);
+    let paged = preimages(memory.query_bits(&nibble, 4, None, 1, Some(1)).unwrap());
+    assert_eq!(paged, expanded[1..2]);
+    assert!(memory.query_bits(&nibble, 4, None, expanded.len(), None).unwrap().is_empty());
+}
+
 #[test]
 fn test_empty_file_source() {
     let dir = tempfile::tempdir().unwrap();
@@ -461,7 +672,7 @@ fn test_file_source_content_hash_deterministic() {
 #[test]
 fn test_query_nonexistent_database() {
     let storage = ParquetStorage::new("/nonexistent/path.parquet");
-    let results = storage.query(&[], None, None).unwrap();
+    let results = storage.query(&[], None, 0, None).unwrap();
     assert!(results.is_empty());
 
     let stats = storage.stats().unwrap();
@@ -509,6 +720,55 @@ async fn test_url_source_content_hash_deterministic() {
     assert_eq!(hash1.len(), 64);
 }
 
+#[tokio::test(flavor = "multi_thread")]
+async fn test_r2_conditional_upload_rejects_concurrent_modification() {
+    use shaha::storage::{ObjectVersion, R2Config, R2Object};
+    use wiremock::matchers::{header, header_exists, method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    let mock_server = MockServer::start().await;
+
+    Mock::given(method("HEAD"))
+        .and(path("/bucket/db.parquet"))
+        .respond_with(ResponseTemplate::new(200).insert_header("ETag", "\"v1\""))
+        .mount(&mock_server)
+        .await;
+    // Another writer replaced the object after our HEAD
+    Mock::given(method("PUT"))
+        .and(path("/bucket/db.parquet"))
+        .and(header("If-Match", "\"v1\""))
+        .and(header_exists("Authorization"))
+        .respond_with(ResponseTemplate::new(412))
+        .expect(1)
+        .mount(&mock_server)
+        .await;
+    Mock::given(method("PUT"))
+        .and(path("/bucket/new.parquet"))
+        .and(header("If-None-Match", "*"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&mock_server)
+        .await;
+
+    let uri = mock_server.uri();
+    let (version, conflict, created) = tokio::task::spawn_blocking(move || {
+        let object = R2Object::new(R2Config::new(&uri, "key", "secret", "bucket", "db.parquet"));
+        let version = object.version().unwrap();
+        let conflict = object.put_if(b"data".to_vec(), &version);
+        let new_object =
+            R2Object::new(R2Config::new(&uri, "key", "secret", "bucket", "new.parquet"));
+        let created = new_object.put_if(b"data".to_vec(), &ObjectVersion::Absent);
+        (version, conflict, created)
+    })
+    .await
+    .unwrap();
+
+    assert_eq!(version, ObjectVersion::ETag("\"v1\"".to_string()));
+    let err = format!("{:#}", conflict.unwrap_err());
+    assert!(err.contains("modified by another writer"), "{}", err);
+    created.unwrap();
+}
+
 #[test]
 fn test_url_source_fetch_error_connection_refused() {
     let result = UrlSource::new("http://127.0.0.1:1/words.txt");
@@ -519,23 +779,140 @@ fn test_url_source_fetch_error_connection_refused() {
 }
 
 #[tokio::test(flavor = "multi_thread")]
-async fn test_url_source_http_500_succeeds() {
+async fn test_url_source_http_500_fails_after_retries() {
     use wiremock::matchers::method;
     use wiremock::{Mock, MockServer, ResponseTemplate};
 
     let mock_server = MockServer::start().await;
 
     Mock::given(method("GET"))
-        .respond_with(ResponseTemplate::new(500))
+        .respond_with(ResponseTemplate::new(500).set_body_string("<html>oops</html>"))
+        .expect(3)
         .mount(&mock_server)
         .await;
 
     let uri = mock_server.uri();
-    let source = tokio::task::spawn_blocking(move || UrlSource::new(&uri))
-        .await
+    let dir = tempfile::tempdir().unwrap();
+    let db_path = dir.path().join("test.parquet");
+    let output = tokio::task::spawn_blocking({
+        let db_path = db_path.clone();
+        move || {
+            std::process::Command::new(env!("CARGO_BIN_EXE_shaha"))
+                .args(["build", "--from", &format!("{}/words.txt", uri)])
+                .args(["--retries", "2", "--retry-delay-ms", "1", "-o", db_path.to_str().unwrap()])
+                .output()
+                .expect("Failed to run build")
+        }
+    })
+    .await
+    .unwrap();
+
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("Failed to fetch URL"), "{}", stderr);
+    assert!(stderr.contains("500"), "{}", stderr);
+    assert!(!db_path.exists());
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_url_source_sends_bearer_and_custom_headers() {
+    use shaha::source::{parse_header, UrlOptions};
+    use wiremock::matchers::{header, method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    let mock_server = MockServer::start().await;
+
+    Mock::given(method("GET"))
+        .and(path("/private/words.txt"))
+        .and(header("Authorization", "Bearer s3cret"))
+        .and(header("X-Team", "red"))
+        .respond_with(ResponseTemplate::new(200).set_body_string("alpha\nbeta\n"))
+        .expect(1)
+        .mount(&mock_server)
+        .await;
+
+    assert_eq!(
+        parse_header("  X-Team :  red ").unwrap(),
+        ("X-Team".to_string(), "red".to_string())
+    );
+    assert!(parse_header("no colon here").is_err());
+    assert!(parse_header(": value").is_err());
+    assert!(parse_header("Bad Name: value").is_err());
+
+    let url = format!("{}/private/words.txt", mock_server.uri());
+    let words = tokio::task::spawn_blocking(move || {
+        let options = UrlOptions {
+            bearer: Some("s3cret".to_string()),
+            headers: vec![parse_header("X-Team: red").unwrap()],
+            ..UrlOptions::default()
+        };
+        let source = UrlSource::with_options(&url, &options).unwrap();
+        source.words().unwrap().collect::<Vec<_>>()
+    })
+    .await
+    .unwrap();
+
+    assert_eq!(words, vec!["alpha", "beta"]);
+
+    let conflicting = UrlOptions {
+        bearer: Some("s3cret".to_string()),
+        headers: vec![parse_header("Authorization: Basic abc").unwrap()],
+        ..UrlOptions::default()
+    };
+    let err = UrlSource::with_options("http://127.0.0.1:1/words.txt", &conflicting)
+        .err()
         .unwrap();
+    assert!(err.to_string().contains("cannot be combined"), "{}", err);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_url_source_retries_server_errors() {
+    use shaha::source::UrlOptions;
+    use std::time::Duration;
+    use wiremock::matchers::{method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    let mock_server = MockServer::start().await;
+
+    // Mocks are matched in mount order; the first one is used up after two calls
+    Mock::given(method("GET"))
+        .and(path("/flaky.txt"))
+        .respond_with(ResponseTemplate::new(503))
+        .up_to_n_times(2)
+        .expect(2)
+        .mount(&mock_server)
+        .await;
+    Mock::given(method("GET"))
+        .and(path("/flaky.txt"))
+        .respond_with(ResponseTemplate::new(200).set_body_string("alpha\nbeta\n"))
+        .expect(1)
+        .mount(&mock_server)
+        .await;
+    Mock::given(method("GET"))
+        .and(path("/missing.txt"))
+        .respond_with(ResponseTemplate::new(404).set_body_string("not found"))
+        .expect(1)
+        .mount(&mock_server)
+        .await;
+
+    let uri = mock_server.uri();
+    let (words, missing) = tokio::task::spawn_blocking(move || {
+        let options = UrlOptions {
+            retry_delay: Duration::from_millis(10),
+            ..UrlOptions::default()
+        };
+        let source = UrlSource::with_options(format!("{}/flaky.txt", uri), &options).unwrap();
+        let words = source.words().unwrap().collect::<Vec<_>>();
+        // Client errors are not retried, and their body is not read as words
+        let missing = UrlSource::with_options(format!("{}/missing.txt", uri), &options);
+        (words, missing.err().map(|err| format!("{:#}", err)))
+    })
+    .await
+    .unwrap();
 
-    assert!(source.is_ok());
+    assert_eq!(words, vec!["alpha", "beta"]);
+    let missing = missing.expect("404 must fail the fetch");
+    assert!(missing.contains("404"), "{}", missing);
 }
 
 #[tokio::test(flavor = "multi_thread")]
@@ -748,6 +1125,55 @@ fn test_dry_run_shows_append_info() {
     );
 }
 
+#[test]
+fn test_dry_run_append_merge_preview() {
+    let dir = tempfile::tempdir().unwrap();
+    let first_path = dir.path().join("first.txt");
+    let second_path = dir.path().join("second.txt");
+    let db_path = dir.path().join("test.parquet");
+    fs::write(&first_path, "alpha\nbeta\ngamma\n").unwrap();
+    fs::write(&second_path, "beta\ngamma\ndelta\nbeta\n").unwrap();
+
+    let build = |words: &std::path::Path, name: &str, extra: &[&str]| {
+        std::process::Command::new(env!("CARGO_BIN_EXE_shaha"))
+            .args([
+                "build",
+                words.to_str().unwrap(),
+                "-n",
+                name,
+                "-o",
+                db_path.to_str().unwrap(),
+            ])
+            .args(extra)
+            .output()
+            .expect("Failed to run shaha")
+    };
+
+    assert!(build(&first_path, "first", &[]).status.success());
+    let before = fs::read(&db_path).unwrap();
+
+    let output = build(&second_path, "second", &["--append", "--dry-run"]);
+    assert!(output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains(
+            "Merge preview: 2 existing records would gain source 'second', 1 new records, 4 total"
+        ),
+        "{}",
+        stderr
+    );
+    assert!(stderr.contains("Would write 4 records"));
+    assert_eq!(fs::read(&db_path).unwrap(), before);
+
+    let output = build(&first_path, "first", &["--append", "--dry-run", "--force"]);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("0 existing records would gain source 'first', 0 new records, 3 total"),
+        "{}",
+        stderr
+    );
+}
+
 #[test]
 fn test_dry_run_shows_already_processed() {
     let dir = tempfile::tempdir().unwrap();
@@ -1045,3 +1471,4482 @@ fn test_query_quiet_suppresses_summary() {
         stderr
     );
 }
+
+#[test]
+fn test_build_page_settings_take_effect() {
+    use parquet::basic::PageType;
+    use parquet::file::reader::{FileReader, SerializedFileReader};
+
+    let dir = tempfile::tempdir().unwrap();
+    let words_path = dir.path().join("words.txt");
+    let words: Vec<String> = (0..300).map(|i| format!("word{}", i)).collect();
+    fs::write(&words_path, words.join("\n")).unwrap();
+
+    let build = |db: &std::path::Path, extra: &[&str]| {
+        let status = std::process::Command::new(env!("CARGO_BIN_EXE_shaha"))
+            .args(["build", words_path.to_str().unwrap(), "-o", db.to_str().unwrap()])
+            .args(extra)
+            .status()
+            .expect("Failed to build database");
+        assert!(status.success());
+    };
+    let hash_pages = |db: &std::path::Path| -> Vec<PageType> {
+        let reader = SerializedFileReader::new(fs::File::open(db).unwrap()).unwrap();
+        let row_group = reader.get_row_group(0).unwrap();
+        row_group
+            .get_column_page_reader(0)
+            .unwrap()
+            .map(|page| page.unwrap().page_type())
+            .filter(|t| matches!(t, PageType::DATA_PAGE | PageType::DATA_PAGE_V2))
+            .collect()
+    };
+
+    let default_db = dir.path().join("default.parquet");
+    build(&default_db, &[]);
+    assert_eq!(hash_pages(&default_db), vec![PageType::DATA_PAGE]);
+
+    let tuned_db = dir.path().join("tuned.parquet");
+    build(
+        &tuned_db,
+        &["--page-rows", "50", "--page-size", "65536", "--data-page-version", "2"],
+    );
+    let pages = hash_pages(&tuned_db);
+    assert_eq!(pages.len(), 6, "{:?}", pages);
+    assert!(pages.iter().all(|t| *t == PageType::DATA_PAGE_V2));
+
+    let reader = SerializedFileReader::new(fs::File::open(&tuned_db).unwrap()).unwrap();
+    let metadata: std::collections::HashMap<String, String> = reader
+        .metadata()
+        .file_metadata()
+        .key_value_metadata()
+        .unwrap()
+        .iter()
+        .filter_map(|kv| Some((kv.key.clone(), kv.value.clone()?)))
+        .collect();
+    assert_eq!(metadata["shaha:page_rows"], "50");
+    assert_eq!(metadata["shaha:page_size"], "65536");
+    assert_eq!(metadata["shaha:data_page_version"], "2");
+
+    let storage = ParquetStorage::new(&tuned_db);
+    assert_eq!(storage.stats().unwrap().total_records, 300);
+    let sha256 = hasher::get_hasher("sha256").unwrap();
+    for word in ["word0", "word149", "word299"] {
+        let results = storage.query(&sha256.hash(word.as_bytes()), None, 0, None).unwrap();
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].preimage, word);
+    }
+}
+
+#[test]
+fn test_large_preimages_split_row_groups() {
+    use parquet::file::reader::{FileReader, SerializedFileReader};
+
+    let dir = tempfile::tempdir().unwrap();
+    let db_path = dir.path().join("test.parquet");
+
+    let sha256 = hasher::get_hasher("sha256").unwrap();
+
+    let mut records: Vec<HashRecord> = (0..200)
+        .map(|i| {
+            let word = if i % 10 == 0 {
+                format!("{}{}", "a".repeat(10_000), i)
+            } else {
+                format!("word{}", i)
+            };
+            HashRecord {
+                hash: sha256.hash(word.as_bytes()),
+                preimage: word,
+                algorithm: "sha256".to_string(),
+                sources: vec!["test".to_string()],
+            }
+        })
+        .collect();
+    records.sort_by(|a, b| a.hash.cmp(&b.hash));
+
+    let max_bytes = 32 * 1024;
+    let mut storage = ParquetStorage::new(&db_path).with_max_row_group_bytes(max_bytes);
+    storage.write_batch(records.clone()).unwrap();
+    storage.finish().unwrap();
+
+    let reader = SerializedFileReader::new(fs::File::open(&db_path).unwrap()).unwrap();
+    let metadata = reader.metadata();
+    assert!(metadata.num_row_groups() > 1);
+    for rg in metadata.row_groups() {
+        assert!(
+            rg.total_byte_size() as usize <= max_bytes * 2,
+            "Row group too large: {} bytes",
+            rg.total_byte_size()
+        );
+    }
+
+    let storage = ParquetStorage::new(&db_path);
+    assert_eq!(storage.stats().unwrap().total_records, 200);
+    for record in records.iter().step_by(10) {
+        let results = storage.query(&record.hash, None, 0, None).unwrap();
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].preimage, record.preimage);
+    }
+}
+
+#[test]
+fn test_query_mask_matches_known_record() {
+    let dir = tempfile::tempdir().unwrap();
+    let words_path = dir.path().join("words.txt");
+    let db_path = dir.path().join("test.parquet");
+
+    {
+        let mut file = fs::File::create(&words_path).unwrap();
+        writeln!(file, "hello").unwrap();
+        writeln!(file, "world").unwrap();
+    }
+
+    std::process::Command::new(env!("CARGO_BIN_EXE_shaha"))
+        .args([
+            "build",
+            words_path.to_str().unwrap(),
+            "-o",
+            db_path.to_str().unwrap(),
+        ])
+        .output()
+        .expect("Failed to build database");
+
+    let sha256 = hasher::get_hasher("sha256").unwrap();
+    let hash_hex = hex::encode(sha256.hash(b"hello"));
+    let mask = format!("{}?{}", &hash_hex[..3], &hash_hex[4..8]);
+
+    let output = std::process::Command::new(env!("CARGO_BIN_EXE_shaha"))
+        .args(["query", "--mask", &mask, "-d", db_path.to_str().unwrap()])
+        .output()
+        .expect("Failed to run query");
+
+    assert!(output.status.success());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("hello"));
+    assert!(!stdout.contains("world"));
+
+    let output = std::process::Command::new(env!("CARGO_BIN_EXE_shaha"))
+        .args([
+            "query",
+            "--mask",
+            "??00",
+            "--max-expansion",
+            "16",
+            "-d",
+            db_path.to_str().unwrap(),
+        ])
+        .output()
+        .expect("Failed to run query");
+
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("--max-expansion"));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_url_source_origin_stored_in_source_meta() {
+    use wiremock::matchers::method;
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    let mock_server = MockServer::start().await;
+
+    Mock::given(method("GET"))
+        .respond_with(ResponseTemplate::new(200).set_body_string("hello\nworld\n"))
+        .mount(&mock_server)
+        .await;
+
+    let dir = tempfile::tempdir().unwrap();
+    let db_path = dir.path().join("test.parquet");
+    let url = format!("{}/words.txt", mock_server.uri());
+
+    let build_url = url.clone();
+    let build_db = db_path.clone();
+    let output = tokio::task::spawn_blocking(move || {
+        std::process::Command::new(env!("CARGO_BIN_EXE_shaha"))
+            .args(["build", "--from", &build_url, "-o", build_db.to_str().unwrap()])
+            .output()
+            .expect("Failed to run shaha")
+    })
+    .await
+    .unwrap();
+
+    assert!(output.status.success());
+
+    let storage = ParquetStorage::new(&db_path);
+    let source_meta = storage.get_source_meta().unwrap();
+    let meta = source_meta.get("words").expect("source meta for 'words'");
+    assert_eq!(meta.url.as_deref(), Some(url.as_str()));
+    assert!(meta.retrieved_at.is_some());
+
+    let output = std::process::Command::new(env!("CARGO_BIN_EXE_shaha"))
+        .args(["info", db_path.to_str().unwrap(), "--verbose"])
+        .output()
+        .expect("Failed to run info");
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains(&url));
+}
+
+#[test]
+fn test_seclists_pull_pins_revision() {
+    use std::process::Command;
+
+    let dir = tempfile::tempdir().unwrap();
+    let repo = dir.path().join("seclists-mirror");
+    let cache = dir.path().join("cache");
+    fs::create_dir_all(repo.join("Passwords")).unwrap();
+
+    let git = |args: &[&str]| {
+        let output = Command::new("git")
+            .args(["-c", "user.name=test", "-c", "user.email=test@example.com"])
+            .args(args)
+            .current_dir(&repo)
+            .output()
+            .expect("Failed to run git");
+        assert!(output.status.success(), "git {:?} failed", args);
+        String::from_utf8_lossy(&output.stdout).trim().to_string()
+    };
+
+    git(&["init", "--quiet"]);
+    fs::write(repo.join("Passwords/common.txt"), "hello\n").unwrap();
+    git(&["add", "."]);
+    git(&["commit", "--quiet", "-m", "first"]);
+    git(&["tag", "v1"]);
+    let pinned = git(&["rev-parse", "HEAD"]);
+
+    fs::write(repo.join("Passwords/common.txt"), "hello\nworld\n").unwrap();
+    git(&["commit", "--quiet", "-am", "second"]);
+
+    let output = Command::new(env!("CARGO_BIN_EXE_shaha"))
+        .args(["source", "pull", "seclists", "--rev", "v1"])
+        .env("XDG_CACHE_HOME", &cache)
+        .env("SHAHA_SECLISTS_REPO", format!("file://{}", repo.display()))
+        .output()
+        .expect("Failed to run shaha");
+
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains(&pinned));
+
+    let checkout = cache.join("shaha").join("seclists");
+    let head = Command::new("git")
+        .args(["rev-parse", "HEAD"])
+        .current_dir(&checkout)
+        .output()
+        .unwrap();
+    assert_eq!(String::from_utf8_lossy(&head.stdout).trim(), pinned);
+    assert_eq!(
+        fs::read_to_string(checkout.join("Passwords/common.txt")).unwrap(),
+        "hello\n"
+    );
+}
+
+#[test]
+fn test_seclists_list_and_build_non_txt_files() {
+    use std::process::Command;
+
+    let dir = tempfile::tempdir().unwrap();
+    let cache = dir.path().join("cache");
+    let seclists = cache.join("shaha").join("seclists");
+    fs::create_dir_all(seclists.join("Fuzzing")).unwrap();
+    fs::create_dir_all(seclists.join("Passwords")).unwrap();
+    fs::create_dir_all(seclists.join(".git")).unwrap();
+    fs::write(seclists.join("Fuzzing/special.fuzz"), "fuzzword\n").unwrap();
+    fs::write(seclists.join("Passwords/common.txt"), "hello\n").unwrap();
+    fs::write(seclists.join("Passwords/bare"), "bare\n").unwrap();
+    fs::write(seclists.join(".git/HEAD"), "ref\n").unwrap();
+
+    let shaha = |args: &[&str]| {
+        let output = Command::new(env!("CARGO_BIN_EXE_shaha"))
+            .args(args)
+            .env("XDG_CACHE_HOME", &cache)
+            .output()
+            .expect("Failed to run shaha");
+        assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+        String::from_utf8_lossy(&output.stdout).to_string()
+    };
+
+    assert_eq!(shaha(&["source", "list", "seclists"]), "Passwords/common.txt\n");
+    assert_eq!(
+        shaha(&["source", "list", "seclists", "--all-extensions"]),
+        "Fuzzing/special.fuzz\nPasswords/bare\nPasswords/common.txt\n"
+    );
+    assert_eq!(
+        shaha(&["source", "list", "seclists", "--extension", "fuzz", "--extension", ""]),
+        "Fuzzing/special.fuzz\nPasswords/bare\n"
+    );
+
+    let db_path = dir.path().join("fuzz.parquet");
+    shaha(&["build", "--from", "seclists:Fuzzing/special.fuzz", "-o", db_path.to_str().unwrap()]);
+    let hash = hasher::get_hasher("sha256").unwrap().hash(b"fuzzword");
+    let found = ParquetStorage::new(&db_path).query(&hash, None, 0, None).unwrap();
+    assert_eq!(found[0].sources, vec!["Fuzzing/special.fuzz"]);
+}
+
+#[test]
+fn test_build_output_is_deterministic() {
+    let dir = tempfile::tempdir().unwrap();
+    let words_path = dir.path().join("words.txt");
+
+    {
+        let mut file = fs::File::create(&words_path).unwrap();
+        for i in 0..150_000 {
+            writeln!(file, "word{}", i % 120_000).unwrap();
+        }
+    }
+
+    let mut builds = Vec::new();
+    for name in ["a.parquet", "b.parquet"] {
+        let db_path = dir.path().join(name);
+        let output = std::process::Command::new(env!("CARGO_BIN_EXE_shaha"))
+            .args([
+                "build",
+                words_path.to_str().unwrap(),
+                "-o",
+                db_path.to_str().unwrap(),
+                "-a",
+                "sha256",
+                "-a",
+                "md5",
+            ])
+            .output()
+            .expect("Failed to run shaha");
+        assert!(output.status.success());
+
+        let mut records = Vec::new();
+        ParquetStorage::new(&db_path)
+            .for_each_record(|r| {
+                records.push((r.hash, r.algorithm, r.preimage));
+                Ok(())
+            })
+            .unwrap();
+        builds.push(records);
+    }
+
+    assert_eq!(builds[0].len(), 240_000);
+    assert_eq!(builds[0], builds[1]);
+}
+
+#[test]
+fn test_build_writes_parquet_and_csv_outputs() {
+    let dir = tempfile::tempdir().unwrap();
+    let words_path = dir.path().join("words.txt");
+    let db_path = dir.path().join("out.parquet");
+    let csv_path = dir.path().join("out.csv");
+
+    {
+        let mut file = fs::File::create(&words_path).unwrap();
+        writeln!(file, "hello").unwrap();
+        writeln!(file, "a,b").unwrap();
+    }
+
+    let output = std::process::Command::new(env!("CARGO_BIN_EXE_shaha"))
+        .args([
+            "build",
+            words_path.to_str().unwrap(),
+            "-o",
+            db_path.to_str().unwrap(),
+            "-o",
+            csv_path.to_str().unwrap(),
+        ])
+        .output()
+        .expect("Failed to run shaha");
+    assert!(output.status.success());
+
+    let sha256 = hasher::get_hasher("sha256").unwrap();
+    let storage = ParquetStorage::new(&db_path);
+    assert_eq!(storage.stats().unwrap().total_records, 2);
+    let results = storage.query(&sha256.hash(b"hello"), None, 0, None).unwrap();
+    assert_eq!(results[0].preimage, "hello");
+
+    let mut reader = csv::Reader::from_path(&csv_path).unwrap();
+    let headers = reader.headers().unwrap().clone();
+    assert_eq!(
+        headers.iter().collect::<Vec<_>>(),
+        vec!["hash", "preimage", "algorithm", "sources"]
+    );
+    let rows: Vec<csv::StringRecord> = reader.records().map(|r| r.unwrap()).collect();
+    assert_eq!(rows.len(), 2);
+    let row = rows.iter().find(|r| &r[1] == "a,b").expect("quoted preimage");
+    assert_eq!(&row[0], hex::encode(sha256.hash(b"a,b")));
+    assert_eq!(&row[2], "sha256");
+    assert_eq!(&row[3], "words");
+}
+
+#[test]
+fn test_hex_stored_hash_roundtrip() {
+    use arrow::array::{Array, StringArray};
+    use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+    use shaha::storage::HashEncoding;
+
+    let dir = tempfile::tempdir().unwrap();
+    let db_path = dir.path().join("test.parquet");
+
+    let sha256 = hasher::get_hasher("sha256").unwrap();
+    let mut records: Vec<HashRecord> = ["hello", "world", "test"]
+        .iter()
+        .map(|word| HashRecord {
+            hash: sha256.hash(word.as_bytes()),
+            preimage: word.to_string(),
+            algorithm: "sha256".to_string(),
+            sources: vec!["test".to_string()],
+        })
+        .collect();
+    records.sort_by(|a, b| a.hash.cmp(&b.hash));
+
+    let mut storage = ParquetStorage::new(&db_path).with_hash_encoding(HashEncoding::Hex);
+    storage.write_batch(records).unwrap();
+    storage.finish().unwrap();
+
+    let file = fs::File::open(&db_path).unwrap();
+    let mut reader = ParquetRecordBatchReaderBuilder::try_new(file)
+        .unwrap()
+        .build()
+        .unwrap();
+    let batch = reader.next().unwrap().unwrap();
+    let stored = batch
+        .column(0)
+        .as_any()
+        .downcast_ref::<StringArray>()
+        .expect("hash column stored as utf8");
+    let hello_hex = hex::encode(sha256.hash(b"hello"));
+    assert!((0..stored.len()).any(|i| stored.value(i) == hello_hex));
+
+    let storage = ParquetStorage::new(&db_path);
+    let results = storage.query(&sha256.hash(b"hello"), None, 0, None).unwrap();
+    assert_eq!(results.len(), 1);
+    assert_eq!(results[0].preimage, "hello");
+    assert_eq!(results[0].hash, sha256.hash(b"hello"));
+
+    let results = storage.query(&sha256.hash(b"world")[..3], None, 0, None).unwrap();
+    assert_eq!(results.len(), 1);
+    assert_eq!(results[0].preimage, "world");
+
+    let mut count = 0;
+    storage
+        .for_each_record(|r| {
+            assert_eq!(r.hash, sha256.hash(r.preimage.as_bytes()));
+            count += 1;
+            Ok(())
+        })
+        .unwrap();
+    assert_eq!(count, 3);
+}
+
+#[test]
+fn test_query_db_dir_more_shards_than_open_file_limit() {
+    let dir = tempfile::tempdir().unwrap();
+    let shards = dir.path().join("shards");
+    fs::create_dir(&shards).unwrap();
+
+    for i in 0..5 {
+        let words_path = dir.path().join(format!("words{}.txt", i));
+        fs::write(&words_path, format!("shardword{}\n", i)).unwrap();
+
+        let status = std::process::Command::new(env!("CARGO_BIN_EXE_shaha"))
+            .args([
+                "build",
+                words_path.to_str().unwrap(),
+                "-a",
+                "sha256",
+                "-o",
+                shards.join(format!("shard{}.parquet", i)).to_str().unwrap(),
+            ])
+            .status()
+            .expect("Failed to build shard");
+        assert!(status.success());
+    }
+
+    let sha256 = hasher::get_hasher("sha256").unwrap();
+    for i in 0..5 {
+        let word = format!("shardword{}", i);
+        let hash_hex = hex::encode(sha256.hash(word.as_bytes()));
+
+        let output = std::process::Command::new(env!("CARGO_BIN_EXE_shaha"))
+            .args([
+                "query",
+                &hash_hex,
+                "--db-dir",
+                shards.to_str().unwrap(),
+                "--max-open-files",
+                "2",
+            ])
+            .output()
+            .expect("Failed to run query");
+
+        assert!(output.status.success());
+        assert!(String::from_utf8_lossy(&output.stdout).contains(&word));
+    }
+
+    let hash_hex = hex::encode(sha256.hash(b"shardword3"));
+    let output = std::process::Command::new(env!("CARGO_BIN_EXE_shaha"))
+        .args([
+            "query",
+            &hash_hex,
+            "-d",
+            shards.join("shard0.parquet").to_str().unwrap(),
+            "-d",
+            shards.join("shard3.parquet").to_str().unwrap(),
+            "--max-open-files",
+            "1",
+        ])
+        .output()
+        .expect("Failed to run query");
+
+    assert!(output.status.success());
+    assert!(String::from_utf8_lossy(&output.stdout).contains("shardword3"));
+}
+
+#[test]
+fn test_timestamp_sources_preserved_across_append() {
+    let dir = tempfile::tempdir().unwrap();
+    let db_path = dir.path().join("test.parquet");
+    let first_words = dir.path().join("first.txt");
+    let second_words = dir.path().join("second.txt");
+    fs::write(&first_words, "alpha\nbeta\n").unwrap();
+    fs::write(&second_words, "gamma\ndelta\n").unwrap();
+
+    let build = |words: &std::path::Path, name: &str, append: bool| {
+        let mut cmd = std::process::Command::new(env!("CARGO_BIN_EXE_shaha"));
+        cmd.args([
+            "build",
+            words.to_str().unwrap(),
+            "-n",
+            name,
+            "-o",
+            db_path.to_str().unwrap(),
+            "--timestamp-sources",
+        ]);
+        if append {
+            cmd.arg("--append");
+        }
+        assert!(cmd.status().expect("Failed to build").success());
+    };
+
+    build(&first_words, "first", false);
+    let first_seen = ParquetStorage::new(&db_path).get_source_meta().unwrap()["first"]
+        .first_seen
+        .clone()
+        .expect("first_seen recorded");
+
+    std::thread::sleep(std::time::Duration::from_millis(1100));
+    build(&second_words, "second", true);
+
+    let meta = ParquetStorage::new(&db_path).get_source_meta().unwrap();
+    assert_eq!(meta["first"].first_seen.as_deref(), Some(first_seen.as_str()));
+    let second_seen = meta["second"].first_seen.clone().expect("first_seen recorded");
+    assert_ne!(second_seen, first_seen);
+
+    fs::write(&first_words, "alpha\nbeta\nepsilon\n").unwrap();
+    build(&first_words, "first", true);
+
+    let meta = ParquetStorage::new(&db_path).get_source_meta().unwrap();
+    assert_eq!(meta["first"].first_seen.as_deref(), Some(first_seen.as_str()));
+    assert_eq!(meta["second"].first_seen.as_deref(), Some(second_seen.as_str()));
+
+    let output = std::process::Command::new(env!("CARGO_BIN_EXE_shaha"))
+        .args(["info", db_path.to_str().unwrap(), "--verbose"])
+        .output()
+        .expect("Failed to run info");
+    assert!(String::from_utf8_lossy(&output.stdout).contains(&first_seen));
+}
+
+#[test]
+fn test_prefix_only_bits_non_byte_aligned() {
+    let dir = tempfile::tempdir().unwrap();
+    let words_path = dir.path().join("words.txt");
+    let db_path = dir.path().join("test.parquet");
+    fs::write(&words_path, "hello\nworld\npassword\n").unwrap();
+
+    let status = std::process::Command::new(env!("CARGO_BIN_EXE_shaha"))
+        .args([
+            "build",
+            words_path.to_str().unwrap(),
+            "-a",
+            "sha256",
+            "-o",
+            db_path.to_str().unwrap(),
+            "--prefix-only-bits",
+            "20",
+        ])
+        .status()
+        .expect("Failed to build database");
+    assert!(status.success());
+
+    let sha256 = hasher::get_hasher("sha256").unwrap();
+    let hash = sha256.hash(b"hello");
+    let storage = ParquetStorage::new(&db_path);
+
+    let results = storage.query(&hash, None, 0, None).unwrap();
+    let record = results
+        .iter()
+        .find(|r| r.preimage == "hello")
+        .expect("hello is a member");
+    assert_eq!(record.hash, vec![hash[0], hash[1], hash[2] & 0xF0]);
+
+    let mut low_nibble_changed = hash.clone();
+    low_nibble_changed[2] ^= 0x0F;
+    let results = storage.query(&low_nibble_changed, None, 0, None).unwrap();
+    assert!(results.iter().any(|r| r.preimage == "hello"));
+
+    let mut bit_20_changed = hash.clone();
+    bit_20_changed[2] ^= 0x10;
+    let results = storage.query(&bit_20_changed, None, 0, None).unwrap();
+    assert!(!results.iter().any(|r| r.preimage == "hello"));
+
+    let results = storage.query(&hash[..2], None, 0, None).unwrap();
+    assert!(results.iter().any(|r| r.preimage == "hello"));
+
+    let output = std::process::Command::new(env!("CARGO_BIN_EXE_shaha"))
+        .args(["query", &hex::encode(&hash), "-d", db_path.to_str().unwrap()])
+        .output()
+        .expect("Failed to run query");
+    assert!(output.status.success());
+    assert!(String::from_utf8_lossy(&output.stdout).contains("hello"));
+}
+
+#[test]
+fn test_query_cache_serves_repeated_lookups() {
+    let dir = tempfile::tempdir().unwrap();
+    let db_path = dir.path().join("test.parquet");
+    let sha256 = hasher::get_hasher("sha256").unwrap();
+
+    let write = |word: &str| {
+        let mut storage = ParquetStorage::new(&db_path);
+        storage
+            .write_batch(vec![HashRecord {
+                hash: sha256.hash(word.as_bytes()),
+                preimage: word.to_string(),
+                algorithm: "sha256".to_string(),
+                sources: vec![],
+            }])
+            .unwrap();
+        storage.finish().unwrap();
+    };
+
+    write("password");
+    let hash = sha256.hash(b"password");
+    let cached = CachedStorage::new(ParquetStorage::new(&db_path), 16).with_watch_path(&db_path);
+
+    let first = cached.query(&hash, None, 0, None).unwrap();
+    let second = cached.query(&hash, None, 0, None).unwrap();
+    assert_eq!(first.len(), 1);
+    assert_eq!(second[0].preimage, "password");
+    assert_eq!(cached.misses(), 1);
+    assert_eq!(cached.hits(), 1);
+
+    cached.query(&hash, Some("md5"), 0, None).unwrap();
+    assert_eq!(cached.misses(), 2);
+
+    std::thread::sleep(std::time::Duration::from_millis(20));
+    write("hunter2");
+
+    assert!(cached.query(&hash, None, 0, None).unwrap().is_empty());
+    assert_eq!(cached.misses(), 3);
+    assert_eq!(cached.hits(), 1);
+}
+
+#[cfg(unix)]
+#[test]
+fn test_build_from_fifo() {
+    let dir = tempfile::tempdir().unwrap();
+    let fifo_path = dir.path().join("words.fifo");
+    let db_path = dir.path().join("test.parquet");
+
+    let status = std::process::Command::new("mkfifo")
+        .arg(&fifo_path)
+        .status()
+        .expect("Failed to run mkfifo");
+    assert!(status.success());
+
+    let source = FileSource::new(&fifo_path);
+    assert_eq!(source.content_hash().unwrap(), None);
+
+    let writer_path = fifo_path.clone();
+    let writer = std::thread::spawn(move || {
+        let mut fifo = fs::OpenOptions::new().write(true).open(writer_path).unwrap();
+        writeln!(fifo, "hello").unwrap();
+        writeln!(fifo, "world").unwrap();
+    });
+
+    let output = std::process::Command::new(env!("CARGO_BIN_EXE_shaha"))
+        .args([
+            "build",
+            fifo_path.to_str().unwrap(),
+            "-o",
+            db_path.to_str().unwrap(),
+        ])
+        .output()
+        .expect("Failed to build from fifo");
+    writer.join().unwrap();
+
+    assert!(output.status.success());
+    let stats = ParquetStorage::new(&db_path).stats().unwrap();
+    assert_eq!(stats.total_records, 2);
+}
+
+#[test]
+fn test_build_skip_lines() {
+    let dir = tempfile::tempdir().unwrap();
+    let words_path = dir.path().join("words.txt");
+    let partial_path = dir.path().join("partial.txt");
+    let db_path = dir.path().join("test.parquet");
+    fs::write(&words_path, "alpha\nbeta\ngamma\ndelta\nepsilon\n").unwrap();
+    fs::write(&partial_path, "alpha\nbeta\n").unwrap();
+
+    let output = std::process::Command::new(env!("CARGO_BIN_EXE_shaha"))
+        .args([
+            "build",
+            words_path.to_str().unwrap(),
+            "-a",
+            "sha256",
+            "-o",
+            db_path.to_str().unwrap(),
+            "--skip-lines",
+            "2",
+        ])
+        .output()
+        .expect("Failed to build database");
+    assert!(output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("Skipped first 2 lines"));
+    assert!(stderr.contains("Processed 3 words"));
+
+    let storage = ParquetStorage::new(&db_path);
+    let sha256 = hasher::get_hasher("sha256").unwrap();
+    assert_eq!(storage.stats().unwrap().total_records, 3);
+    assert!(storage.query(&sha256.hash(b"alpha"), None, 0, None).unwrap().is_empty());
+    assert_eq!(storage.query(&sha256.hash(b"gamma"), None, 0, None).unwrap().len(), 1);
+
+    fs::remove_file(&db_path).unwrap();
+    let resume = vec!["--skip-lines", "2", "--append"];
+    for (path, extra) in [(&partial_path, vec![]), (&words_path, resume)] {
+        let status = std::process::Command::new(env!("CARGO_BIN_EXE_shaha"))
+            .args([
+                "build",
+                path.to_str().unwrap(),
+                "-a",
+                "sha256",
+                "-n",
+                "words",
+                "-o",
+                db_path.to_str().unwrap(),
+            ])
+            .args(extra)
+            .status()
+            .expect("Failed to build database");
+        assert!(status.success());
+    }
+    assert_eq!(ParquetStorage::new(&db_path).stats().unwrap().total_records, 5);
+}
+
+#[test]
+fn test_library_build_into_memory_storage() {
+    let dir = tempfile::tempdir().unwrap();
+    let words_path = dir.path().join("words.txt");
+    fs::write(&words_path, "hello\nworld\nhello\n").unwrap();
+
+    let source = FileSource::new(&words_path);
+    let mut options = BuildOptions::new(["sha256", "md5"]).with_source_name("greetings");
+    let mut storage = MemoryStorage::new();
+
+    let summary = build(&source, &mut options, &mut storage).unwrap();
+    assert_eq!(summary.total_words, 3);
+    assert_eq!(summary.unique_words, 2);
+    assert_eq!(summary.records, 4);
+
+    let hash = hasher::get_hasher("md5").unwrap().hash(b"world");
+    let results = storage.query(&hash, None, 0, None).unwrap();
+    assert_eq!(results.len(), 1);
+    assert_eq!(results[0].preimage, "world");
+    assert_eq!(results[0].sources, vec!["greetings"]);
+
+    let stats = storage.stats().unwrap();
+    assert_eq!(stats.total_records, 4);
+    assert_eq!(stats.algorithms, vec!["md5", "sha256"]);
+
+    let hashes: Vec<_> = storage.records().iter().map(|r| r.hash.clone()).collect();
+    assert!(hashes.windows(2).all(|w| w[0] <= w[1]));
+
+    let mut bad = BuildOptions::new(["nope"]);
+    assert!(build(&source, &mut bad, &mut MemoryStorage::new()).is_err());
+}
+
+#[test]
+fn test_library_build_reports_progress() {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    let dir = tempfile::tempdir().unwrap();
+    let words_path = dir.path().join("words.txt");
+    let words: Vec<String> = (0..250_000).map(|i| format!("w{}", i % 200_000)).collect();
+    fs::write(&words_path, words.join("\n")).unwrap();
+
+    let reports = Rc::new(RefCell::new(Vec::new()));
+    let sink = Rc::clone(&reports);
+    let mut options = BuildOptions::default().with_progress(move |p| sink.borrow_mut().push(p));
+
+    let source = FileSource::new(&words_path);
+    let summary = build(&source, &mut options, &mut MemoryStorage::new()).unwrap();
+
+    let reports = reports.borrow();
+    assert!(reports.len() >= 3, "{:?}", reports);
+    assert!(reports.windows(2).all(|w| {
+        w[0].total_words <= w[1].total_words
+            && w[0].unique_words <= w[1].unique_words
+            && w[0].records <= w[1].records
+    }));
+    let last = reports.last().unwrap();
+    assert_eq!(last.total_words, summary.total_words);
+    assert_eq!(last.unique_words, 200_000);
+    assert_eq!(last.records, summary.records);
+}
+
+#[test]
+fn test_build_disk_dedup_spills_runs() {
+    let dir = tempfile::tempdir().unwrap();
+    let words_path = dir.path().join("words.txt");
+    let db_path = dir.path().join("test.parquet");
+    let words: Vec<String> = (0..300).map(|i| format!("word{}", i % 120)).collect();
+    fs::write(&words_path, words.join("\n")).unwrap();
+
+    let output = std::process::Command::new(env!("CARGO_BIN_EXE_shaha"))
+        .args([
+            "build",
+            words_path.to_str().unwrap(),
+            "-a",
+            "sha256",
+            "-a",
+            "md5",
+            "-o",
+            db_path.to_str().unwrap(),
+            "--disk-dedup",
+            "16",
+        ])
+        .output()
+        .expect("Failed to build database");
+    assert!(output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("Processed 300 words (120 unique,"), "{}", stderr);
+
+    let storage = ParquetStorage::new(&db_path);
+    assert_eq!(storage.stats().unwrap().total_records, 240);
+    let sha256 = hasher::get_hasher("sha256").unwrap();
+    let results = storage.query(&sha256.hash(b"word7"), None, 0, None).unwrap();
+    assert_eq!(results.len(), 1);
+    assert_eq!(results[0].sources.len(), 1);
+}
+
+#[test]
+fn test_external_dedup_counts_across_runs() {
+    let mut dedup = ExternalDedup::new(3);
+    for word in ["b", "a", "c", "a", "d", "b", "e", "c", "a"] {
+        dedup.insert(word.to_string()).unwrap();
+    }
+    assert!(dedup.spilled_runs() > 1);
+    assert_eq!(dedup.count_unique().unwrap(), 5);
+
+    // What the build hashes under --disk-dedup: each word once, sorted
+    let mut dedup = ExternalDedup::new(3);
+    for word in ["b", "a", "c", "a", "d", "b", "e", "c", "a"] {
+        dedup.insert(word.to_string()).unwrap();
+    }
+    let unique: Vec<String> = dedup.into_unique().unwrap().map(Result::unwrap).collect();
+    assert_eq!(unique, vec!["a", "b", "c", "d", "e"]);
+}
+
+#[test]
+fn test_external_dedup_spills_into_temp_dir() {
+    let dir = tempfile::tempdir().unwrap();
+    let scratch = dir.path().join("scratch");
+    fs::create_dir(&scratch).unwrap();
+
+    let mut dedup = ExternalDedup::new(2).with_temp_dir(&scratch);
+    for word in ["a", "b", "c", "d"] {
+        dedup.insert(word.to_string()).unwrap();
+    }
+    let run_dirs: Vec<_> = fs::read_dir(&scratch)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .collect();
+    assert_eq!(run_dirs.len(), 1);
+    assert!(run_dirs[0]
+        .file_name()
+        .unwrap()
+        .to_string_lossy()
+        .starts_with("shaha-dedup-"));
+    assert_eq!(fs::read_dir(&run_dirs[0]).unwrap().count(), 2);
+
+    assert_eq!(dedup.count_unique().unwrap(), 4);
+    assert_eq!(fs::read_dir(&scratch).unwrap().count(), 0);
+}
+
+#[test]
+fn test_build_rejects_unwritable_temp_dir() {
+    let dir = tempfile::tempdir().unwrap();
+    let words_path = dir.path().join("words.txt");
+    let db_path = dir.path().join("test.parquet");
+    fs::write(&words_path, "alpha\nbeta\n").unwrap();
+
+    let output = std::process::Command::new(env!("CARGO_BIN_EXE_shaha"))
+        .args([
+            "build",
+            words_path.to_str().unwrap(),
+            "-o",
+            db_path.to_str().unwrap(),
+            "--disk-dedup",
+        ])
+        .env("SHAHA_TMPDIR", words_path.join("scratch"))
+        .output()
+        .expect("Failed to run build");
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("Temp directory"), "{}", stderr);
+    assert!(!db_path.exists());
+}
+
+#[test]
+fn test_build_exclude_db_omits_existing_hashes() {
+    let dir = tempfile::tempdir().unwrap();
+    let reference_words = dir.path().join("reference.txt");
+    let new_words = dir.path().join("new.txt");
+    let reference_db = dir.path().join("reference.parquet");
+    let diff_db = dir.path().join("diff.parquet");
+    fs::write(&reference_words, "alpha\nbeta\n").unwrap();
+    fs::write(&new_words, "beta\ngamma\n").unwrap();
+
+    let build = |words: &std::path::Path, db: &std::path::Path, extra: &[&str]| {
+        std::process::Command::new(env!("CARGO_BIN_EXE_shaha"))
+            .args(["build", words.to_str().unwrap(), "-a", "sha256", "-a", "md5"])
+            .args(["-o", db.to_str().unwrap()])
+            .args(extra)
+            .output()
+            .expect("Failed to build database")
+    };
+    assert!(build(&reference_words, &reference_db, &[]).status.success());
+    let output = build(
+        &new_words,
+        &diff_db,
+        &["--exclude-db", reference_db.to_str().unwrap()],
+    );
+    assert!(output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("Excluded 2 records already in"), "{}", stderr);
+
+    let storage = ParquetStorage::new(&diff_db);
+    assert_eq!(storage.stats().unwrap().total_records, 2);
+    let sha256 = hasher::get_hasher("sha256").unwrap();
+    assert!(storage.query(&sha256.hash(b"beta"), None, 0, None).unwrap().is_empty());
+    assert_eq!(storage.query(&sha256.hash(b"gamma"), None, 0, None).unwrap().len(), 1);
+
+    let missing = build(&new_words, &diff_db, &["--exclude-db", "missing.parquet"]);
+    assert!(!missing.status.success());
+}
+
+#[test]
+fn test_build_sources_file_ingests_each_source() {
+    let dir = tempfile::tempdir().unwrap();
+    let common = dir.path().join("common.txt");
+    let leaked = dir.path().join("leaked.txt");
+    let sources_path = dir.path().join("sources.txt");
+    let db_path = dir.path().join("test.parquet");
+    fs::write(&common, "alpha\nshared\n").unwrap();
+    fs::write(&leaked, "beta\nshared\n").unwrap();
+    fs::write(
+        &sources_path,
+        format!(
+            "# wordlists\n{}\n\n  {}  \n{}\n",
+            common.display(),
+            leaked.display(),
+            common.display()
+        ),
+    )
+    .unwrap();
+
+    let output = std::process::Command::new(env!("CARGO_BIN_EXE_shaha"))
+        .args(["build", "--sources-file", sources_path.to_str().unwrap()])
+        .args(["-o", db_path.to_str().unwrap()])
+        .output()
+        .expect("Failed to build database");
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("Source 3/3"), "{}", stderr);
+    assert!(stderr.contains("Source already processed"), "{}", stderr);
+
+    let storage = ParquetStorage::new(&db_path);
+    let stats = storage.stats().unwrap();
+    assert_eq!(stats.total_records, 3);
+    let mut sources = stats.sources.clone();
+    sources.sort();
+    assert_eq!(sources, vec!["common", "leaked"]);
+
+    let sha256 = hasher::get_hasher("sha256").unwrap();
+    let shared = storage.query(&sha256.hash(b"shared"), None, 0, None).unwrap();
+    let mut shared_sources = shared[0].sources.clone();
+    shared_sources.sort();
+    assert_eq!(shared_sources, vec!["common", "leaked"]);
+}
+
+#[test]
+fn test_build_multiple_inputs_tags_each_source() {
+    let dir = tempfile::tempdir().unwrap();
+    let common = dir.path().join("common.txt");
+    let leaked = dir.path().join("leaked.txt");
+    let extra = dir.path().join("extra.txt");
+    let db_path = dir.path().join("test.parquet");
+    fs::write(&common, "alpha\nshared\n").unwrap();
+    fs::write(&leaked, "beta\nshared\n").unwrap();
+    fs::write(&extra, "gamma\n").unwrap();
+
+    let output = std::process::Command::new(env!("CARGO_BIN_EXE_shaha"))
+        .args(["build", common.to_str().unwrap(), leaked.to_str().unwrap()])
+        .args(["--from", &format!("file:{}", extra.display())])
+        .args(["-o", db_path.to_str().unwrap()])
+        .output()
+        .expect("Failed to build database");
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+
+    let storage = ParquetStorage::new(&db_path);
+    let stats = storage.stats().unwrap();
+    assert_eq!(stats.total_records, 4);
+    let mut sources = stats.sources.clone();
+    sources.sort();
+    assert_eq!(sources, vec!["common", "extra", "leaked"]);
+    assert_eq!(storage.get_source_hashes().unwrap().len(), 3);
+
+    let sha256 = hasher::get_hasher("sha256").unwrap();
+    let shared = storage.query(&sha256.hash(b"shared"), None, 0, None).unwrap();
+    assert_eq!(shared[0].sources, vec!["common", "leaked"]);
+
+    let named = std::process::Command::new(env!("CARGO_BIN_EXE_shaha"))
+        .args(["build", common.to_str().unwrap(), leaked.to_str().unwrap(), "--name", "x"])
+        .args(["-o", db_path.to_str().unwrap()])
+        .output()
+        .expect("Failed to run build");
+    assert!(!named.status.success());
+    assert!(String::from_utf8_lossy(&named.stderr).contains("--name cannot be used with several sources"));
+
+    let if_newer = std::process::Command::new(env!("CARGO_BIN_EXE_shaha"))
+        .args(["build", common.to_str().unwrap(), leaked.to_str().unwrap(), "--if-newer"])
+        .args(["-o", dir.path().join("fresh.parquet").to_str().unwrap()])
+        .output()
+        .expect("Failed to run build");
+    assert!(!if_newer.status.success());
+    assert!(String::from_utf8_lossy(&if_newer.stderr).contains("--if-newer cannot be used with several sources"));
+    assert!(!dir.path().join("fresh.parquet").exists());
+
+    // A failing source fails the whole build, leaving nothing half-written
+    let failed = std::process::Command::new(env!("CARGO_BIN_EXE_shaha"))
+        .args(["build", extra.to_str().unwrap(), dir.path().join("missing.txt").to_str().unwrap()])
+        .args(["-o", dir.path().join("partial.parquet").to_str().unwrap()])
+        .output()
+        .expect("Failed to run build");
+    assert!(!failed.status.success());
+    assert!(!dir.path().join("partial.parquet").exists());
+
+    let before = fs::read(&db_path).unwrap();
+    let failed = std::process::Command::new(env!("CARGO_BIN_EXE_shaha"))
+        .args(["build", "--append", "--force", extra.to_str().unwrap()])
+        .args(["--from", &format!("file:{}", dir.path().join("missing.txt").display())])
+        .args(["-o", db_path.to_str().unwrap()])
+        .output()
+        .expect("Failed to run build");
+    assert!(!failed.status.success());
+    assert_eq!(fs::read(&db_path).unwrap(), before);
+}
+
+#[test]
+fn test_check_reports_found_candidates() {
+    let dir = tempfile::tempdir().unwrap();
+    let words_path = dir.path().join("words.txt");
+    let candidates_path = dir.path().join("candidates.txt");
+    let db_path = dir.path().join("test.parquet");
+    fs::write(&words_path, "password\nletmein\nqwerty\n").unwrap();
+    fs::write(&candidates_path, "letmein\nhunter2\nqwerty\n\nletmein\n").unwrap();
+
+    let status = std::process::Command::new(env!("CARGO_BIN_EXE_shaha"))
+        .args(["build", words_path.to_str().unwrap(), "-a", "sha256", "-a", "md5"])
+        .args(["-o", db_path.to_str().unwrap()])
+        .status()
+        .expect("Failed to build database");
+    assert!(status.success());
+
+    let check = |algo: &str| {
+        std::process::Command::new(env!("CARGO_BIN_EXE_shaha"))
+            .args(["check", candidates_path.to_str().unwrap(), "-d", db_path.to_str().unwrap()])
+            .args(["-a", algo, "--verbose"])
+            .output()
+            .expect("Failed to run check")
+    };
+
+    let output = check("md5");
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let lines: Vec<&str> = stdout.lines().collect();
+    assert_eq!(
+        lines[..3],
+        ["found\tletmein", "missing\thunter2", "found\tqwerty"]
+    );
+    assert!(lines[3].starts_with("2 of 3 candidates found in"), "{}", stdout);
+    assert!(lines[3].ends_with("(md5)"));
+
+    let stdout = String::from_utf8_lossy(&check("sha1").stdout).to_string();
+    assert!(stdout.contains("0 of 3 candidates found"), "{}", stdout);
+}
+
+#[test]
+fn test_hyperloglog_estimate_within_tolerance() {
+    use shaha::dedup::HyperLogLog;
+
+    for exact in [100usize, 5_000, 200_000] {
+        let mut hll = HyperLogLog::new(14);
+        for i in 0..exact * 2 {
+            hll.insert(&format!("word{}", i % exact));
+        }
+        let estimate = hll.estimate() as f64;
+        let error = (estimate - exact as f64).abs() / exact as f64;
+        assert!(error < 0.03, "exact {} estimated {}", exact, estimate);
+    }
+}
+
+#[test]
+fn test_dry_run_approx_reports_estimate() {
+    let dir = tempfile::tempdir().unwrap();
+    let words_path = dir.path().join("words.txt");
+    let db_path = dir.path().join("test.parquet");
+    let words: Vec<String> = (0..20_000).map(|i| format!("word{}", i % 5_000)).collect();
+    fs::write(&words_path, words.join("\n")).unwrap();
+
+    let output = std::process::Command::new(env!("CARGO_BIN_EXE_shaha"))
+        .args(["build", words_path.to_str().unwrap(), "-o", db_path.to_str().unwrap()])
+        .args(["--dry-run", "--approx"])
+        .output()
+        .expect("Failed to run dry run");
+    assert!(output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("[dry-run] Total words: 20,000"), "{}", stderr);
+    assert!(stderr.contains("(approximate, ±0.8%)"), "{}", stderr);
+
+    let line = stderr
+        .lines()
+        .find(|l| l.starts_with("[dry-run] Unique words: ~"))
+        .unwrap();
+    let estimate: f64 = line["[dry-run] Unique words: ~".len()..]
+        .split(' ')
+        .next()
+        .unwrap()
+        .replace(',', "")
+        .parse()
+        .unwrap();
+    assert!((estimate - 5_000.0).abs() / 5_000.0 < 0.03, "{}", line);
+    assert!(!db_path.exists());
+}
+
+#[test]
+fn test_info_compare_reports_deltas() {
+    let dir = tempfile::tempdir().unwrap();
+    let first_words = dir.path().join("first.txt");
+    let second_words = dir.path().join("second.txt");
+    let old_db = dir.path().join("old.parquet");
+    let new_db = dir.path().join("new.parquet");
+    fs::write(&first_words, "alpha\nbeta\n").unwrap();
+    fs::write(&second_words, "gamma\n").unwrap();
+
+    let build = |words: &std::path::Path, name: &str, db: &std::path::Path, extra: &[&str]| {
+        let status = std::process::Command::new(env!("CARGO_BIN_EXE_shaha"))
+            .args([
+                "build",
+                words.to_str().unwrap(),
+                "-n",
+                name,
+                "-o",
+                db.to_str().unwrap(),
+            ])
+            .args(extra)
+            .status()
+            .expect("Failed to build");
+        assert!(status.success());
+    };
+
+    build(&first_words, "first", &old_db, &[]);
+    build(&first_words, "first", &new_db, &["-a", "sha256", "-a", "md5"]);
+    build(&second_words, "second", &new_db, &["-a", "sha256", "-a", "md5", "--append"]);
+
+    let output = std::process::Command::new(env!("CARGO_BIN_EXE_shaha"))
+        .args([
+            "info",
+            old_db.to_str().unwrap(),
+            "--compare",
+            new_db.to_str().unwrap(),
+            "--format",
+            "json",
+        ])
+        .output()
+        .expect("Failed to run info");
+    assert!(output.status.success());
+
+    let json: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(json["total_records"], serde_json::json!([2, 6]));
+    assert_eq!(json["delta"]["records"], 4);
+    assert_eq!(json["delta"]["added_algorithms"], serde_json::json!(["md5"]));
+    assert_eq!(json["delta"]["added_sources"], serde_json::json!(["second"]));
+    assert_eq!(json["delta"]["removed_sources"], serde_json::json!([]));
+
+    let output = std::process::Command::new(env!("CARGO_BIN_EXE_shaha"))
+        .args(["info", old_db.to_str().unwrap(), "--compare", new_db.to_str().unwrap()])
+        .output()
+        .expect("Failed to run info");
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("+4"));
+    assert!(stdout.contains("+second"));
+}
+
+#[test]
+fn test_coarse_index_selects_row_groups_for_short_prefix() {
+    let dir = tempfile::tempdir().unwrap();
+    let record = |first: u8, word: &str| {
+        let mut hash = vec![0x42u8; 32];
+        hash[0] = first;
+        HashRecord {
+            hash,
+            preimage: word.to_string(),
+            algorithm: "sha256".to_string(),
+            sources: vec![],
+        }
+    };
+    let groups = vec![
+        vec![record(0x00, "a"), record(0xff, "b")],
+        vec![record(0x80, "c"), record(0x81, "d")],
+        vec![record(0x10, "e"), record(0x20, "f")],
+    ];
+
+    let write = |path: &std::path::Path, coarse: bool| {
+        let group_bytes = 2 * (32 + 1 + "sha256".len());
+        let mut storage = ParquetStorage::new(path).with_max_row_group_bytes(group_bytes);
+        if coarse {
+            storage = storage.with_coarse_index();
+        }
+        for group in &groups {
+            storage.write_batch(group.clone()).unwrap();
+        }
+        storage.finish().unwrap();
+        storage
+    };
+
+    let indexed = write(&dir.path().join("indexed.parquet"), true);
+    let plain = write(&dir.path().join("plain.parquet"), false);
+
+    assert_eq!(indexed.candidate_row_groups(&[0x80], None).unwrap(), vec![1]);
+    assert_eq!(indexed.candidate_row_groups(&[0xff], None).unwrap(), vec![0]);
+    assert_eq!(indexed.candidate_row_groups(&[0x80, 0x42], None).unwrap(), vec![1]);
+    assert!(indexed.candidate_row_groups(&[0x33], None).unwrap().is_empty());
+    assert_eq!(plain.candidate_row_groups(&[0x80], None).unwrap(), vec![0, 1]);
+
+    let results = indexed.query(&[0x80], None, 0, None).unwrap();
+    assert_eq!(results.len(), 1);
+    assert_eq!(results[0].preimage, "c");
+    assert_eq!(indexed.query(&[0x20], None, 0, None).unwrap()[0].preimage, "f");
+}
+
+#[test]
+fn test_strict_algo_rejects_unknown_algorithm() {
+    let dir = tempfile::tempdir().unwrap();
+    let db_path = dir.path().join("test.parquet");
+    let hash = hasher::get_hasher("sha256").unwrap().hash(b"future");
+
+    let mut storage = ParquetStorage::new(&db_path);
+    storage
+        .write_batch(vec![HashRecord {
+            hash: hash.clone(),
+            preimage: "future".to_string(),
+            algorithm: "sha256-v9".to_string(),
+            sources: vec![],
+        }])
+        .unwrap();
+    storage.finish().unwrap();
+
+    let run = |args: &[&str]| {
+        std::process::Command::new(env!("CARGO_BIN_EXE_shaha"))
+            .args(args)
+            .output()
+            .expect("Failed to run shaha")
+    };
+    let hash_hex = hex::encode(&hash);
+    let db = db_path.to_str().unwrap();
+
+    assert!(run(&["query", &hash_hex, "-d", db]).status.success());
+    assert!(run(&["info", db]).status.success());
+
+    let output = run(&["query", &hash_hex, "-d", db, "--strict-algo"]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("sha256-v9"));
+
+    let output = run(&["info", db, "--strict-algo"]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("sha256-v9"));
+}
+
+#[test]
+fn test_source_name_transform_slug() {
+    let dir = tempfile::tempdir().unwrap();
+    let words_path = dir.path().join("RockYou_2024.txt");
+    let more_path = dir.path().join("more.txt");
+    let db_path = dir.path().join("test.parquet");
+    fs::write(&words_path, "hello\n").unwrap();
+    fs::write(&more_path, "world\n").unwrap();
+
+    let build = |words: &std::path::Path, extra: &[&str]| {
+        let status = std::process::Command::new(env!("CARGO_BIN_EXE_shaha"))
+            .args([
+                "build",
+                words.to_str().unwrap(),
+                "-o",
+                db_path.to_str().unwrap(),
+                "--source-name-transform",
+                "slug",
+            ])
+            .args(extra)
+            .status()
+            .expect("Failed to build");
+        assert!(status.success());
+    };
+
+    build(&words_path, &[]);
+    build(&more_path, &["-n", "RockYou 2024!", "--append"]);
+
+    let stats = ParquetStorage::new(&db_path).stats().unwrap();
+    assert_eq!(stats.sources, vec!["rockyou-2024"]);
+
+    let hash = hasher::get_hasher("sha256").unwrap().hash(b"world");
+    let results = ParquetStorage::new(&db_path).query(&hash, None, 0, None).unwrap();
+    assert_eq!(results[0].sources, vec!["rockyou-2024"]);
+}
+
+#[test]
+fn test_query_odd_length_hex_prefix() {
+    let dir = tempfile::tempdir().unwrap();
+    let words_path = dir.path().join("words.txt");
+    let db_path = dir.path().join("test.parquet");
+    fs::write(&words_path, "hello\nworld\npassword\n").unwrap();
+
+    let status = std::process::Command::new(env!("CARGO_BIN_EXE_shaha"))
+        .args([
+            "build",
+            words_path.to_str().unwrap(),
+            "-o",
+            db_path.to_str().unwrap(),
+        ])
+        .status()
+        .expect("Failed to build database");
+    assert!(status.success());
+
+    let hash = hasher::get_hasher("sha256").unwrap().hash(b"hello");
+    let hash_hex = hex::encode(&hash);
+    let query = |prefix: &str| {
+        std::process::Command::new(env!("CARGO_BIN_EXE_shaha"))
+            .args(["query", prefix, "-d", db_path.to_str().unwrap()])
+            .output()
+            .expect("Failed to run query")
+    };
+
+    let output = query(&hash_hex[..5]);
+    assert!(output.status.success());
+    assert!(String::from_utf8_lossy(&output.stdout).contains("hello"));
+
+    let last = u8::from_str_radix(&hash_hex[4..5], 16).unwrap();
+    let other = format!("{}{:x}", &hash_hex[..4], last ^ 0x1);
+    let output = query(&other);
+    assert!(!String::from_utf8_lossy(&output.stdout).contains("hello"));
+
+    let output = query(&hash_hex[..63]);
+    assert!(output.status.success());
+    assert!(String::from_utf8_lossy(&output.stdout).contains("hello"));
+
+    assert!(!query("abz").status.success());
+
+    let mut memory = MemoryStorage::new();
+    memory
+        .write_batch(ParquetStorage::new(&db_path).query(&[], None, 0, None).unwrap())
+        .unwrap();
+    let results = memory.query_bits(&hash[..3], 20, None, 0, None).unwrap();
+    assert!(results.iter().any(|r| r.preimage == "hello"));
+    let mut flipped = hash[..3].to_vec();
+    flipped[2] ^= 0x10;
+    let results = memory.query_bits(&flipped, 20, None, 0, None).unwrap();
+    assert!(!results.iter().any(|r| r.preimage == "hello"));
+
+    let hex_db = dir.path().join("hex.parquet");
+    let status = std::process::Command::new(env!("CARGO_BIN_EXE_shaha"))
+        .args([
+            "build",
+            words_path.to_str().unwrap(),
+            "-o",
+            hex_db.to_str().unwrap(),
+            "--stored-hash-encoding",
+            "hex",
+        ])
+        .status()
+        .expect("Failed to build database");
+    assert!(status.success());
+
+    let storage = ParquetStorage::new(&hex_db);
+    for bits in [20, 21] {
+        let results = storage.query_bits(&hash[..3], bits, None, 0, None).unwrap();
+        assert!(results.iter().any(|r| r.preimage == "hello"));
+        let results = storage.query_bits(&flipped, bits, None, 0, None).unwrap();
+        assert!(!results.iter().any(|r| r.preimage == "hello"));
+    }
+}
+
+#[test]
+fn test_panic_hook_prints_friendly_report() {
+    let command = shaha::output::redacted_command_line(
+        ["/usr/bin/shaha", "build", "words.txt", "--bearer", "tok", "--hmac-key=k", "--header", "X-Api-Key: abc"]
+            .map(String::from),
+    );
+    assert_eq!(
+        command,
+        "shaha build words.txt --bearer <redacted> --hmac-key=<redacted> --header 'X-Api-Key: <redacted>'"
+    );
+
+    let report = shaha::output::crash_report(&command, "boom at src/main.rs:1");
+    assert!(report.starts_with("error: shaha crashed unexpectedly\n"));
+    assert!(report.contains(&format!("  command: {}\n", command)));
+    assert!(report.contains("  cause:   boom at src/main.rs:1\n"));
+    assert!(report.contains("https://github.com/oritwoen/shaha/issues"));
+    assert!(report.contains(&format!("shaha {}", env!("CARGO_PKG_VERSION"))));
+    assert!(!report.contains("tok") && !report.contains("abc"));
+}
+
+#[test]
+fn test_query_multiple_hashes_grouped() {
+    let dir = tempfile::tempdir().unwrap();
+    let words_path = dir.path().join("words.txt");
+    let db_path = dir.path().join("test.parquet");
+    fs::write(&words_path, "hello\nworld\n").unwrap();
+
+    let status = std::process::Command::new(env!("CARGO_BIN_EXE_shaha"))
+        .args([
+            "build",
+            words_path.to_str().unwrap(),
+            "-o",
+            db_path.to_str().unwrap(),
+        ])
+        .status()
+        .expect("Failed to build database");
+    assert!(status.success());
+
+    let sha256 = hasher::get_hasher("sha256").unwrap();
+    let hello = hex::encode(sha256.hash(b"hello"));
+    let world = hex::encode(sha256.hash(b"world"));
+    let absent = hex::encode(sha256.hash(b"absent"));
+
+    let query = |format: &str| {
+        std::process::Command::new(env!("CARGO_BIN_EXE_shaha"))
+            .args(["query", &hello, &absent, &world, "-d", db_path.to_str().unwrap()])
+            .args(["--format", format])
+            .output()
+            .expect("Failed to run query")
+    };
+
+    let output = query("plain");
+    assert!(output.status.success());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let expected_order = [
+        format!("{}:", hello),
+        "hello (sha256".to_string(),
+        format!("{}:", absent),
+        "(no matches)".to_string(),
+        format!("{}:", world),
+        "world (sha256".to_string(),
+    ];
+    let mut pos = 0;
+    for needle in &expected_order {
+        let found = stdout[pos..]
+            .find(needle.as_str())
+            .unwrap_or_else(|| panic!("missing {}", needle));
+        pos += found + needle.len();
+    }
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Found 2 results for 2 of 3 hashes"));
+
+    let output = query("jsonl");
+    assert!(output.status.success());
+    let lines: Vec<serde_json::Value> = String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+    assert_eq!(lines.len(), 2);
+    assert_eq!(lines[0]["query"], hello.as_str());
+    assert_eq!(lines[0]["preimage"], "hello");
+    assert_eq!(lines[1]["query"], world.as_str());
+
+    let output = query("json");
+    let json: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(json.as_array().unwrap().len(), 3);
+    assert_eq!(json[1]["query"], absent.as_str());
+    assert!(json[1]["results"].as_array().unwrap().is_empty());
+}
+
+#[test]
+fn test_build_from_hibp_range_file() {
+    let dir = tempfile::tempdir().unwrap();
+    let range_path = dir.path().join("5BAA6.txt");
+    let full_path = dir.path().join("pwned.txt");
+    let db_path = dir.path().join("test.parquet");
+
+    let sha1 = hasher::get_hasher("sha1").unwrap();
+    let password = hex::encode(sha1.hash(b"password"));
+    assert!(password.starts_with("5baa6"));
+    let hunter2 = hex::encode(sha1.hash(b"hunter2"));
+
+    fs::write(
+        &range_path,
+        format!(
+            "{}:9545824\r\n011053FD0102E94D6AE2F8B83D76FAF94F6:1\r\n",
+            password[5..].to_uppercase()
+        ),
+    )
+    .unwrap();
+    fs::write(&full_path, format!("{}:17\n", hunter2.to_uppercase())).unwrap();
+
+    let build = |path: &std::path::Path, extra: &[&str]| {
+        std::process::Command::new(env!("CARGO_BIN_EXE_shaha"))
+            .args([
+                "build",
+                "--from",
+                &format!("hibp:{}", path.display()),
+                "-o",
+                db_path.to_str().unwrap(),
+            ])
+            .args(extra)
+            .output()
+            .expect("Failed to build")
+    };
+
+    let output = build(&range_path, &[]);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Counted 9,545,825 occurrences"));
+    assert!(build(&full_path, &["--append"]).status.success());
+
+    let stats = ParquetStorage::new(&db_path).stats().unwrap();
+    assert_eq!(stats.total_records, 3);
+    assert_eq!(stats.algorithms, vec!["sha1"]);
+
+    let output = std::process::Command::new(env!("CARGO_BIN_EXE_shaha"))
+        .args(["query", &password, "-d", db_path.to_str().unwrap()])
+        .output()
+        .expect("Failed to run query");
+    assert!(output.status.success());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("(hash only) (sha1, hibp-5baa6)"));
+
+    let results = ParquetStorage::new(&db_path)
+        .query(&hex::decode(&hunter2).unwrap(), None, 0, None)
+        .unwrap();
+    assert_eq!(results.len(), 1);
+    assert_eq!(results[0].preimage, "");
+    assert_eq!(results[0].sources, vec!["pwned"]);
+}
+
+#[test]
+fn test_build_hibp_validate_hex_skips_invalid() {
+    let dir = tempfile::tempdir().unwrap();
+    let dump_path = dir.path().join("pwned.txt");
+    let db_path = dir.path().join("test.parquet");
+
+    let sha1 = hasher::get_hasher("sha1").unwrap();
+    let password = hex::encode(sha1.hash(b"password")).to_uppercase();
+    let hunter2 = hex::encode(sha1.hash(b"hunter2")).to_uppercase();
+    fs::write(
+        &dump_path,
+        format!(
+            "{}:3\nZZ{}:1\n{}:2\nABCDEF:4\n{}00:5\n",
+            password,
+            &password[2..],
+            hunter2,
+            hunter2
+        ),
+    )
+    .unwrap();
+
+    let build = |extra: &[&str]| {
+        std::process::Command::new(env!("CARGO_BIN_EXE_shaha"))
+            .args([
+                "build",
+                "--from",
+                &format!("hibp:{}", dump_path.display()),
+                "-o",
+                db_path.to_str().unwrap(),
+            ])
+            .args(extra)
+            .output()
+            .expect("Failed to build")
+    };
+
+    assert!(!build(&[]).status.success());
+
+    let output = build(&["--validate-hex"]);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("Skipped 3 invalid lines"), "{}", stderr);
+    assert!(stderr.contains("Counted 5 occurrences"), "{}", stderr);
+
+    let storage = ParquetStorage::new(&db_path);
+    assert_eq!(storage.stats().unwrap().total_records, 2);
+    let password_hash = hex::decode(&password).unwrap();
+    assert_eq!(storage.query(&password_hash, None, 0, None).unwrap().len(), 1);
+}
+
+#[test]
+fn test_query_split_output_by_algorithm() {
+    let dir = tempfile::tempdir().unwrap();
+    let words_path = dir.path().join("words.txt");
+    let db_path = dir.path().join("test.parquet");
+    let out_dir = dir.path().join("split");
+    fs::write(&words_path, "hello\nworld\n").unwrap();
+
+    let status = std::process::Command::new(env!("CARGO_BIN_EXE_shaha"))
+        .args([
+            "build",
+            words_path.to_str().unwrap(),
+            "-a",
+            "sha256",
+            "-a",
+            "md5",
+            "-o",
+            db_path.to_str().unwrap(),
+        ])
+        .status()
+        .expect("Failed to build database");
+    assert!(status.success());
+
+    let query = |format: &str| {
+        std::process::Command::new(env!("CARGO_BIN_EXE_shaha"))
+            .args(["query", "--mask", "??", "-d", db_path.to_str().unwrap()])
+            .args(["--format", format, "--split-output", out_dir.to_str().unwrap()])
+            .output()
+            .expect("Failed to run query")
+    };
+
+    let output = query("jsonl");
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    assert!(output.stdout.is_empty());
+
+    for algorithm in ["md5", "sha256"] {
+        let content = fs::read_to_string(out_dir.join(format!("{}.jsonl", algorithm))).unwrap();
+        let records: Vec<serde_json::Value> = content
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(records.len(), 2);
+        assert!(records.iter().all(|r| r["algorithm"] == algorithm));
+        let mut preimages: Vec<&str> = records.iter().map(|r| r["preimage"].as_str().unwrap()).collect();
+        preimages.sort();
+        assert_eq!(preimages, vec!["hello", "world"]);
+    }
+
+    assert!(query("plain").status.success());
+    let plain = fs::read_to_string(out_dir.join("sha256.txt")).unwrap();
+    assert!(plain.contains("hello (sha256"));
+    assert!(!plain.contains("md5"));
+}
+
+#[test]
+fn test_query_output_file_writes_results() {
+    let dir = tempfile::tempdir().unwrap();
+    let words_path = dir.path().join("words.txt");
+    let db_path = dir.path().join("test.parquet");
+    let out_path = dir.path().join("results.json");
+    fs::write(&words_path, "hello\nworld\n").unwrap();
+
+    let status = std::process::Command::new(env!("CARGO_BIN_EXE_shaha"))
+        .args(["build", words_path.to_str().unwrap(), "-o", db_path.to_str().unwrap()])
+        .status()
+        .expect("Failed to build database");
+    assert!(status.success());
+
+    let hello = hex::encode(hasher::get_hasher("sha256").unwrap().hash(b"hello"));
+    let output = std::process::Command::new(env!("CARGO_BIN_EXE_shaha"))
+        .args(["query", &hello, "-d", db_path.to_str().unwrap(), "--format", "json"])
+        .args(["--output-file", out_path.to_str().unwrap()])
+        .output()
+        .expect("Failed to run query");
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    assert!(output.stdout.is_empty());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Found 1 result"));
+
+    let results: Vec<serde_json::Value> =
+        serde_json::from_str(&fs::read_to_string(&out_path).unwrap()).unwrap();
+    assert_eq!(results.len(), 1);
+    assert_eq!(results[0]["preimage"], "hello");
+    assert_eq!(results[0]["hash"], hello);
+
+    // JSONL and plain are streamed to the file and match stdout byte for byte
+    let world = hex::encode(hasher::get_hasher("sha256").unwrap().hash(b"world"));
+    for format in ["jsonl", "plain"] {
+        let query = |extra: &[&str]| {
+            std::process::Command::new(env!("CARGO_BIN_EXE_shaha"))
+                .args(["query", &hello, &world, "-d", db_path.to_str().unwrap(), "--format", format])
+                .args(extra)
+                .output()
+                .expect("Failed to run query")
+        };
+        let path = dir.path().join(format!("results.{}", format));
+        let written = query(&["--output-file", path.to_str().unwrap()]);
+        assert!(written.status.success(), "{}", String::from_utf8_lossy(&written.stderr));
+        assert_eq!(fs::read(&path).unwrap(), query(&[]).stdout);
+    }
+    let lines = fs::read_to_string(dir.path().join("results.jsonl")).unwrap();
+    let records: Vec<serde_json::Value> =
+        lines.lines().map(|line| serde_json::from_str(line).unwrap()).collect();
+    assert_eq!(records.len(), 2);
+    assert_eq!(records[1]["query"], world);
+    assert_eq!(records[1]["preimage"], "world");
+}
+
+#[test]
+fn test_append_algorithm_mismatch_requires_flag() {
+    let dir = tempfile::tempdir().unwrap();
+    let first_path = dir.path().join("first.txt");
+    let second_path = dir.path().join("second.txt");
+    let db_path = dir.path().join("test.parquet");
+    fs::write(&first_path, "alpha\nbeta\n").unwrap();
+    fs::write(&second_path, "gamma\n").unwrap();
+
+    let build = |words: &std::path::Path, algo: &str, extra: &[&str]| {
+        std::process::Command::new(env!("CARGO_BIN_EXE_shaha"))
+            .args([
+                "build",
+                words.to_str().unwrap(),
+                "-a",
+                algo,
+                "-o",
+                db_path.to_str().unwrap(),
+            ])
+            .args(extra)
+            .output()
+            .expect("Failed to build database")
+    };
+
+    assert!(build(&first_path, "sha256", &[]).status.success());
+
+    let output = build(&second_path, "md5", &["--append"]);
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("Algorithm mismatch"), "{}", stderr);
+    assert!(stderr.contains("[sha256]") && stderr.contains("[md5]"));
+    assert_eq!(ParquetStorage::new(&db_path).stats().unwrap().total_records, 2);
+
+    let output = build(&second_path, "md5", &["--append", "--allow-algo-mismatch"]);
+    assert!(output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("warning: algorithm mismatch"));
+    let mut stats = ParquetStorage::new(&db_path).stats().unwrap();
+    stats.algorithms.sort();
+    assert_eq!(stats.total_records, 3);
+    assert_eq!(stats.algorithms, vec!["md5", "sha256"]);
+}
+
+#[test]
+fn test_read_parquet_with_missing_or_null_sources() {
+    use arrow::array::{ArrayRef, BinaryArray, ListBuilder, RecordBatch, StringArray, StringBuilder};
+    use arrow::datatypes::{DataType, Field, Schema};
+    use std::sync::Arc;
+
+    let dir = tempfile::tempdir().unwrap();
+    let sha256 = hasher::get_hasher("sha256").unwrap();
+    let hashes = [sha256.hash(b"alpha"), sha256.hash(b"beta")];
+
+    let write = |path: &std::path::Path, fields: Vec<Field>, columns: Vec<ArrayRef>| {
+        let batch = RecordBatch::try_new(Arc::new(Schema::new(fields)), columns).unwrap();
+        let file = fs::File::create(path).unwrap();
+        let mut writer = parquet::arrow::ArrowWriter::try_new(file, batch.schema(), None).unwrap();
+        writer.write(&batch).unwrap();
+        writer.close().unwrap();
+    };
+    let base_fields = || {
+        vec![
+            Field::new("hash", DataType::Binary, false),
+            Field::new("preimage", DataType::Utf8, false),
+            Field::new("algorithm", DataType::Utf8, false),
+        ]
+    };
+    let base_columns = || -> Vec<ArrayRef> {
+        vec![
+            Arc::new(BinaryArray::from_iter_values(hashes.iter())),
+            Arc::new(StringArray::from(vec!["alpha", "beta"])),
+            Arc::new(StringArray::from(vec!["sha256", "sha256"])),
+        ]
+    };
+
+    let missing_path = dir.path().join("missing.parquet");
+    write(&missing_path, base_fields(), base_columns());
+
+    let nullable_path = dir.path().join("nullable.parquet");
+    let mut sources = ListBuilder::new(StringBuilder::new());
+    sources.append_null();
+    sources.values().append_value("external");
+    sources.append(true);
+    let mut fields = base_fields();
+    fields.push(Field::new(
+        "sources",
+        DataType::List(Arc::new(Field::new("item", DataType::Utf8, true))),
+        true,
+    ));
+    let mut columns = base_columns();
+    columns.push(Arc::new(sources.finish()));
+    write(&nullable_path, fields, columns);
+
+    let storage = ParquetStorage::new(&missing_path);
+    let results = storage.query(&hashes[0], None, 0, None).unwrap();
+    assert_eq!(results.len(), 1);
+    assert_eq!(results[0].preimage, "alpha");
+    assert!(results[0].sources.is_empty());
+    assert_eq!(storage.stats().unwrap().total_records, 2);
+
+    let storage = ParquetStorage::new(&nullable_path);
+    let alpha = storage.query(&hashes[0], None, 0, None).unwrap();
+    assert!(alpha[0].sources.is_empty());
+    let beta = storage.query(&hashes[1], None, 0, None).unwrap();
+    assert_eq!(beta[0].sources, vec!["external"]);
+    assert_eq!(storage.stats().unwrap().sources, vec!["external"]);
+
+    let mut count = 0;
+    storage
+        .for_each_record(|_| {
+            count += 1;
+            Ok(())
+        })
+        .unwrap();
+    assert_eq!(count, 2);
+}
+
+#[test]
+fn test_build_field_extracts_password_column() {
+    let dir = tempfile::tempdir().unwrap();
+    let words_path = dir.path().join("dump.txt");
+    let db_path = dir.path().join("test.parquet");
+    fs::write(
+        &words_path,
+        "alice:hunter2\nbob:letmein:extra\nnopassword\ncarol:\ndave:hunter2\n",
+    )
+    .unwrap();
+
+    let output = std::process::Command::new(env!("CARGO_BIN_EXE_shaha"))
+        .args([
+            "build",
+            words_path.to_str().unwrap(),
+            "-o",
+            db_path.to_str().unwrap(),
+            "--field",
+            "2",
+        ])
+        .output()
+        .expect("Failed to build database");
+    assert!(output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("Skipped 2 lines without field 2"), "{}", stderr);
+    assert!(stderr.contains("Processed 3 words (2 unique"), "{}", stderr);
+
+    let storage = ParquetStorage::new(&db_path);
+    let sha256 = hasher::get_hasher("sha256").unwrap();
+    assert_eq!(storage.stats().unwrap().total_records, 2);
+    let results = storage.query(&sha256.hash(b"letmein"), None, 0, None).unwrap();
+    assert_eq!(results[0].preimage, "letmein");
+    assert!(storage
+        .query(&sha256.hash(b"alice:hunter2"), None, 0, None)
+        .unwrap()
+        .is_empty());
+}
+
+#[test]
+fn test_info_verbose_reports_bloom_stats() {
+    let dir = tempfile::tempdir().unwrap();
+    let words_path = dir.path().join("words.txt");
+    let db_path = dir.path().join("test.parquet");
+    fs::write(&words_path, "alpha\nbeta\ngamma\n").unwrap();
+
+    let status = std::process::Command::new(env!("CARGO_BIN_EXE_shaha"))
+        .args([
+            "build",
+            words_path.to_str().unwrap(),
+            "-a",
+            "sha256",
+            "-a",
+            "md5",
+            "-o",
+            db_path.to_str().unwrap(),
+        ])
+        .status()
+        .expect("Failed to build database");
+    assert!(status.success());
+
+    let bloom = ParquetStorage::new(&db_path).bloom_stats().unwrap().unwrap();
+    assert_eq!(bloom.items, Some(6));
+    assert!(bloom.set_bits > 0 && bloom.fill_ratio() < 0.01);
+
+    let output = std::process::Command::new(env!("CARGO_BIN_EXE_shaha"))
+        .args(["info", db_path.to_str().unwrap(), "--verbose", "--format", "json"])
+        .output()
+        .expect("Failed to run info");
+    assert!(output.status.success());
+    let json: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(json["bloom"]["items"], 6);
+    assert_eq!(json["bloom"]["bits"], bloom.bits);
+    assert!(json["bloom"]["false_positive_rate"].as_f64().unwrap() < 1e-6);
+    assert!(!String::from_utf8_lossy(&output.stderr).contains("warning"));
+
+    let output = std::process::Command::new(env!("CARGO_BIN_EXE_shaha"))
+        .args(["info", db_path.to_str().unwrap(), "--verbose"])
+        .output()
+        .expect("Failed to run info");
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("Items:    6"), "{}", stdout);
+    assert!(stdout.contains("Est. FPR:"));
+}
+
+#[test]
+fn test_build_provenance_records_redacted_command_line() {
+    let dir = tempfile::tempdir().unwrap();
+    let words_path = dir.path().join("words.txt");
+    let db_path = dir.path().join("test.parquet");
+    fs::write(&words_path, "alpha\nbeta\n").unwrap();
+
+    let status = std::process::Command::new(env!("CARGO_BIN_EXE_shaha"))
+        .args(["build", words_path.to_str().unwrap(), "-a", "sha256", "--provenance"])
+        .args(["--secret-access-key", "hunter2", "--bearer=tok123"])
+        .args(["--header", "X-Api-Key: letmein"])
+        .args(["-o", db_path.to_str().unwrap()])
+        .status()
+        .expect("Failed to build database");
+    assert!(status.success());
+
+    let provenance = ParquetStorage::new(&db_path).get_provenance().unwrap();
+    assert_eq!(provenance.shaha_version.as_deref(), Some(env!("CARGO_PKG_VERSION")));
+    let command = provenance.build_command.unwrap();
+    assert!(command.starts_with("shaha build "), "{}", command);
+    assert!(command.contains("--provenance --secret-access-key <redacted> --bearer=<redacted>"));
+    assert!(command.contains("--header 'X-Api-Key: <redacted>'"), "{}", command);
+    for secret in ["hunter2", "tok123", "letmein"] {
+        assert!(!command.contains(secret), "{}", command);
+    }
+
+    let output = std::process::Command::new(env!("CARGO_BIN_EXE_shaha"))
+        .args(["info", db_path.to_str().unwrap(), "--verbose"])
+        .output()
+        .expect("Failed to run info");
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains(&format!("Version:    {}", env!("CARGO_PKG_VERSION"))), "{}", stdout);
+    assert!(stdout.contains(&format!("Command:    {}", command)), "{}", stdout);
+
+    // URL sources keep their path but not userinfo or query-string tokens
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = listener.local_addr().unwrap();
+    std::thread::spawn(move || {
+        use std::io::Read;
+        for mut stream in listener.incoming().flatten() {
+            let mut request = [0u8; 4096];
+            let _ = stream.read(&mut request);
+            let _ = stream.write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 6\r\nConnection: close\r\n\r\nalpha\n");
+        }
+    });
+    let url = format!("http://user:pa55@{}/list.txt?token=s3cret&X-Amz-Signature=abcdef", address);
+    let url_db = dir.path().join("url.parquet");
+    let output = std::process::Command::new(env!("CARGO_BIN_EXE_shaha"))
+        .args(["build", "--from", &url, "--provenance", "-o", url_db.to_str().unwrap()])
+        .output()
+        .expect("Failed to build database");
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    let command = ParquetStorage::new(&url_db).get_provenance().unwrap().build_command.unwrap();
+    let expected = format!("--from http://<redacted>@{}/list.txt?<redacted>", address);
+    assert!(command.contains(&expected), "{}", command);
+    for secret in ["pa55", "s3cret", "abcdef"] {
+        assert!(!command.contains(secret), "{}", command);
+    }
+
+    // Without the flag only the version is recorded
+    let plain_path = dir.path().join("plain.parquet");
+    let status = std::process::Command::new(env!("CARGO_BIN_EXE_shaha"))
+        .args(["build", words_path.to_str().unwrap(), "-o", plain_path.to_str().unwrap()])
+        .status()
+        .expect("Failed to build database");
+    assert!(status.success());
+    let provenance = ParquetStorage::new(&plain_path).get_provenance().unwrap();
+    assert!(provenance.shaha_version.is_some());
+    assert!(provenance.build_command.is_none());
+}
+
+#[cfg(unix)]
+#[test]
+fn test_build_provenance_redacts_hmac_key_and_survives_non_utf8_args() {
+    use std::os::unix::ffi::OsStrExt;
+
+    let dir = tempfile::tempdir().unwrap();
+    let words_path = dir.path().join(std::ffi::OsStr::from_bytes(b"words-\xff.txt"));
+    let db_path = dir.path().join("hmac.parquet");
+    fs::write(&words_path, "alpha\nbeta\n").unwrap();
+
+    let output = std::process::Command::new(env!("CARGO_BIN_EXE_shaha"))
+        .arg("build")
+        .arg(&words_path)
+        .args(["--hmac-key", "736563726574", "--provenance", "-o", db_path.to_str().unwrap()])
+        .output()
+        .expect("Failed to build database");
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+
+    let command = ParquetStorage::new(&db_path).get_provenance().unwrap().build_command.unwrap();
+    assert!(command.contains("--hmac-key <redacted>"), "{}", command);
+    assert!(!command.contains("736563726574"), "{}", command);
+    assert!(command.contains("words-\u{FFFD}.txt"), "{}", command);
+}
+
+#[test]
+fn test_info_summary_format_is_one_line() {
+    let dir = tempfile::tempdir().unwrap();
+    let words_path = dir.path().join("words.txt");
+    let db_path = dir.path().join("test.parquet");
+    let words: Vec<String> = (0..600).map(|i| format!("word{}", i)).collect();
+    fs::write(&words_path, words.join("\n")).unwrap();
+
+    let status = std::process::Command::new(env!("CARGO_BIN_EXE_shaha"))
+        .args(["build", words_path.to_str().unwrap(), "-a", "sha256", "-a", "md5"])
+        .args(["-o", db_path.to_str().unwrap()])
+        .status()
+        .expect("Failed to build database");
+    assert!(status.success());
+
+    let output = std::process::Command::new(env!("CARGO_BIN_EXE_shaha"))
+        .args(["info", db_path.to_str().unwrap(), "--format", "summary"])
+        .output()
+        .expect("Failed to run info");
+    assert!(output.status.success());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert_eq!(stdout.lines().count(), 1, "{}", stdout);
+    let expected = format!("{}: 1,200 records, 2 algos, 1 source, ", db_path.display());
+    assert!(stdout.starts_with(&expected), "{}", stdout);
+    let size = stdout.trim_end().rsplit(", ").next().unwrap();
+    let (amount, unit) = size.split_once(' ').unwrap();
+    assert!(amount.parse::<f64>().is_ok() && unit.ends_with('B'), "{}", stdout);
+}
+
+#[test]
+fn test_large_numbers_are_grouped() {
+    assert_eq!(shaha::output::format_number(2_000_000), "2,000,000");
+    assert_eq!(shaha::output::format_number(999), "999");
+    assert_eq!(shaha::output::format_number(0), "0");
+
+    let dir = tempfile::tempdir().unwrap();
+    let words_path = dir.path().join("words.txt");
+    let db_path = dir.path().join("test.parquet");
+    let words: Vec<String> = (0..1500).map(|i| format!("word{}", i)).collect();
+    fs::write(&words_path, words.join("\n")).unwrap();
+
+    let output = std::process::Command::new(env!("CARGO_BIN_EXE_shaha"))
+        .args(["build", words_path.to_str().unwrap(), "-a", "sha256", "-a", "md5"])
+        .args(["-o", db_path.to_str().unwrap()])
+        .output()
+        .expect("Failed to build database");
+    assert!(output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("Processed 1,500 words (1,500 unique,"), "{}", stderr);
+    assert!(stderr.contains("Generated 3,000 hash records"), "{}", stderr);
+
+    let output = std::process::Command::new(env!("CARGO_BIN_EXE_shaha"))
+        .args(["info", db_path.to_str().unwrap()])
+        .output()
+        .expect("Failed to run info");
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("Records:    3,000"), "{}", stdout);
+    assert!(stdout.contains("Preimages:  1,500"), "{}", stdout);
+}
+
+#[test]
+fn test_info_reports_distinct_preimages() {
+    let dir = tempfile::tempdir().unwrap();
+    let words_path = dir.path().join("words.txt");
+    let db_path = dir.path().join("test.parquet");
+    fs::write(&words_path, "alpha\nbeta\ngamma\nalpha\n").unwrap();
+
+    let status = std::process::Command::new(env!("CARGO_BIN_EXE_shaha"))
+        .args(["build", words_path.to_str().unwrap(), "-a", "sha256", "-a", "md5"])
+        .args(["-o", db_path.to_str().unwrap()])
+        .status()
+        .expect("Failed to build database");
+    assert!(status.success());
+
+    let stats = ParquetStorage::new(&db_path).stats().unwrap();
+    assert_eq!(stats.total_records, 6);
+    assert_eq!(stats.distinct_preimages, Some(3));
+
+    let output = std::process::Command::new(env!("CARGO_BIN_EXE_shaha"))
+        .args(["info", db_path.to_str().unwrap(), "--format", "json"])
+        .output()
+        .expect("Failed to run info");
+    let json: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(json["distinct_preimages"], 3);
+    assert_eq!(json["total_records"], 6);
+
+    let output = std::process::Command::new(env!("CARGO_BIN_EXE_shaha"))
+        .args(["info", db_path.to_str().unwrap()])
+        .output()
+        .expect("Failed to run info");
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("Preimages:  3"), "{}", stdout);
+
+    let mut memory = MemoryStorage::new();
+    memory
+        .write_batch(vec![
+            HashRecord {
+                hash: vec![1],
+                preimage: "alpha".to_string(),
+                algorithm: "sha256".to_string(),
+                sources: vec![],
+            },
+            HashRecord {
+                hash: vec![2],
+                preimage: "alpha".to_string(),
+                algorithm: "md5".to_string(),
+                sources: vec![],
+            },
+        ])
+        .unwrap();
+    assert_eq!(memory.stats().unwrap().distinct_preimages, Some(1));
+}
+
+#[test]
+fn test_build_max_file_size_writes_partial_database() {
+    let dir = tempfile::tempdir().unwrap();
+    let words_path = dir.path().join("words.txt");
+    let full_path = dir.path().join("full.parquet");
+    let capped_path = dir.path().join("capped.parquet");
+    let words: Vec<String> = (0..3000).map(|i| format!("password{}", i)).collect();
+    fs::write(&words_path, words.join("\n")).unwrap();
+
+    let build = |db: &std::path::Path, extra: &[&str]| {
+        std::process::Command::new(env!("CARGO_BIN_EXE_shaha"))
+            .args(["build", words_path.to_str().unwrap(), "-o", db.to_str().unwrap()])
+            .args(extra)
+            .output()
+            .expect("Failed to build database")
+    };
+
+    assert!(build(&full_path, &[]).status.success());
+    let full_size = fs::metadata(&full_path).unwrap().len();
+    let cap = full_size - 1;
+
+    let output = build(&capped_path, &["--max-file-size", &cap.to_string()]);
+    assert!(output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("at --max-file-size"), "{}", stderr);
+    assert!(fs::metadata(&capped_path).unwrap().len() <= cap);
+
+    let storage = ParquetStorage::new(&capped_path);
+    let written = storage.stats().unwrap().total_records;
+    assert!(written > 0 && written < 3000, "{}", written);
+    assert!(stderr.contains(&format!(
+        "wrote {} records, dropped {}",
+        shaha::output::format_number(written),
+        shaha::output::format_number(3000 - written)
+    )));
+
+    let mut first = None;
+    storage
+        .for_each_record(|record| {
+            first.get_or_insert(record);
+            Ok(())
+        })
+        .unwrap();
+    let first = first.unwrap();
+    let results = storage.query(&first.hash, None, 0, None).unwrap();
+    assert_eq!(results[0].preimage, first.preimage);
+}
+
+#[test]
+fn test_partition_by_algo_skips_other_row_groups() {
+    let dir = tempfile::tempdir().unwrap();
+    let words_path = dir.path().join("words.txt");
+    let partitioned_path = dir.path().join("partitioned.parquet");
+    let plain_path = dir.path().join("plain.parquet");
+    fs::write(&words_path, "alpha\nbeta\ngamma\n").unwrap();
+
+    let build = |db: &std::path::Path, extra: &[&str]| {
+        let status = std::process::Command::new(env!("CARGO_BIN_EXE_shaha"))
+            .args([
+                "build",
+                words_path.to_str().unwrap(),
+                "-a",
+                "sha256",
+                "-a",
+                "md5",
+                "-a",
+                "sha1",
+                "-o",
+                db.to_str().unwrap(),
+            ])
+            .args(extra)
+            .status()
+            .expect("Failed to build database");
+        assert!(status.success());
+    };
+    build(&partitioned_path, &["--partition-by-algo"]);
+    build(&plain_path, &[]);
+
+    let partitioned = ParquetStorage::new(&partitioned_path);
+    let plain = ParquetStorage::new(&plain_path);
+    assert_eq!(partitioned.candidate_row_groups(&[], None).unwrap().len(), 3);
+    assert_eq!(partitioned.candidate_row_groups(&[], Some("md5")).unwrap().len(), 1);
+    assert_eq!(plain.candidate_row_groups(&[], Some("md5")).unwrap().len(), 1);
+    assert_eq!(plain.candidate_row_groups(&[], None).unwrap().len(), 1);
+
+    let sha1 = hasher::get_hasher("sha1").unwrap();
+    let results = partitioned
+        .query(&sha1.hash(b"beta"), Some("sha1"), 0, None)
+        .unwrap();
+    assert_eq!(results.len(), 1);
+    assert_eq!(results[0].preimage, "beta");
+    assert!(partitioned
+        .query(&sha1.hash(b"beta"), Some("md5"), 0, None)
+        .unwrap()
+        .is_empty());
+
+    let all = partitioned.query(&[], Some("sha256"), 0, None).unwrap();
+    assert_eq!(all.len(), 3);
+    assert!(all.iter().all(|r| r.algorithm == "sha256"));
+}
+
+#[test]
+fn test_query_falls_back_to_scan_with_corrupt_bloom() {
+    use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+    use parquet::arrow::ArrowWriter;
+
+    let dir = tempfile::tempdir().unwrap();
+    let words_path = dir.path().join("words.txt");
+    let db_path = dir.path().join("test.parquet");
+    let corrupt_path = dir.path().join("corrupt.parquet");
+    fs::write(&words_path, "alpha\nbeta\n").unwrap();
+
+    let status = std::process::Command::new(env!("CARGO_BIN_EXE_shaha"))
+        .args(["build", words_path.to_str().unwrap(), "-o", db_path.to_str().unwrap()])
+        .status()
+        .expect("Failed to build database");
+    assert!(status.success());
+
+    let builder = ParquetRecordBatchReaderBuilder::try_new(fs::File::open(&db_path).unwrap()).unwrap();
+    let metadata = builder.metadata().file_metadata().key_value_metadata().cloned().unwrap();
+    let schema = builder.schema().clone();
+    let batches: Vec<_> = builder.build().unwrap().map(Result::unwrap).collect();
+
+    let mut writer = ArrowWriter::try_new(fs::File::create(&corrupt_path).unwrap(), schema, None).unwrap();
+    for batch in &batches {
+        writer.write(batch).unwrap();
+    }
+    for mut kv in metadata.into_iter().filter(|kv| kv.key != "ARROW:schema") {
+        if kv.key == "shaha:bloom_bitmap" {
+            let encoded = kv.value.unwrap();
+            kv.value = Some(format!("{}!#", &encoded[..encoded.len() / 2]));
+        }
+        writer.append_key_value_metadata(kv);
+    }
+    writer.close().unwrap();
+
+    let sha256 = hasher::get_hasher("sha256").unwrap();
+    let storage = ParquetStorage::new(&corrupt_path);
+    let results = storage.query(&sha256.hash(b"beta"), None, 0, None).unwrap();
+    assert_eq!(results.len(), 1);
+    assert_eq!(results[0].preimage, "beta");
+    assert!(storage.bloom_stats().unwrap().is_none());
+
+    let output = std::process::Command::new(env!("CARGO_BIN_EXE_shaha"))
+        .args(["query", &hex::encode(sha256.hash(b"alpha")), "-d", corrupt_path.to_str().unwrap()])
+        .output()
+        .expect("Failed to run query");
+    assert!(output.status.success());
+    assert!(String::from_utf8_lossy(&output.stdout).contains("alpha (sha256"));
+
+    let output = std::process::Command::new(env!("CARGO_BIN_EXE_shaha"))
+        .args(["info", corrupt_path.to_str().unwrap(), "--verbose"])
+        .output()
+        .expect("Failed to run info");
+    assert!(output.status.success());
+}
+
+#[test]
+fn test_rules_file_generates_variants() {
+    let rules = Rules::parse("# keep the word\n:\nc $1\nu\nr\n^! l\nt\n").unwrap();
+    assert_eq!(rules.len(), 6);
+    let variants: Vec<String> = rules.apply("PassWord").collect();
+    assert_eq!(
+        variants,
+        vec!["PassWord", "Password1", "PASSWORD", "droWssaP", "!password", "pASSwORD"]
+    );
+    assert!(Rules::parse("sa@\n").is_err());
+    assert!(Rules::parse("$\n").is_err());
+
+    let dir = tempfile::tempdir().unwrap();
+    let words_path = dir.path().join("words.txt");
+    let rules_path = dir.path().join("best.rule");
+    let db_path = dir.path().join("test.parquet");
+    fs::write(&words_path, "summer\nwinter\n").unwrap();
+    fs::write(&rules_path, ":\nc $2 $0 $2 $4\n").unwrap();
+
+    let output = std::process::Command::new(env!("CARGO_BIN_EXE_shaha"))
+        .args([
+            "build",
+            words_path.to_str().unwrap(),
+            "-o",
+            db_path.to_str().unwrap(),
+            "--rules-file",
+            rules_path.to_str().unwrap(),
+        ])
+        .output()
+        .expect("Failed to build database");
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Applying 2 rules"));
+
+    let storage = ParquetStorage::new(&db_path);
+    assert_eq!(storage.stats().unwrap().total_records, 4);
+    let sha256 = hasher::get_hasher("sha256").unwrap();
+    let results = storage.query(&sha256.hash(b"Summer2024"), None, 0, None).unwrap();
+    assert_eq!(results[0].preimage, "Summer2024");
+    assert_eq!(storage.query(&sha256.hash(b"winter"), None, 0, None).unwrap().len(), 1);
+}
+
+#[test]
+fn test_build_case_all_hashes_transformed_preimages() {
+    let dir = tempfile::tempdir().unwrap();
+    let words_path = dir.path().join("words.txt");
+    let db_path = dir.path().join("test.parquet");
+    fs::write(&words_path, "Hello\n").unwrap();
+
+    let output = std::process::Command::new(env!("CARGO_BIN_EXE_shaha"))
+        .args(["build", words_path.to_str().unwrap(), "-o", db_path.to_str().unwrap()])
+        .args(["-a", "sha256", "--case", "all"])
+        .output()
+        .expect("Failed to build database");
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+
+    // as-is and capitalize both give "Hello"; it is hashed once
+    let storage = ParquetStorage::new(&db_path);
+    assert_eq!(storage.stats().unwrap().total_records, 3);
+    let sha256 = hasher::get_hasher("sha256").unwrap();
+    for preimage in ["hello", "HELLO", "Hello"] {
+        let found = storage.query(&sha256.hash(preimage.as_bytes()), None, 0, None).unwrap();
+        assert_eq!(found[0].preimage, preimage);
+    }
+}
+
+#[test]
+fn test_fallback_storage_consults_fallback_only_on_miss() {
+    use std::cell::Cell;
+    use std::rc::Rc;
+
+    let sha256 = hasher::get_hasher("sha256").unwrap();
+    let storage_with = |word: &str| {
+        let mut storage = MemoryStorage::new();
+        storage
+            .write_batch(vec![HashRecord {
+                hash: sha256.hash(word.as_bytes()),
+                preimage: word.to_string(),
+                algorithm: "sha256".to_string(),
+                sources: vec![],
+            }])
+            .unwrap();
+        storage.finish().unwrap();
+        storage
+    };
+
+    let remote = Rc::new(Cell::new(Some(storage_with("remote"))));
+    let opened = Rc::new(Cell::new(0));
+    let (remote_handle, opened_handle) = (remote.clone(), opened.clone());
+    let storage = FallbackStorage::new(Box::new(storage_with("local")), move || {
+        opened_handle.set(opened_handle.get() + 1);
+        Ok(Box::new(remote_handle.take().unwrap()) as Box<dyn Storage>)
+    });
+
+    let local = storage.query(&sha256.hash(b"local"), None, 0, None).unwrap();
+    assert_eq!(local[0].preimage, "local");
+    assert_eq!(opened.get(), 0);
+
+    let found = storage.query(&sha256.hash(b"remote"), None, 0, None).unwrap();
+    assert_eq!(found[0].preimage, "remote");
+    assert!(storage.query(&sha256.hash(b"nowhere"), None, 0, None).unwrap().is_empty());
+    assert_eq!(opened.get(), 1);
+    assert_eq!(storage.fallback_hits(), 1);
+}
+
+#[test]
+fn test_query_fallback_r2_skips_remote_on_local_hit() {
+    let dir = tempfile::tempdir().unwrap();
+    let words_path = dir.path().join("words.txt");
+    let db_path = dir.path().join("test.parquet");
+    fs::write(&words_path, "hello\n").unwrap();
+
+    let status = std::process::Command::new(env!("CARGO_BIN_EXE_shaha"))
+        .args(["build", words_path.to_str().unwrap(), "-o", db_path.to_str().unwrap()])
+        .status()
+        .expect("Failed to build database");
+    assert!(status.success());
+
+    let hello = hex::encode(hasher::get_hasher("sha256").unwrap().hash(b"hello"));
+    let output = std::process::Command::new(env!("CARGO_BIN_EXE_shaha"))
+        .args(["query", &hello, "-d", db_path.to_str().unwrap(), "--fallback-r2"])
+        .args(["--endpoint", "127.0.0.1:1", "--bucket", "hashes"])
+        .args(["--access-key-id", "key", "--secret-access-key", "secret"])
+        .output()
+        .expect("Failed to run query");
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    assert!(String::from_utf8_lossy(&output.stdout).contains("hello"));
+    assert!(!String::from_utf8_lossy(&output.stderr).contains("R2 fallback"));
+}
+
+#[test]
+fn test_sort_modes_produce_identical_order() {
+    use shaha::build::{sort_records, sort_records_by_algorithm, SortMode};
+
+    let keys = |records: &[HashRecord]| -> Vec<(Vec<u8>, String)> {
+        records.iter().map(|r| (r.hash.clone(), r.algorithm.clone())).collect()
+    };
+
+    let records: Vec<HashRecord> = ["sha256", "md5"]
+        .iter()
+        .flat_map(|algo| {
+            let hasher = hasher::get_hasher(algo).unwrap();
+            (0..2_000).map(move |i| HashRecord {
+                hash: hasher.hash(format!("w{}", i).as_bytes()),
+                preimage: format!("w{}", i),
+                algorithm: algo.to_string(),
+                sources: vec![],
+            })
+        })
+        .collect();
+
+    let mut serial = records.clone();
+    let mut parallel = records.clone();
+    sort_records(&mut serial, SortMode::Serial);
+    sort_records(&mut parallel, SortMode::Parallel);
+    assert_eq!(keys(&serial), keys(&parallel));
+    assert!(serial.windows(2).all(|w| w[0].hash <= w[1].hash));
+
+    let mut serial = records.clone();
+    let mut parallel = records.clone();
+    sort_records_by_algorithm(&mut serial, SortMode::Serial);
+    sort_records_by_algorithm(&mut parallel, SortMode::Parallel);
+    assert_eq!(keys(&serial), keys(&parallel));
+    assert!(serial.windows(2).all(|w| w[0].algorithm <= w[1].algorithm));
+
+    // Spilled runs merge back into the same order as the in-memory sort
+    let dir = tempfile::tempdir().unwrap();
+    let mut spilled = ExternalSort::new(Some(700), SortMode::Auto)
+        .by_algorithm()
+        .with_temp_dir(dir.path());
+    for record in records {
+        spilled.push(record).unwrap();
+    }
+    assert!(spilled.spilled_runs() > 1);
+    let sorted = spilled.into_sorted().unwrap();
+    assert!(sorted.as_slice().is_none());
+    let merged: Vec<HashRecord> = sorted.into_iter().collect::<Result<_, _>>().unwrap();
+    assert_eq!(keys(&merged), keys(&serial));
+    assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 0);
+}
+
+#[test]
+fn test_build_sort_buffer_size_matches_in_memory_build() {
+    let dir = tempfile::tempdir().unwrap();
+    let words_path = dir.path().join("words.txt");
+    let more_path = dir.path().join("more.txt");
+    let words: Vec<String> = (0..500).map(|i| format!("word{}", i)).collect();
+    fs::write(&words_path, words[..300].join("\n")).unwrap();
+    fs::write(&more_path, words[200..].join("\n")).unwrap();
+
+    let mut hashes = Vec::new();
+    for (name, extra) in [("memory", &[][..]), ("spilled", &["--sort-buffer-size", "64"][..])] {
+        let db_path = dir.path().join(format!("{}.parquet", name));
+        for (input, append) in [(&words_path, &[][..]), (&more_path, &["--append"][..])] {
+            let output = std::process::Command::new(env!("CARGO_BIN_EXE_shaha"))
+                .args(["build", input.to_str().unwrap(), "-a", "sha256", "-a", "md5"])
+                .args(["-o", db_path.to_str().unwrap()])
+                .args(["--temp-dir", dir.path().join("tmp").to_str().unwrap()])
+                .args(append)
+                .args(extra)
+                .output()
+                .expect("Failed to build database");
+            assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+        }
+
+        let mut rows = Vec::new();
+        ParquetStorage::new(&db_path)
+            .for_each_record(|record| {
+                rows.push((record.hash, record.algorithm, record.preimage, record.sources));
+                Ok(())
+            })
+            .unwrap();
+        hashes.push(rows);
+    }
+    assert_eq!(hashes[0].len(), 1000);
+    assert_eq!(hashes[0], hashes[1]);
+    assert_eq!(fs::read_dir(dir.path().join("tmp")).unwrap().count(), 0);
+
+    let output = std::process::Command::new(env!("CARGO_BIN_EXE_shaha"))
+        .args(["build", words_path.to_str().unwrap(), "--sort-buffer-size", "64"])
+        .args(["--chunk-output", "--max-chunk-size", "100000"])
+        .output()
+        .expect("Failed to run build");
+    assert!(!output.status.success());
+}
+
+// Benchmark: cargo test --release -- --ignored bench_serial_vs_parallel_sort --nocapture
+#[test]
+#[ignore]
+fn bench_serial_vs_parallel_sort() {
+    use shaha::build::{sort_records, SortMode};
+
+    let sha256 = hasher::get_hasher("sha256").unwrap();
+    let records: Vec<HashRecord> = (0..2_000_000)
+        .map(|i| HashRecord {
+            hash: sha256.hash(format!("w{}", i).as_bytes()),
+            preimage: format!("w{}", i),
+            algorithm: "sha256".to_string(),
+            sources: vec![],
+        })
+        .collect();
+
+    for mode in [SortMode::Serial, SortMode::Parallel] {
+        let mut copy = records.clone();
+        let started = std::time::Instant::now();
+        sort_records(&mut copy, mode);
+        println!("{:?}: {} records in {:?}", mode, copy.len(), started.elapsed());
+    }
+}
+
+#[cfg(unix)]
+#[test]
+fn test_source_list_aspell_verbose_counts_words() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let dir = tempfile::tempdir().unwrap();
+    let fake = dir.path().join("aspell");
+    fs::write(
+        &fake,
+        r#"#!/bin/sh
+case "$1" in
+  --version) echo "fake aspell" ;;
+  dicts) printf 'en\npl\n' ;;
+  -d) if [ "$2" = en ]; then printf 'apple\nbanana\ncherry\n'; else printf 'jabłko\n'; fi ;;
+esac
+"#,
+    )
+    .unwrap();
+    fs::set_permissions(&fake, fs::Permissions::from_mode(0o755)).unwrap();
+    let path = format!("{}:{}", dir.path().display(), std::env::var("PATH").unwrap());
+
+    let output = std::process::Command::new(env!("CARGO_BIN_EXE_shaha"))
+        .args(["source", "list", "aspell", "--verbose"])
+        .env("PATH", &path)
+        .output()
+        .expect("Failed to run source list");
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "en\t3 words\npl\t1 word\n");
+
+    let output = std::process::Command::new(env!("CARGO_BIN_EXE_shaha"))
+        .args(["source", "list", "aspell"])
+        .env("PATH", &path)
+        .output()
+        .expect("Failed to run source list");
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "en\npl\n");
+}
+
+#[test]
+fn test_merge_strategy_controls_colliding_records() {
+    let dir = tempfile::tempdir().unwrap();
+    let words_path = dir.path().join("words.txt");
+    fs::write(&words_path, "hello\n").unwrap();
+    let hash = hasher::get_hasher("sha256").unwrap().hash(b"hello");
+
+    // A stored record whose preimage differs but hashes the same, as an md5
+    // collision pair would
+    let existing_db = |name: &str, preimage: &str| {
+        let db_path = dir.path().join(name);
+        let mut storage = ParquetStorage::new(&db_path);
+        storage
+            .write_batch(vec![HashRecord {
+                hash: hash.clone(),
+                preimage: preimage.to_string(),
+                algorithm: "sha256".to_string(),
+                sources: vec!["old".to_string()],
+            }])
+            .unwrap();
+        storage.finish().unwrap();
+        db_path
+    };
+    let append = |db_path: &std::path::Path, strategy: &str| {
+        std::process::Command::new(env!("CARGO_BIN_EXE_shaha"))
+            .args(["build", words_path.to_str().unwrap(), "-o", db_path.to_str().unwrap()])
+            .args(["--append", "-n", "new", "--merge-strategy", strategy])
+            .output()
+            .expect("Failed to run build")
+    };
+    let sources = |db_path: &std::path::Path| {
+        ParquetStorage::new(db_path).query(&hash, None, 0, None).unwrap()[0]
+            .sources
+            .clone()
+    };
+
+    let db = existing_db("union.parquet", "hello");
+    assert!(append(&db, "union-sources").status.success());
+    assert_eq!(sources(&db), vec!["new", "old"]);
+
+    let db = existing_db("first.parquet", "hello");
+    assert!(append(&db, "first").status.success());
+    assert_eq!(sources(&db), vec!["old"]);
+
+    let db = existing_db("same.parquet", "hello");
+    assert!(append(&db, "fail").status.success());
+    assert_eq!(sources(&db), vec!["new", "old"]);
+
+    let db = existing_db("collision.parquet", "imposter");
+    let output = append(&db, "fail");
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("Hash collision"), "{}", stderr);
+    assert!(stderr.contains("'imposter' and 'hello'"), "{}", stderr);
+    assert_eq!(sources(&db), vec!["old"]);
+}
+
+#[test]
+fn test_build_reports_hash_collisions() {
+    // Wang et al. 2004: two 128-byte blocks with the same md5
+    let block_a = hex::decode(
+        "d131dd02c5e6eec4693d9a0698aff95c2fcab58712467eab4004583eb8fb7f89\
+         55ad340609f4b30283e488832571415a085125e8f7cdc99fd91dbdf280373c5b\
+         d8823e3156348f5bae6dacd436c919c6dd53e2b487da03fd02396306d248cda0\
+         e99f33420f577ee8ce54b67080a80d1ec69821bcb6a8839396f9652b6ff72a70",
+    )
+    .unwrap();
+    let mut block_b = block_a.clone();
+    for i in [19, 45, 59, 83, 109, 123] {
+        block_b[i] ^= 0x80;
+    }
+    let md5 = hasher::get_hasher("md5").unwrap();
+    assert_ne!(block_a, block_b);
+    assert_eq!(hex::encode(md5.hash(&block_a)), "79054025255fb1a26e4bc422aef54eb4");
+    assert_eq!(md5.hash(&block_a), md5.hash(&block_b));
+
+    // The blocks are not valid UTF-8 lines, so store one side of a collision
+    // directly and append a word whose md5 matches it.
+    let dir = tempfile::tempdir().unwrap();
+    let words_path = dir.path().join("words.txt");
+    let db_path = dir.path().join("test.parquet");
+    fs::write(&words_path, "hello\nworld\n").unwrap();
+    let mut storage = ParquetStorage::new(&db_path);
+    storage
+        .write_batch(vec![HashRecord {
+            hash: md5.hash(b"hello"),
+            preimage: format!("$HEX[{}]", hex::encode(&block_a)),
+            algorithm: "md5".to_string(),
+            sources: vec!["old".to_string()],
+        }])
+        .unwrap();
+    storage.finish().unwrap();
+
+    let info = |db: &std::path::Path| {
+        let output = std::process::Command::new(env!("CARGO_BIN_EXE_shaha"))
+            .args(["info", db.to_str().unwrap(), "--format", "json"])
+            .output()
+            .expect("Failed to run info");
+        serde_json::from_slice::<serde_json::Value>(&output.stdout).unwrap()
+    };
+    assert_eq!(info(&db_path)["collisions"], 0);
+
+    let output = std::process::Command::new(env!("CARGO_BIN_EXE_shaha"))
+        .args(["build", words_path.to_str().unwrap(), "-o", db_path.to_str().unwrap()])
+        .args(["-a", "md5", "--append"])
+        .output()
+        .expect("Failed to run build");
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("1 hash collision (distinct preimages"));
+    assert_eq!(info(&db_path)["collisions"], 1);
+
+    let output = std::process::Command::new(env!("CARGO_BIN_EXE_shaha"))
+        .args(["info", db_path.to_str().unwrap()])
+        .output()
+        .expect("Failed to run info");
+    assert!(String::from_utf8_lossy(&output.stdout).contains("Collisions: 1"));
+}
+
+#[test]
+fn test_build_binary_records_round_trip_base64() {
+    use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+
+    let dir = tempfile::tempdir().unwrap();
+    let blobs_dir = dir.path().join("blobs");
+    fs::create_dir(&blobs_dir).unwrap();
+    let cert: Vec<u8> = vec![0x30, 0x82, 0x01, 0x0a, 0x00, 0xff, 0xfe, 0x80];
+    fs::write(blobs_dir.join("a.der"), &cert).unwrap();
+    fs::write(blobs_dir.join("b.bin"), [0u8, 1, 2, 3]).unwrap();
+    fs::write(blobs_dir.join("c.der"), &cert).unwrap();
+
+    let db_path = dir.path().join("blobs.parquet");
+    let output = std::process::Command::new(env!("CARGO_BIN_EXE_shaha"))
+        .args(["build", blobs_dir.to_str().unwrap(), "-o", db_path.to_str().unwrap()])
+        .args(["--binary-records", "files"])
+        .output()
+        .expect("Failed to run build");
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    assert_eq!(ParquetStorage::new(&db_path).stats().unwrap().total_records, 2);
+
+    let hash = hex::encode(hasher::get_hasher("sha256").unwrap().hash(&cert));
+    let output = std::process::Command::new(env!("CARGO_BIN_EXE_shaha"))
+        .args(["query", &hash, "-d", db_path.to_str().unwrap(), "--format", "json"])
+        .output()
+        .expect("Failed to run query");
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    let results: Vec<serde_json::Value> = serde_json::from_slice(&output.stdout).unwrap();
+    let preimage = results[0]["preimage"].as_str().unwrap();
+    assert_eq!(BASE64.decode(preimage).unwrap(), cert);
+
+    let stream_path = dir.path().join("records.bin");
+    let mut stream = Vec::new();
+    for record in [&cert[..], b"\x00", b""] {
+        stream.extend_from_slice(&(record.len() as u32).to_be_bytes());
+        stream.extend_from_slice(record);
+    }
+    fs::write(&stream_path, &stream).unwrap();
+    let stream_db = dir.path().join("stream.parquet");
+    let build_stream = || {
+        std::process::Command::new(env!("CARGO_BIN_EXE_shaha"))
+            .args(["build", stream_path.to_str().unwrap(), "-o", stream_db.to_str().unwrap()])
+            .args(["--binary-records", "length-prefixed", "--force"])
+            .output()
+            .expect("Failed to run build")
+    };
+    let output = build_stream();
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    let storage = ParquetStorage::new(&stream_db);
+    assert_eq!(storage.stats().unwrap().total_records, 3);
+    let empty = storage
+        .query(&hasher::get_hasher("sha256").unwrap().hash(b""), None, 0, None)
+        .unwrap();
+    assert_eq!(empty[0].preimage, "");
+
+    stream.extend_from_slice(&8u32.to_be_bytes());
+    stream.extend_from_slice(b"short");
+    fs::write(&stream_path, &stream).unwrap();
+    let output = build_stream();
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Record 4 is truncated (expected 8 bytes)"));
+}
+
+#[test]
+fn test_append_merge_reports_progress() {
+    let dir = tempfile::tempdir().unwrap();
+    let first = dir.path().join("first.txt");
+    let second = dir.path().join("second.txt");
+    let db_path = dir.path().join("test.parquet");
+    let words: Vec<String> = (0..50_000).map(|i| format!("word{}", i)).collect();
+    fs::write(&first, words.join("\n")).unwrap();
+    fs::write(&second, "word1\nextra\n").unwrap();
+
+    let status = std::process::Command::new(env!("CARGO_BIN_EXE_shaha"))
+        .args(["build", first.to_str().unwrap(), "-o", db_path.to_str().unwrap()])
+        .status()
+        .expect("Failed to build database");
+    assert!(status.success());
+
+    let append = |quiet: bool| {
+        let mut command = std::process::Command::new(env!("CARGO_BIN_EXE_shaha"));
+        if quiet {
+            command.arg("--quiet");
+        }
+        command
+            .args(["build", second.to_str().unwrap(), "-o", db_path.to_str().unwrap()])
+            .args(["--append", "--force"])
+            .output()
+            .expect("Failed to run build")
+    };
+
+    let output = append(false);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    let lines: Vec<&str> = stderr
+        .lines()
+        .filter(|line| line.starts_with("Merging existing records:"))
+        .collect();
+    assert_eq!(lines.len(), 10, "{}", stderr);
+    assert!(lines[0].contains("5,000 of 50,000 (10%)"));
+    assert!(lines[9].contains("50,000 of 50,000 (100%), 1 sources merged"));
+
+    let output = append(true);
+    assert!(output.status.success());
+    assert!(!String::from_utf8_lossy(&output.stderr).contains("Merging"));
+}
+
+#[test]
+fn test_build_write_manifest_matches_info() {
+    let dir = tempfile::tempdir().unwrap();
+    let words_path = dir.path().join("words.txt");
+    let db_path = dir.path().join("demo.parquet");
+    fs::write(&words_path, "alpha\nbeta\ngamma\n").unwrap();
+
+    let output = std::process::Command::new(env!("CARGO_BIN_EXE_shaha"))
+        .args(["build", words_path.to_str().unwrap(), "-o", db_path.to_str().unwrap()])
+        .args(["-a", "sha256", "-a", "md5", "-n", "demo", "--write-manifest"])
+        .output()
+        .expect("Failed to run build");
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+
+    let manifest_path = dir.path().join("demo.manifest.json");
+    let manifest: serde_json::Value =
+        serde_json::from_str(&fs::read_to_string(&manifest_path).unwrap()).unwrap();
+
+    let output = std::process::Command::new(env!("CARGO_BIN_EXE_shaha"))
+        .args(["info", db_path.to_str().unwrap(), "--format", "json"])
+        .output()
+        .expect("Failed to run info");
+    let info: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+
+    for field in ["total_records", "distinct_preimages", "collisions", "file_size_bytes"] {
+        assert_eq!(manifest[field], info[field], "{}", field);
+    }
+    let sorted = |value: &serde_json::Value| {
+        let mut items: Vec<String> = value
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|v| v.as_str().unwrap().to_string())
+            .collect();
+        items.sort();
+        items
+    };
+    assert_eq!(sorted(&manifest["algorithms"]), sorted(&info["algorithms"]));
+    let manifest_sources: Vec<&String> = manifest["sources"].as_object().unwrap().keys().collect();
+    assert_eq!(manifest_sources, vec!["demo"]);
+    assert_eq!(sorted(&info["sources"]), vec!["demo"]);
+
+    assert_eq!(manifest["database"], "demo.parquet");
+    assert_eq!(manifest["total_records"], 6);
+    assert_eq!(manifest["source_hashes"].as_array().unwrap().len(), 1);
+    assert!(manifest["sources"]["demo"]["path"].as_str().unwrap().ends_with("words.txt"));
+    assert_eq!(manifest["shaha_version"], env!("CARGO_PKG_VERSION"));
+    assert!(manifest["built_at"].as_str().unwrap().ends_with('Z'));
+}
+
+#[test]
+fn test_build_salt_prefix_and_suffix() {
+    let dir = tempfile::tempdir().unwrap();
+    let words_path = dir.path().join("words.txt");
+    fs::write(&words_path, "hello\n").unwrap();
+    let build = |db: &std::path::Path, extra: &[&str]| {
+        std::process::Command::new(env!("CARGO_BIN_EXE_shaha"))
+            .args(["build", words_path.to_str().unwrap(), "-o", db.to_str().unwrap()])
+            .args(["-a", "sha256", "-a", "md5"])
+            .args(extra)
+            .output()
+            .expect("Failed to run build")
+    };
+
+    let prefix_db = dir.path().join("prefix.parquet");
+    let output = build(&prefix_db, &["--salt", "a1b2"]);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    let suffix_db = dir.path().join("suffix.parquet");
+    let output = build(&suffix_db, &["--salt", "a1b2", "--salt-position", "suffix"]);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+
+    let salted = |salt_first: bool| {
+        let mut input = Vec::new();
+        if salt_first {
+            input.extend_from_slice(&[0xa1, 0xb2]);
+        }
+        input.extend_from_slice(b"hello");
+        if !salt_first {
+            input.extend_from_slice(&[0xa1, 0xb2]);
+        }
+        input
+    };
+    for algo in ["sha256", "md5"] {
+        let hasher = hasher::get_hasher(algo).unwrap();
+        for (db, salt_first) in [(&prefix_db, true), (&suffix_db, false)] {
+            let storage = ParquetStorage::new(db);
+            let found = storage.query(&hasher.hash(&salted(salt_first)), Some(algo), 0, None).unwrap();
+            assert_eq!(found[0].preimage, "hello", "{} {}", algo, db.display());
+            assert!(storage.query(&hasher.hash(b"hello"), Some(algo), 0, None).unwrap().is_empty());
+        }
+    }
+
+    let output = std::process::Command::new(env!("CARGO_BIN_EXE_shaha"))
+        .args(["info", suffix_db.to_str().unwrap(), "--format", "json"])
+        .output()
+        .expect("Failed to run info");
+    let info: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(info["salt"]["salt"], "a1b2");
+    assert_eq!(info["salt"]["position"], "suffix");
+
+    let candidates = dir.path().join("candidates.txt");
+    fs::write(&candidates, "hello\nnope\n").unwrap();
+    let output = std::process::Command::new(env!("CARGO_BIN_EXE_shaha"))
+        .args(["check", candidates.to_str().unwrap(), "-d", suffix_db.to_str().unwrap()])
+        .output()
+        .expect("Failed to run check");
+    assert!(String::from_utf8_lossy(&output.stdout).starts_with("1 of 2 candidates found"));
+
+    let output = build(&dir.path().join("empty.parquet"), &["--salt", ""]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("--salt must not be empty"));
+
+    let output = build(&prefix_db, &["--append", "--force", "--salt", "ffff"]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr)
+        .contains("built with salt a1b2 (prefix) but this build uses salt ffff (prefix)"));
+}
+
+#[test]
+fn test_parquet_query_timings_accumulate_per_lookup() {
+    use shaha::storage::SharedTimings;
+
+    let dir = tempfile::tempdir().unwrap();
+    let db_path = dir.path().join("test.parquet");
+    let sha256 = hasher::get_hasher("sha256").unwrap();
+
+    let mut storage = ParquetStorage::new(&db_path);
+    storage
+        .write_batch(vec![HashRecord {
+            hash: sha256.hash(b"hello"),
+            preimage: "hello".to_string(),
+            algorithm: "sha256".to_string(),
+            sources: vec!["test".to_string()],
+        }])
+        .unwrap();
+    storage.finish().unwrap();
+
+    let timings = SharedTimings::default();
+    let storage = ParquetStorage::new(&db_path).with_timings(timings.clone());
+    assert_eq!(storage.query(&sha256.hash(b"hello"), None, 0, None).unwrap().len(), 1);
+    // The bloom filter only answers whole-hash queries of a named algorithm
+    assert!(storage.query(&sha256.hash(b"absent"), Some("sha256"), 0, None).unwrap().is_empty());
+
+    let timings = *timings.lock().unwrap();
+    assert_eq!(timings.lookups, 2);
+    assert!(timings.metadata > std::time::Duration::ZERO);
+    assert!(timings.bloom > std::time::Duration::ZERO);
+}
+
+#[test]
+fn test_query_timing_reports_phases() {
+    let dir = tempfile::tempdir().unwrap();
+    let words_path = dir.path().join("words.txt");
+    let db_path = dir.path().join("test.parquet");
+    fs::write(&words_path, "hello\n").unwrap();
+
+    let status = std::process::Command::new(env!("CARGO_BIN_EXE_shaha"))
+        .args(["build", words_path.to_str().unwrap(), "-o", db_path.to_str().unwrap()])
+        .status()
+        .expect("Failed to build database");
+    assert!(status.success());
+
+    let hello = hex::encode(hasher::get_hasher("sha256").unwrap().hash(b"hello"));
+    let output = std::process::Command::new(env!("CARGO_BIN_EXE_shaha"))
+        .args(["query", &hello, "-d", db_path.to_str().unwrap(), "--timing"])
+        .output()
+        .expect("Failed to run query");
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("Timing (1 lookup):"), "{}", stderr);
+    for phase in ["metadata", "bloom", "row groups", "scan", "total"] {
+        assert!(stderr.contains(&format!("  {}", phase)), "{}", stderr);
+    }
+    assert!(!String::from_utf8_lossy(&output.stdout).contains("Timing"));
+}
+
+#[test]
+fn test_build_hmac_key() {
+    let dir = tempfile::tempdir().unwrap();
+    let words_path = dir.path().join("words.txt");
+    let db_path = dir.path().join("hmac.parquet");
+    fs::write(&words_path, "what do ya want for nothing?\n").unwrap();
+    let build = |extra: &[&str]| {
+        std::process::Command::new(env!("CARGO_BIN_EXE_shaha"))
+            .args(["build", words_path.to_str().unwrap(), "-o", db_path.to_str().unwrap()])
+            .args(["--hmac-key", "4a656665", "--force"])
+            .args(extra)
+            .output()
+            .expect("Failed to run build")
+    };
+
+    let output = build(&["-a", "sha256"]);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+
+    // RFC 4231 test case 2, key "Jefe"
+    let expected =
+        hex::decode("5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843").unwrap();
+    let found = ParquetStorage::new(&db_path).query(&expected, None, 0, None).unwrap();
+    assert_eq!(found[0].preimage, "what do ya want for nothing?");
+    assert_eq!(found[0].algorithm, "hmac-sha256");
+
+    let output = std::process::Command::new(env!("CARGO_BIN_EXE_shaha"))
+        .args(["query", "5bdcc146", "-d", db_path.to_str().unwrap(), "--strict-algo"])
+        .output()
+        .expect("Failed to run query");
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+
+    let output = build(&["-a", "blake3"]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("--hmac-key does not support blake3"));
+}
+
+#[test]
+fn test_build_rounds() {
+    let dir = tempfile::tempdir().unwrap();
+    let words_path = dir.path().join("words.txt");
+    let db_path = dir.path().join("rounds.parquet");
+    fs::write(&words_path, "hello\n").unwrap();
+    let shaha = |args: &[&str]| {
+        std::process::Command::new(env!("CARGO_BIN_EXE_shaha"))
+            .args(args)
+            .output()
+            .expect("Failed to run shaha")
+    };
+    let db = db_path.to_str().unwrap();
+
+    let output = shaha(&["build", words_path.to_str().unwrap(), "-o", db, "-a", "sha256", "--rounds", "2"]);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+
+    let sha256 = hasher::get_hasher("sha256").unwrap();
+    let twice = hex::encode(sha256.hash(&sha256.hash(b"hello")));
+    let output = shaha(&["query", &twice, "-d", db, "--strict-algo", "--format", "jsonl"]);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("\"algorithm\":\"sha256x2\""), "{}", stdout);
+
+    let output = shaha(&["info", db, "--format", "json"]);
+    let info: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(info["rounds"], 2);
+
+    let candidates = dir.path().join("candidates.txt");
+    fs::write(&candidates, "hello\nnope\n").unwrap();
+    let output = shaha(&["check", candidates.to_str().unwrap(), "-d", db, "--verbose"]);
+    assert!(String::from_utf8_lossy(&output.stdout).contains("found\thello"));
+
+    let output = shaha(&[
+        "build", words_path.to_str().unwrap(), "-o", db, "-a", "sha256",
+        "--append", "--force", "--rounds", "3",
+    ]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr)
+        .contains("was built with --rounds 2 but this build uses --rounds 3"));
+
+    // The salt goes into the first round only: sha256(sha256(salt || word))
+    let salted_db = dir.path().join("salted-rounds.parquet");
+    let salted_db = salted_db.to_str().unwrap();
+    let output = shaha(&[
+        "build", words_path.to_str().unwrap(), "-o", salted_db, "-a", "sha256",
+        "--rounds", "2", "--salt", "5a17", "--salt-position", "suffix",
+    ]);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    let expected = hex::encode(sha256.hash(&sha256.hash(b"hello\x5a\x17")));
+    let output = shaha(&["query", &expected, "-d", salted_db, "--strict-algo", "--format", "jsonl"]);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    assert!(String::from_utf8_lossy(&output.stdout).contains("\"preimage\":\"hello\""));
+    let output = shaha(&["check", candidates.to_str().unwrap(), "-d", salted_db, "--verbose"]);
+    assert!(String::from_utf8_lossy(&output.stdout).contains("found\thello"));
+}
+
+#[test]
+fn test_query_only_prints_bare_fields() {
+    let dir = tempfile::tempdir().unwrap();
+    let words_path = dir.path().join("words.txt");
+    let db_path = dir.path().join("test.parquet");
+    fs::write(&words_path, "hello\nworld\n").unwrap();
+
+    let status = std::process::Command::new(env!("CARGO_BIN_EXE_shaha"))
+        .args(["build", words_path.to_str().unwrap(), "-o", db_path.to_str().unwrap()])
+        .args(["-a", "sha256", "-a", "md5"])
+        .status()
+        .expect("Failed to build database");
+    assert!(status.success());
+
+    let hello = hex::encode(hasher::get_hasher("sha256").unwrap().hash(b"hello"));
+    let world = hex::encode(hasher::get_hasher("md5").unwrap().hash(b"world"));
+    let query = |only: &str| {
+        let output = std::process::Command::new(env!("CARGO_BIN_EXE_shaha"))
+            .args(["query", &hello, &world, "-d", db_path.to_str().unwrap(), "--only", only])
+            .output()
+            .expect("Failed to run query");
+        assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+        String::from_utf8(output.stdout).unwrap()
+    };
+
+    assert_eq!(query("preimage"), "hello\nworld\n");
+    assert_eq!(query("hash"), format!("{}\n{}\n", hello, world));
+}
+
+#[test]
+fn test_query_uppercase_hex() {
+    let dir = tempfile::tempdir().unwrap();
+    let words_path = dir.path().join("words.txt");
+    let db_path = dir.path().join("test.parquet");
+    fs::write(&words_path, "hello\n").unwrap();
+
+    let status = std::process::Command::new(env!("CARGO_BIN_EXE_shaha"))
+        .args(["build", words_path.to_str().unwrap(), "-o", db_path.to_str().unwrap()])
+        .args(["-a", "sha256"])
+        .status()
+        .expect("Failed to build database");
+    assert!(status.success());
+
+    let hello = hasher::get_hasher("sha256").unwrap().hash(b"hello");
+    let query = |format: &str| {
+        let output = std::process::Command::new(env!("CARGO_BIN_EXE_shaha"))
+            .args(["query", "2cf24d", "-d", db_path.to_str().unwrap()])
+            .args(["--format", format, "--uppercase"])
+            .output()
+            .expect("Failed to run query");
+        assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+        String::from_utf8(output.stdout).unwrap()
+    };
+
+    let json: serde_json::Value = serde_json::from_str(&query("json")).unwrap();
+    assert_eq!(json[0]["hash"], hex::encode_upper(&hello));
+    // Plain output has no hash column, so the flag changes nothing
+    assert_eq!(query("plain"), "hello (sha256, words)\n");
+}
+
+#[test]
+fn test_preimages_over_limit_flags_duplicate_records() {
+    use shaha::build::preimages_over_limit;
+
+    // A transform bug that emits "hello" three times under different hashes
+    let record = |hash: &[u8], preimage: &str| HashRecord {
+        hash: hash.to_vec(),
+        preimage: preimage.to_string(),
+        algorithm: "sha256".to_string(),
+        sources: vec!["test".to_string()],
+    };
+    let records = vec![
+        record(b"a", "hello"),
+        record(b"b", "hello"),
+        record(b"c", "hello"),
+        record(b"d", "world"),
+        record(b"e", ""),
+        record(b"f", ""),
+    ];
+
+    assert_eq!(preimages_over_limit(&records, 1), vec![("hello".to_string(), 3)]);
+    assert!(preimages_over_limit(&records, 3).is_empty());
+}
+
+#[test]
+fn test_build_preimage_max_count_warns_or_fails() {
+    let dir = tempfile::tempdir().unwrap();
+    let words_path = dir.path().join("words.txt");
+    let db_path = dir.path().join("test.parquet");
+    fs::write(&words_path, "hello\nworld\n").unwrap();
+    let build = |extra: &[&str]| {
+        std::process::Command::new(env!("CARGO_BIN_EXE_shaha"))
+            .args(["build", words_path.to_str().unwrap(), "-o", db_path.to_str().unwrap()])
+            .args(["-a", "sha256", "-a", "md5", "--force"])
+            .args(extra)
+            .output()
+            .expect("Failed to run build")
+    };
+
+    let output = build(&[]);
+    assert!(output.status.success());
+    assert!(!String::from_utf8_lossy(&output.stderr).contains("preimage(s)"));
+
+    let output = build(&["--preimage-max-count", "1"]);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    assert!(String::from_utf8_lossy(&output.stderr)
+        .contains("warning: 2 preimage(s) produced more than 1 records (e.g. 'hello' has 2)"));
+
+    let output = build(&["--preimage-max-count", "1", "--strict"]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("2 preimage(s) produced more than 1"));
+}
+
+#[test]
+fn test_build_from_compressed_stdin() {
+    use std::io::Read;
+    use std::process::{Command, Stdio};
+
+    let words = b"hello\nworld\n".to_vec();
+    let mut gzip = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+    gzip.write_all(&words).unwrap();
+    let mut xz = Vec::new();
+    liblzma::read::XzEncoder::new(&words[..], 6).read_to_end(&mut xz).unwrap();
+    let inputs = [
+        ("gzip", gzip.finish().unwrap()),
+        ("zstd", zstd::encode_all(&words[..], 0).unwrap()),
+        ("xz", xz),
+        ("plain", words.clone()),
+    ];
+
+    let dir = tempfile::tempdir().unwrap();
+    let sha256 = hasher::get_hasher("sha256").unwrap();
+    for (format, bytes) in inputs {
+        let db_path = dir.path().join(format!("{}.parquet", format));
+        let mut child = Command::new(env!("CARGO_BIN_EXE_shaha"))
+            .args(["build", "--from", "-", "-o", db_path.to_str().unwrap()])
+            .stdin(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .expect("Failed to run build");
+        child.stdin.take().unwrap().write_all(&bytes).unwrap();
+        let output = child.wait_with_output().unwrap();
+        assert!(output.status.success(), "{}: {}", format, String::from_utf8_lossy(&output.stderr));
+
+        let storage = ParquetStorage::new(&db_path);
+        assert_eq!(storage.stats().unwrap().total_records, 2, "{}", format);
+        let found = storage.query(&sha256.hash(b"world"), None, 0, None).unwrap();
+        assert_eq!(found[0].preimage, "world", "{}", format);
+    }
+}
+
+#[test]
+fn test_hash_prints_each_algorithm() {
+    let output = std::process::Command::new(env!("CARGO_BIN_EXE_shaha"))
+        .args(["hash", "hello", "world", "-a", "sha256", "-a", "md5"])
+        .output()
+        .expect("Failed to run hash");
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let lines: Vec<&str> = stdout.lines().collect();
+    assert_eq!(
+        lines,
+        [
+            "sha256  2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824  hello",
+            "md5  5d41402abc4b2a76b9719d911017c592  hello",
+            "sha256  486ea46224d1bb4fb680f34f7c9ad96a8f24ec88be73ea8e5a6c65260e9cb8a7  world",
+            "md5  7d793037a0760186574b0282f2f435e7  world",
+        ]
+    );
+}
+
+#[test]
+fn test_hash_salt_uppercase_and_stdin() {
+    use shaha::hasher::Hasher;
+    use std::process::{Command, Stdio};
+
+    let salted = hasher::SaltedHasher::new(
+        hasher::get_hasher("sha256").unwrap(),
+        b"NaCl".to_vec(),
+        hasher::SaltPosition::Suffix,
+    );
+    let mut child = Command::new(env!("CARGO_BIN_EXE_shaha"))
+        .args(["hash", "-", "--salt", "4e61436c", "--salt-position", "suffix", "--uppercase"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .expect("Failed to run hash");
+    child.stdin.take().unwrap().write_all(b"hello\n").unwrap();
+    let output = child.wait_with_output().unwrap();
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        format!("sha256  {}  hello\n", hex::encode_upper(salted.hash(b"hello")))
+    );
+
+    let output = Command::new(env!("CARGO_BIN_EXE_shaha"))
+        .args(["hash", "hello", "--salt", ""])
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("--salt must not be empty"));
+}
+
+#[test]
+fn test_build_if_newer_skips_fresh_output() {
+    use std::time::{Duration, SystemTime};
+
+    let dir = tempfile::tempdir().unwrap();
+    let words_path = dir.path().join("words.txt");
+    let db_path = dir.path().join("hashes.parquet");
+    fs::write(&words_path, "hello\nworld\n").unwrap();
+
+    let build = |extra: &[&str]| {
+        let output = std::process::Command::new(env!("CARGO_BIN_EXE_shaha"))
+            .args(["build", words_path.to_str().unwrap(), "-o", db_path.to_str().unwrap(), "--if-newer"])
+            .args(extra)
+            .output()
+            .expect("Failed to run build");
+        assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+        String::from_utf8(output.stderr).unwrap()
+    };
+    let set_source_mtime = |mtime: SystemTime| {
+        fs::File::options()
+            .write(true)
+            .open(&words_path)
+            .unwrap()
+            .set_modified(mtime)
+            .unwrap();
+    };
+
+    build(&[]);
+    assert_eq!(ParquetStorage::new(&db_path).stats().unwrap().total_records, 2);
+
+    // Output newer than the source: skipped, so the extra word is not picked up
+    fs::write(&words_path, "hello\nworld\nextra\n").unwrap();
+    set_source_mtime(SystemTime::now() - Duration::from_secs(3600));
+    let stderr = build(&[]);
+    assert!(stderr.contains("Output is newer than the source"), "{}", stderr);
+    assert_eq!(ParquetStorage::new(&db_path).stats().unwrap().total_records, 2);
+
+    build(&["--force"]);
+    assert_eq!(ParquetStorage::new(&db_path).stats().unwrap().total_records, 3);
+
+    // Source touched after the output: rebuilds
+    fs::write(&words_path, "hello\n").unwrap();
+    set_source_mtime(SystemTime::now() + Duration::from_secs(3600));
+    build(&[]);
+    assert_eq!(ParquetStorage::new(&db_path).stats().unwrap().total_records, 1);
+}
+
+#[test]
+fn test_build_if_newer_needs_local_source() {
+    let dir = tempfile::tempdir().unwrap();
+    let db_path = dir.path().join("hashes.parquet");
+    let output = std::process::Command::new(env!("CARGO_BIN_EXE_shaha"))
+        .args(["build", "--from", "-", "-o", db_path.to_str().unwrap(), "--if-newer"])
+        .stdin(std::process::Stdio::null())
+        .output()
+        .expect("Failed to run build");
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("--if-newer needs a file or seclists source"));
+}
+
+#[test]
+fn test_build_from_compressed_file() {
+    let dir = tempfile::tempdir().unwrap();
+    let words_path = dir.path().join("words.txt.zst");
+    let db_path = dir.path().join("hashes.parquet");
+    fs::write(&words_path, zstd::encode_all(&b"hello\nworld\n"[..], 0).unwrap()).unwrap();
+
+    let output = std::process::Command::new(env!("CARGO_BIN_EXE_shaha"))
+        .args(["build", words_path.to_str().unwrap(), "-o", db_path.to_str().unwrap()])
+        .output()
+        .expect("Failed to run build");
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+
+    let storage = ParquetStorage::new(&db_path);
+    assert_eq!(storage.stats().unwrap().total_records, 2);
+    let sha256 = hasher::get_hasher("sha256").unwrap();
+    assert_eq!(storage.query(&sha256.hash(b"hello"), None, 0, None).unwrap()[0].preimage, "hello");
+
+    fs::write(&words_path, "hello\n").unwrap();
+    let output = std::process::Command::new(env!("CARGO_BIN_EXE_shaha"))
+        .args(["build", words_path.to_str().unwrap(), "-o", db_path.to_str().unwrap(), "--force"])
+        .output()
+        .expect("Failed to run build");
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("has a zstd extension but is not zstd data"));
+}
+
+#[test]
+fn test_query_offset_pages_cli_results() {
+    let dir = tempfile::tempdir().unwrap();
+    let words_path = dir.path().join("words.txt");
+    let first_db = dir.path().join("first.parquet");
+    let second_db = dir.path().join("second.parquet");
+    let words: Vec<String> = (0..40).map(|i| format!("word{}", i)).collect();
+    fs::write(&words_path, words.join("\n")).unwrap();
+
+    for (db, algo) in [(&first_db, "sha256"), (&second_db, "md5")] {
+        let status = std::process::Command::new(env!("CARGO_BIN_EXE_shaha"))
+            .args(["build", words_path.to_str().unwrap(), "-o", db.to_str().unwrap(), "-a", algo])
+            .status()
+            .expect("Failed to build database");
+        assert!(status.success());
+    }
+
+    let run = |dbs: &[&std::path::PathBuf], extra: &[&str]| {
+        let mut cmd = std::process::Command::new(env!("CARGO_BIN_EXE_shaha"));
+        cmd.args(["query", "--mask", "??", "--format", "json"]);
+        for db in dbs {
+            cmd.args(["-d", db.to_str().unwrap()]);
+        }
+        cmd.args(extra).output().expect("Failed to run query")
+    };
+    let query = |dbs: &[&std::path::PathBuf], extra: &[&str]| -> Vec<String> {
+        let output = run(dbs, extra);
+        assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+        let results: Vec<serde_json::Value> = serde_json::from_slice(&output.stdout).unwrap();
+        results
+            .iter()
+            .map(|r| format!("{}:{}", r["algorithm"], r["preimage"]))
+            .collect()
+    };
+
+    for dbs in [&[&first_db][..], &[&first_db, &second_db][..]] {
+        let all = query(dbs, &[]);
+        assert_eq!(all.len(), 40 * dbs.len());
+        assert_eq!(query(dbs, &["--offset", "10", "--limit", "5"]), all[10..15]);
+        assert_eq!(query(dbs, &["--offset", "35"]), all[35..]);
+
+        let output = run(dbs, &["--offset", "500", "--limit", "5"]);
+        assert!(!output.status.success());
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(stderr.contains("No matches found at offset 500"), "{}", stderr);
+    }
+}
+
+#[test]
+fn test_info_shows_source_content_hashes() {
+    let dir = tempfile::tempdir().unwrap();
+    let words_path = dir.path().join("words.txt");
+    let db_path = dir.path().join("test.parquet");
+    fs::write(&words_path, "hello\nworld\n").unwrap();
+
+    let status = std::process::Command::new(env!("CARGO_BIN_EXE_shaha"))
+        .args(["build", words_path.to_str().unwrap(), "-o", db_path.to_str().unwrap()])
+        .status()
+        .expect("Failed to build database");
+    assert!(status.success());
+    let content_hash = FileSource::new(&words_path).content_hash().unwrap().unwrap();
+
+    let output = std::process::Command::new(env!("CARGO_BIN_EXE_shaha"))
+        .args(["info", db_path.to_str().unwrap(), "--format", "json"])
+        .output()
+        .expect("Failed to run info");
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    let json: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(json["source_hashes"], serde_json::json!([content_hash]));
+
+    let output = std::process::Command::new(env!("CARGO_BIN_EXE_shaha"))
+        .args(["info", db_path.to_str().unwrap()])
+        .output()
+        .expect("Failed to run info");
+    assert!(!String::from_utf8_lossy(&output.stdout).contains(&content_hash));
+
+    let output = std::process::Command::new(env!("CARGO_BIN_EXE_shaha"))
+        .args(["info", db_path.to_str().unwrap(), "--show-source-hashes"])
+        .output()
+        .expect("Failed to run info");
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains(&format!("Source hashes:\n  {}\n", content_hash)), "{}", stdout);
+}
+
+#[test]
+fn test_count_matches_query_len() {
+    use shaha::storage::HashEncoding;
+
+    let dir = tempfile::tempdir().unwrap();
+    let sha256 = hasher::get_hasher("sha256").unwrap();
+    let md5 = hasher::get_hasher("md5").unwrap();
+    let records: Vec<HashRecord> = (0..200)
+        .flat_map(|i| {
+            let word = format!("word{}", i);
+            [("sha256", &sha256), ("md5", &md5)].map(|(algo, hasher)| HashRecord {
+                hash: hasher.hash(word.as_bytes()),
+                preimage: word.clone(),
+                algorithm: algo.to_string(),
+                sources: vec!["test".to_string()],
+            })
+        })
+        .collect();
+
+    let mut memory = MemoryStorage::new();
+    memory.write_batch(records.clone()).unwrap();
+    let mut storages: Vec<Box<dyn Storage>> = vec![Box::new(memory)];
+    for encoding in [HashEncoding::Binary, HashEncoding::Hex] {
+        let db_path = dir.path().join(format!("{:?}.parquet", encoding));
+        let mut storage = ParquetStorage::new(&db_path).with_hash_encoding(encoding);
+        storage.write_batch(records.clone()).unwrap();
+        storage.finish().unwrap();
+        storages.push(Box::new(ParquetStorage::new(&db_path)));
+    }
+
+    let target = sha256.hash(b"word7");
+    let prefixes: [(&[u8], usize); 6] = [
+        (&[], 0),
+        (&target[..1], 8),
+        (&target[..1], 4),
+        (&target[..2], 12),
+        (&target, 256),
+        (&[0xff, 0xff, 0xff], 24),
+    ];
+    for storage in &storages {
+        for (prefix, bits) in prefixes {
+            for algo in [None, Some("sha256"), Some("md5")] {
+                let expected = storage.query_bits(prefix, bits, algo, 0, None).unwrap().len();
+                assert_eq!(
+                    storage.count_bits(prefix, bits, algo).unwrap(),
+                    expected,
+                    "{} bits of {}, {:?}",
+                    bits,
+                    hex::encode(prefix),
+                    algo
+                );
+                if bits % 8 == 0 {
+                    let whole = &prefix[..bits / 8];
+                    assert_eq!(storage.count(whole, algo).unwrap(), expected);
+                }
+            }
+        }
+    }
+    assert_eq!(storages[1].count(&[], None).unwrap(), 400);
+}
+
+#[test]
+fn test_query_count_flag() {
+    let dir = tempfile::tempdir().unwrap();
+    let words_path = dir.path().join("words.txt");
+    let db_path = dir.path().join("test.parquet");
+    fs::write(&words_path, "hello\nworld\n").unwrap();
+
+    let status = std::process::Command::new(env!("CARGO_BIN_EXE_shaha"))
+        .args(["build", words_path.to_str().unwrap(), "-o", db_path.to_str().unwrap()])
+        .args(["-a", "sha256", "-a", "md5"])
+        .status()
+        .expect("Failed to build database");
+    assert!(status.success());
+
+    let hello = hex::encode(hasher::get_hasher("sha256").unwrap().hash(b"hello"));
+    let output = std::process::Command::new(env!("CARGO_BIN_EXE_shaha"))
+        .args(["query", &hello, "ffffffff", "-d", db_path.to_str().unwrap(), "--count"])
+        .output()
+        .expect("Failed to run query");
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), "1\n0\n");
+
+    let output = std::process::Command::new(env!("CARGO_BIN_EXE_shaha"))
+        .args(["query", "--mask", "??", "-d", db_path.to_str().unwrap(), "--count"])
+        .output()
+        .expect("Failed to run query");
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), "4\n");
+}
+
+#[test]
+fn test_query_count_only_counts_prefix_matches() {
+    let dir = tempfile::tempdir().unwrap();
+    let words_path = dir.path().join("words.txt");
+    let db_path = dir.path().join("test.parquet");
+    let words: Vec<String> = (0..300).map(|i| format!("word{}", i)).collect();
+    fs::write(&words_path, words.join("\n")).unwrap();
+
+    let status = std::process::Command::new(env!("CARGO_BIN_EXE_shaha"))
+        .args(["build", words_path.to_str().unwrap(), "-o", db_path.to_str().unwrap()])
+        .args(["-a", "sha256", "-a", "md5"])
+        .status()
+        .expect("Failed to build database");
+    assert!(status.success());
+
+    let mut expected = [0usize; 2];
+    ParquetStorage::new(&db_path)
+        .for_each_record(|record| {
+            if record.hash[0] >> 4 == 0xa {
+                expected[0] += 1;
+                expected[1] += usize::from(record.algorithm == "md5");
+            }
+            Ok(())
+        })
+        .unwrap();
+    assert!(expected[0] > 1);
+
+    let count_only = |extra: &[&str]| {
+        let output = std::process::Command::new(env!("CARGO_BIN_EXE_shaha"))
+            .args(["query", "a", "-d", db_path.to_str().unwrap(), "--count-only"])
+            .args(extra)
+            .output()
+            .expect("Failed to run query");
+        assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+        String::from_utf8(output.stdout).unwrap()
+    };
+    assert_eq!(count_only(&[]), format!("{}\n", expected[0]));
+    assert_eq!(count_only(&["--algo", "md5"]), format!("{}\n", expected[1]));
+}
+
+#[test]
+fn test_build_max_variants_per_word_caps_transforms() {
+    use shaha::rules::CaseMode;
+
+    let dir = tempfile::tempdir().unwrap();
+    let words_path = dir.path().join("words.txt");
+    let rules_path = dir.path().join("explode.rule");
+    let db_path = dir.path().join("test.parquet");
+    fs::write(&words_path, "summer\nwinter\n").unwrap();
+    let rule_lines: Vec<String> = (0..10)
+        .map(|d| format!("${}", d))
+        .chain(["^!", "^@", "r", ":"].map(String::from))
+        .collect();
+    fs::write(&rules_path, rule_lines.join("\n")).unwrap();
+
+    // case all x 14 rules = 56 variants per word, some of them duplicates
+    let rules = CaseMode::All.rules().then(&Rules::parse(&rule_lines.join("\n")).unwrap());
+    let expected = |word: &str| -> Vec<String> {
+        let mut variants = rules.variants(word);
+        assert_eq!(variants.len(), 56);
+        variants.sort();
+        variants.dedup();
+        variants.truncate(5);
+        variants
+    };
+
+    let output = std::process::Command::new(env!("CARGO_BIN_EXE_shaha"))
+        .args(["build", words_path.to_str().unwrap(), "-o", db_path.to_str().unwrap()])
+        .args(["--case", "all", "--rules-file", rules_path.to_str().unwrap()])
+        .args(["--max-variants-per-word", "5"])
+        .output()
+        .expect("Failed to build database");
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("variants over --max-variants-per-word 5"), "{}", stderr);
+
+    let storage = ParquetStorage::new(&db_path);
+    let mut stored: Vec<String> = storage
+        .query(&[], None, 0, None)
+        .unwrap()
+        .into_iter()
+        .map(|r| r.preimage)
+        .collect();
+    stored.sort();
+    let mut wanted = [expected("summer"), expected("winter")].concat();
+    wanted.sort();
+    assert_eq!(stored, wanted);
+
+    let output = std::process::Command::new(env!("CARGO_BIN_EXE_shaha"))
+        .args(["build", words_path.to_str().unwrap(), "-o", db_path.to_str().unwrap()])
+        .args(["--max-variants-per-word", "0", "--force"])
+        .output()
+        .expect("Failed to build database");
+    assert!(!output.status.success());
+}
+
+#[test]
+fn test_merge_combines_overlapping_databases() {
+    let dir = tempfile::tempdir().unwrap();
+    let first_words = dir.path().join("first.txt");
+    let second_words = dir.path().join("second.txt");
+    let first_db = dir.path().join("first.parquet");
+    let second_db = dir.path().join("second.parquet");
+    let salted_db = dir.path().join("salted.parquet");
+    let merged_db = dir.path().join("merged.parquet");
+    fs::write(&first_words, "hello\nworld\n").unwrap();
+    fs::write(&second_words, "world\nfoo\n").unwrap();
+
+    let build = |words: &std::path::Path, db: &std::path::Path, extra: &[&str]| {
+        let status = std::process::Command::new(env!("CARGO_BIN_EXE_shaha"))
+            .args(["build", words.to_str().unwrap(), "-o", db.to_str().unwrap()])
+            .args(["-a", "sha256", "-a", "md5"])
+            .args(extra)
+            .status()
+            .expect("Failed to build database");
+        assert!(status.success());
+    };
+    build(&first_words, &first_db, &[]);
+    build(&second_words, &second_db, &[]);
+    build(&second_words, &salted_db, &["--salt", "00ff"]);
+
+    let output = std::process::Command::new(env!("CARGO_BIN_EXE_shaha"))
+        .args(["merge", first_db.to_str().unwrap(), second_db.to_str().unwrap()])
+        .args(["-o", merged_db.to_str().unwrap()])
+        .output()
+        .expect("Failed to run merge");
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+
+    let merged = ParquetStorage::new(&merged_db);
+    let mut stats = merged.stats().unwrap();
+    stats.sources.sort();
+    assert_eq!(stats.total_records, 6);
+    assert_eq!(stats.sources, ["first", "second"]);
+
+    let sha256 = hasher::get_hasher("sha256").unwrap();
+    let world = merged.query(&sha256.hash(b"world"), Some("sha256"), 0, None).unwrap();
+    assert_eq!(world.len(), 1);
+    assert_eq!(world[0].sources, ["first", "second"]);
+    let foo = merged.query(&sha256.hash(b"foo"), None, 0, None).unwrap();
+    assert_eq!(foo[0].sources, ["second"]);
+
+    // Rebuilt bloom filter covers records from both inputs
+    let bloom = merged.bloom_stats().unwrap().unwrap();
+    assert_eq!(bloom.items, Some(6));
+
+    let mut expected_hashes = ParquetStorage::new(&first_db).get_source_hashes().unwrap();
+    expected_hashes.extend(ParquetStorage::new(&second_db).get_source_hashes().unwrap());
+    assert_eq!(expected_hashes.len(), 2);
+    assert_eq!(merged.get_source_hashes().unwrap(), expected_hashes);
+    assert_eq!(
+        merged.get_source_meta().unwrap().keys().collect::<Vec<_>>(),
+        ["first", "second"]
+    );
+
+    let output = std::process::Command::new(env!("CARGO_BIN_EXE_shaha"))
+        .args(["merge", first_db.to_str().unwrap(), salted_db.to_str().unwrap()])
+        .args(["-o", dir.path().join("bad.parquet").to_str().unwrap()])
+        .output()
+        .expect("Failed to run merge");
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("different salt"));
+}
+
+#[test]
+fn test_export_csv_round_trips_records() {
+    let dir = tempfile::tempdir().unwrap();
+    let words = dir.path().join("words.txt");
+    let db = dir.path().join("hashes.parquet");
+    let csv_path = dir.path().join("out.csv");
+    fs::write(&words, "hello\nworld\nsay \"hi\", ok\n").unwrap();
+
+    let status = std::process::Command::new(env!("CARGO_BIN_EXE_shaha"))
+        .args(["build", words.to_str().unwrap(), "-o", db.to_str().unwrap()])
+        .args(["-a", "sha256", "-a", "md5"])
+        .status()
+        .expect("Failed to build database");
+    assert!(status.success());
+
+    let output = std::process::Command::new(env!("CARGO_BIN_EXE_shaha"))
+        .args(["export", db.to_str().unwrap(), "--format", "csv", "-o", csv_path.to_str().unwrap()])
+        .output()
+        .expect("Failed to run export");
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+
+    let mut reader = csv::Reader::from_path(&csv_path).unwrap();
+    assert_eq!(reader.headers().unwrap(), vec!["hash", "preimage", "algorithm", "sources"]);
+    let rows: Vec<csv::StringRecord> = reader.records().map(Result::unwrap).collect();
+    assert_eq!(rows.len(), 6);
+
+    let sha256 = hasher::get_hasher("sha256").unwrap();
+    let quoted = "say \"hi\", ok";
+    let row = rows
+        .iter()
+        .find(|row| &row[1] == quoted && &row[2] == "sha256")
+        .expect("quoted preimage survives CSV quoting");
+    assert_eq!(&row[0], hex::encode(sha256.hash(quoted.as_bytes())));
+    assert_eq!(&row[3], "words");
+
+    let output = std::process::Command::new(env!("CARGO_BIN_EXE_shaha"))
+        .args(["export", db.to_str().unwrap(), "-o", "-", "--algo", "md5"])
+        .output()
+        .expect("Failed to run export");
+    assert!(output.status.success());
+    let mut reader = csv::Reader::from_reader(output.stdout.as_slice());
+    let algos: Vec<String> = reader.records().map(|row| row.unwrap()[2].to_string()).collect();
+    assert_eq!(algos, ["md5", "md5", "md5"]);
+}
+
+#[test]
+fn test_build_bloom_seed_gives_identical_bloom() {
+    use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+
+    let dir = tempfile::tempdir().unwrap();
+    let words_path = dir.path().join("words.txt");
+    fs::write(&words_path, "alpha\nbeta\ngamma\n").unwrap();
+
+    let bloom_metadata = |name: &str, extra: &[&str]| {
+        let db_path = dir.path().join(name);
+        let status = std::process::Command::new(env!("CARGO_BIN_EXE_shaha"))
+            .args(["build", words_path.to_str().unwrap(), "-o", db_path.to_str().unwrap()])
+            .args(extra)
+            .status()
+            .expect("Failed to build database");
+        assert!(status.success());
+
+        let builder =
+            ParquetRecordBatchReaderBuilder::try_new(fs::File::open(&db_path).unwrap()).unwrap();
+        let metadata = builder.metadata().file_metadata().key_value_metadata().cloned().unwrap();
+        let value = |key: &str| metadata.iter().find(|kv| kv.key == key).unwrap().value.clone();
+        (value("shaha:bloom_bitmap"), value("shaha:bloom_keys"))
+    };
+
+    let first = bloom_metadata("first.parquet", &["--bloom-seed", "release-1"]);
+    let second = bloom_metadata("second.parquet", &["--bloom-seed", "release-1"]);
+    assert_eq!(first, second);
+
+    let other_seed = bloom_metadata("other.parquet", &["--bloom-seed", "release-2"]);
+    assert_ne!(first.1, other_seed.1);
+    let unseeded = bloom_metadata("unseeded.parquet", &[]);
+    assert_ne!(first.1, unseeded.1);
+
+    let sha256 = hasher::get_hasher("sha256").unwrap();
+    let storage = ParquetStorage::new(dir.path().join("first.parquet"));
+    assert_eq!(storage.query(&sha256.hash(b"beta"), None, 0, None).unwrap().len(), 1);
+}
+
+#[test]
+fn test_import_csv_into_parquet() {
+    let dir = tempfile::tempdir().unwrap();
+    let csv_path = dir.path().join("in.csv");
+    let db = dir.path().join("imported.parquet");
+    let md5 = hasher::get_hasher("md5").unwrap();
+    let sha1 = hasher::get_hasher("sha1").unwrap();
+    fs::write(
+        &csv_path,
+        format!(
+            "hash,preimage,algorithm,sources\n{},\"a, \"\"quoted\"\" word\",md5,leak;forum\n{},hello,sha1,\n",
+            hex::encode(md5.hash(b"a, \"quoted\" word")),
+            hex::encode(sha1.hash(b"hello")),
+        ),
+    )
+    .unwrap();
+
+    let import = |input: &std::path::Path, extra: &[&str]| {
+        std::process::Command::new(env!("CARGO_BIN_EXE_shaha"))
+            .args(["import", input.to_str().unwrap(), "-o", db.to_str().unwrap()])
+            .args(extra)
+            .output()
+            .expect("Failed to run import")
+    };
+    let output = import(&csv_path, &[]);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+
+    let storage = ParquetStorage::new(&db);
+    let results = storage.query(&md5.hash(b"a, \"quoted\" word"), Some("md5"), 0, None).unwrap();
+    assert_eq!(results.len(), 1);
+    assert_eq!(results[0].preimage, "a, \"quoted\" word");
+    assert_eq!(results[0].sources, ["leak", "forum"]);
+    assert!(storage.query(&sha1.hash(b"hello"), None, 0, None).unwrap()[0].sources.is_empty());
+
+    // --append merges sources of a hash that is already stored
+    let more = dir.path().join("more.csv");
+    fs::write(
+        &more,
+        format!("hash,preimage,algorithm,sources\n{},hello,sha1,rockyou\n", hex::encode(sha1.hash(b"hello"))),
+    )
+    .unwrap();
+    let output = import(&more, &["--append"]);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    assert_eq!(storage.stats().unwrap().total_records, 2);
+    assert_eq!(storage.query(&sha1.hash(b"hello"), None, 0, None).unwrap()[0].sources, ["rockyou"]);
+
+    let bad = dir.path().join("bad.csv");
+    fs::write(&bad, "hash,preimage,algorithm,sources\n00ff,x,md5,\n").unwrap();
+    let output = import(&bad, &[]);
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("line 2"), "{}", stderr);
+    assert!(stderr.contains("md5 hash should be 16 bytes, got 2"), "{}", stderr);
+}
+
+#[test]
+fn test_build_minimal_metadata_queries_through_statistics() {
+    use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+
+    let dir = tempfile::tempdir().unwrap();
+    let words_path = dir.path().join("words.txt");
+    let db_path = dir.path().join("minimal.parquet");
+    fs::write(&words_path, "alpha\nbeta\ngamma\n").unwrap();
+
+    let status = std::process::Command::new(env!("CARGO_BIN_EXE_shaha"))
+        .args(["build", words_path.to_str().unwrap(), "-o", db_path.to_str().unwrap()])
+        .args(["-a", "sha256", "-a", "md5", "--minimal-metadata"])
+        .status()
+        .expect("Failed to build database");
+    assert!(status.success());
+
+    let builder = ParquetRecordBatchReaderBuilder::try_new(fs::File::open(&db_path).unwrap()).unwrap();
+    let mut keys: Vec<String> = builder
+        .metadata()
+        .file_metadata()
+        .key_value_metadata()
+        .unwrap()
+        .iter()
+        .map(|kv| kv.key.clone())
+        .filter(|key| key.starts_with("shaha:"))
+        .collect();
+    keys.sort();
+    assert_eq!(keys, ["shaha:hash_encoding", "shaha:version"]);
+
+    let storage = ParquetStorage::new(&db_path);
+    assert!(storage.bloom_stats().unwrap().is_none());
+    assert_eq!(storage.stats().unwrap().total_records, 6);
+
+    let sha256 = hasher::get_hasher("sha256").unwrap();
+    let results = storage.query(&sha256.hash(b"beta"), None, 0, None).unwrap();
+    assert_eq!(results.len(), 1);
+    assert_eq!(results[0].preimage, "beta");
+    assert!(storage.query(&sha256.hash(b"delta"), None, 0, None).unwrap().is_empty());
+}
+
+#[test]
+fn test_import_potfile_infers_algorithm_by_length() {
+    let dir = tempfile::tempdir().unwrap();
+    let pot = dir.path().join("cracked.pot");
+    let db = dir.path().join("cracked.parquet");
+    let md5 = hasher::get_hasher("md5").unwrap();
+    let sha1 = hasher::get_hasher("sha1").unwrap();
+    fs::write(
+        &pot,
+        format!(
+            "{}:pass:word:1\n{}:letmein\r\n\n{}:$HEX[74616209656e64]\n",
+            hex::encode(md5.hash(b"pass:word:1")),
+            hex::encode_upper(sha1.hash(b"letmein")),
+            hex::encode(md5.hash(b"tab\tend")),
+        ),
+    )
+    .unwrap();
+
+    let import = |extra: &[&str]| {
+        std::process::Command::new(env!("CARGO_BIN_EXE_shaha"))
+            .args(["import", "--format", "potfile", pot.to_str().unwrap(), "-o", db.to_str().unwrap()])
+            .args(extra)
+            .output()
+            .expect("Failed to run import")
+    };
+
+    // md5 and ntlm share a length, so the algorithm has to be narrowed down
+    let output = import(&[]);
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("cracked.pot line 1"), "{}", stderr);
+    assert!(stderr.contains("pass --algo"), "{}", stderr);
+
+    let output = import(&["--algo", "md5", "--algo", "sha1"]);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+
+    let storage = ParquetStorage::new(&db);
+    assert_eq!(storage.stats().unwrap().total_records, 3);
+    let colon = storage.query(&md5.hash(b"pass:word:1"), Some("md5"), 0, None).unwrap();
+    assert_eq!(colon[0].preimage, "pass:word:1");
+    assert_eq!(colon[0].sources, ["cracked"]);
+    let upper = storage.query(&sha1.hash(b"letmein"), Some("sha1"), 0, None).unwrap();
+    assert_eq!(upper[0].preimage, "letmein");
+    let escaped = storage.query(&md5.hash(b"tab\tend"), None, 0, None).unwrap();
+    assert_eq!(escaped[0].preimage, "tab\tend");
+}
+
+#[test]
+fn test_export_potfile_writes_hash_plain_lines() {
+    let dir = tempfile::tempdir().unwrap();
+    let words = dir.path().join("words.txt");
+    let db = dir.path().join("hashes.parquet");
+    let pot = dir.path().join("out.pot");
+    fs::write(&words, "user:secret\nhello\n").unwrap();
+
+    let status = std::process::Command::new(env!("CARGO_BIN_EXE_shaha"))
+        .args(["build", words.to_str().unwrap(), "-o", db.to_str().unwrap()])
+        .args(["-a", "md5", "-a", "sha1"])
+        .status()
+        .expect("Failed to build database");
+    assert!(status.success());
+
+    let output = std::process::Command::new(env!("CARGO_BIN_EXE_shaha"))
+        .args(["export", db.to_str().unwrap(), "--format", "potfile", "-o", pot.to_str().unwrap()])
+        .output()
+        .expect("Failed to run export");
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+
+    let md5 = hasher::get_hasher("md5").unwrap();
+    let sha1 = hasher::get_hasher("sha1").unwrap();
+    let mut lines: Vec<String> = fs::read_to_string(&pot).unwrap().lines().map(String::from).collect();
+    lines.sort();
+    let mut expected = vec![
+        format!("{}:user:secret", hex::encode(md5.hash(b"user:secret"))),
+        format!("{}:hello", hex::encode(md5.hash(b"hello"))),
+        format!("{}:user:secret", hex::encode(sha1.hash(b"user:secret"))),
+        format!("{}:hello", hex::encode(sha1.hash(b"hello"))),
+    ];
+    expected.sort();
+    assert_eq!(lines, expected);
+
+    let output = std::process::Command::new(env!("CARGO_BIN_EXE_shaha"))
+        .args(["export", db.to_str().unwrap(), "--format", "potfile", "--algo", "sha1"])
+        .output()
+        .expect("Failed to run export");
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert_eq!(stdout.lines().count(), 2);
+    assert!(stdout.contains(&format!("{}:hello", hex::encode(sha1.hash(b"hello")))));
+
+    // Salted databases carry the salt; the others cannot be expressed
+    let build = |name: &str, extra: &[&str]| {
+        let db = dir.path().join(name);
+        let status = std::process::Command::new(env!("CARGO_BIN_EXE_shaha"))
+            .args(["build", words.to_str().unwrap(), "-o", db.to_str().unwrap(), "-a", "md5"])
+            .args(extra)
+            .status()
+            .expect("Failed to build database");
+        assert!(status.success());
+        db
+    };
+    let export = |db: &std::path::Path| {
+        std::process::Command::new(env!("CARGO_BIN_EXE_shaha"))
+            .args(["export", db.to_str().unwrap(), "--format", "potfile"])
+            .output()
+            .expect("Failed to run export")
+    };
+
+    let salted = export(&build("salted.parquet", &["--salt", &hex::encode("NaCl")]));
+    assert!(salted.status.success());
+    let stdout = String::from_utf8_lossy(&salted.stdout);
+    assert!(stdout.contains(&format!("{}:NaCl:hello", hex::encode(md5.hash(b"NaClhello")))), "{}", stdout);
+
+    let binary_salt = export(&build("binary-salt.parquet", &["--salt", "00ff"]));
+    let stdout = String::from_utf8_lossy(&binary_salt.stdout);
+    assert!(stdout.contains(&format!("{}:00ff:hello", hex::encode(md5.hash(b"\x00\xffhello")))), "{}", stdout);
+
+    for (name, extra, reason) in [
+        ("rounds.parquet", &["--rounds", "3"][..], "--rounds 3"),
+        ("hmac.parquet", &["--hmac-key", "6b6579"][..], "HMAC key"),
+        ("prefix.parquet", &["--prefix-only-bits", "32"][..], "--prefix-only-bits"),
+    ] {
+        let output = export(&build(name, extra));
+        assert!(!output.status.success(), "{}", name);
+        assert!(String::from_utf8_lossy(&output.stderr).contains(reason), "{}", name);
+        assert!(output.stdout.is_empty());
+    }
+}
+
+#[test]
+fn test_repl_runs_scripted_commands() {
+    let dir = tempfile::tempdir().unwrap();
+    let words = dir.path().join("words.txt");
+    let db = dir.path().join("hashes.parquet");
+    fs::write(&words, "password\npassw0rd\nhello\n").unwrap();
+
+    let status = std::process::Command::new(env!("CARGO_BIN_EXE_shaha"))
+        .args(["build", words.to_str().unwrap(), "-o", db.to_str().unwrap()])
+        .args(["-a", "sha256", "-a", "md5"])
+        .status()
+        .expect("Failed to build database");
+    assert!(status.success());
+
+    let sha256 = hasher::get_hasher("sha256").unwrap();
+    let md5 = hasher::get_hasher("md5").unwrap();
+    let script = format!(
+        "q {}\np hello\np nope\ngrep assw\nbogus\ninfo\nquit\nq {}\n",
+        &hex::encode(sha256.hash(b"hello"))[..12],
+        hex::encode(md5.hash(b"password")),
+    );
+
+    let mut child = std::process::Command::new(env!("CARGO_BIN_EXE_shaha"))
+        .args(["repl", db.to_str().unwrap()])
+        .stdin(std::process::Stdio::piped())
+        .stdout(std::process::Stdio::piped())
+        .stderr(std::process::Stdio::piped())
+        .spawn()
+        .expect("Failed to start repl");
+    child.stdin.take().unwrap().write_all(script.as_bytes()).unwrap();
+    let output = child.wait_with_output().unwrap();
+    assert!(output.status.success());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+
+    assert!(stdout.contains("hello (sha256, words)\n1 result\n"), "{}", stdout);
+    assert!(stdout.contains(&format!("found   md5  {}", hex::encode(md5.hash(b"hello")))), "{}", stdout);
+    assert!(stdout.contains(&format!("missing sha256  {}", hex::encode(sha256.hash(b"nope")))), "{}", stdout);
+    assert_eq!(stdout.matches("(md5, words)").count(), 2, "{}", stdout);
+    assert!(stdout.contains("4 results"), "{}", stdout);
+    assert!(stdout.contains("Records:    6"), "{}", stdout);
+    assert!(stderr.contains("Unknown command: bogus"), "{}", stderr);
+    // Nothing after quit runs
+    assert_eq!(stdout.matches("password (md5").count(), 1, "{}", stdout);
+}
+
+#[test]
+fn test_append_keeps_sources_a_sorted_deduplicated_set() {
+    let dir = tempfile::tempdir().unwrap();
+    let db = dir.path().join("hashes.parquet");
+    let mixed_case = dir.path().join("RockYou.txt");
+    let alpha = dir.path().join("alpha.txt");
+    fs::create_dir(dir.path().join("lower")).unwrap();
+    let lower_case = dir.path().join("lower").join("rockyou.txt");
+    fs::write(&mixed_case, "hello\n").unwrap();
+    fs::write(&lower_case, "hello\nworld\n").unwrap();
+    fs::write(&alpha, "hello\nzzz\n").unwrap();
+
+    let build = |words: &std::path::Path, extra: &[&str]| {
+        let status = std::process::Command::new(env!("CARGO_BIN_EXE_shaha"))
+            .args(["build", words.to_str().unwrap(), "-o", db.to_str().unwrap()])
+            .args(extra)
+            .status()
+            .expect("Failed to build database");
+        assert!(status.success());
+    };
+    let sha256 = hasher::get_hasher("sha256").unwrap();
+    let sources = || {
+        ParquetStorage::new(&db).query(&sha256.hash(b"hello"), None, 0, None).unwrap()[0]
+            .sources
+            .clone()
+    };
+
+    build(&mixed_case, &[]);
+    build(&mixed_case, &["--append", "--force"]);
+    build(&mixed_case, &["--append", "--force"]);
+    assert_eq!(sources(), ["RockYou"]);
+
+    build(&alpha, &["--append"]);
+    assert_eq!(sources(), ["RockYou", "alpha"]);
+
+    // Without a transform the names stay distinct; with one, stored names
+    // are canonicalized as well
+    build(&lower_case, &["--append", "--source-name-transform", "lower"]);
+    assert_eq!(sources(), ["alpha", "rockyou"]);
+    let world = ParquetStorage::new(&db).query(&sha256.hash(b"world"), None, 0, None).unwrap();
+    assert_eq!(world[0].sources, ["rockyou"]);
+    let meta = ParquetStorage::new(&db).get_source_meta().unwrap();
+    assert_eq!(meta.keys().collect::<Vec<_>>(), ["alpha", "rockyou"]);
+}
+
+#[test]
+fn test_dir_source_reads_txt_files_recursively() {
+    let dir = tempfile::tempdir().unwrap();
+    let lists = dir.path().join("lists");
+    fs::create_dir_all(lists.join("nested")).unwrap();
+    fs::write(lists.join("b.txt"), "banana\ncherry\n").unwrap();
+    fs::write(lists.join("nested").join("a.txt"), "apple\n").unwrap();
+    fs::write(lists.join("skip.csv"), "not,a,wordlist\n").unwrap();
+    fs::write(lists.join(".hidden.txt"), "secret\n").unwrap();
+
+    let source = shaha::source::parse(&format!("dir:{}", lists.display())).unwrap();
+    assert_eq!(source.name(), "lists");
+    let mut words: Vec<String> = source.words().unwrap().collect();
+    words.sort();
+    assert_eq!(words, ["apple", "banana", "cherry"]);
+
+    let hash = source.content_hash().unwrap().unwrap();
+    let again = shaha::source::parse(&format!("dir:{}", lists.display())).unwrap();
+    assert_eq!(again.content_hash().unwrap(), Some(hash.clone()));
+    fs::write(lists.join("nested").join("a.txt"), "apricot\n").unwrap();
+    assert_ne!(source.content_hash().unwrap().unwrap(), hash);
+
+    assert!(shaha::source::parse(&format!("dir:{}", dir.path().join("missing").display())).is_err());
+}
+
+#[test]
+fn test_build_chunk_output_writes_contiguous_parts() {
+    let dir = tempfile::tempdir().unwrap();
+    let words_path = dir.path().join("words.txt");
+    let out_dir = dir.path().join("parts");
+    fs::create_dir(&out_dir).unwrap();
+    let output = out_dir.join("hashes.parquet");
+    let words: Vec<String> = (0..3000).map(|i| format!("word{}", i)).collect();
+    fs::write(&words_path, words.join("\n")).unwrap();
+
+    let build = |max_bytes: &str| {
+        let result = std::process::Command::new(env!("CARGO_BIN_EXE_shaha"))
+            .args(["build", words_path.to_str().unwrap(), "-o", output.to_str().unwrap()])
+            .args(["--force", "--chunk-output", "--max-chunk-size", max_bytes])
+            .output()
+            .expect("Failed to build database");
+        assert!(result.status.success());
+        String::from_utf8_lossy(&result.stderr).to_string()
+    };
+    build("65536");
+    assert!(!output.exists());
+
+    let mut total = 0;
+    let mut previous_max: Option<Vec<u8>> = None;
+    let mut index = 0;
+    loop {
+        let part = out_dir.join(format!("hashes.part-{:03}.parquet", index));
+        if !part.exists() {
+            break;
+        }
+        assert!(fs::metadata(&part).unwrap().len() <= 65536);
+        let storage = ParquetStorage::new(&part);
+        assert!(storage.bloom_stats().unwrap().is_some());
+        let mut hashes = Vec::new();
+        storage.for_each_record(|record| {
+            hashes.push(record.hash);
+            Ok(())
+        }).unwrap();
+        assert!(hashes.windows(2).all(|pair| pair[0] <= pair[1]));
+        if let Some(max) = &previous_max {
+            assert!(max < &hashes[0], "part {} overlaps the previous one", index);
+        }
+        previous_max = hashes.last().cloned();
+        total += hashes.len();
+        index += 1;
+    }
+    assert!(index > 1, "expected several parts, got {}", index);
+    assert_eq!(total, 3000);
+
+    let sha256 = hasher::get_hasher("sha256").unwrap();
+    let output = std::process::Command::new(env!("CARGO_BIN_EXE_shaha"))
+        .args(["query", &hex::encode(sha256.hash(b"word1234")), "--db-dir", out_dir.to_str().unwrap()])
+        .output()
+        .expect("Failed to run query");
+    assert!(String::from_utf8_lossy(&output.stdout).contains("word1234"));
+
+    // A roomier rebuild leaves no stale parts behind
+    let stderr = build("10000000");
+    let only_part = out_dir.join("hashes.part-000.parquet");
+    assert!(only_part.exists());
+    assert!(!out_dir.join("hashes.part-001.parquet").exists());
+    assert!(stderr.contains(&format!("Wrote to {}\n", only_part.display())), "{}", stderr);
+
+    // Appending later sources would not see the parts written by earlier ones
+    let sources_file = dir.path().join("sources.txt");
+    fs::write(&sources_file, format!("{}\n", words_path.display())).unwrap();
+    let result = std::process::Command::new(env!("CARGO_BIN_EXE_shaha"))
+        .args(["build", "--sources-file", sources_file.to_str().unwrap()])
+        .args(["-o", out_dir.join("hashes.parquet").to_str().unwrap()])
+        .args(["--chunk-output", "--max-chunk-size", "65536"])
+        .output()
+        .expect("Failed to run build");
+    assert!(!result.status.success());
+    assert!(String::from_utf8_lossy(&result.stderr).contains("--chunk-output"));
+}
+
+#[test]
+fn test_build_filters_candidates_by_length_and_charset() {
+    let dir = tempfile::tempdir().unwrap();
+    let words_path = dir.path().join("words.txt");
+    fs::write(
+        &words_path,
+        "abc\nabcd1234\npässwörd\n12345678\nhello world\n1234567890123\nabcd1234\n",
+    )
+    .unwrap();
+
+    let build = |name: &str, flags: &[&str]| {
+        let db_path = dir.path().join(name);
+        let output = std::process::Command::new(env!("CARGO_BIN_EXE_shaha"))
+            .args(["build", words_path.to_str().unwrap(), "-o", db_path.to_str().unwrap()])
+            .args(flags)
+            .output()
+            .expect("Failed to build database");
+        assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+        let mut preimages = Vec::new();
+        ParquetStorage::new(&db_path)
+            .for_each_record(|record| {
+                preimages.push(record.preimage);
+                Ok(())
+            })
+            .unwrap();
+        preimages.sort();
+        (preimages, String::from_utf8_lossy(&output.stderr).to_string())
+    };
+
+    let (preimages, stderr) =
+        build("alnum.parquet", &["--min-len", "8", "--max-len", "10", "--charset", "alnum"]);
+    assert_eq!(preimages, vec!["12345678", "abcd1234"]);
+    assert!(
+        stderr.contains("Processed 7 words (2 unique, 1 duplicates skipped, 4 filtered)"),
+        "{}",
+        stderr
+    );
+
+    let (preimages, _) = build("digits.parquet", &["--charset", "digits"]);
+    assert_eq!(preimages, vec!["12345678", "1234567890123"]);
+
+    // Length counts characters: "pässwörd" is 8 long though 10 bytes
+    let (preimages, _) = build("exact.parquet", &["--min-len", "8", "--max-len", "8"]);
+    assert_eq!(preimages, vec!["12345678", "abcd1234", "pässwörd"]);
+
+    let (preimages, _) = build("ascii.parquet", &["--charset", "ascii", "--max-len", "3"]);
+    assert_eq!(preimages, vec!["abc"]);
+}
+
+#[test]
+fn test_append_extends_metadata_like_a_full_rebuild() {
+    use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+
+    let dir = tempfile::tempdir().unwrap();
+    let base = dir.path().join("base.txt");
+    let daily = dir.path().join("daily.txt");
+    let db_path = dir.path().join("test.parquet");
+    let rebuilt_path = dir.path().join("rebuilt.parquet");
+    fs::write(&base, "alpha\nbeta\ngamma\n").unwrap();
+    fs::write(&daily, "beta\ndelta\n").unwrap();
+
+    let build = |input: &std::path::Path, extra: &[&str]| {
+        let output = std::process::Command::new(env!("CARGO_BIN_EXE_shaha"))
+            .args(["build", input.to_str().unwrap(), "-o", db_path.to_str().unwrap()])
+            .args(["--bloom-seed", "daily", "-a", "sha256", "-a", "md5"])
+            .args(extra)
+            .output()
+            .expect("Failed to build database");
+        assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+        String::from_utf8_lossy(&output.stderr).to_string()
+    };
+    build(&base, &[]);
+    let stderr = build(&daily, &["--append"]);
+    assert!(stderr.contains("Extending metadata of"), "{}", stderr);
+
+    // Recompute everything from the appended rows
+    let mut records = Vec::new();
+    ParquetStorage::new(&db_path)
+        .for_each_record(|record| {
+            records.push(record);
+            Ok(())
+        })
+        .unwrap();
+    let mut rebuilt = ParquetStorage::with_expected_capacity(&rebuilt_path, records.len())
+        .with_bloom_seed(blake3::hash(b"daily").as_bytes());
+    rebuilt.write_batch(records).unwrap();
+    rebuilt.finish().unwrap();
+
+    let metadata = |path: &std::path::Path| {
+        let builder =
+            ParquetRecordBatchReaderBuilder::try_new(fs::File::open(path).unwrap()).unwrap();
+        let metadata = builder.metadata().file_metadata().key_value_metadata().cloned().unwrap();
+        [
+            "shaha:total_records",
+            "shaha:distinct_preimages",
+            "shaha:bloom_bitmap",
+            "shaha:bloom_keys",
+            "shaha:bloom_items",
+            "shaha:bloom_hashes",
+        ]
+        .map(|key| metadata.iter().find(|kv| kv.key == key).unwrap().value.clone())
+    };
+    assert_eq!(metadata(&db_path), metadata(&rebuilt_path));
+
+    let sorted_stats = |path: &std::path::Path| {
+        let mut stats = ParquetStorage::new(path).stats().unwrap();
+        stats.algorithms.sort();
+        stats.sources.sort();
+        (stats.total_records, stats.algorithms, stats.sources)
+    };
+    assert_eq!(sorted_stats(&db_path), sorted_stats(&rebuilt_path));
+    assert_eq!(sorted_stats(&db_path).2, vec!["base", "daily"]);
+
+    let sha256 = hasher::get_hasher("sha256").unwrap();
+    let storage = ParquetStorage::new(&db_path);
+    assert_eq!(storage.query(&sha256.hash(b"delta"), Some("sha256"), 0, None).unwrap().len(), 1);
+}
+
+#[test]
+fn test_build_rules_spec_mangles_words() {
+    let dir = tempfile::tempdir().unwrap();
+    let words_path = dir.path().join("words.txt");
+    fs::write(&words_path, "password\n").unwrap();
+
+    let build = |name: &str, spec: &str| {
+        let db_path = dir.path().join(name);
+        let output = std::process::Command::new(env!("CARGO_BIN_EXE_shaha"))
+            .args(["build", words_path.to_str().unwrap(), "-o", db_path.to_str().unwrap()])
+            .args(["--rules", spec])
+            .output()
+            .expect("Failed to build database");
+        (db_path, output)
+    };
+    let preimages = |db_path: &std::path::Path| {
+        let mut preimages = Vec::new();
+        ParquetStorage::new(db_path)
+            .for_each_record(|record| {
+                preimages.push(record.preimage);
+                Ok(())
+            })
+            .unwrap();
+        preimages.sort();
+        preimages
+    };
+
+    let (digits_db, output) = build("digits.parquet", "append-digits:0-9");
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    let mut expected: Vec<String> = (0..10).map(|n| format!("password{}", n)).collect();
+    expected.push("password".to_string());
+    expected.sort();
+    assert_eq!(preimages(&digits_db), expected);
+    let meta = ParquetStorage::new(&digits_db).get_source_meta().unwrap();
+    assert_eq!(meta["words"].rules.as_deref(), Some("append-digits:0-9"));
+
+    let (mixed_db, output) = build("mixed.parquet", "leet, capitalize");
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    assert_eq!(preimages(&mixed_db), vec!["Password", "p455w0rd", "password"]);
+
+    let (_, output) = build("bad.parquet", "append-digits:9-0");
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("9 is greater than 0"));
+    let (_, output) = build("unknown.parquet", "reverse");
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("unknown rule 'reverse'"));
+}
+
+#[test]
+fn test_build_on_invalid_utf8_lines() {
+    let dir = tempfile::tempdir().unwrap();
+    let words_path = dir.path().join("words.txt");
+    fs::write(&words_path, b"alpha\n\nbe\xffta\r\ngamma\n").unwrap();
+
+    let build = |name: &str, mode: &str| {
+        let db_path = dir.path().join(name);
+        let output = std::process::Command::new(env!("CARGO_BIN_EXE_shaha"))
+            .args(["build", words_path.to_str().unwrap(), "-o", db_path.to_str().unwrap()])
+            .args(["--on-invalid", mode])
+            .output()
+            .expect("Failed to build database");
+        (db_path, output)
+    };
+    let preimages = |db_path: &std::path::Path| {
+        let mut preimages = Vec::new();
+        ParquetStorage::new(db_path)
+            .for_each_record(|record| {
+                preimages.push(record.preimage);
+                Ok(())
+            })
+            .unwrap();
+        preimages.sort();
+        preimages
+    };
+
+    let (db_path, output) = build("fail.parquet", "fail");
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("words.txt line 3 is not valid UTF-8"), "{}", stderr);
+    assert!(!db_path.exists());
+
+    let (db_path, output) = build("skip.parquet", "skip");
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    assert_eq!(preimages(&db_path), vec!["alpha", "gamma"]);
+
+    // Lines after the invalid one are still read
+    let (db_path, output) = build("lossy.parquet", "lossy");
+    assert!(output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("1 lines are not valid UTF-8"));
+    assert_eq!(preimages(&db_path), vec!["alpha", "be\u{FFFD}ta", "gamma"]);
+}
+
+#[test]
+fn test_invalid_lines_are_tallied_per_source() {
+    use shaha::source::{DirSource, InvalidLines, OnInvalid};
+
+    let dir = tempfile::tempdir().unwrap();
+    let lists = dir.path().join("lists");
+    fs::create_dir(&lists).unwrap();
+    fs::write(lists.join("a.txt"), b"alpha\nbe\xffta\n").unwrap();
+    fs::write(lists.join("b.txt"), b"gamma\n").unwrap();
+
+    let skipping = FileSource::new(lists.join("a.txt")).with_on_invalid(OnInvalid::Skip);
+    let clean = FileSource::new(lists.join("b.txt")).with_on_invalid(OnInvalid::Skip);
+    assert_eq!(skipping.words().unwrap().collect::<Vec<_>>(), vec!["alpha"]);
+    assert_eq!(clean.words().unwrap().collect::<Vec<_>>(), vec!["gamma"]);
+    let first = Some((lists.join("a.txt").display().to_string(), 2));
+    assert_eq!(skipping.invalid_lines(), InvalidLines { count: 1, first: first.clone() });
+    assert_eq!(clean.invalid_lines(), InvalidLines::default());
+
+    // Under fail a dir: source stops at the first invalid line of any file
+    let failing = DirSource::new(&lists).unwrap().with_on_invalid(OnInvalid::Fail);
+    assert_eq!(failing.words().unwrap().collect::<Vec<_>>(), vec!["alpha"]);
+    assert_eq!(failing.invalid_lines(), InvalidLines { count: 1, first });
+}
+
+#[test]
+fn test_range_source_pads_to_start_width() {
+    let padded = shaha::source::parse("range:0000-0012").unwrap();
+    assert_eq!(padded.name(), "range_0000_0012");
+    let words: Vec<String> = padded.words().unwrap().collect();
+    assert_eq!(words.len(), 13);
+    assert_eq!(words[0], "0000");
+    assert_eq!(words[7], "0007");
+    assert_eq!(words[12], "0012");
+
+    let unpadded = shaha::source::parse("range:8-11").unwrap();
+    assert_eq!(unpadded.name(), "range_8_11");
+    assert_eq!(unpadded.words().unwrap().collect::<Vec<_>>(), vec!["8", "9", "10", "11"]);
+
+    // The same spec always hashes the same; the full u64 range streams lazily
+    let again = shaha::source::parse("range:8-11").unwrap();
+    assert_eq!(unpadded.content_hash().unwrap(), again.content_hash().unwrap());
+    assert_ne!(unpadded.content_hash().unwrap(), padded.content_hash().unwrap());
+    let huge = shaha::source::parse("range:0-18446744073709551615").unwrap();
+    assert_eq!(huge.words().unwrap().take(2).collect::<Vec<_>>(), vec!["0", "1"]);
+
+    assert!(shaha::source::parse("range:9-1").is_err());
+    assert!(shaha::source::parse("range:-5-5").is_err());
+    assert!(shaha::source::parse("range:100").is_err());
+
+    let dir = tempfile::tempdir().unwrap();
+    let db_path = dir.path().join("pins.parquet");
+    let output = std::process::Command::new(env!("CARGO_BIN_EXE_shaha"))
+        .args(["build", "--from", "range:000-999", "-a", "md5", "-o", db_path.to_str().unwrap()])
+        .output()
+        .expect("Failed to build database");
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    let storage = ParquetStorage::new(&db_path);
+    let stats = storage.stats().unwrap();
+    assert_eq!(stats.total_records, 1000);
+    assert_eq!(stats.sources, vec!["range_000_999"]);
+    let md5 = hasher::get_hasher("md5").unwrap();
+    let results = storage.query(&md5.hash(b"042"), Some("md5"), 0, None).unwrap();
+    assert_eq!(results[0].preimage, "042");
+}